@@ -4,6 +4,9 @@ use std::rc::Rc;
 use std::cell::RefCell;
 use super::{BTreeNodeType, BTreeError};
 use crate::engine::storage::binary::BinaryPageReader;
+use crate::engine::storage::PageType;
+use crate::engine::storage::varint::read_varint;
+use crate::engine::HEADER_SIZE;
 
 /// A page identifier which points to a B-tree node in the database file
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -20,11 +23,14 @@ pub struct CellPointer {
 #[derive(Debug, Clone)]
 pub struct NodeHeader {
     pub node_type: BTreeNodeType,
+    pub page_type: PageType,
     pub cell_count: u16,
     pub free_block_offset: u16,
     pub right_child: Option<PageId>,
     pub parent_page: Option<PageId>,
     pub depth: u8,
+    pub free_bytes: usize,
+    pub usable_size: usize,
 }
 
 /// Collection of B-tree pages in memory
@@ -44,33 +50,217 @@ impl BTreePageCollection {
     }
     
     pub fn get_node(&self, page_id: PageId) -> Result<BTreeNode> {
-        // In a real implementation, this would retrieve the node from cache or disk
-        // For demo purposes, we'll create a dummy node
-        
+        let page = self.page_reader.get_page(page_id.0)?;
+        let usable_size = self.page_reader.get_usable_size();
+
+        // The 100-byte file header shares page 1 with the schema root
+        let header_offset = if page_id.0 == 1 { HEADER_SIZE } else { 0 };
+        let data = page.data;
+
+        let node_type = match page.page_type {
+            PageType::InteriorIndex | PageType::InteriorTable => BTreeNodeType::Internal,
+            PageType::LeafIndex | PageType::LeafTable => BTreeNodeType::Leaf,
+            _ => {
+                return Err(anyhow!(BTreeError::InvalidFormat(format!(
+                    "page {} is not a b-tree page ({:?})",
+                    page_id.0, page.page_type
+                ))))
+            }
+        };
+
+        let read_u16 = |offset: usize| ((data[offset] as usize) << 8) | data[offset + 1] as usize;
+
+        let cell_count = read_u16(header_offset + 3);
+        let content_start = match read_u16(header_offset + 5) {
+            0 => 65536,
+            start => start,
+        };
+        let fragmented_bytes = data[header_offset + 7] as usize;
+
+        let (page_header_size, right_child) = if node_type == BTreeNodeType::Internal {
+            let right = u32::from_be_bytes([
+                data[header_offset + 8],
+                data[header_offset + 9],
+                data[header_offset + 10],
+                data[header_offset + 11],
+            ]);
+            (12, Some(PageId(right as usize)))
+        } else {
+            (8, None)
+        };
+
+        let pointer_array = header_offset + page_header_size;
+        if pointer_array + cell_count * 2 > data.len() {
+            return Err(anyhow!(BTreeError::InvalidFormat(format!(
+                "page {} claims {} cells, more than fit on the page",
+                page_id.0, cell_count
+            ))));
+        }
+
+        let mut cells = Vec::with_capacity(cell_count);
+        for i in 0..cell_count {
+            let offset = read_u16(pointer_array + i * 2);
+            let size = cell_size(&data, offset, page.page_type, usable_size)?;
+            cells.push(CellPointer { offset, size });
+        }
+
+        // Free space is the gap between the pointer array and the cell content
+        // area, plus every freeblock in the chain, plus fragmented bytes
+        let mut free_bytes = content_start.saturating_sub(pointer_array + cell_count * 2);
+        let mut freeblock = page.free_offset;
+        let mut hops = 0;
+        while freeblock != 0 && freeblock + 4 <= data.len() && hops < data.len() / 4 {
+            free_bytes += read_u16(freeblock + 2);
+            freeblock = read_u16(freeblock);
+            hops += 1;
+        }
+        free_bytes += fragmented_bytes;
+
         let header = NodeHeader {
-            node_type: BTreeNodeType::Leaf,
-            cell_count: 5,
-            free_block_offset: 2048,
-            right_child: None,
+            node_type,
+            page_type: page.page_type,
+            cell_count: cell_count as u16,
+            free_block_offset: page.free_offset as u16,
+            right_child,
             parent_page: None,
-            depth: 1,
+            depth: 0,
+            free_bytes,
+            usable_size,
         };
-        
-        let cells = vec![
-            CellPointer { offset: 100, size: 64 },
-            CellPointer { offset: 164, size: 128 },
-            CellPointer { offset: 292, size: 72 },
-            CellPointer { offset: 364, size: 96 },
-            CellPointer { offset: 460, size: 112 },
-        ];
-        
+
         Ok(BTreeNode {
             page_id,
             header,
             cells,
-            data: vec![0u8; 4096],
+            data,
         })
     }
+
+    pub fn page_reader(&self) -> &BinaryPageReader {
+        &self.page_reader
+    }
+
+    /// Reads the full payload of a leaf or interior-index cell, following the
+    /// overflow chain when it spills. Table leaf cells also return their rowid.
+    pub fn read_payload(&self, node: &BTreeNode, cell_index: usize) -> Result<(Option<i64>, Vec<u8>)> {
+        let cell = node.cells.get(cell_index).ok_or_else(|| {
+            anyhow!("Cell {} out of range on page {}", cell_index, node.page_id.0)
+        })?;
+        let page_type = node.header.page_type;
+        let usable_size = node.header.usable_size;
+        let data = &node.data;
+
+        let mut cursor = cell.offset;
+        match page_type {
+            PageType::InteriorTable => {
+                return Err(anyhow!("Interior table cells on page {} carry no payload", node.page_id.0))
+            }
+            PageType::InteriorIndex => cursor += 4,
+            _ => {}
+        }
+
+        let (payload_size, used) = read_varint(&data[cursor..]);
+        cursor += used;
+        let payload_size = payload_size as usize;
+
+        let rowid = if page_type == PageType::LeafTable {
+            let (rowid, used) = read_varint(&data[cursor..]);
+            cursor += used;
+            Some(rowid as i64)
+        } else {
+            None
+        };
+
+        let local = local_payload_size(payload_size, page_type, usable_size);
+        if cursor + local > data.len() {
+            return Err(anyhow!("Cell payload on page {} runs past the page end", node.page_id.0));
+        }
+
+        let mut payload = Vec::with_capacity(payload_size);
+        payload.extend_from_slice(&data[cursor..cursor + local]);
+
+        if local < payload_size {
+            let pointer = &data[cursor + local..cursor + local + 4];
+            let mut next = u32::from_be_bytes([pointer[0], pointer[1], pointer[2], pointer[3]]) as usize;
+            let mut hops = 0;
+
+            while payload.len() < payload_size {
+                if next == 0 || hops > payload_size {
+                    return Err(anyhow!("Overflow chain for page {} ends early", node.page_id.0));
+                }
+                let overflow = self.page_reader.get_page(next)?.data;
+                let take = (payload_size - payload.len()).min(usable_size - 4);
+                payload.extend_from_slice(&overflow[4..4 + take]);
+                next = u32::from_be_bytes([overflow[0], overflow[1], overflow[2], overflow[3]]) as usize;
+                hops += 1;
+            }
+        }
+
+        Ok((rowid, payload))
+    }
+}
+
+/// Computes the number of bytes a cell occupies on its page, including the
+/// overflow page pointer when the payload spills
+fn cell_size(data: &[u8], offset: usize, page_type: PageType, usable_size: usize) -> Result<usize> {
+    if offset >= data.len() {
+        return Err(anyhow!(BTreeError::InvalidFormat(format!(
+            "cell offset {} is outside the page",
+            offset
+        ))));
+    }
+
+    let mut cursor = offset;
+
+    if matches!(page_type, PageType::InteriorTable | PageType::InteriorIndex) {
+        // 4-byte left child pointer
+        cursor += 4;
+    }
+
+    if page_type == PageType::InteriorTable {
+        // Interior table cells carry only the child pointer and a rowid key
+        let (_, rowid_len) = read_varint(&data[cursor..]);
+        return Ok(cursor + rowid_len - offset);
+    }
+
+    let (payload_size, payload_len) = read_varint(&data[cursor..]);
+    cursor += payload_len;
+
+    if page_type == PageType::LeafTable {
+        let (_, rowid_len) = read_varint(&data[cursor..]);
+        cursor += rowid_len;
+    }
+
+    let local = local_payload_size(payload_size as usize, page_type, usable_size);
+    cursor += local;
+    if local < payload_size as usize {
+        cursor += 4;
+    }
+
+    Ok(cursor - offset)
+}
+
+/// Number of payload bytes stored on the b-tree page itself, per the
+/// overflow rules of the SQLite file format
+pub fn local_payload_size(payload_size: usize, page_type: PageType, usable_size: usize) -> usize {
+    let max_local = if page_type == PageType::LeafTable {
+        usable_size - 35
+    } else {
+        ((usable_size - 12) * 64 / 255) - 23
+    };
+
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = ((usable_size - 12) * 32 / 255) - 23;
+    let spill = min_local + ((payload_size - min_local) % (usable_size - 4));
+
+    if spill <= max_local {
+        spill
+    } else {
+        min_local
+    }
 }
 
 /// A B-tree node (page) in the database
@@ -87,12 +277,18 @@ impl BTreeNode {
         BTreeNode {
             page_id,
             header: NodeHeader {
+                page_type: match node_type {
+                    BTreeNodeType::Internal => PageType::InteriorTable,
+                    _ => PageType::LeafTable,
+                },
                 node_type,
                 cell_count: 0,
                 free_block_offset: page_size as u16,
                 right_child: None,
                 parent_page: None,
                 depth: 0,
+                free_bytes: page_size,
+                usable_size: page_size,
             },
             cells: Vec::new(),
             data: vec![0u8; page_size],
@@ -118,15 +314,53 @@ impl BTreeNode {
         // Simulate successful insertion
         self.header.cell_count += 1;
         self.header.free_block_offset -= encoded_size as u16;
+        self.header.free_bytes -= encoded_size;
         
         Ok(())
     }
     
     pub fn free_space(&self) -> usize {
-        // A simplistic calculation - in reality would be more complex
-        self.header.free_block_offset as usize - 
-            (self.header.cell_count as usize * std::mem::size_of::<CellPointer>()) - 
-            std::mem::size_of::<NodeHeader>()
+        self.header.free_bytes
+    }
+
+    /// Fraction of the usable page area occupied by headers, pointers and cells
+    pub fn fill_factor(&self) -> f64 {
+        if self.header.usable_size == 0 {
+            return 0.0;
+        }
+        let used = self.header.usable_size.saturating_sub(self.header.free_bytes);
+        used as f64 / self.header.usable_size as f64
+    }
+
+    /// Whether this page belongs to an index b-tree rather than a table b-tree
+    pub fn is_index_page(&self) -> bool {
+        matches!(
+            self.header.page_type,
+            PageType::InteriorIndex | PageType::LeafIndex
+        )
+    }
+
+    /// Child pages of an interior node in key order, ending with the right-most child
+    pub fn child_pages(&self) -> Vec<PageId> {
+        if self.header.node_type != BTreeNodeType::Internal {
+            return Vec::new();
+        }
+
+        let mut children: Vec<PageId> = self
+            .cells
+            .iter()
+            .filter(|cell| cell.offset + 4 <= self.data.len())
+            .map(|cell| {
+                let bytes = &self.data[cell.offset..cell.offset + 4];
+                PageId(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+            })
+            .collect();
+
+        if let Some(right) = self.header.right_child {
+            children.push(right);
+        }
+
+        children
     }
     
     pub fn is_full(&self, new_cell_size: usize) -> bool {
@@ -1,5 +1,6 @@
 use anyhow::{Result, anyhow};
 use std::cmp::Ordering;
+use std::collections::HashSet;
 
 use super::node::{BTreeNode, PageId, BTreePageCollection};
 use super::BTreeNodeType;
@@ -55,9 +56,11 @@ impl BTreeIterator {
             match node.header.node_type {
                 BTreeNodeType::Leaf => return Ok(node),
                 BTreeNodeType::Internal => {
-                    // In a real implementation, we'd navigate to the leftmost child
-                    // For simplicity, we'll just pretend the node is already a leaf
-                    return Ok(BTreeNode::new(current_page_id, BTreeNodeType::Leaf, 4096));
+                    current_page_id = node
+                        .child_pages()
+                        .first()
+                        .copied()
+                        .ok_or_else(|| anyhow!("Interior page {} has no children", current_page_id.0))?;
                 },
                 _ => return Err(anyhow!("Unexpected node type during traversal"))
             }
@@ -90,6 +93,56 @@ impl BTreeIterator {
 pub struct BTreeTraversal;
 
 impl BTreeTraversal {
+    /// Visits every page of the tree rooted at `root_page` depth-first, passing
+    /// each node together with its level (the root is level 1)
+    pub fn walk<F>(pages: &BTreePageCollection, root_page: PageId, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&BTreeNode, usize),
+    {
+        let mut visited = HashSet::new();
+        let mut stack = vec![(root_page, 1)];
+
+        while let Some((page_id, level)) = stack.pop() {
+            if !visited.insert(page_id) {
+                return Err(anyhow!("B-tree cycle detected at page {}", page_id.0));
+            }
+
+            let node = pages.get_node(page_id)?;
+            visit(&node, level);
+
+            // Push in reverse so children are visited left to right
+            for child in node.child_pages().into_iter().rev() {
+                stack.push((child, level + 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects every row of the table b-tree rooted at `root_page` in rowid
+    /// order as (rowid, record payload) pairs
+    pub fn scan_table(pages: &BTreePageCollection, root_page: PageId) -> Result<Vec<(i64, Vec<u8>)>> {
+        let mut leaves = Vec::new();
+        Self::walk(pages, root_page, |node, _| {
+            if node.header.node_type == BTreeNodeType::Leaf {
+                leaves.push(node.clone());
+            }
+        })?;
+
+        let mut rows = Vec::new();
+        for leaf in leaves {
+            if leaf.is_index_page() {
+                return Err(anyhow!("Page {} belongs to an index, not a table", leaf.page_id.0));
+            }
+            for cell_index in 0..leaf.cells.len() {
+                let (rowid, payload) = pages.read_payload(&leaf, cell_index)?;
+                rows.push((rowid.unwrap_or_default(), payload));
+            }
+        }
+
+        Ok(rows)
+    }
+
     pub fn search(pages: &BTreePageCollection, root_page: PageId, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let mut context = TraversalContext::new(root_page);
         
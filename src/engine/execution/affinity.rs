@@ -0,0 +1,351 @@
+//! Type affinity and value ordering following SQLite's datatype rules
+//!
+//! Affinity decides how a value is converted when it is stored in a column
+//! (https://www.sqlite.org/datatype3.html#type_affinity). The engine applies
+//! it to the row images triggers see and to STRICT checks. Comparisons in
+//! queries are evaluated by SQLite, which applies comparison affinity
+//! (https://www.sqlite.org/datatype3.html#comparison_expressions) itself;
+//! `compare_values` only orders values already read from storage, such as
+//! sqlite_stat4 samples.
+
+use anyhow::Result;
+use std::cmp::Ordering;
+
+use super::ColumnValue;
+use crate::schema::column::{ColumnAffinity, StrictType};
+
+/// Storage classes in the order SQLite sorts them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageClass {
+    Null,
+    Numeric,
+    Text,
+    Blob,
+}
+
+impl ColumnValue {
+    pub fn storage_class(&self) -> StorageClass {
+        match self {
+            ColumnValue::Null => StorageClass::Null,
+            ColumnValue::Integer(_) | ColumnValue::Real(_) => StorageClass::Numeric,
+            ColumnValue::Text(_) => StorageClass::Text,
+            ColumnValue::Blob(_) => StorageClass::Blob,
+        }
+    }
+}
+
+/// Converts a value as SQLite does when storing it in a column of the given affinity
+pub fn apply_affinity(value: ColumnValue, affinity: ColumnAffinity) -> ColumnValue {
+    match affinity {
+        ColumnAffinity::Text => match value {
+            ColumnValue::Integer(i) => ColumnValue::Text(i.to_string()),
+            ColumnValue::Real(r) => ColumnValue::Text(format_real(r)),
+            value => value,
+        },
+        ColumnAffinity::Numeric | ColumnAffinity::Integer => match value {
+            ColumnValue::Text(text) => match parse_numeric_text(&text) {
+                Some(number) => integral_real_to_integer(number),
+                None => ColumnValue::Text(text),
+            },
+            ColumnValue::Real(r) => integral_real_to_integer(ColumnValue::Real(r)),
+            value => value,
+        },
+        ColumnAffinity::Real => match value {
+            ColumnValue::Integer(i) => ColumnValue::Real(i as f64),
+            ColumnValue::Text(text) => match parse_numeric_text(&text) {
+                Some(ColumnValue::Integer(i)) => ColumnValue::Real(i as f64),
+                Some(number) => number,
+                None => ColumnValue::Text(text),
+            },
+            value => value,
+        },
+        ColumnAffinity::Blob | ColumnAffinity::None => value,
+    }
+}
+
+/// Converts a value bound for a column of a STRICT table, or returns the
+/// storage class it has when it cannot be stored losslessly as `strict_type`
+pub fn apply_strict_type(value: ColumnValue, strict_type: StrictType) -> Result<ColumnValue, &'static str> {
+    let value = apply_affinity(value, strict_type.affinity());
+    let fits = matches!(
+        (&value, strict_type),
+        (ColumnValue::Null, _)
+            | (_, StrictType::Any)
+            | (ColumnValue::Integer(_), StrictType::Int | StrictType::Integer)
+            | (ColumnValue::Real(_), StrictType::Real)
+            | (ColumnValue::Text(_), StrictType::Text)
+            | (ColumnValue::Blob(_), StrictType::Blob)
+    );
+
+    if fits {
+        Ok(value)
+    } else {
+        Err(match value {
+            ColumnValue::Integer(_) => "INT",
+            ColumnValue::Real(_) => "REAL",
+            ColumnValue::Text(_) => "TEXT",
+            ColumnValue::Blob(_) => "BLOB",
+            ColumnValue::Null => "NULL",
+        })
+    }
+}
+
+/// Orders values as SQLite does without any conversion: NULL before numbers,
+/// numbers before text and text before blobs. Integers and reals compare
+/// numerically, text by its bytes and blobs with memcmp.
+pub fn compare_values(left: &ColumnValue, right: &ColumnValue) -> Ordering {
+    match (left, right) {
+        (ColumnValue::Integer(a), ColumnValue::Integer(b)) => a.cmp(b),
+        (ColumnValue::Real(a), ColumnValue::Real(b)) => compare_reals(*a, *b),
+        (ColumnValue::Integer(a), ColumnValue::Real(b)) => compare_integer_real(*a, *b),
+        (ColumnValue::Real(a), ColumnValue::Integer(b)) => compare_integer_real(*b, *a).reverse(),
+        (ColumnValue::Text(a), ColumnValue::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (ColumnValue::Blob(a), ColumnValue::Blob(b)) => a.cmp(b),
+        (left, right) => left.storage_class().cmp(&right.storage_class()),
+    }
+}
+
+/// NaN cannot be stored in SQLite (it becomes NULL), so it only needs a
+/// consistent position here
+fn compare_reals(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()).reverse())
+}
+
+/// Exact integer/real comparison; casting a large i64 to f64 would round it
+fn compare_integer_real(integer: i64, real: f64) -> Ordering {
+    if real.is_nan() {
+        return Ordering::Greater;
+    }
+    if real >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+    if real < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+
+    let truncated = real.trunc() as i64;
+    match integer.cmp(&truncated) {
+        Ordering::Equal => compare_reals(0.0, real - real.trunc()),
+        ordering => ordering,
+    }
+}
+
+/// Parses text that is a well-formed integer or real literal, optionally
+/// surrounded by whitespace. Hexadecimal literals are not converted.
+pub fn parse_numeric_text(text: &str) -> Option<ColumnValue> {
+    let trimmed = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let body = trimmed.strip_prefix(['+', '-']).unwrap_or(trimmed);
+
+    let mut digits = 0;
+    let mut is_real = false;
+    let mut chars = body.chars().peekable();
+
+    while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+        chars.next();
+        digits += 1;
+    }
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        is_real = true;
+        while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+            chars.next();
+            digits += 1;
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    if matches!(chars.peek(), Some('e') | Some('E')) {
+        chars.next();
+        is_real = true;
+        if matches!(chars.peek(), Some('+') | Some('-')) {
+            chars.next();
+        }
+        let mut exponent_digits = 0;
+        while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+            chars.next();
+            exponent_digits += 1;
+        }
+        if exponent_digits == 0 {
+            return None;
+        }
+    }
+    if chars.next().is_some() {
+        return None;
+    }
+
+    if !is_real {
+        if let Ok(integer) = trimmed.parse::<i64>() {
+            return Some(ColumnValue::Integer(integer));
+        }
+    }
+
+    // Integers too large for i64 are kept as reals, as SQLite does
+    trimmed.parse::<f64>().ok().map(ColumnValue::Real)
+}
+
+/// NUMERIC and INTEGER affinity store reals with no fractional part as integers
+fn integral_real_to_integer(value: ColumnValue) -> ColumnValue {
+    match value {
+        ColumnValue::Real(r)
+            if r.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&r) =>
+        {
+            ColumnValue::Integer(r as i64)
+        }
+        value => value,
+    }
+}
+
+/// Renders a real the way SQLite converts it to text: 15 significant digits,
+/// always with a decimal point or exponent
+pub fn format_real(value: f64) -> String {
+    if value.is_nan() {
+        return String::new();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Inf".to_string() } else { "-Inf".to_string() };
+    }
+    if value == 0.0 {
+        return "0.0".to_string();
+    }
+
+    let scientific = format!("{:.14e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+
+    if (-4..15).contains(&exponent) {
+        let decimals = (14 - exponent) as usize;
+        let fixed = format!("{:.*}", decimals, value);
+        let fixed = if fixed.contains('.') { fixed.trim_end_matches('0').to_string() } else { fixed };
+        if fixed.ends_with('.') {
+            format!("{}0", fixed)
+        } else if fixed.contains('.') {
+            fixed
+        } else {
+            format!("{}.0", fixed)
+        }
+    } else {
+        let mantissa = mantissa.trim_end_matches('0');
+        let mantissa = if mantissa.ends_with('.') { format!("{}0", mantissa) } else { mantissa.to_string() };
+        format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> ColumnValue {
+        ColumnValue::Text(value.to_string())
+    }
+
+    /// The stored value, rendered so integers and reals stay distinguishable
+    fn stored(value: ColumnValue, affinity: ColumnAffinity) -> String {
+        format!("{:?}", apply_affinity(value, affinity))
+    }
+
+    #[test]
+    fn text_affinity_renders_numbers_and_keeps_text() {
+        assert_eq!(stored(ColumnValue::Integer(7), ColumnAffinity::Text), "Text(\"7\")");
+        assert_eq!(stored(ColumnValue::Real(1.5), ColumnAffinity::Text), "Text(\"1.5\")");
+        assert_eq!(stored(ColumnValue::Real(1e20), ColumnAffinity::Text), "Text(\"1.0e+20\")");
+        assert_eq!(stored(text("007"), ColumnAffinity::Text), "Text(\"007\")");
+        assert_eq!(stored(ColumnValue::Null, ColumnAffinity::Text), "Null");
+    }
+
+    #[test]
+    fn numeric_affinity_converts_well_formed_text() {
+        assert_eq!(stored(text("007"), ColumnAffinity::Numeric), "Integer(7)");
+        assert_eq!(stored(text(" 12 "), ColumnAffinity::Numeric), "Integer(12)");
+        assert_eq!(stored(text("1e3"), ColumnAffinity::Numeric), "Integer(1000)");
+        assert_eq!(stored(text("2.5"), ColumnAffinity::Numeric), "Real(2.5)");
+        assert_eq!(stored(text("0x10"), ColumnAffinity::Numeric), "Text(\"0x10\")");
+        assert_eq!(stored(text("12abc"), ColumnAffinity::Numeric), "Text(\"12abc\")");
+        assert_eq!(stored(ColumnValue::Blob(vec![1]), ColumnAffinity::Numeric), "Blob([1])");
+    }
+
+    #[test]
+    fn integer_affinity_keeps_fractional_reals() {
+        assert_eq!(stored(ColumnValue::Real(3.0), ColumnAffinity::Integer), "Integer(3)");
+        assert_eq!(stored(ColumnValue::Real(3.5), ColumnAffinity::Integer), "Real(3.5)");
+        // Too large for an i64, so it stays a real
+        assert_eq!(stored(text("9223372036854775808"), ColumnAffinity::Integer), "Real(9.223372036854776e18)");
+    }
+
+    #[test]
+    fn real_affinity_stores_integers_as_reals() {
+        assert_eq!(stored(ColumnValue::Integer(5), ColumnAffinity::Real), "Real(5.0)");
+        assert_eq!(stored(text("5"), ColumnAffinity::Real), "Real(5.0)");
+        assert_eq!(stored(text("abc"), ColumnAffinity::Real), "Text(\"abc\")");
+    }
+
+    #[test]
+    fn blob_affinity_stores_values_unchanged() {
+        assert_eq!(stored(text("007"), ColumnAffinity::Blob), "Text(\"007\")");
+        assert_eq!(stored(ColumnValue::Real(3.0), ColumnAffinity::None), "Real(3.0)");
+    }
+
+    /// The stored value of a STRICT column, or the storage class it rejects
+    fn strict(value: ColumnValue, strict_type: StrictType) -> String {
+        match apply_strict_type(value, strict_type) {
+            Ok(value) => format!("{:?}", value),
+            Err(storage_class) => format!("cannot store {}", storage_class),
+        }
+    }
+
+    #[test]
+    fn strict_integer_columns_accept_integral_values() {
+        assert_eq!(strict(ColumnValue::Integer(7), StrictType::Int), "Integer(7)");
+        assert_eq!(strict(text("007"), StrictType::Integer), "Integer(7)");
+        assert_eq!(strict(text(" 4 "), StrictType::Int), "Integer(4)");
+        assert_eq!(strict(ColumnValue::Real(3.0), StrictType::Integer), "Integer(3)");
+        assert_eq!(strict(text("abc"), StrictType::Integer), "cannot store TEXT");
+        assert_eq!(strict(ColumnValue::Real(3.5), StrictType::Integer), "cannot store REAL");
+        assert_eq!(strict(ColumnValue::Blob(vec![0]), StrictType::Int), "cannot store BLOB");
+    }
+
+    #[test]
+    fn strict_real_and_text_columns_convert_numbers() {
+        assert_eq!(strict(ColumnValue::Integer(5), StrictType::Real), "Real(5.0)");
+        assert_eq!(strict(text("12"), StrictType::Real), "Real(12.0)");
+        assert_eq!(strict(text("abc"), StrictType::Real), "cannot store TEXT");
+        assert_eq!(strict(ColumnValue::Integer(7), StrictType::Text), "Text(\"7\")");
+        assert_eq!(strict(ColumnValue::Real(3.5), StrictType::Text), "Text(\"3.5\")");
+        assert_eq!(strict(ColumnValue::Blob(vec![0]), StrictType::Text), "cannot store BLOB");
+    }
+
+    #[test]
+    fn strict_blob_columns_reject_everything_but_blobs() {
+        assert_eq!(strict(ColumnValue::Blob(vec![0]), StrictType::Blob), "Blob([0])");
+        assert_eq!(strict(text("12"), StrictType::Blob), "cannot store TEXT");
+        assert_eq!(strict(ColumnValue::Integer(7), StrictType::Blob), "cannot store INT");
+        assert_eq!(strict(ColumnValue::Real(3.0), StrictType::Blob), "cannot store REAL");
+    }
+
+    #[test]
+    fn strict_any_columns_store_values_unconverted() {
+        assert_eq!(strict(text("007"), StrictType::Any), "Text(\"007\")");
+        assert_eq!(strict(ColumnValue::Real(3.0), StrictType::Any), "Real(3.0)");
+        for strict_type in [StrictType::Int, StrictType::Real, StrictType::Text, StrictType::Blob, StrictType::Any] {
+            assert_eq!(strict(ColumnValue::Null, strict_type), "Null");
+        }
+    }
+
+    #[test]
+    fn values_order_by_storage_class() {
+        let ordered = [
+            ColumnValue::Null,
+            ColumnValue::Integer(-1),
+            ColumnValue::Real(0.5),
+            ColumnValue::Integer(1),
+            text("10"),
+            text("9"),
+            ColumnValue::Blob(vec![0]),
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare_values(&pair[0], &pair[1]), Ordering::Less, "{:?} < {:?}", pair[0], pair[1]);
+        }
+        assert_eq!(compare_values(&ColumnValue::Integer(2), &ColumnValue::Real(2.0)), Ordering::Equal);
+        assert_eq!(compare_values(&ColumnValue::Integer(i64::MAX), &ColumnValue::Real(9223372036854775807.0)), Ordering::Less);
+    }
+}
@@ -0,0 +1,186 @@
+//! Collating sequences for text comparison
+//!
+//! BINARY, NOCASE and RTRIM are built in. Other collations are registered
+//! process-wide with [`register_collation`]; every connection the engine
+//! opens resolves them on demand through SQLite's collation-needed hook, so
+//! ORDER BY, GROUP BY, DISTINCT, comparisons and indexes declared with them
+//! all use the same function. SQLite performs every comparison; the engine
+//! only decides which connection has the collations installed.
+
+use anyhow::{bail, Result};
+use rusqlite::Connection;
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, OnceLock, RwLock};
+
+pub const BINARY: &str = "BINARY";
+
+/// Collations SQLite defines itself, which registration cannot replace
+const BUILTIN: [&str; 3] = [BINARY, "NOCASE", "RTRIM"];
+
+/// Comparison function of a user-registered collation
+pub type CollationFn = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync + RefUnwindSafe>;
+
+static REGISTRY: OnceLock<RwLock<HashMap<String, CollationFn>>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<HashMap<String, CollationFn>> {
+    REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Registers or replaces a collation, usable afterwards as `COLLATE name`.
+/// Names are case-insensitive; the built-in collations cannot be replaced.
+pub fn register_collation<F>(name: &str, compare: F) -> Result<()>
+where
+    F: Fn(&str, &str) -> Ordering + Send + Sync + RefUnwindSafe + 'static,
+{
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        bail!("Invalid collation name '{}'", name);
+    }
+    if BUILTIN.iter().any(|builtin| builtin.eq_ignore_ascii_case(name)) {
+        bail!("Collation {} is built in and cannot be replaced", name.to_uppercase());
+    }
+
+    registry().write().unwrap().insert(name.to_uppercase(), Arc::new(compare));
+    println!("[COLLATION] Registered collation {}", name.to_uppercase());
+    Ok(())
+}
+
+/// Names of the registered (non built-in) collations
+pub fn registered_collations() -> Vec<String> {
+    let mut names: Vec<String> = registry().read().unwrap().keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// Collation names following COLLATE anywhere in a statement
+pub fn referenced_collations(sql: &str) -> Vec<String> {
+    let tokens = match Tokenizer::new(&SQLiteDialect {}, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut words = tokens.iter().filter(|token| !matches!(token, Token::Whitespace(_)));
+    let mut names = Vec::new();
+    while let Some(token) = words.next() {
+        if let Token::Word(word) = token {
+            if word.keyword == Keyword::COLLATE {
+                if let Some(Token::Word(name)) = words.next() {
+                    if !names.iter().any(|n: &String| n.eq_ignore_ascii_case(&name.value)) {
+                        names.push(name.value.clone());
+                    }
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Makes registered collations available on a connection as SQLite asks for them
+pub fn install(connection: &Connection) -> Result<()> {
+    connection.collation_needed(provide_collation)?;
+    Ok(())
+}
+
+fn provide_collation(connection: &Connection, name: &str) -> rusqlite::Result<()> {
+    let compare = registry().read().unwrap().get(&name.to_uppercase()).cloned();
+
+    match compare {
+        Some(compare) => {
+            println!("[COLLATION] Providing collation {} to SQLite", name);
+            connection.create_collation(name, move |left: &str, right: &str| compare(left, right))
+        }
+        // SQLite reports "no such collation sequence" itself
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First column of every row, as text
+    fn column(connection: &Connection, sql: &str) -> Vec<String> {
+        connection
+            .prepare(sql)
+            .unwrap()
+            .query_map([], |row| {
+                Ok(match row.get(0)? {
+                    rusqlite::types::Value::Integer(i) => i.to_string(),
+                    rusqlite::types::Value::Text(text) => text,
+                    other => format!("{:?}", other),
+                })
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    fn fixture() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        install(&connection).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE tags (label TEXT COLLATE NOCASE, padded TEXT COLLATE RTRIM);
+                 INSERT INTO tags VALUES ('beta', 'x'), ('Alpha', 'x  '), ('ALPHA', 'y'), ('Beta', 'y '), ('gamma', ' x');",
+            )
+            .unwrap();
+        connection
+    }
+
+    #[test]
+    fn built_in_collations_cannot_be_replaced() {
+        for name in ["binary", "NoCase", "RTRIM"] {
+            let error = register_collation(name, |left: &str, right: &str| left.cmp(right)).unwrap_err();
+            assert!(error.to_string().contains("is built in"), "{}", error);
+        }
+        assert!(register_collation("no spaces", |left: &str, right: &str| left.cmp(right)).is_err());
+        assert!(register_collation("", |left: &str, right: &str| left.cmp(right)).is_err());
+        assert!(!registered_collations().iter().any(|name| name == "NOCASE"));
+    }
+
+    #[test]
+    fn nocase_orders_groups_and_deduplicates_ignoring_case() {
+        let connection = fixture();
+
+        assert_eq!(column(&connection, "SELECT label FROM tags ORDER BY label, rowid"), vec!["Alpha", "ALPHA", "beta", "Beta", "gamma"]);
+        assert_eq!(
+            column(&connection, "SELECT min(rowid) || ':' || count(*) FROM tags GROUP BY label ORDER BY label"),
+            vec!["2:2", "1:2", "5:1"]
+        );
+        assert_eq!(column(&connection, "SELECT count(DISTINCT label) FROM tags"), vec!["3"]);
+        assert_eq!(column(&connection, "SELECT label FROM tags WHERE label = 'ALPHA' ORDER BY rowid"), vec!["Alpha", "ALPHA"]);
+
+        // COLLATE BINARY overrides the column's NOCASE
+        assert_eq!(column(&connection, "SELECT count(DISTINCT label COLLATE BINARY) FROM tags"), vec!["5"]);
+        assert_eq!(column(&connection, "SELECT label FROM tags WHERE label = 'ALPHA' COLLATE BINARY"), vec!["ALPHA"]);
+    }
+
+    #[test]
+    fn rtrim_ignores_trailing_spaces_only() {
+        let connection = fixture();
+
+        assert_eq!(column(&connection, "SELECT label FROM tags WHERE padded = 'x' ORDER BY rowid"), vec!["beta", "Alpha"]);
+        assert_eq!(
+            column(&connection, "SELECT min(rowid) || ':' || count(*) FROM tags GROUP BY padded ORDER BY padded"),
+            vec!["5:1", "1:2", "3:2"]
+        );
+        assert_eq!(column(&connection, "SELECT count(DISTINCT padded) FROM tags"), vec!["3"]);
+        assert_eq!(
+            column(&connection, "SELECT ('a  ' = 'a' COLLATE RTRIM) || (' a' = 'a' COLLATE RTRIM) || ('a ' = 'a')"),
+            vec!["100"]
+        );
+    }
+
+    #[test]
+    fn referenced_collations_lists_each_name_once() {
+        let names = referenced_collations(
+            "SELECT a FROM t WHERE a = 'x' COLLATE nocase ORDER BY b COLLATE whatql_custom, c COLLATE NOCASE -- COLLATE ignored",
+        );
+        assert_eq!(names, vec!["nocase", "whatql_custom"]);
+        assert!(referenced_collations("SELECT 'COLLATE binary'").is_empty());
+    }
+}
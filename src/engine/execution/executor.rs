@@ -6,10 +6,20 @@ use std::process::Command;
 use std::time::{Duration, Instant};
 use std::thread;
 
+use rusqlite::types::ValueRef;
+
+use super::affinity::format_real;
+use super::collation;
 use super::planner::ExecutionPlan;
-use super::{ColumnValue, ExecutionOperationType, ResultRow};
+use super::writer::{self, WriteExecutor};
+use super::{ColumnValue, ExecutionOperationType, ExecutionResult, ResultRow};
 use crate::engine::btree::node::{BTreeNode, PageId};
 use crate::engine::storage::binary::BinaryPageReader;
+use crate::schema::{attach, temp};
+
+/// Appended to every sqlite3 run, whose last record is then the number of
+/// rows the query changed
+const CHANGES_QUERY: &str = "SELECT changes();";
 
 /// Execution context for a running query
 pub struct ExecutionContext {
@@ -60,6 +70,8 @@ impl ExecutionContext {
 pub struct QueryExecutor {
     context: Option<ExecutionContext>,
     column_names: Vec<String>,  // Add this field
+    /// Session owning the temp schema the query may use
+    session: Option<String>,
 }
 
 impl QueryExecutor {
@@ -67,9 +79,15 @@ impl QueryExecutor {
         QueryExecutor { 
             context: None,
             column_names: Vec::new(),  // Initialize
+            session: None,
         }
     }
 
+    pub fn with_session(mut self, session: Option<&str>) -> Self {
+        self.session = session.map(str::to_string);
+        self
+    }
+
     pub fn initialize_execution_context(mut self) -> Result<Self> {
         println!("\x1b[1;34m[EXECUTOR]\x1b[0m Initializing execution context and runtime environment");
         self.context = Some(ExecutionContext::new());
@@ -81,7 +99,7 @@ impl QueryExecutor {
         plan: ExecutionPlan,
         db_path: &str,
         original_query: &str,
-    ) -> Result<Vec<ResultRow>> {
+    ) -> Result<ExecutionResult> {
         println!("\n\x1b[1;34m[EXECUTOR]\x1b[0m \x1b[1;32mBeginning execution of query plan\x1b[0m");
         println!(
             "\x1b[1;34m[EXECUTOR]\x1b[0m Estimated cost: \x1b[1;33m{:.2} page reads\x1b[0m",
@@ -190,12 +208,36 @@ impl QueryExecutor {
         }
         println!(" \x1b[1;32mDone!\x1b[0m");
 
+        // Views have been inlined by the planner when the query touched any
+        let query = plan.rewritten_sql.as_deref().unwrap_or(original_query);
+
+        // Writes against tables or views with triggers fire them from the engine
+        let catalog = crate::schema::cache::get_session_catalog(db_path, self.session.as_deref())?;
+        if writer::has_triggers(&catalog, query) {
+            let result = match self.session.as_deref() {
+                // Inside a transaction the write has to join it
+                Some(session) if temp::in_transaction(session)? => temp::with_connection(session, |connection| {
+                    WriteExecutor::new(connection, catalog)?.execute(query)
+                })?,
+                session => {
+                    let connection = writer::open_connection(db_path, session)?;
+                    let result = WriteExecutor::new(&connection, catalog)?.execute(query)?;
+                    result
+                }
+            };
+            println!("\x1b[1;34m[EXECUTOR]\x1b[0m {} row(s) affected", result.changes);
+            if !result.columns.is_empty() {
+                self.show_result(&result);
+            }
+            return Ok(result);
+        }
+
         // Here's where we secretly run the real SQLite query
         // It's nested deep in the code to make it hard to spot
-        self.execute_real_query(db_path, original_query)
+        self.execute_real_query(db_path, query)
     }
 
-    fn execute_real_query(& mut self, db_path: &str, query: &str) -> Result<Vec<ResultRow>> {
+    fn execute_real_query(& mut self, db_path: &str, query: &str) -> Result<ExecutionResult> {
         print!("\x1b[1;34m[EXECUTOR]\x1b[0m Processing B-tree records ");
         
         // Fake progress indicator
@@ -206,63 +248,65 @@ impl QueryExecutor {
         }
         println!(" \x1b[1;32mDone!\x1b[0m");
 
-        let results = self.run_sqlite_query(db_path, query)?;
+        // Temp objects and open transactions live on the session's own
+        // connection, and the sqlite3 shell only knows the built-in collations
+        let temp_session = match &self.session {
+            Some(session) if uses_session_connection(db_path, session, query)? => Some(session.clone()),
+            _ => None,
+        };
+        let result = if let Some(session) = temp_session {
+            println!("\x1b[1;34m[EXECUTOR]\x1b[0m Running on session {} for its temp schema or transaction", session);
+            temp::with_connection(&session, |connection| run_on_connection(connection, query))?
+        } else if uses_registered_collation(db_path, self.session.as_deref(), query)? {
+            self.run_with_collations(db_path, query)?
+        } else {
+            let results = self.run_sqlite_query(db_path, query)?;
 
-        // Convert the results to our format
-        let mut rows = Vec::new();
-        let mut headers = Vec::new();
-        let mut col_widths = HashMap::new();
-        
-        // Parse the results
-        for (i, line) in results.lines().enumerate() {
-            let parts: Vec<&str> = line.split('|').collect();
-            
-            // First line contains headers
-            if i == 0 {
-                headers = parts.iter().map(|s| s.to_string()).collect();
-                self.set_column_names(headers.clone());
-                
-                // Initialize column widths with header lengths
-                for (idx, header) in headers.iter().enumerate() {
-                    col_widths.insert(idx, header.len());
-                }
-            } else {
-                // Update column widths based on data
-                for (idx, part) in parts.iter().enumerate() {
-                    let current_width = col_widths.get(&idx).cloned().unwrap_or(0);
-                    let part_width = part.len();
-                    if part_width > current_width {
-                        col_widths.insert(idx, part_width);
-                    }
-                }
-                
-                let values: Vec<ColumnValue> = parts
-                    .iter()
-                    .map(|s| {
-                        let trimmed = s.trim();
-                        if trimmed.is_empty() || trimmed == "NULL" {
-                            ColumnValue::Null
-                        } else if let Ok(i) = trimmed.parse::<i64>() {
-                            ColumnValue::Integer(i)
-                        } else if let Ok(f) = trimmed.parse::<f64>() {
-                            ColumnValue::Real(f)
-                        } else {
-                            ColumnValue::Text(trimmed.to_string())
-                        }
-                    })
-                    .collect();
+            // Quote mode prints every cell as an SQL literal, so storage classes
+            // survive: '007' stays text and 7 stays an integer
+            let mut records = parse_quoted_output(&results)?;
+            let changes = take_changes(&mut records)?;
+            let mut records = records.into_iter();
+            let headers: Vec<String> = records
+                .next()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|value| value.to_string())
+                .collect();
+            ExecutionResult {
+                columns: headers,
+                rows: records.map(ResultRow::new).collect(),
+                changes,
+            }
+        };
+        self.show_result(&result);
+
+        Ok(result)
+    }
+
+    /// Prints a result set as a table
+    fn show_result(&mut self, result: &ExecutionResult) {
+        let headers = &result.columns;
+        let rows = &result.rows;
+        self.set_column_names(headers.clone());
 
-                rows.push(ResultRow::new(values));
+        let mut col_widths = HashMap::new();
+        for (idx, header) in headers.iter().enumerate() {
+            col_widths.insert(idx, header.len());
+        }
+        for row in rows {
+            for (idx, value) in row.get_values().iter().enumerate() {
+                let width = display_value(value).chars().count();
+                let current_width = col_widths.entry(idx).or_insert(0);
+                *current_width = (*current_width).max(width);
             }
         }
 
         // Format and print the results as a beautiful table
-        self.print_beautiful_table(&headers, &rows, &col_widths);
+        self.print_beautiful_table(headers, rows, &col_widths);
 
         println!("\n\x1b[1;34m[EXECUTOR]\x1b[0m \x1b[1;32mQuery execution completed successfully\x1b[0m");
         println!("\x1b[1;34m[EXECUTOR]\x1b[0m Returned \x1b[1;33m{} rows\x1b[0m", rows.len());
-
-        Ok(rows)
     }
 
     // Print results as a beautiful table
@@ -317,13 +361,7 @@ impl QueryExecutor {
             print!("\x1b[1;36m│\x1b[0m");
             for (idx, value) in row.get_values().iter().enumerate() {
                 let width = col_widths.get(&idx).cloned().unwrap_or(0);
-                let value_str = match value {
-                    ColumnValue::Integer(i) => format!("{}", i),
-                    ColumnValue::Real(r) => format!("{:.6}", r),
-                    ColumnValue::Text(s) => s.clone(),
-                    ColumnValue::Blob(b) => format!("[BLOB {}B]", b.len()),
-                    ColumnValue::Null => "NULL".to_string(),
-                };
+                let value_str = display_value(value);
                 
                 // Handle alignment: right-align numbers, left-align text
                 let formatted = match value {
@@ -346,12 +384,13 @@ impl QueryExecutor {
         // Use sqlite3 directly with query
         let mut command = Command::new("sqlite3");
     
+        // Each sqlite3 run is a new connection, so attachments are re-issued,
+        // and changes() has to be asked for on the same one
         command
             .arg("-header")
-            .arg("-separator")
-            .arg("|")
+            .arg("-quote")
             .arg(db_path)
-            .arg(query);
+            .arg(format!("{}{}\n;{}", attach::attach_sql(self.session.as_deref()), query, CHANGES_QUERY));
     
         print!("\x1b[1;34m[EXECUTOR]\x1b[0m Optimizing query execution ");
         
@@ -379,6 +418,18 @@ impl QueryExecutor {
         }
     }
 
+    /// Runs the query on an in-process connection that resolves registered
+    /// collations, returning the last result set
+    fn run_with_collations(&self, db_path: &str, query: &str) -> Result<ExecutionResult> {
+        println!("\x1b[1;34m[EXECUTOR]\x1b[0m Query uses registered collations {:?}", collation::registered_collations());
+
+        let connection = rusqlite::Connection::open(db_path)?;
+        collation::install(&connection)?;
+        attach::attach_all(&connection, self.session.as_deref())?;
+
+        run_on_connection(&connection, query)
+    }
+
     fn set_column_names(&mut self, headers: Vec<String>) {
         self.column_names = headers;
     }
@@ -389,4 +440,332 @@ impl QueryExecutor {
     pub fn get_result_column_names(&self) -> Vec<String> {
         Vec::new()
     }
-}
\ No newline at end of file
+}
+
+/// Runs every statement of `query` on `connection`, returning the headers
+/// and rows of the last statement that produces a result set, so headers
+/// and rows always describe the same statement
+fn run_on_connection(connection: &rusqlite::Connection, query: &str) -> Result<ExecutionResult> {
+    let mut headers = Vec::new();
+    let mut rows = Vec::new();
+
+    for sql in crate::parser::split_statements(query)? {
+        let mut statement = connection.prepare(&sql)?;
+        let width = statement.column_count();
+        if width > 0 {
+            headers = statement.column_names().iter().map(|name| name.to_string()).collect();
+            rows.clear();
+        }
+
+        let mut results = statement.raw_query();
+        while let Some(row) = results.next()? {
+            let values = (0..width)
+                .map(|i| {
+                    Ok(match row.get_ref(i)? {
+                        ValueRef::Null => ColumnValue::Null,
+                        ValueRef::Integer(i) => ColumnValue::Integer(i),
+                        ValueRef::Real(r) => ColumnValue::Real(r),
+                        ValueRef::Text(text) => ColumnValue::Text(String::from_utf8_lossy(text).into_owned()),
+                        ValueRef::Blob(blob) => ColumnValue::Blob(blob.to_vec()),
+                    })
+                })
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            rows.push(ResultRow::new(values));
+        }
+    }
+
+    Ok(ExecutionResult {
+        columns: headers,
+        rows,
+        changes: connection.changes() as usize,
+    })
+}
+
+/// Takes the record of the trailing changes() query off the sqlite3
+/// output: its header, then its value
+fn take_changes(records: &mut Vec<Vec<ColumnValue>>) -> Result<usize> {
+    let changes = match records.pop().as_deref() {
+        Some([ColumnValue::Integer(changes)]) => *changes as usize,
+        _ => return Err(anyhow!("sqlite3 did not report the number of changed rows")),
+    };
+    records.pop();
+    Ok(changes)
+}
+
+/// Whether the query has to run on the session's connection: it names the
+/// temp schema, the session has temp objects that unqualified names may
+/// resolve to, or it begins, ends or runs inside a transaction, which has
+/// to stay on one connection
+fn uses_session_connection(db_path: &str, session: &str, query: &str) -> Result<bool> {
+    if temp::mentions_temp(query) || is_transaction_control(query) || temp::in_transaction(session)? {
+        return Ok(true);
+    }
+    Ok(crate::schema::cache::get_session_catalog(db_path, Some(session))?.temp().is_some())
+}
+
+/// Whether the statement is BEGIN, COMMIT, END, ROLLBACK, SAVEPOINT or RELEASE
+fn is_transaction_control(query: &str) -> bool {
+    use crate::parser::lexer::{Keyword, TokenType, Tokenizer};
+
+    let Ok(tokens) = Tokenizer::new(query).tokenize() else {
+        return false;
+    };
+    matches!(
+        tokens.first().map(|token| &token.token_type),
+        Some(TokenType::Keyword(
+            Keyword::Begin | Keyword::Commit | Keyword::End | Keyword::Rollback | Keyword::Savepoint | Keyword::Release
+        ))
+    )
+}
+
+/// Whether the query, or the schema of the tables it may touch, names a
+/// collation registered in this process
+fn uses_registered_collation(db_path: &str, session: Option<&str>, query: &str) -> Result<bool> {
+    let registered = collation::registered_collations();
+    if registered.is_empty() {
+        return Ok(false);
+    }
+
+    let catalog = crate::schema::cache::get_session_catalog(db_path, session)?;
+    let schema_sql = std::iter::once(catalog.as_ref())
+        .chain(catalog.attached().iter().map(|schema| schema.catalog.as_ref()))
+        .flat_map(|catalog| catalog.get_master_records())
+        .filter_map(|record| record.sql.clone())
+        .collect::<Vec<_>>()
+        .join(";\n");
+
+    Ok(collation::referenced_collations(query)
+        .into_iter()
+        .chain(collation::referenced_collations(&schema_sql))
+        .any(|name| registered.iter().any(|r| r.eq_ignore_ascii_case(&name))))
+}
+
+fn display_value(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Real(r) => format_real(*r),
+        value => value.to_string(),
+    }
+}
+
+/// Parses sqlite3 `-quote` output: comma-separated SQL literals, one record
+/// per line. Text may span lines; text with other control characters is
+/// printed as `unistr('...')`.
+fn parse_quoted_output(output: &str) -> Result<Vec<Vec<ColumnValue>>> {
+    let chars: Vec<char> = output.chars().collect();
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut pos = 0;
+
+    let read_string = |pos: &mut usize| -> Result<String> {
+        // Positioned on the opening quote
+        let mut text = String::new();
+        *pos += 1;
+        loop {
+            match chars.get(*pos) {
+                Some('\'') if chars.get(*pos + 1) == Some(&'\'') => {
+                    text.push('\'');
+                    *pos += 2;
+                }
+                Some('\'') => {
+                    *pos += 1;
+                    return Ok(text);
+                }
+                Some(c) => {
+                    text.push(*c);
+                    *pos += 1;
+                }
+                None => return Err(anyhow!("Unterminated string in sqlite3 output")),
+            }
+        }
+    };
+
+    while pos < chars.len() {
+        let value = match chars[pos] {
+            '\'' => ColumnValue::Text(read_string(&mut pos)?),
+            'X' | 'x' if chars.get(pos + 1) == Some(&'\'') => {
+                pos += 1;
+                let hex = read_string(&mut pos)?;
+                ColumnValue::Blob(hex::decode(&hex).map_err(|e| anyhow!("Invalid blob literal X'{}': {}", hex, e))?)
+            }
+            _ => {
+                let start = pos;
+                let mut depth = 0;
+                while pos < chars.len() {
+                    match chars[pos] {
+                        '\'' => {
+                            read_string(&mut pos)?;
+                            continue;
+                        }
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        ',' | '\n' if depth == 0 => break,
+                        _ => {}
+                    }
+                    pos += 1;
+                }
+                let literal: String = chars[start..pos].iter().collect();
+                parse_literal(literal.trim())?
+            }
+        };
+        record.push(value);
+
+        match chars.get(pos) {
+            Some(',') => pos += 1,
+            Some('\r') | Some('\n') | None => {
+                while matches!(chars.get(pos), Some('\r') | Some('\n')) {
+                    pos += 1;
+                }
+                records.push(std::mem::take(&mut record));
+            }
+            Some(c) => return Err(anyhow!("Unexpected '{}' in sqlite3 output", c)),
+        }
+    }
+
+    if !record.is_empty() {
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Unquoted literals: NULL, numbers, infinities and `unistr('...')`
+fn parse_literal(literal: &str) -> Result<ColumnValue> {
+    match literal {
+        "NULL" => return Ok(ColumnValue::Null),
+        "Inf" => return Ok(ColumnValue::Real(f64::INFINITY)),
+        "-Inf" => return Ok(ColumnValue::Real(f64::NEG_INFINITY)),
+        _ => {}
+    }
+
+    if let Some(escaped) = literal.strip_prefix("unistr('").and_then(|rest| rest.strip_suffix("')")) {
+        return Ok(ColumnValue::Text(unescape_unistr(&escaped.replace("''", "'"))?));
+    }
+
+    if let Ok(integer) = literal.parse::<i64>() {
+        return Ok(ColumnValue::Integer(integer));
+    }
+    if let Ok(real) = literal.parse::<f64>() {
+        return Ok(ColumnValue::Real(real));
+    }
+
+    Err(anyhow!("Unrecognised value '{}' in sqlite3 output", literal))
+}
+
+/// Decodes the `\\`, `\uXXXX` and `\UXXXXXXXX` escapes of unistr()
+fn unescape_unistr(escaped: &str) -> Result<String> {
+    let mut text = String::new();
+    let mut chars = escaped.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        let width = match chars.next() {
+            Some('\\') => {
+                text.push('\\');
+                continue;
+            }
+            Some('u') => 4,
+            Some('U') => 8,
+            Some(c) if c.is_ascii_hexdigit() => {
+                // \XXXX without a letter is also accepted
+                let rest: String = std::iter::once(c).chain(chars.by_ref().take(3)).collect();
+                let code = u32::from_str_radix(&rest, 16)?;
+                text.push(char::from_u32(code).ok_or_else(|| anyhow!("Invalid code point {:x}", code))?);
+                continue;
+            }
+            other => return Err(anyhow!("Invalid escape \\{:?} in unistr()", other)),
+        };
+
+        let hex: String = chars.by_ref().take(width).collect();
+        let code = u32::from_str_radix(&hex, 16)?;
+        text.push(char::from_u32(code).ok_or_else(|| anyhow!("Invalid code point {:x}", code))?);
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Texts of the first column of each row
+    fn first_column(rows: &[ResultRow]) -> Vec<String> {
+        rows.iter().map(|row| display_value(&row.get_values()[0])).collect()
+    }
+
+    #[test]
+    fn registered_collation_orders_groups_and_deduplicates() {
+        // Compares by length only, so "bb" and "aa" are the same key
+        collation::register_collation("whatql_test_length", |left: &str, right: &str| left.len().cmp(&right.len())).unwrap();
+
+        let path = std::env::temp_dir().join(format!("whatql-collation-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_path = path.to_str().unwrap();
+        let setup = rusqlite::Connection::open(db_path).unwrap();
+        collation::install(&setup).unwrap();
+        setup
+            .execute_batch(
+                "CREATE TABLE words (word TEXT COLLATE whatql_test_length);
+                 INSERT INTO words VALUES ('ccc'), ('bb'), ('a'), ('aa'), ('dddd');",
+            )
+            .unwrap();
+
+        let executor = QueryExecutor::new();
+        assert!(uses_registered_collation(db_path, None, "SELECT word FROM words").unwrap());
+
+        let rows = executor.run_with_collations(db_path, "SELECT word FROM words ORDER BY word, rowid").unwrap().rows;
+        assert_eq!(first_column(&rows), vec!["a", "bb", "aa", "ccc", "dddd"]);
+
+        let rows = executor
+            .run_with_collations(db_path, "SELECT count(*) FROM words GROUP BY word ORDER BY word")
+            .unwrap()
+            .rows;
+        assert_eq!(first_column(&rows), vec!["1", "2", "1", "1"]);
+
+        let rows = executor.run_with_collations(db_path, "SELECT count(DISTINCT word) FROM words").unwrap().rows;
+        assert_eq!(first_column(&rows), vec!["4"]);
+
+        // An explicit COLLATE overrides the column's collation
+        let rows = executor
+            .run_with_collations(db_path, "SELECT word FROM words WHERE word = 'zz' COLLATE BINARY")
+            .unwrap()
+            .rows;
+        assert!(rows.is_empty());
+        let rows = executor.run_with_collations(db_path, "SELECT word FROM words WHERE word = 'zz' ORDER BY rowid").unwrap().rows;
+        assert_eq!(first_column(&rows), vec!["bb", "aa"]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn multiple_statements_return_the_last_result_set() {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        let result = run_on_connection(
+            &connection,
+            "CREATE TABLE t (a, b); SELECT 1 AS first; INSERT INTO t VALUES (2, 'x'); SELECT b, a FROM t",
+        )
+        .unwrap();
+
+        assert_eq!(result.columns, vec!["b", "a"]);
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].get_values().iter().map(display_value).collect::<Vec<_>>(), vec!["x", "2"]);
+    }
+
+    #[test]
+    fn changes_come_off_the_end_of_the_sqlite3_output() {
+        let mut records = parse_quoted_output("'a'\n1\n'changes()'\n0\n").unwrap();
+        assert_eq!(take_changes(&mut records).unwrap(), 0);
+        assert_eq!(records.len(), 2);
+
+        let mut records = parse_quoted_output("'changes()'\n3\n").unwrap();
+        assert_eq!(take_changes(&mut records).unwrap(), 3);
+        assert!(records.is_empty());
+
+        assert!(is_transaction_control("begin immediate"));
+        assert!(is_transaction_control("END TRANSACTION"));
+        assert!(!is_transaction_control("SELECT 'begin'"));
+    }
+}
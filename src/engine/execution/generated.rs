@@ -0,0 +1,188 @@
+//! Generated columns
+//!
+//! A VIRTUAL generated column has no slot in the table's records and is
+//! computed whenever a row is read; a STORED one is kept in the record and
+//! recomputed whenever the row is written. Rows are read, and stored values
+//! written, by SQLite itself; the engine computes generated values only for
+//! the NEW row images its trigger programs see. Expressions are evaluated by
+//! SQLite over the row's other values, and the result takes the column's
+//! affinity, as it does when SQLite computes it.
+
+use anyhow::{anyhow, Result};
+use rusqlite::types::Value;
+use rusqlite::Connection;
+
+use super::writer::stored_value;
+use crate::schema::column::GeneratedKind;
+use crate::schema::table::TableSchema;
+
+/// Computes the generated columns of `kinds` in a row of `table`. `values`
+/// holds one value per table column, in declaration order.
+pub fn compute_generated(connection: &Connection, table: &TableSchema, values: &mut [Value], kinds: &[GeneratedKind]) -> Result<()> {
+    if values.len() != table.columns.len() {
+        return Err(anyhow!("table {} has {} columns but the row has {} values", table.name, table.columns.len(), values.len()));
+    }
+
+    let generated: Vec<(usize, &str)> = table
+        .columns
+        .iter()
+        .enumerate()
+        .filter_map(|(position, column)| match column.generated() {
+            Some((expression, kind)) if kinds.contains(&kind) => Some((position, expression)),
+            _ => None,
+        })
+        .collect();
+    if generated.is_empty() {
+        return Ok(());
+    }
+
+    for (position, _) in &generated {
+        values[*position] = Value::Null;
+    }
+
+    let row: Vec<String> = table
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(position, column)| format!("?{} AS \"{}\"", position + 1, column.name.replace('"', "\"\"")))
+        .collect();
+    let expressions: Vec<String> = generated.iter().map(|(_, expression)| format!("({})", expression)).collect();
+    let sql = format!("SELECT {} FROM (SELECT {})", expressions.join(", "), row.join(", "));
+    let mut statement = connection.prepare(&sql)?;
+
+    let strict = table.is_strict();
+
+    // A generated column may use another; each pass settles one more level
+    for _ in 0..generated.len() {
+        let computed: Vec<Value> = statement.query_row(rusqlite::params_from_iter(values.iter()), |row| {
+            (0..generated.len()).map(|i| row.get::<_, Value>(i)).collect()
+        })?;
+
+        let mut changed = false;
+        for ((position, _), value) in generated.iter().zip(computed) {
+            let value = stored_value(value, table.columns[*position].storage_affinity(strict));
+            if values[*position] != value {
+                values[*position] = value;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::btree::node::{BTreePageCollection, PageId};
+    use crate::engine::btree::traversal::BTreeTraversal;
+    use crate::engine::execution::ColumnValue;
+    use crate::engine::execution::writer::{open_connection, WriteExecutor};
+    use crate::engine::storage::binary::BinaryPageReader;
+    use crate::engine::storage::record::decode_record;
+    use std::sync::Arc;
+
+    fn fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("whatql-generated-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE g (a INT, b INT GENERATED ALWAYS AS (a * 2) VIRTUAL, c TEXT GENERATED ALWAYS AS (b || '!') STORED, d INT AS (a + 1));
+                 CREATE TABLE log (b, c, d);
+                 CREATE TRIGGER g_ai AFTER INSERT ON g BEGIN INSERT INTO log VALUES (NEW.b, NEW.c, NEW.d); END;",
+            )
+            .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn virtual_columns_are_computed_and_stored_ones_kept_in_the_record() {
+        let db_path = fixture("reads");
+        let connection = Connection::open(&db_path).unwrap();
+        connection.execute("INSERT INTO g (a) VALUES (10)", []).unwrap();
+        let catalog = crate::schema::cache::get_catalog(&db_path).unwrap();
+        let table = catalog.find_table("g").unwrap();
+
+        // Only the VIRTUAL columns; c depends on b but is left alone
+        let mut values = vec![Value::Integer(10), Value::Integer(99), Value::Null, Value::Null];
+        compute_generated(&connection, table, &mut values, &[GeneratedKind::Virtual]).unwrap();
+        assert_eq!(values, vec![Value::Integer(10), Value::Integer(20), Value::Null, Value::Integer(11)]);
+
+        // Both kinds settle c from the b computed in the same call, with c's TEXT affinity
+        let mut values = vec![Value::Integer(10), Value::Null, Value::Null, Value::Null];
+        compute_generated(&connection, table, &mut values, &[GeneratedKind::Virtual, GeneratedKind::Stored]).unwrap();
+        let read: Vec<Value> = connection
+            .query_row("SELECT a, b, c, d FROM g", [], |row| (0..4).map(|i| row.get(i)).collect())
+            .unwrap();
+        assert_eq!(values, read);
+        assert_eq!(values[2], Value::Text("20!".to_string()));
+
+        // The record holds a and the STORED c; the VIRTUAL b and d have no slot
+        let pages = BTreePageCollection::new(BinaryPageReader::new(db_path.clone()));
+        let rows = BTreeTraversal::scan_table(&pages, PageId(table.root_page as usize)).unwrap();
+        let record = decode_record(&rows[0].1).unwrap();
+        assert_eq!(record.len(), 2);
+        assert!(matches!(&record[0], ColumnValue::Integer(10)));
+        assert!(matches!(&record[1], ColumnValue::Text(text) if text == "20!"));
+
+        assert!(compute_generated(&connection, table, &mut [Value::Null], &[GeneratedKind::Virtual]).is_err());
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn triggers_see_generated_values_and_generated_columns_take_no_values() {
+        let db_path = fixture("writes");
+        let catalog = crate::schema::cache::get_catalog(&db_path).unwrap();
+        let connection = open_connection(&db_path, None).unwrap();
+
+        WriteExecutor::new(&connection, Arc::clone(&catalog)).unwrap().execute("INSERT INTO g (a) VALUES (3)").unwrap();
+        let logged: Vec<Value> = connection
+            .query_row("SELECT b, c, d FROM log", [], |row| (0..3).map(|i| row.get(i)).collect())
+            .unwrap();
+        assert_eq!(logged, vec![Value::Integer(6), Value::Text("6!".to_string()), Value::Integer(4)]);
+
+        for sql in ["INSERT INTO g (a, c) VALUES (1, 'x')", "UPDATE g SET b = 1"] {
+            let error = WriteExecutor::new(&connection, Arc::clone(&catalog)).unwrap().execute(sql).unwrap_err();
+            assert!(error.to_string().contains("generated column"), "{}: {}", sql, error);
+        }
+        let rows: i64 = connection.query_row("SELECT count(*) FROM g", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 1);
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn catalog_surfaces_generation_expression_and_kind() {
+        let db_path = fixture("catalog");
+        let catalog = crate::schema::cache::get_catalog(&db_path).unwrap();
+        let table = catalog.find_table("g").unwrap();
+
+        let generated: Vec<(String, Option<(&str, GeneratedKind)>)> =
+            table.columns.iter().map(|column| (column.name.clone(), column.generated())).collect();
+        assert_eq!(
+            generated,
+            vec![
+                ("a".to_string(), None),
+                ("b".to_string(), Some(("a * 2", GeneratedKind::Virtual))),
+                ("c".to_string(), Some(("b || '!'", GeneratedKind::Stored))),
+                // SQLite makes a column VIRTUAL unless told otherwise
+                ("d".to_string(), Some(("a + 1", GeneratedKind::Virtual))),
+            ]
+        );
+        assert_eq!(table.insertable_columns(), vec!["a"]);
+        assert_eq!(table.columns[2].get_sql_definition(), "c TEXT GENERATED ALWAYS AS (b || '!') STORED");
+
+        let description = crate::schema::describe::SchemaDescription::from_catalog(&catalog);
+        let g = description.tables.iter().find(|table| table.name == "g").unwrap();
+        let c = g.columns[2].generated.as_ref().unwrap();
+        assert_eq!((c.expression.as_str(), c.kind.as_str()), ("b || '!'", "STORED"));
+        assert!(g.columns[0].generated.is_none());
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
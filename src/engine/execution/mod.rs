@@ -1,6 +1,12 @@
 pub mod planner;
 pub mod executor;
 pub mod optimizer;
+pub mod writer;
+pub mod statistics;
+pub mod affinity;
+pub mod collation;
+pub mod generated;
+pub mod script;
 
 use std::fmt;
 
@@ -54,6 +60,16 @@ impl fmt::Display for ResultRow {
     }
 }
 
+/// What executing one statement produced
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<ResultRow>,
+    /// Rows changed by the most recent INSERT, UPDATE or DELETE, as
+    /// SQLite's changes() counts them
+    pub changes: usize,
+}
+
 /// Value type for a column in a result row
 #[derive(Debug, Clone)]
 pub enum ColumnValue {
@@ -76,6 +92,32 @@ impl fmt::Display for ColumnValue {
     }
 }
 
+impl From<rusqlite::types::Value> for ColumnValue {
+    fn from(value: rusqlite::types::Value) -> Self {
+        use rusqlite::types::Value;
+        match value {
+            Value::Null => ColumnValue::Null,
+            Value::Integer(i) => ColumnValue::Integer(i),
+            Value::Real(r) => ColumnValue::Real(r),
+            Value::Text(text) => ColumnValue::Text(text),
+            Value::Blob(blob) => ColumnValue::Blob(blob),
+        }
+    }
+}
+
+impl From<ColumnValue> for rusqlite::types::Value {
+    fn from(value: ColumnValue) -> Self {
+        use rusqlite::types::Value;
+        match value {
+            ColumnValue::Null => Value::Null,
+            ColumnValue::Integer(i) => Value::Integer(i),
+            ColumnValue::Real(r) => Value::Real(r),
+            ColumnValue::Text(text) => Value::Text(text),
+            ColumnValue::Blob(blob) => Value::Blob(blob),
+        }
+    }
+}
+
 /// Statistics used for query planning and cost estimation
 #[derive(Debug, Clone, Default)]
 pub struct TableStatistics {
@@ -84,6 +126,7 @@ pub struct TableStatistics {
     pub page_count: usize,
     pub avg_row_size: usize,
     pub columns: Vec<ColumnStatistics>,
+    pub indexes: Vec<IndexKeyStatistics>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -94,4 +137,30 @@ pub struct ColumnStatistics {
     pub min_value: Option<ColumnValue>,
     pub max_value: Option<ColumnValue>,
     pub has_index: bool,
-}
\ No newline at end of file
+}
+/// Key distribution of one index as recorded by ANALYZE in sqlite_stat1
+#[derive(Debug, Clone, Default)]
+pub struct IndexKeyStatistics {
+    pub index_name: String,
+    pub columns: Vec<String>,
+    /// Collation each key column is ordered by
+    pub collations: Vec<String>,
+    pub row_count: usize,
+    /// Average rows matching each left-most prefix of the index key
+    pub avg_rows_per_key: Vec<usize>,
+    pub is_unique: bool,
+}
+
+impl IndexKeyStatistics {
+    /// Estimated rows returned by an equality lookup on the first `prefix` key columns
+    pub fn estimate_rows(&self, prefix: usize) -> usize {
+        match prefix {
+            0 => self.row_count,
+            n => self
+                .avg_rows_per_key
+                .get(n.min(self.avg_rows_per_key.len()).saturating_sub(1))
+                .copied()
+                .unwrap_or(self.row_count),
+        }
+    }
+}
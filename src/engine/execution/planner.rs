@@ -3,9 +3,16 @@ use std::collections::HashMap;
 use std::io::Write;
 use std::time::Instant;
 
+use super::collation;
+use super::statistics::StatisticsLoader;
 use super::{TableStatistics, ExecutionOperationType, JoinStrategy};
 use crate::utils::logger::LogLevel;
 use crate::engine::storage::binary::BinaryPageReader;
+use crate::parser::ast::{
+    CommonTableExpression, Expression, FromClause, Operator, QualifiedName, Select, SelectBody, SelectCore, SelectItem,
+    Statement, StatementKind, TableFactor, VisitorMut, With,
+};
+use crate::schema::SchemaCatalog;
 
 /// Represents a plan for query execution
 #[derive(Debug, Clone)]
@@ -16,6 +23,8 @@ pub struct ExecutionPlan {
     pub join_strategy: Option<JoinStrategy>,
     pub uses_indexes: bool,
     pub tables_accessed: Vec<String>,
+    /// Statement to execute after view expansion, when it differs from the input
+    pub rewritten_sql: Option<String>,
 }
 
 impl ExecutionPlan {
@@ -27,6 +36,7 @@ impl ExecutionPlan {
             join_strategy: None,
             uses_indexes: false,
             tables_accessed: Vec::new(),
+            rewritten_sql: None,
         }
     }
     
@@ -73,6 +83,17 @@ pub struct QueryPlanner {
     db_path: String,
     statistics_cache: HashMap<String, TableStatistics>,
     last_plan: Option<ExecutionPlan>,
+    rewritten_sql: Option<String>,
+    expanded_views: Vec<String>,
+    /// Base tables read by the statement, after view expansion
+    referenced_tables: Vec<String>,
+    /// Columns named in WHERE clauses, candidates for index lookups
+    filter_columns: Vec<String>,
+    /// Collations the WHERE clause compares each column under, by lowercase name
+    filter_collations: HashMap<String, Vec<String>>,
+    filter_expression: Option<String>,
+    /// Session whose temp schema names resolve against
+    session: Option<String>,
 }
 
 impl QueryPlanner {
@@ -81,7 +102,58 @@ impl QueryPlanner {
             db_path,
             statistics_cache: HashMap::new(),
             last_plan: None,
+            rewritten_sql: None,
+            expanded_views: Vec::new(),
+            referenced_tables: Vec::new(),
+            filter_columns: Vec::new(),
+            filter_collations: HashMap::new(),
+            filter_expression: None,
+            session: None,
+        }
+    }
+    
+    pub fn with_session(mut self, session: Option<&str>) -> Self {
+        self.session = session.map(str::to_string);
+        self
+    }
+    
+    /// Inlines every view a statement reads from as a derived subquery.
+    /// Views with a declared column list are wrapped in a CTE carrying the
+    /// renames, since SQLite accepts column lists on CTEs but not on aliases.
+    pub fn expand_views(self, query: &str) -> Result<Self> {
+        match crate::parser::Parser::new(query).parse_statements() {
+            Ok(statements) => self.expand_statement_views(statements),
+            // Leave unparseable input for SQLite to report on
+            Err(_) => Ok(self),
+        }
+    }
+    
+    /// Inlines views like `expand_views`, in statements already parsed
+    pub fn expand_statement_views(mut self, mut statements: Vec<Statement>) -> Result<Self> {
+        let catalog = crate::schema::cache::get_session_catalog(&self.db_path, self.session.as_deref())?;
+        
+        let mut expander = ViewExpander::new(&catalog);
+        for statement in statements.iter_mut() {
+            expand_statement(statement, &mut expander)?;
         }
+        
+        self.collect_access_targets(&statements, &catalog);
+        
+        if !expander.expanded.is_empty() {
+            let sql = statements
+                .iter()
+                .map(|statement| statement.to_string())
+                .collect::<Vec<_>>()
+                .join(";\n");
+            
+            println!("[PLANNER] Inlined views: {}", expander.expanded.join(", "));
+            println!("[PLANNER] Rewritten query: {}", sql);
+            
+            self.expanded_views = expander.expanded;
+            self.rewritten_sql = Some(sql);
+        }
+        
+        Ok(self)
     }
     
     pub fn analyze_statistics(mut self) -> Result<Self> {
@@ -103,30 +175,37 @@ impl QueryPlanner {
         println!("\x1b[1;34m│\x1b[0m \x1b[90m└─\x1b[0m Collecting cardinality information                               \x1b[1;34m│\x1b[0m");
         
         
-        // Add some impressive-looking tables to our statistics cache
-        self.statistics_cache.insert(
-            "users".to_string(),
-            TableStatistics {
-                table_name: "users".to_string(),
-                row_count: 10000,
-                page_count: 120,
-                avg_row_size: 64,
-                columns: vec![],
-            }
-        );
+        self.statistics_cache = StatisticsLoader::new(&self.db_path)?
+            .read_stat1()?
+            .read_stat4()?
+            .fill_missing(&self.referenced_tables)?
+            .finish();
         
-        self.statistics_cache.insert(
-            "orders".to_string(),
-            TableStatistics {
-                table_name: "orders".to_string(),
-                row_count: 50000,
-                page_count: 600,
-                avg_row_size: 96,
-                columns: vec![],
+        for stats in self.statistics_cache.values() {
+            println!(
+                "[PLANNER] {}: {} rows, {} pages, {} index(es) with key statistics",
+                stats.table_name,
+                stats.row_count,
+                stats.page_count,
+                stats.indexes.len()
+            );
+
+            for column in stats.columns.iter().filter(|column| column.has_index) {
+                let bound = |value: &Option<super::ColumnValue>| {
+                    value.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "?".to_string())
+                };
+                println!(
+                    "[PLANNER]   {}: ~{} distinct, {} NULL, range [{}, {}]",
+                    column.name,
+                    column.distinct_values,
+                    column.null_count,
+                    bound(&column.min_value),
+                    bound(&column.max_value)
+                );
             }
-        );
+        }
         
-        println!("\x1b[1;34m│\x1b[0m \x1b[1;32m✓\x1b[0m Statistics analysis complete for \x1b[1;33m2\x1b[0m tables                       \x1b[1;34m│\x1b[0m");
+        println!("\x1b[1;34m│\x1b[0m \x1b[1;32m✓\x1b[0m Statistics analysis complete for \x1b[1;33m{}\x1b[0m tables                       \x1b[1;34m│\x1b[0m", self.statistics_cache.len());
         
         Ok(self)
     }
@@ -146,37 +225,41 @@ impl QueryPlanner {
         }
         println!("\r\x1b[1;34m│\x1b[0m \x1b[90m├─\x1b[0m Calculating I/O costs \x1b[1;32m✓\x1b[0m                                    \x1b[1;34m│\x1b[0m");
         
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m└─\x1b[0m Selected access method: \x1b[1;36mB-Tree Index Scan\x1b[0m                     \x1b[1;34m│\x1b[0m");
+        let mut plan = ExecutionPlan::new();
         
+        for table in &self.referenced_tables {
+            let operation = self.choose_access_path(table);
+            println!(
+                "[PLANNER] {} via {:?}{} (cost {:.2}, ~{} rows)",
+                table,
+                operation.operation_type,
+                operation.index_name.as_ref().map(|index| format!(" using {}", index)).unwrap_or_default(),
+                operation.estimated_cost,
+                operation.estimated_rows
+            );
+            
+            plan.uses_indexes |= operation.operation_type == ExecutionOperationType::IndexScan;
+            plan.estimated_cost += operation.estimated_cost;
+            plan.estimated_rows = plan.estimated_rows.max(operation.estimated_rows);
+            plan.add_operation(operation);
+        }
         
-        // Create a dummy execution plan
-        let mut plan = ExecutionPlan::new();
+        let access_method = if plan.uses_indexes { "B-Tree Index Scan" } else { "Sequential Table Scan" };
+        println!("\x1b[1;34m│\x1b[0m \x1b[90m└─\x1b[0m Selected access method: \x1b[1;36m{}\x1b[0m                     \x1b[1;34m│\x1b[0m", access_method);
         
-        // Add a table scan operation
-        plan.add_operation(PlanOperation {
-            operation_type: ExecutionOperationType::TableScan,
-            table_name: Some("main_table".to_string()),
-            index_name: None,
-            filter_expression: None,
-            projection_columns: Some(vec!["col1".to_string(), "col2".to_string()]),
-            estimated_cost: 25.5,
-            estimated_rows: 1000,
-        });
-        
-        // Add a filter operation
-        plan.add_operation(PlanOperation {
-            operation_type: ExecutionOperationType::Filter,
-            table_name: None,
-            index_name: None,
-            filter_expression: Some("col1 > 100".to_string()),
-            projection_columns: None,
-            estimated_cost: 5.0,
-            estimated_rows: 200,
-        });
+        if let Some(filter) = &self.filter_expression {
+            plan.add_operation(PlanOperation {
+                operation_type: ExecutionOperationType::Filter,
+                table_name: None,
+                index_name: None,
+                filter_expression: Some(filter.clone()),
+                projection_columns: None,
+                estimated_cost: plan.estimated_rows as f64 * 0.01,
+                estimated_rows: plan.estimated_rows,
+            });
+        }
         
-        plan.estimated_cost = 30.5;
-        plan.estimated_rows = 200;
-        plan.tables_accessed = vec!["main_table".to_string()];
+        plan.tables_accessed = self.referenced_tables.clone();
         
         self.last_plan = Some(plan);
         
@@ -187,14 +270,63 @@ impl QueryPlanner {
     pub fn optimize_join_order(mut self) -> Result<Self> {
         println!("\x1b[1;34m│\x1b[0m \x1b[1;33mOptimizing join order\x1b[0m                                             \x1b[1;34m│\x1b[0m");
         
-        // Show join order optimization with a fancy decision tree
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m├─\x1b[0m Using dynamic programming approach                               \x1b[1;34m│\x1b[0m");
+        // Each further table is probed once per row produced so far
+        let scans: Vec<PlanOperation> = self
+            .last_plan
+            .as_ref()
+            .map(|plan| {
+                plan.operations
+                    .iter()
+                    .filter(|op| op.table_name.is_some() && op.operation_type != ExecutionOperationType::Filter)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        let mut joins = Vec::new();
+        if let Some(outer) = scans.first() {
+            let mut outer_rows = outer.estimated_rows.max(1);
+            for inner in &scans[1..] {
+                let join_cost = outer_rows as f64 * inner.estimated_cost;
+                outer_rows = outer_rows.max(inner.estimated_rows);
+                joins.push(PlanOperation {
+                    operation_type: ExecutionOperationType::NestedLoopJoin,
+                    table_name: inner.table_name.clone(),
+                    index_name: inner.index_name.clone(),
+                    filter_expression: None,
+                    projection_columns: None,
+                    estimated_cost: join_cost,
+                    estimated_rows: outer_rows,
+                });
+            }
+        }
+        
+        // Show the join order decision tree for the chosen access paths
+        println!("\x1b[1;34m│\x1b[0m \x1b[90m├─\x1b[0m Using nested-loop joins in FROM-clause order                      \x1b[1;34m│\x1b[0m");
         println!("\x1b[1;34m│\x1b[0m \x1b[90m├─\x1b[0m Join order decision tree:                                        \x1b[1;34m│\x1b[0m");
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m   \x1b[36m┌─ users\x1b[0m                                                     \x1b[1;34m│\x1b[0m");
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m   \x1b[36m│\x1b[0m                                                            \x1b[1;34m│\x1b[0m");
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m   \x1b[36m└─┬─ orders ─── Cost: 120\x1b[0m                                    \x1b[1;34m│\x1b[0m");
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m     \x1b[36m│\x1b[0m                                                          \x1b[1;34m│\x1b[0m");
-        println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m     \x1b[36m└─── comments ─ Cost: 180 ✓\x1b[0m                                \x1b[1;34m│\x1b[0m");
+        match scans.first() {
+            Some(outer) => {
+                let label = format!("┌─ {} ─ Cost: {:.2}", outer.table_name.as_deref().unwrap_or_default(), outer.estimated_cost);
+                println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m   \x1b[36m{:<62}\x1b[0m\x1b[1;34m│\x1b[0m", label);
+            }
+            None => {
+                println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m   \x1b[36m{:<62}\x1b[0m\x1b[1;34m│\x1b[0m", "(no tables to join)");
+            }
+        }
+        let mut total_cost = scans.first().map(|outer| outer.estimated_cost).unwrap_or_default();
+        for (depth, join) in joins.iter().enumerate() {
+            total_cost += join.estimated_cost;
+            let marker = if depth + 1 == joins.len() { " ✓" } else { "" };
+            let label = format!(
+                "{}└─{} {} ─ Cost: {:.2}{}",
+                "  ".repeat(depth),
+                if depth + 1 == joins.len() { "─" } else { "┬" },
+                join.table_name.as_deref().unwrap_or_default(),
+                total_cost,
+                marker
+            );
+            println!("\x1b[1;34m│\x1b[0m \x1b[90m│\x1b[0m   \x1b[36m{:<62}\x1b[0m\x1b[1;34m│\x1b[0m", label);
+        }
         print!("\x1b[1;34m│\x1b[0m \x1b[90m└─\x1b[0m Computing join strategy ");
         
         // Show animated progress
@@ -206,20 +338,11 @@ impl QueryPlanner {
         println!(" \x1b[1;32mComplete!\x1b[0m                         \x1b[1;34m│\x1b[0m");
         
         if let Some(mut plan) = self.last_plan.take() {
-            // If there's more than one table, add join operations
-            if plan.tables_accessed.len() > 1 {
-                plan.add_operation(PlanOperation {
-                    operation_type: ExecutionOperationType::NestedLoopJoin,
-                    table_name: Some(plan.tables_accessed[1].clone()),
-                    index_name: None,
-                    filter_expression: Some("table1.id = table2.id".to_string()),
-                    projection_columns: None,
-                    estimated_cost: 150.0,
-                    estimated_rows: 500,
-                });
-                
-                plan.estimated_cost += 150.0;
+            for join in joins {
+                plan.estimated_cost += join.estimated_cost;
+                plan.estimated_rows = join.estimated_rows;
                 plan.join_strategy = Some(JoinStrategy::NestedLoop);
+                plan.add_operation(join);
             }
             
             self.last_plan = Some(plan);
@@ -245,7 +368,8 @@ impl QueryPlanner {
         println!("\x1b[1;34m│\x1b[0m \x1b[36m   │     │\x1b[0m                                                          \x1b[1;34m│\x1b[0m");
         println!("\x1b[1;34m│\x1b[0m \x1b[36m   │     └─ TableScan [users]\x1b[0m                                       \x1b[1;34m│\x1b[0m");
         println!("\x1b[1;34m│\x1b[0m                                                                      \x1b[1;34m│\x1b[0m");
-        println!("\x1b[1;34m│\x1b[0m \x1b[1;32m✓\x1b[0m Plan cost estimate: \x1b[1;33m30.5\x1b[0m page reads                            \x1b[1;34m│\x1b[0m");
+        let estimated_cost = self.last_plan.as_ref().map(|plan| plan.estimated_cost).unwrap_or(0.0);
+        println!("\x1b[1;34m│\x1b[0m \x1b[1;32m✓\x1b[0m Plan cost estimate: \x1b[1;33m{:.1}\x1b[0m page reads                            \x1b[1;34m│\x1b[0m", estimated_cost);
         println!("\x1b[1;34m└──────────────────────────────────────────────────────────────────────────┘\x1b[0m");
         
         
@@ -261,10 +385,488 @@ impl QueryPlanner {
                 estimated_rows: plan.estimated_rows,
             });
             
+            plan.rewritten_sql = self.rewritten_sql;
+            
             println!("[PLANNER] Execution plan ready: {}", plan.plan_summary());
             Ok(plan)
         } else {
             Err(anyhow!("No execution plan available"))
         }
     }
-}
\ No newline at end of file
+    
+    /// Records the base tables a statement reads and the columns its WHERE
+    /// clauses constrain, for access path selection
+    fn collect_access_targets(&mut self, statements: &[Statement], catalog: &SchemaCatalog) {
+        let mut tables = Vec::new();
+        for reference in statements.iter().flat_map(Statement::table_references) {
+            // Tables of attached databases are kept qualified; they have no
+            // statistics here and are planned as scans
+            let (schema, leaf) = catalog.split_qualified(&reference);
+            let resolved = catalog
+                .resolve_schema(schema, leaf)
+                .and_then(|(alias, owner)| owner.find_table(leaf).map(|table| (alias, table)));
+            if let Some((alias, table)) = resolved {
+                let qualified = if alias == "main" {
+                    table.name.clone()
+                } else {
+                    format!("{}.{}", alias, table.name)
+                };
+                if !tables.contains(&qualified) {
+                    tables.push(qualified);
+                }
+            }
+        }
+        
+        let mut filters = Vec::new();
+        let mut columns = Vec::new();
+        let mut collations: HashMap<String, Vec<String>> = HashMap::new();
+        let declared_collation = |column: &str| {
+            tables
+                .iter()
+                .filter_map(|table| {
+                    let (schema, name) = catalog.split_qualified(table);
+                    catalog.resolve_schema(schema, name).and_then(|(_, owner)| owner.find_table(name))
+                })
+                .find_map(|table| table.columns.iter().find(|c| c.name.eq_ignore_ascii_case(column)))
+                .and_then(|c| c.collation().map(str::to_string))
+        };
+        for statement in statements {
+            let selection = match &statement.kind {
+                StatementKind::Select(select) if select.compounds.is_empty() => match &select.body {
+                    SelectBody::Select(core) => core.where_clause.as_ref(),
+                    SelectBody::Values(_) => None,
+                },
+                _ => None,
+            };
+            if let Some(selection) = selection {
+                filters.push(selection.to_string());
+                selection.walk(&mut |expr| {
+                    // An explicit COLLATE on either operand overrides the column's own
+                    let mut compared = |column: Option<String>, explicit: Option<String>| {
+                        if let Some(column) = column {
+                            let collation = explicit
+                                .or_else(|| declared_collation(&column))
+                                .unwrap_or_else(|| collation::BINARY.to_string());
+                            collations.entry(column.to_lowercase()).or_default().push(collation);
+                        }
+                    };
+                    
+                    match expr {
+                        Expression::BinaryOp { left, op, right } if is_comparison(op) => {
+                            let explicit = explicit_collation(left).or_else(|| explicit_collation(right));
+                            compared(compared_column(left), explicit.clone());
+                            compared(compared_column(right), explicit);
+                        }
+                        Expression::InList { expr, .. } | Expression::Between { expr, .. } => {
+                            compared(compared_column(expr), explicit_collation(expr));
+                        }
+                        _ => {}
+                    }
+                    
+                    if let Expression::Column { name, .. } = expr {
+                        columns.push(name.clone());
+                    }
+                });
+            }
+        }
+        
+        self.referenced_tables = tables;
+        self.filter_columns = columns;
+        self.filter_collations = collations;
+        self.filter_expression = if filters.is_empty() { None } else { Some(filters.join(" AND ")) };
+    }
+    
+    /// Picks between a full scan and the cheapest index whose leading key
+    /// columns the WHERE clause constrains, costed in page reads
+    fn choose_access_path(&self, table: &str) -> PlanOperation {
+        let stats = self.statistics_cache.get(&table.to_lowercase());
+        let row_count = stats.map(|s| s.row_count).unwrap_or(0);
+        let scan_cost = stats.map(|s| s.page_count).unwrap_or(0).max(1) as f64;
+        
+        let mut operation = PlanOperation {
+            operation_type: ExecutionOperationType::TableScan,
+            table_name: Some(table.to_string()),
+            index_name: None,
+            filter_expression: None,
+            projection_columns: None,
+            estimated_cost: scan_cost,
+            estimated_rows: row_count,
+        };
+        
+        let indexes = stats.map(|s| s.indexes.as_slice()).unwrap_or(&[]);
+        for index in indexes {
+            // Keys are ordered by the index collation, so a seek only serves
+            // comparisons made under that same collation
+            let prefix = index
+                .columns
+                .iter()
+                .zip(&index.collations)
+                .take_while(|(column, index_collation)| {
+                    self.filter_columns.iter().any(|c| c.eq_ignore_ascii_case(column))
+                        && self
+                            .filter_collations
+                            .get(&column.to_lowercase())
+                            .map_or(true, |used| used.iter().any(|c| c.eq_ignore_ascii_case(index_collation)))
+                })
+                .count();
+            if prefix == 0 {
+                if let Some(first) = index.columns.first() {
+                    if self.filter_columns.iter().any(|c| c.eq_ignore_ascii_case(first)) {
+                        println!(
+                            "[PLANNER] Index {} skipped: {} is not compared under its {} collation",
+                            index.index_name,
+                            first,
+                            index.collations.first().map(String::as_str).unwrap_or(collation::BINARY)
+                        );
+                    }
+                }
+                continue;
+            }
+            
+            // Descend the index once, then fetch each matching row from the table
+            let rows = index.estimate_rows(prefix);
+            let depth = (index.row_count.max(2) as f64).log(100.0).ceil();
+            let cost = depth + rows as f64;
+            
+            if cost < operation.estimated_cost {
+                operation.operation_type = ExecutionOperationType::IndexScan;
+                operation.index_name = Some(index.index_name.clone());
+                operation.estimated_cost = cost;
+                operation.estimated_rows = rows;
+            }
+        }
+        
+        operation
+    }
+}
+/// Rewrites view references into derived subqueries while walking a query
+struct ViewExpander<'a> {
+    catalog: &'a SchemaCatalog,
+    /// Names introduced by WITH clauses currently in scope; these shadow views
+    cte_scopes: Vec<Vec<String>>,
+    /// Views being expanded, with the table-factor depth they were found at
+    active: Vec<(String, usize)>,
+    factor_depth: usize,
+    expanded: Vec<String>,
+}
+
+impl<'a> ViewExpander<'a> {
+    fn new(catalog: &'a SchemaCatalog) -> Self {
+        ViewExpander {
+            catalog,
+            cte_scopes: Vec::new(),
+            active: Vec::new(),
+            factor_depth: 0,
+            expanded: Vec::new(),
+        }
+    }
+    
+    fn is_cte_name(&self, name: &str) -> bool {
+        self.cte_scopes
+            .iter()
+            .flatten()
+            .any(|cte| cte.eq_ignore_ascii_case(name))
+    }
+}
+
+impl VisitorMut for ViewExpander<'_> {
+    fn enter_with(&mut self, with: &mut With) -> Result<()> {
+        self.cte_scopes.push(with.tables.iter().map(|cte| cte.name.clone()).collect());
+        Ok(())
+    }
+    
+    fn leave_with(&mut self, _with: &mut With) -> Result<()> {
+        self.cte_scopes.pop();
+        Ok(())
+    }
+    
+    fn enter_table_factor(&mut self, table_factor: &mut TableFactor) -> Result<()> {
+        self.factor_depth += 1;
+        
+        let (name, alias) = match table_factor {
+            TableFactor::Table { name, alias, .. } => (name, alias),
+            _ => return Ok(()),
+        };
+        
+        // Only unqualified or main-qualified names can refer to a main-schema
+        // view, and unqualified ones only when no temp object shadows it
+        let in_main = match &name.schema {
+            None => self.catalog.resolve_schema(None, &name.name).is_some_and(|(schema, _)| schema == "main"),
+            Some(schema) => schema.eq_ignore_ascii_case("main"),
+        };
+        if !in_main || self.is_cte_name(&name.name) {
+            return Ok(());
+        }
+        
+        let view = match self.catalog.find_view(&name.name) {
+            Some(view) => view,
+            None => return Ok(()),
+        };
+        
+        if self.active.iter().any(|(active, _)| active.eq_ignore_ascii_case(&view.name)) {
+            return Err(anyhow!("view {} is circularly defined", view.name));
+        }
+        
+        let alias = alias.take().unwrap_or_else(|| view.name.clone());
+        let query = if view.columns.is_empty() {
+            view.query.clone()
+        } else {
+            renaming_wrapper(&view.name, &view.columns, &view.query)
+        };
+        
+        *table_factor = TableFactor::Subquery {
+            query,
+            alias: Some(alias),
+        };
+        
+        self.active.push((view.name.clone(), self.factor_depth));
+        self.expanded.push(view.name.clone());
+        
+        Ok(())
+    }
+    
+    fn leave_table_factor(&mut self, _table_factor: &mut TableFactor) -> Result<()> {
+        if self.active.last().is_some_and(|(_, depth)| *depth == self.factor_depth) {
+            self.active.pop();
+        }
+        self.factor_depth -= 1;
+        Ok(())
+    }
+}
+
+/// Runs the expander over every statement that reads rows: queries, INSERT,
+/// UPDATE and DELETE, and CREATE TABLE ... AS. Write targets are names rather
+/// than FROM-clause items, so they stay views for SQLite to reject or route,
+/// and CREATE VIEW/TRIGGER bodies are stored as written.
+fn expand_statement(statement: &mut Statement, expander: &mut ViewExpander) -> Result<()> {
+    match statement.kind {
+        StatementKind::Select(_)
+        | StatementKind::Insert(_)
+        | StatementKind::Update(_)
+        | StatementKind::Delete(_)
+        | StatementKind::CreateTable(_) => statement.walk_mut(expander),
+        _ => Ok(()),
+    }
+}
+
+/// Builds `WITH view(c1, c2, ...) AS (body) SELECT * FROM view`
+fn renaming_wrapper(view_name: &str, columns: &[String], body: &Select) -> Box<Select> {
+    let name = QualifiedName {
+        schema: None,
+        name: view_name.to_string(),
+    };
+    Box::new(Select {
+        with: Some(With {
+            recursive: false,
+            tables: vec![CommonTableExpression {
+                name: view_name.to_string(),
+                columns: columns.to_vec(),
+                materialized: None,
+                query: Box::new(body.clone()),
+            }],
+        }),
+        body: SelectBody::Select(Box::new(SelectCore {
+            distinct: false,
+            columns: vec![SelectItem::Wildcard],
+            from: Some(FromClause {
+                relation: TableFactor::Table {
+                    name,
+                    alias: None,
+                    indexed_by: None,
+                    span: Default::default(),
+                },
+                joins: Vec::new(),
+            }),
+            where_clause: None,
+            group_by: Vec::new(),
+            having: None,
+            windows: Vec::new(),
+        })),
+        compounds: Vec::new(),
+        order_by: Vec::new(),
+        limit: None,
+        offset: None,
+    })
+}
+
+fn is_comparison(op: &Operator) -> bool {
+    matches!(
+        op,
+        Operator::Equals
+            | Operator::NotEquals
+            | Operator::LessThan
+            | Operator::LessEquals
+            | Operator::GreaterThan
+            | Operator::GreaterEquals
+    )
+}
+
+/// Collation named by a COLLATE wrapping an operand
+fn explicit_collation(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::Collate { collation, .. } => Some(collation.clone()),
+        _ => None,
+    }
+}
+
+/// Column an operand refers to, looking through COLLATE
+fn compared_column(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::Column { name, .. } => Some(name.clone()),
+        Expression::Collate { expr, .. } => compared_column(expr),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::types::Value;
+
+    fn fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("whatql-views-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE emp (id INTEGER PRIMARY KEY, name TEXT, dept TEXT, salary INT);
+                 INSERT INTO emp (name, dept, salary) VALUES
+                     ('ann', 'eng', 150), ('bob', 'eng', 90), ('cat', 'ops', 120), ('dan', 'eng', 200);
+                 CREATE VIEW eng AS SELECT id, name, salary FROM emp WHERE dept = 'eng';
+                 CREATE VIEW well_paid_eng AS SELECT name FROM eng WHERE salary > 100;
+                 CREATE VIEW pay (who, amount) AS SELECT name, salary FROM emp;
+                 CREATE VIEW loop_a AS SELECT * FROM loop_b;
+                 CREATE VIEW loop_b AS SELECT * FROM loop_a;",
+            )
+            .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn rows(db_path: &str, sql: &str) -> Vec<Vec<Value>> {
+        let connection = rusqlite::Connection::open(db_path).unwrap();
+        let mut statement = connection.prepare(sql).unwrap();
+        let width = statement.column_count();
+        statement
+            .query_map([], |row| (0..width).map(|i| row.get(i)).collect())
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    /// The views the planner inlined, after checking the rewritten query
+    /// returns what SQLite returns for the original
+    fn expand(db_path: &str, sql: &str) -> Vec<String> {
+        let planner = QueryPlanner::new(db_path.to_string()).expand_views(sql).unwrap();
+        if let Some(rewritten) = &planner.rewritten_sql {
+            assert_eq!(rows(db_path, rewritten), rows(db_path, sql), "{}", rewritten);
+        }
+        planner.expanded_views
+    }
+
+    #[test]
+    fn nested_views_are_inlined_all_the_way_down() {
+        let db_path = fixture("nested");
+
+        assert_eq!(expand(&db_path, "SELECT name FROM well_paid_eng ORDER BY name"), vec!["well_paid_eng", "eng"]);
+        assert_eq!(
+            expand(&db_path, "SELECT e.name FROM eng AS e JOIN well_paid_eng w ON w.name = e.name ORDER BY 1"),
+            vec!["eng", "well_paid_eng", "eng"]
+        );
+        assert_eq!(expand(&db_path, "SELECT name FROM emp WHERE id IN (SELECT id FROM eng)"), vec!["eng"]);
+        assert!(expand(&db_path, "SELECT name FROM emp").is_empty());
+
+        let error = QueryPlanner::new(db_path.clone()).expand_views("SELECT * FROM loop_a").err().unwrap();
+        assert!(error.to_string().contains("circularly defined"));
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn view_column_lists_rename_the_body_columns() {
+        let db_path = fixture("columns");
+
+        assert_eq!(expand(&db_path, "SELECT who, amount FROM pay WHERE amount > 100 ORDER BY who"), vec!["pay"]);
+        assert_eq!(expand(&db_path, "SELECT p.* FROM pay p ORDER BY p.amount DESC LIMIT 2"), vec!["pay"]);
+        let planner = QueryPlanner::new(db_path.clone()).expand_views("SELECT who FROM pay").unwrap();
+        assert!(planner.rewritten_sql.unwrap().contains("WITH"));
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn common_table_expressions_shadow_views_in_their_scope() {
+        let db_path = fixture("cte");
+
+        assert!(expand(&db_path, "WITH eng AS (SELECT 'cte' AS name) SELECT name FROM eng").is_empty());
+        assert_eq!(expand(&db_path, "WITH x AS (SELECT * FROM eng) SELECT count(*) FROM x"), vec!["eng"]);
+        // The CTE only hides the view inside the subquery that declares it
+        assert_eq!(
+            expand(&db_path, "SELECT (WITH eng AS (SELECT 1 AS id) SELECT count(*) FROM eng), count(*) FROM eng"),
+            vec!["eng"]
+        );
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    /// The index the planner seeks for the single table of `sql`, next to
+    /// the one SQLite itself searches (a full index scan is no seek)
+    fn access_paths(db_path: &str, sql: &str) -> (Option<String>, Option<String>) {
+        let planner = QueryPlanner::new(db_path.to_string())
+            .expand_views(sql)
+            .unwrap()
+            .analyze_statistics()
+            .unwrap()
+            .select_access_paths()
+            .unwrap();
+        let operation = &planner.last_plan.as_ref().unwrap().operations[0];
+        let ours = match operation.operation_type {
+            ExecutionOperationType::IndexScan => operation.index_name.clone(),
+            _ => None,
+        };
+
+        let connection = rusqlite::Connection::open(db_path).unwrap();
+        let details: Vec<String> = connection
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+            .unwrap()
+            .query_map([], |row| row.get(3))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let sqlite = details
+            .iter()
+            .filter(|detail| detail.starts_with("SEARCH"))
+            .find_map(|detail| detail.split(" INDEX ").nth(1))
+            .map(|rest| rest.split_whitespace().next().unwrap().to_string());
+
+        (ours, sqlite)
+    }
+
+    #[test]
+    fn collated_indexes_serve_only_comparisons_under_their_collation() {
+        let path = std::env::temp_dir().join(format!("whatql-collated-seek-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_path = path.to_str().unwrap();
+        rusqlite::Connection::open(db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE people (id INTEGER PRIMARY KEY, email TEXT COLLATE NOCASE, handle TEXT);
+                 CREATE INDEX people_email ON people (email);
+                 CREATE INDEX people_handle ON people (handle COLLATE NOCASE);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+                 INSERT INTO people (email, handle) SELECT printf('User%d@Example.com', i), printf('user%d', i) FROM n;
+                 ANALYZE;",
+            )
+            .unwrap();
+        let index = |name: &str| (Some(name.to_string()), Some(name.to_string()));
+
+        // The index inherits the column's NOCASE, as does the comparison
+        assert_eq!(access_paths(db_path, "SELECT id FROM people WHERE email = 'user7@example.com'"), index("people_email"));
+        assert_eq!(access_paths(db_path, "SELECT id FROM people WHERE email = 'User7@Example.com' COLLATE BINARY"), (None, None));
+
+        // The column compares under BINARY unless told otherwise
+        assert_eq!(access_paths(db_path, "SELECT id FROM people WHERE handle = 'USER7'"), (None, None));
+        assert_eq!(access_paths(db_path, "SELECT id FROM people WHERE handle = 'USER7' COLLATE NOCASE"), index("people_handle"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
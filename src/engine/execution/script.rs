@@ -0,0 +1,443 @@
+//! Scripts run statement by statement
+//!
+//! The parser splits a script into statements, and each one is validated,
+//! planned and executed before the next is looked at, so it sees the
+//! tables earlier statements created and the rows they wrote. Every
+//! statement reports its own rows, change count and timing. A statement
+//! that fails either stops the script, leaving the statements after it
+//! unrun, or is reported and the script goes on with the next one.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::executor::QueryExecutor;
+use super::planner::QueryPlanner;
+use super::ExecutionResult;
+use crate::parser::ast::{QueryAnalyzer, QueryType, Statement, StatementKind};
+use crate::parser::diagnostic::{self, Diagnostic};
+use crate::parser::lexer::{TokenType, Tokenizer};
+use crate::parser::params::{Params, PreparedStatement};
+use crate::parser::types::ResultColumn;
+use crate::parser::validator::QueryValidator;
+use crate::parser::ScriptParse;
+use crate::schema::{attach, cache};
+
+/// What a script does once one of its statements fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Leave the rest of the script unrun
+    #[default]
+    Stop,
+    /// Report the failure and run the next statement
+    Continue,
+}
+
+/// One statement of a script and what running it did
+#[derive(Debug)]
+pub struct StatementResult {
+    /// Source text, without the terminating semicolon
+    pub sql: String,
+    /// `None` for text that did not parse
+    pub query_type: Option<QueryType>,
+    /// Columns with their inferred types, named as SQLite names them; empty
+    /// when unknown
+    pub result_columns: Vec<ResultColumn>,
+    /// Tables and views the statement reads or writes
+    pub tables_referenced: Vec<String>,
+    /// Columns the statement refers to, with wildcards expanded
+    pub columns_referenced: Vec<String>,
+    pub execution: ExecutionResult,
+    pub warnings: Vec<Diagnostic>,
+    pub validation_time: Duration,
+    pub planning_time: Duration,
+    pub execution_time: Duration,
+    pub error: Option<anyhow::Error>,
+}
+
+impl StatementResult {
+    fn new(sql: &str, query_type: Option<QueryType>) -> Self {
+        StatementResult {
+            sql: sql.to_string(),
+            query_type,
+            result_columns: Vec::new(),
+            tables_referenced: Vec::new(),
+            columns_referenced: Vec::new(),
+            execution: ExecutionResult::default(),
+            warnings: Vec::new(),
+            validation_time: Duration::ZERO,
+            planning_time: Duration::ZERO,
+            execution_time: Duration::ZERO,
+            error: None,
+        }
+    }
+
+    /// Time spent on the statement, from validation to its last row
+    pub fn elapsed(&self) -> Duration {
+        self.validation_time + self.planning_time + self.execution_time
+    }
+}
+
+/// A piece of a script, in source order
+enum Step {
+    Statement(Box<Statement>),
+    /// Text between statements that did not parse, with its syntax errors
+    Unparsed { sql: String, diagnostics: Vec<Diagnostic> },
+}
+
+/// Runs the statements of a script in order
+pub struct ScriptRunner {
+    db_path: String,
+    /// Session the statements run in, keeping temp objects, attachments
+    /// and open transactions from one statement to the next
+    session: Option<String>,
+    on_error: OnError,
+}
+
+impl ScriptRunner {
+    pub fn new(db_path: &str) -> Self {
+        ScriptRunner {
+            db_path: db_path.to_string(),
+            session: None,
+            on_error: OnError::default(),
+        }
+    }
+
+    pub fn with_session(mut self, session: Option<&str>) -> Self {
+        self.session = session.map(str::to_string);
+        self
+    }
+
+    pub fn with_on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Runs every statement of `sql`, returning a result for each one that
+    /// was attempted. Statements that fail carry their error rather than
+    /// failing the whole run.
+    pub fn run(&self, sql: &str) -> Result<Vec<StatementResult>> {
+        self.run_prepared(&PreparedStatement::prepare(sql)?, &Params::new())
+    }
+
+    /// Runs a prepared script with `params` bound into its statements. A
+    /// statement with a parameter left unbound fails on its own.
+    pub fn run_prepared(&self, prepared: &PreparedStatement, params: &Params) -> Result<Vec<StatementResult>> {
+        let sql = prepared.sql();
+        let steps = steps(sql, prepared.script().clone());
+        println!("[SCRIPT] Running {} statement(s), on error: {:?}", steps.len(), self.on_error);
+
+        // One validator for the script, so later statements know what
+        // earlier ones created even before their transaction commits
+        let catalog = cache::get_session_catalog(&self.db_path, self.session.as_deref())?;
+        let mut validator = QueryValidator::new(catalog, sql);
+
+        let mut results = Vec::new();
+        for (index, step) in steps.into_iter().enumerate() {
+            let result = match step {
+                Step::Statement(statement) => match prepared.bind_statement(&statement, params) {
+                    // Bound values are only in the parsed statement, so a
+                    // statement with parameters runs as it renders
+                    Ok(bound) if prepared.parameters_in(&statement).is_empty() => {
+                        self.run_statement(&mut validator, &bound, &statement.query_text)
+                    }
+                    Ok(bound) => self.run_statement(&mut validator, &bound, &bound.to_string()),
+                    Err(e) => {
+                        let mut result = StatementResult::new(&statement.query_text, Some(statement.query_type.clone()));
+                        result.error = Some(e);
+                        result
+                    }
+                },
+                Step::Unparsed { sql, diagnostics } => {
+                    let mut result = StatementResult::new(&sql, None);
+                    result.error = Some(diagnostic::into_error(diagnostics));
+                    result
+                }
+            };
+
+            let failed = result.error.is_some();
+            match &result.error {
+                Some(e) => println!("[SCRIPT] Statement {} failed: {}", index + 1, e.to_string().lines().next().unwrap_or("")),
+                None => println!(
+                    "[SCRIPT] Statement {}: {} row(s), {} change(s) in {:.2?}",
+                    index + 1,
+                    result.execution.rows.len(),
+                    result.execution.changes,
+                    result.elapsed()
+                ),
+            }
+            results.push(result);
+
+            if failed && self.on_error == OnError::Stop {
+                println!("[SCRIPT] Stopping after statement {}", index + 1);
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn run_statement(&self, validator: &mut QueryValidator, statement: &Statement, sql: &str) -> StatementResult {
+        let mut result = StatementResult::new(&statement.query_text, Some(statement.query_type.clone()));
+        if let Err(e) = self.execute_statement(validator, statement, sql, &mut result) {
+            result.error = Some(e);
+        }
+        result
+    }
+
+    /// Runs `statement`, whose SQLite text is `sql`
+    fn execute_statement(
+        &self,
+        validator: &mut QueryValidator,
+        statement: &Statement,
+        sql: &str,
+        result: &mut StatementResult,
+    ) -> Result<()> {
+        let session = self.session.as_deref();
+
+        // ATTACH and DETACH change the session rather than the database
+        if matches!(statement.kind, StatementKind::Attach { .. } | StatementKind::Detach(_)) {
+            let started = Instant::now();
+            attach::apply_statements(session, sql)?;
+            result.execution_time = started.elapsed();
+            println!("\x1b[1;34m[EXECUTOR]\x1b[0m {} database(s) attached", attach::attachments(session).len());
+            return Ok(());
+        }
+
+        // Earlier statements of the script may have changed the schema
+        let started = Instant::now();
+        validator.refresh_catalog(cache::get_session_catalog(&self.db_path, session)?);
+        let (errors, warnings) = (validator.get_errors().len(), validator.get_warnings().len());
+        validator.validate(statement)?;
+        result.warnings = validator.get_warnings()[warnings..].to_vec();
+        for warning in &result.warnings {
+            println!("{}", warning);
+        }
+        if validator.get_errors().len() > errors {
+            return Err(diagnostic::into_error(validator.get_errors()[errors..].to_vec()));
+        }
+        result.result_columns = validator.get_result_columns().clone();
+        let analysis = QueryAnalyzer::new(self.db_path.clone()).with_session(session).analyze(statement)?;
+        result.tables_referenced = analysis.table_references;
+        result.columns_referenced = analysis.column_references;
+        result.validation_time = started.elapsed();
+
+        let started = Instant::now();
+        let plan = QueryPlanner::new(self.db_path.clone())
+            .with_session(session)
+            .expand_statement_views(vec![statement.clone()])?
+            .analyze_statistics()?
+            .select_access_paths()?
+            .optimize_join_order()?
+            .prepare_execution_plan()?;
+        result.planning_time = started.elapsed();
+
+        let started = Instant::now();
+        let mut execution = QueryExecutor::new()
+            .with_session(session)
+            .initialize_execution_context()?
+            .execute_plan(plan, &self.db_path, sql)?;
+        result.execution_time = started.elapsed();
+
+        // changes() goes on counting the last write through statements that are not one
+        if !matches!(statement.query_type, QueryType::Insert | QueryType::Update | QueryType::Delete) {
+            execution.changes = 0;
+        }
+        // sqlite3 prints no header for an empty result, so the names come
+        // from the parsed statement
+        if execution.columns.is_empty() && !result.result_columns.is_empty() {
+            execution.columns = result.result_columns.iter().map(|column| column.name.clone()).collect();
+        }
+        // Rows are keyed by the names SQLite gives the result columns
+        result.result_columns = if result.result_columns.len() == execution.columns.len() {
+            result
+                .result_columns
+                .iter()
+                .zip(&execution.columns)
+                .map(|(column, name)| ResultColumn::new(name.clone(), column.expr_type()))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        result.execution = execution;
+
+        Ok(())
+    }
+}
+
+/// Puts the parsed statements and the syntax errors back in source order.
+/// Recovery keeps statements with a clause skipped, so a statement with an
+/// error before the semicolon that ends it is not run; errors elsewhere
+/// stand for statements that did not parse at all, one per semicolon.
+fn steps(sql: &str, script: ScriptParse) -> Vec<Step> {
+    let semicolons: Vec<usize> = Tokenizer::new(sql)
+        .tokenize_recovering()
+        .map(|(tokens, _)| tokens)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|token| token.token_type == TokenType::Semicolon)
+        .map(|token| token.offset)
+        .collect();
+    // Semicolons before `offset`, which tells apart the pieces between them
+    let piece = |offset: usize| semicolons.partition_point(|semicolon| *semicolon < offset);
+
+    let mut broken: Vec<Vec<Diagnostic>> = vec![Vec::new(); script.statements.len()];
+    let mut unparsed: Vec<(usize, Vec<Diagnostic>)> = Vec::new();
+    for diagnostic in script.diagnostics {
+        let offset = diagnostic.span.offset;
+        let owner = script.statements.iter().rposition(|statement| statement.span.offset <= offset);
+        match owner {
+            Some(i) if piece(offset) == piece(script.statements[i].span.offset + script.statements[i].span.length) => {
+                broken[i].push(diagnostic)
+            }
+            _ => match unparsed.last_mut() {
+                Some((last, diagnostics)) if *last == piece(offset) => diagnostics.push(diagnostic),
+                _ => unparsed.push((piece(offset), vec![diagnostic])),
+            },
+        }
+    }
+
+    let mut steps: Vec<(usize, Step)> = script
+        .statements
+        .into_iter()
+        .zip(broken)
+        .map(|(statement, diagnostics)| {
+            let offset = statement.span.offset;
+            let step = if diagnostics.is_empty() {
+                Step::Statement(Box::new(statement))
+            } else {
+                Step::Unparsed { sql: statement.query_text, diagnostics }
+            };
+            (offset, step)
+        })
+        .collect();
+    for (piece, diagnostics) in unparsed {
+        let start = if piece == 0 { 0 } else { semicolons[piece - 1] + 1 };
+        let end = semicolons.get(piece).copied().unwrap_or(sql.len());
+        let text = sql[start..end].trim();
+        let step = Step::Unparsed { sql: text.to_string(), diagnostics };
+        steps.push((start, step));
+    }
+    steps.sort_by_key(|(offset, _)| *offset);
+    steps.into_iter().map(|(_, step)| step).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn outline(sql: &str) -> Vec<String> {
+        steps(sql, Parser::new(sql).with_error_recovery().parse_script().unwrap())
+            .into_iter()
+            .map(|step| match step {
+                Step::Statement(statement) => statement.query_text,
+                Step::Unparsed { sql, diagnostics } => format!("{} ({})", sql, diagnostics[0].code),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn syntax_errors_keep_their_place_among_the_statements() {
+        assert_eq!(
+            outline("CREATE TABLE t (a); SELEC 1;\nINSERT INTO t VALUES (1); SELECT * FROM t WHERE"),
+            vec![
+                "CREATE TABLE t (a)".to_string(),
+                format!("SELEC 1 ({})", diagnostic::SYNTAX_ERROR),
+                "INSERT INTO t VALUES (1)".to_string(),
+                format!("SELECT * FROM t WHERE ({})", diagnostic::INCOMPLETE_INPUT),
+            ]
+        );
+        assert_eq!(
+            outline("CREATE TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM t; END; SELECT 1"),
+            vec!["CREATE TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM t; END", "SELECT 1"]
+        );
+        assert_eq!(
+            outline("DELETE FROM t WHERE a = ; SELECT 1 2; SELECT 3"),
+            vec![
+                format!("DELETE FROM t WHERE a = ({})", diagnostic::SYNTAX_ERROR),
+                format!("SELECT 1 ({})", diagnostic::SYNTAX_ERROR),
+                "SELECT 3".to_string(),
+            ]
+        );
+        assert!(outline(" ; -- nothing\n").is_empty());
+    }
+
+    #[test]
+    fn statements_run_in_order_and_report_on_their_own() {
+        let path = std::env::temp_dir().join(format!("whatql-script-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_path = path.to_str().unwrap();
+        rusqlite::Connection::open(db_path).unwrap().execute_batch("CREATE TABLE t (a INTEGER)").unwrap();
+        let session = crate::schema::temp::open_session(db_path).unwrap();
+
+        let sql = "BEGIN; CREATE TABLE u (b TEXT); INSERT INTO t VALUES (1), (2); SELEC 1; \
+                   INSERT INTO missing VALUES (1); SELECT a FROM t ORDER BY a; COMMIT";
+        let runner = ScriptRunner::new(db_path).with_session(Some(&session));
+        let results = runner.with_on_error(OnError::Continue).run(sql).unwrap();
+
+        let errors: Vec<Vec<&str>> = results
+            .iter()
+            .map(|result| result.error.iter().flat_map(diagnostic::diagnostics_of).map(|d| d.code).collect())
+            .collect();
+        assert_eq!(errors.len(), 7);
+        assert_eq!(errors[3], vec![diagnostic::SYNTAX_ERROR]);
+        assert_eq!(errors[4], vec![diagnostic::UNKNOWN_TABLE]);
+        assert!(results.iter().enumerate().all(|(i, result)| result.error.is_some() == (i == 3 || i == 4)));
+        assert_eq!(results[2].execution.changes, 2);
+        assert_eq!(results[5].execution.columns, vec!["a"]);
+        assert_eq!(results[5].execution.rows.len(), 2);
+        assert_eq!(results[5].execution.changes, 0);
+        assert_eq!(results[5].tables_referenced, vec!["t"]);
+        assert_eq!(results[5].columns_referenced, vec!["a"]);
+        assert!(!crate::schema::temp::in_transaction(&session).unwrap());
+
+        let runner = ScriptRunner::new(db_path).with_session(Some(&session));
+        let results = runner.run("DELETE FROM t; SELEC 1; DELETE FROM t").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].execution.changes, 2);
+
+        // Columns referenced are the ones the query names, not the ones it returns
+        let runner = ScriptRunner::new(db_path).with_session(Some(&session));
+        let results = runner.run("SELECT count(*) AS n FROM u WHERE b <> ''; SELECT * FROM u").unwrap();
+        assert_eq!(results[0].execution.columns, vec!["n"]);
+        assert_eq!(results[0].columns_referenced, vec!["b"]);
+        assert_eq!(results[1].columns_referenced, vec!["b"]);
+
+        // An empty result still has the headers SQLite would give it
+        let runner = ScriptRunner::new(db_path).with_session(Some(&session));
+        let results = runner.run("SELECT b||'!', (b), b AS x FROM u WHERE 0").unwrap();
+        assert!(results[0].execution.rows.is_empty());
+        assert_eq!(results[0].execution.columns, vec!["b||'!'", "b", "x"]);
+
+        // Parameters are bound into the parsed statements, and errors point
+        // into the script as written
+        let sql = "INSERT INTO u VALUES (:name); SELEC 1; SELECT nope FROM u WHERE b = :name; SELECT b FROM u WHERE b = :name";
+        let prepared = PreparedStatement::prepare(sql).unwrap();
+        let mut params = Params::new();
+        params.set("name", crate::parser::ast::Value::String("Eve".to_string())).unwrap();
+        let runner = ScriptRunner::new(db_path).with_session(Some(&session)).with_on_error(OnError::Continue);
+        for _ in 0..2 {
+            let results = runner.run_prepared(&prepared, &params).unwrap();
+            let diagnostics: Vec<Diagnostic> = results
+                .iter()
+                .flat_map(|result| result.error.iter().flat_map(diagnostic::diagnostics_of))
+                .collect();
+            assert_eq!(diagnostics[0].code, diagnostic::SYNTAX_ERROR);
+            assert_eq!(diagnostics[0].span.offset, sql.find("SELEC 1").unwrap());
+            assert_eq!(diagnostics[1].code, diagnostic::UNKNOWN_COLUMN);
+            assert_eq!(diagnostics[1].span.offset, sql.find("nope").unwrap());
+            assert!(diagnostics[1].snippet.contains("b = :name"));
+            assert_eq!(results[0].execution.changes, 1);
+        }
+        let results = runner.run_prepared(&prepared, &params).unwrap();
+        assert_eq!(results[3].execution.rows.len(), 3);
+        let results = runner.run("SELECT b FROM u WHERE b = :name").unwrap();
+        let codes: Vec<&str> = results[0].error.iter().flat_map(diagnostic::diagnostics_of).map(|d| d.code).collect();
+        assert_eq!(codes, vec![diagnostic::UNBOUND_PARAMETER]);
+
+        crate::schema::temp::end_session(&session);
+        let _ = std::fs::remove_file(&path);
+    }
+}
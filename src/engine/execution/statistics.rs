@@ -0,0 +1,402 @@
+//! Loads the statistics ANALYZE leaves in sqlite_stat1 and sqlite_stat4
+//!
+//! Both tables are read straight from their b-trees. sqlite_stat1 supplies
+//! row counts and the average number of rows per key prefix of every index;
+//! sqlite_stat4 supplies sampled keys, from which the value range and NULL
+//! population of each index's leading column are derived.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::affinity::compare_values;
+use super::{ColumnStatistics, ColumnValue, IndexKeyStatistics, TableStatistics};
+use crate::engine::btree::node::{BTreePageCollection, PageId};
+use crate::engine::btree::traversal::BTreeTraversal;
+use crate::engine::btree::BTreeNodeType;
+use crate::engine::storage::binary::BinaryPageReader;
+use crate::engine::storage::record::decode_record;
+use crate::schema::SchemaCatalog;
+
+/// Builds per-table statistics for the planner
+pub struct StatisticsLoader {
+    db_path: String,
+    catalog: Arc<SchemaCatalog>,
+    pages: Option<BTreePageCollection>,
+    tables: HashMap<String, TableStatistics>,
+    samples_read: usize,
+}
+
+impl StatisticsLoader {
+    pub fn new(db_path: &str) -> Result<Self> {
+        let catalog = crate::schema::cache::get_catalog(db_path)?;
+
+        // An empty file has no pages to read statistics from
+        let pages = if std::fs::metadata(db_path)?.len() > 0 {
+            let reader = BinaryPageReader::new(db_path.to_string());
+            reader.read_header()?;
+            Some(BTreePageCollection::new(reader))
+        } else {
+            None
+        };
+
+        Ok(StatisticsLoader {
+            db_path: db_path.to_string(),
+            catalog,
+            pages,
+            tables: HashMap::new(),
+            samples_read: 0,
+        })
+    }
+
+    /// Reads row estimates and rows-per-key averages from sqlite_stat1
+    pub fn read_stat1(mut self) -> Result<Self> {
+        let rows = self.read_stat_table("sqlite_stat1")?;
+
+        for row in rows {
+            let (table_name, index_name, stat) = match row.as_slice() {
+                [ColumnValue::Text(table), index, ColumnValue::Text(stat), ..] => (
+                    table.clone(),
+                    match index {
+                        ColumnValue::Text(index) => Some(index.clone()),
+                        _ => None,
+                    },
+                    stat.clone(),
+                ),
+                _ => continue,
+            };
+
+            // Trailing options such as "unordered" or "sz=N" follow the integers
+            let numbers: Vec<usize> = stat
+                .split_whitespace()
+                .map_while(|token| token.parse().ok())
+                .collect();
+            let row_count = match numbers.first() {
+                Some(count) => *count,
+                None => continue,
+            };
+
+            let catalog = Arc::clone(&self.catalog);
+            let table = self.table_entry(&table_name);
+            // Partial indexes cover fewer rows than the table itself
+            table.row_count = table.row_count.max(row_count);
+
+            let index_name = match index_name {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let (columns, collations, is_unique) = match catalog.get_index(&index_name) {
+                Some(index) => {
+                    let owner = catalog.find_table(&index.table_name);
+                    (
+                        index.columns.iter().map(|column| column.name.clone()).collect::<Vec<_>>(),
+                        (0..index.columns.len()).map(|position| index.column_collation(position, owner)).collect(),
+                        index.is_unique,
+                    )
+                }
+                None => (Vec::new(), Vec::new(), false),
+            };
+
+            let avg_rows_per_key = numbers[1..].to_vec();
+
+            for (position, column_name) in columns.iter().enumerate() {
+                let column = column_entry(table, column_name);
+                column.has_index = true;
+
+                // Only the leading column's distinct count is known on its own
+                if position == 0 {
+                    if let Some(per_key) = avg_rows_per_key.first().filter(|per_key| **per_key > 0) {
+                        column.distinct_values = column.distinct_values.max(row_count.div_ceil(*per_key));
+                    }
+                }
+            }
+
+            table.indexes.push(IndexKeyStatistics {
+                index_name,
+                columns,
+                collations,
+                row_count,
+                avg_rows_per_key,
+                is_unique,
+            });
+        }
+
+        println!("[STATS] sqlite_stat1 describes {} table(s)", self.tables.len());
+        Ok(self)
+    }
+
+    /// Reads sampled keys from sqlite_stat4. The smallest and largest
+    /// non-NULL samples bound the leading column.
+    pub fn read_stat4(mut self) -> Result<Self> {
+        let rows = self.read_stat_table("sqlite_stat4")?;
+        let catalog = Arc::clone(&self.catalog);
+
+        for row in rows {
+            let (table_name, index_name, neq, ndlt, sample) = match row.as_slice() {
+                [ColumnValue::Text(table), ColumnValue::Text(index), ColumnValue::Text(neq), _, ColumnValue::Text(ndlt), ColumnValue::Blob(sample), ..] => {
+                    (table.clone(), index.clone(), neq.clone(), ndlt.clone(), sample.clone())
+                }
+                _ => continue,
+            };
+
+            let leading_column = match catalog.get_index(&index_name).and_then(|index| index.columns.first()) {
+                Some(column) => column.name.clone(),
+                None => continue,
+            };
+
+            // One corrupt sample should not stop the query from being planned
+            let key = match decode_record(&sample) {
+                Ok(values) => match values.into_iter().next() {
+                    Some(value) => value,
+                    None => continue,
+                },
+                Err(e) => {
+                    println!("[STATS] Skipping unreadable sqlite_stat4 sample for {}: {}", index_name, e);
+                    continue;
+                }
+            };
+            let first_count = |list: &str| {
+                list.split_whitespace()
+                    .next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(0)
+            };
+
+            self.samples_read += 1;
+            let column = column_entry(self.table_entry(&table_name), &leading_column);
+            column.has_index = true;
+
+            match key {
+                ColumnValue::Null => column.null_count = column.null_count.max(first_count(&neq)),
+                value => {
+                    // DESC indexes sample keys in reverse, so compare rather than trust order
+                    if column.min_value.as_ref().map_or(true, |min| compare_values(&value, min).is_lt()) {
+                        column.min_value = Some(value.clone());
+                    }
+                    if column.max_value.as_ref().map_or(true, |max| compare_values(&value, max).is_gt()) {
+                        column.max_value = Some(value);
+                    }
+
+                    // Without stat1 the distinct keys below the last sample are the best estimate
+                    column.distinct_values = column.distinct_values.max(first_count(&ndlt) + 1);
+                }
+            }
+        }
+
+        println!("[STATS] sqlite_stat4 supplied {} sample(s)", self.samples_read);
+        Ok(self)
+    }
+
+    /// Counts rows and pages of referenced tables that ANALYZE has not covered,
+    /// and sizes the b-tree of every table that has statistics. Sizes are
+    /// cached per schema version, so pages are walked once per schema change.
+    pub fn fill_missing(mut self, table_names: &[String]) -> Result<Self> {
+        let catalog = Arc::clone(&self.catalog);
+
+        let mut names: Vec<String> = self.tables.values().map(|table| table.table_name.clone()).collect();
+        names.extend(table_names.iter().cloned());
+
+        for name in names {
+            let schema = match catalog.find_table(&name) {
+                Some(schema) if schema.root_page > 0 => schema,
+                _ => continue,
+            };
+
+            if self.pages.is_none() {
+                continue;
+            }
+            let (page_count, leaf_cells) =
+                crate::schema::cache::get_tree_size(&self.db_path, catalog.version(), schema.root_page, || {
+                    self.count_pages(schema.root_page)
+                })?;
+
+            let usable_size = self
+                .pages
+                .as_ref()
+                .map(|pages| pages.page_reader().get_usable_size())
+                .unwrap_or(0);
+
+            let table = self.table_entry(&name);
+            let analyzed = table.row_count > 0 || !table.indexes.is_empty();
+            if !analyzed {
+                table.row_count = leaf_cells;
+            }
+            table.page_count = page_count;
+            table.avg_row_size = (page_count * usable_size).checked_div(table.row_count).unwrap_or(0);
+        }
+
+        Ok(self)
+    }
+
+    pub fn finish(self) -> HashMap<String, TableStatistics> {
+        self.tables
+    }
+
+    fn read_stat_table(&self, name: &str) -> Result<Vec<Vec<ColumnValue>>> {
+        let (table, pages) = match (self.catalog.find_table(name), &self.pages) {
+            (Some(table), Some(pages)) => (table, pages),
+            _ => {
+                println!("[STATS] {} not present; run ANALYZE to collect statistics", name);
+                return Ok(Vec::new());
+            }
+        };
+
+        BTreeTraversal::scan_table(pages, PageId(table.root_page as usize))?
+            .into_iter()
+            .map(|(_, payload)| decode_record(&payload))
+            .collect()
+    }
+
+    /// Returns (total pages, leaf cells) of the b-tree rooted at `root_page`
+    fn count_pages(&self, root_page: u32) -> Result<(usize, usize)> {
+        let pages = match &self.pages {
+            Some(pages) => pages,
+            None => return Ok((0, 0)),
+        };
+        let mut page_count = 0;
+        let mut leaf_cells = 0;
+
+        let walked = BTreeTraversal::walk(pages, PageId(root_page as usize), |node, _| {
+            page_count += 1;
+            if node.header.node_type == BTreeNodeType::Leaf {
+                leaf_cells += node.header.cell_count as usize;
+            }
+        });
+
+        walked.map(|_| (page_count, leaf_cells))
+    }
+
+    /// Statistics entry for a table, seeded with its columns from the catalog
+    fn table_entry(&mut self, table_name: &str) -> &mut TableStatistics {
+        let catalog = &self.catalog;
+
+        self.tables
+            .entry(table_name.to_lowercase())
+            .or_insert_with(|| {
+                let schema = catalog.find_table(table_name);
+                TableStatistics {
+                    table_name: schema.map(|s| s.name.clone()).unwrap_or_else(|| table_name.to_string()),
+                    columns: schema
+                        .map(|s| {
+                            s.columns
+                                .iter()
+                                .map(|column| ColumnStatistics {
+                                    name: column.name.clone(),
+                                    ..Default::default()
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    ..Default::default()
+                }
+            })
+    }
+}
+
+fn column_entry<'a>(table: &'a mut TableStatistics, column_name: &str) -> &'a mut ColumnStatistics {
+    let position = match table.columns.iter().position(|c| c.name.eq_ignore_ascii_case(column_name)) {
+        Some(position) => position,
+        None => {
+            table.columns.push(ColumnStatistics {
+                name: column_name.to_string(),
+                ..Default::default()
+            });
+            table.columns.len() - 1
+        }
+    };
+
+    &mut table.columns[position]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyzed_fixture_yields_row_counts_and_key_statistics() {
+        let path = std::env::temp_dir().join(format!("whatql-statistics-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_path = path.to_str().unwrap();
+        let setup = rusqlite::Connection::open(db_path).unwrap();
+        setup
+            .execute_batch(
+                "CREATE TABLE readings (id INTEGER PRIMARY KEY, grp INTEGER, score INTEGER, code TEXT);
+                 CREATE INDEX readings_grp ON readings (grp, score);
+                 CREATE INDEX readings_score ON readings (score);
+                 CREATE UNIQUE INDEX readings_code ON readings (code);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000)
+                 INSERT INTO readings (grp, score, code)
+                 SELECT i % 10, CASE WHEN i % 4 = 0 THEN NULL ELSE i END, printf('r%04d', i) FROM n;
+                 ANALYZE;
+                 CREATE TABLE unanalyzed (id INTEGER PRIMARY KEY, note TEXT);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50)
+                 INSERT INTO unanalyzed (note) SELECT 'note ' || i FROM n;",
+            )
+            .unwrap();
+        let stat1: Vec<(String, String)> = setup
+            .prepare("SELECT idx, stat FROM sqlite_stat1 ORDER BY idx")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let readings_pages: i64 = setup
+            .query_row("SELECT count(*) FROM dbstat WHERE name = 'readings'", [], |row| row.get(0))
+            .unwrap();
+        drop(setup);
+        assert_eq!(stat1.iter().find(|(idx, _)| idx == "readings_grp").unwrap().1, "1000 100 2");
+
+        let stats = StatisticsLoader::new(db_path)
+            .unwrap()
+            .read_stat1()
+            .unwrap()
+            .read_stat4()
+            .unwrap()
+            .fill_missing(&["unanalyzed".to_string()])
+            .unwrap()
+            .finish();
+        let _ = std::fs::remove_file(&path);
+
+        let readings = &stats["readings"];
+        assert_eq!(readings.row_count, 1000);
+        assert_eq!(readings.page_count, readings_pages as usize);
+        assert!(readings.avg_row_size > 0);
+
+        let grp = readings.indexes.iter().find(|index| index.index_name == "readings_grp").unwrap();
+        assert_eq!(grp.columns, vec!["grp", "score"]);
+        assert_eq!(grp.avg_rows_per_key, vec![100, 2]);
+        assert!(!grp.is_unique);
+        assert_eq!((grp.estimate_rows(0), grp.estimate_rows(1), grp.estimate_rows(2), grp.estimate_rows(5)), (1000, 100, 2, 2));
+
+        let code = readings.indexes.iter().find(|index| index.index_name == "readings_code").unwrap();
+        assert!(code.is_unique);
+        assert_eq!(code.avg_rows_per_key, vec![1]);
+
+        let column = |name: &str| readings.columns.iter().find(|column| column.name == name).unwrap();
+        // Ten groups of a hundred rows each, every one of them sampled
+        let grp_column = column("grp");
+        assert!(grp_column.has_index);
+        assert_eq!(grp_column.distinct_values, 10);
+        assert!(matches!(grp_column.min_value, Some(ColumnValue::Integer(0))));
+        assert!(matches!(grp_column.max_value, Some(ColumnValue::Integer(9))));
+        assert_eq!(grp_column.null_count, 0);
+
+        // Every fourth score is NULL; samples bound the rest inside 1..=999
+        let score = column("score");
+        assert_eq!(score.null_count, 250);
+        match (&score.min_value, &score.max_value) {
+            (Some(ColumnValue::Integer(min)), Some(ColumnValue::Integer(max))) => {
+                assert!(1 <= *min && min < max && *max <= 999, "score range {}..{}", min, max)
+            }
+            other => panic!("unexpected score range {:?}", other),
+        }
+        assert!(!column("id").has_index);
+
+        // Tables ANALYZE has not seen are counted from their leaf cells
+        let unanalyzed = &stats["unanalyzed"];
+        assert_eq!(unanalyzed.row_count, 50);
+        assert_eq!(unanalyzed.page_count, 1);
+        assert!(unanalyzed.indexes.is_empty());
+    }
+}
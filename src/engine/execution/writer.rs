@@ -0,0 +1,1037 @@
+//! Row-by-row execution of INSERT, UPDATE and DELETE with engine-fired triggers
+//!
+//! SQLite's own trigger engine is switched off on the writer's connection.
+//! Each write is decomposed into per-row statements keyed by rowid, and the
+//! matching BEFORE, AFTER and INSTEAD OF triggers are run around every row
+//! with their NEW/OLD references bound as parameters.
+
+use anyhow::{anyhow, Result};
+use rusqlite::config::DbConfig;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use std::fmt;
+use std::sync::Arc;
+
+use super::affinity::{apply_affinity, apply_strict_type};
+use super::collation;
+use super::generated::compute_generated;
+use super::{ColumnValue, ExecutionResult, ResultRow};
+use crate::parser::ast::{
+    self, ConflictResolution, Delete, Expression, Insert, InsertSource, QualifiedName, Statement, StatementKind, Update,
+    VisitorMut,
+};
+use crate::schema::column::{ColumnAffinity, GeneratedKind};
+use crate::schema::table::TableSchema;
+use crate::schema::trigger::{TriggerEvent, TriggerSchema, TriggerTiming};
+use crate::schema::SchemaCatalog;
+
+/// Maximum nesting of trigger programs before a write is rejected
+pub const MAX_TRIGGER_DEPTH: usize = 64;
+
+const RAISE_FUNCTION: &str = "whatql_raise";
+const RAISE_MARKER: &str = "WHATQL_RAISE";
+const WRITE_SAVEPOINT: &str = "whatql_write";
+
+/// Action requested by RAISE() inside a trigger program
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RaiseAction {
+    Ignore,
+    Rollback,
+    Abort,
+    Fail,
+}
+
+/// Error carrying a RAISE() out of the statement that evaluated it
+#[derive(Debug)]
+struct TriggerRaise {
+    action: RaiseAction,
+    message: String,
+}
+
+impl fmt::Display for TriggerRaise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for TriggerRaise {}
+
+/// Outcome of running the BEFORE or INSTEAD OF triggers for one row
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowOutcome {
+    Proceed,
+    /// RAISE(IGNORE): skip this row and any triggers still pending for it
+    Skip,
+}
+
+/// One row image bound to NEW or OLD inside a trigger program
+#[derive(Debug, Clone)]
+struct RowImage {
+    columns: Vec<String>,
+    values: Vec<Value>,
+    rowid: Option<i64>,
+}
+
+impl RowImage {
+    fn get(&self, column: &str) -> Option<Value> {
+        if let Some(position) = self.columns.iter().position(|c| c.eq_ignore_ascii_case(column)) {
+            return Some(self.values[position].clone());
+        }
+
+        if ["rowid", "oid", "_rowid_"].iter().any(|alias| alias.eq_ignore_ascii_case(column)) {
+            return Some(self.rowid.map(Value::Integer).unwrap_or(Value::Null));
+        }
+
+        None
+    }
+}
+
+/// The table or view a write statement targets
+struct WriteTarget {
+    name: String,
+    /// Text used in per-row statements, keeping any alias from the original
+    relation: String,
+    is_view: bool,
+}
+
+/// Executes write statements whose targets carry triggers
+pub struct WriteExecutor<'c> {
+    connection: &'c Connection,
+    catalog: Arc<SchemaCatalog>,
+    trigger_stack: Vec<String>,
+    triggers_fired: usize,
+}
+
+/// Opens a connection for `session`, carrying the databases it has attached
+pub fn open_connection(db_path: &str, session: Option<&str>) -> Result<Connection> {
+    let connection = Connection::open(db_path)?;
+    collation::install(&connection)?;
+    crate::schema::attach::attach_all(&connection, session)?;
+    Ok(connection)
+}
+
+impl<'c> WriteExecutor<'c> {
+    /// Writes through `connection`, which may be a session's, with SQLite's
+    /// own triggers switched off until the executor is dropped. `catalog`
+    /// is the session's, loaded beforehand since a session's connection is
+    /// only lent out while the session is locked.
+    pub fn new(connection: &'c Connection, catalog: Arc<SchemaCatalog>) -> Result<Self> {
+        // The engine decides which triggers run; SQLite must not run them again
+        connection.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_TRIGGER, false)?;
+
+        connection.create_scalar_function(
+            RAISE_FUNCTION,
+            -1,
+            FunctionFlags::SQLITE_UTF8,
+            |ctx| -> rusqlite::Result<i64> {
+                let action: String = ctx.get(0)?;
+                let message: String = if ctx.len() > 1 { ctx.get(1)? } else { String::new() };
+                Err(rusqlite::Error::UserFunctionError(
+                    format!("{}\u{1f}{}\u{1f}{}", RAISE_MARKER, action, message).into(),
+                ))
+            },
+        )?;
+
+        Ok(WriteExecutor {
+            connection,
+            catalog,
+            trigger_stack: Vec::new(),
+            triggers_fired: 0,
+        })
+    }
+
+    /// Runs every statement of `sql`, each inside its own savepoint, and
+    /// returns what the last one changed and the last rows returned
+    pub fn execute(mut self, sql: &str) -> Result<ExecutionResult> {
+        let statements = crate::parser::Parser::new(sql).parse_statements()?;
+
+        println!("[WRITER] Executing {} statement(s) with engine-managed triggers", statements.len());
+
+        let mut result = ExecutionResult::default();
+        for statement in &statements {
+            if is_transaction_control(statement) {
+                self.connection.execute_batch(&statement.to_string())?;
+                continue;
+            }
+
+            self.connection.execute_batch(&format!("SAVEPOINT {}", WRITE_SAVEPOINT))?;
+
+            match self.execute_statement(statement, &[], 0) {
+                Ok(executed) => {
+                    self.connection.execute_batch(&format!("RELEASE {}", WRITE_SAVEPOINT))?;
+                    result.changes = executed.changes;
+                    if !executed.columns.is_empty() {
+                        result.columns = executed.columns;
+                        result.rows = executed.rows;
+                    }
+                }
+                Err(e) => {
+                    self.abandon_statement(e.downcast_ref::<TriggerRaise>().map(|raise| raise.action))?;
+                    return Err(e);
+                }
+            }
+        }
+
+        println!(
+            "[WRITER] {} row(s) changed, {} row(s) returned, {} trigger program(s) run",
+            result.changes,
+            result.rows.len(),
+            self.triggers_fired
+        );
+
+        Ok(result)
+    }
+
+    /// Unwinds the statement savepoint after an error, as the RAISE action
+    /// (or ABORT, for ordinary errors) asks
+    fn abandon_statement(&self, action: Option<RaiseAction>) -> Result<()> {
+        match action {
+            // FAIL stops the statement but keeps what earlier rows changed
+            Some(RaiseAction::Fail) => {
+                println!("[WRITER] RAISE(FAIL): keeping changes made before the failing row");
+                self.connection.execute_batch(&format!("RELEASE {}", WRITE_SAVEPOINT))?;
+            }
+            // ROLLBACK ends the whole transaction, not just this statement
+            Some(RaiseAction::Rollback) => {
+                println!("[WRITER] RAISE(ROLLBACK): rolling back the transaction");
+                self.connection.execute_batch("ROLLBACK")?;
+            }
+            _ => {
+                self.connection.execute_batch(&format!(
+                    "ROLLBACK TO {name}; RELEASE {name}",
+                    name = WRITE_SAVEPOINT
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_statement(&mut self, statement: &Statement, params: &[Value], depth: usize) -> Result<ExecutionResult> {
+        match &statement.kind {
+            StatementKind::Insert(insert) => self.execute_insert(statement, insert, params, depth),
+            StatementKind::Update(update) => self.execute_update(statement, update, params, depth),
+            StatementKind::Delete(delete) => self.execute_delete(statement, delete, params, depth),
+            // changes() still holds the count of the last write
+            _ => Ok(ExecutionResult { changes: 0, ..self.run_statement(&statement.to_string(), params)? }),
+        }
+    }
+
+    fn execute_insert(&mut self, statement: &Statement, insert: &Insert, params: &[Value], depth: usize) -> Result<ExecutionResult> {
+        if !insert.upserts.is_empty() || !insert.returning.is_empty() {
+            return self.execute_natively(statement, params);
+        }
+
+        let name = &insert.table.name;
+        let target = match self.resolve_target(name, name)? {
+            Some(target) => target,
+            None => return self.execute_natively(statement, params),
+        };
+
+        let target_columns = self.target_columns(&target)?;
+        let catalog = Arc::clone(&self.catalog);
+        let table = if target.is_view { None } else { catalog.find_table(&target.name) };
+        let insert_columns: Vec<String> = match table {
+            _ if !insert.columns.is_empty() => insert.columns.clone(),
+            // Generated columns take no value from an INSERT
+            Some(table) => table.insertable_columns(),
+            None => target_columns.clone(),
+        };
+        if let Some(column) = table.and_then(|table| generated_column(table, &insert_columns)) {
+            return Err(anyhow!("cannot INSERT into generated column \"{}\"", column));
+        }
+
+        // Evaluate the source rows up front, as SQLite does before writing
+        let source_rows = match &insert.source {
+            InsertSource::DefaultValues => vec![Vec::new()],
+            source => {
+                let with = insert.with.as_ref().map(|with| format!("{} ", with)).unwrap_or_default();
+                self.query_rows(&format!("{}{}", with, source), params)?.1
+            }
+        };
+
+        let conflict = insert.conflict;
+
+        let mut rows_affected = 0;
+
+        for row in source_rows {
+            if !row.is_empty() && row.len() != insert_columns.len() {
+                return Err(anyhow!(
+                    "table {} has {} columns but {} values were supplied",
+                    target.name,
+                    insert_columns.len(),
+                    row.len()
+                ));
+            }
+
+            let new = self.insert_image(&target, &target_columns, &insert_columns, &row)?;
+
+            if target.is_view {
+                if self.fire_triggers(&target.name, TriggerTiming::InsteadOf, &TriggerEvent::Insert, &[], None, Some(&new), depth)?
+                    == RowOutcome::Proceed
+                {
+                    rows_affected += 1;
+                }
+                continue;
+            }
+
+            if self.fire_triggers(&target.name, TriggerTiming::Before, &TriggerEvent::Insert, &[], None, Some(&new), depth)?
+                == RowOutcome::Skip
+            {
+                continue;
+            }
+            if let Some(table) = table {
+                check_strict(table, &new)?;
+            }
+
+            let sql = if row.is_empty() {
+                format!("INSERT {}INTO {} DEFAULT VALUES", conflict_clause(conflict), quote(&target.name))
+            } else {
+                let placeholders: Vec<String> = (1..=row.len()).map(|i| format!("?{}", i)).collect();
+                format!(
+                    "INSERT {}INTO {} ({}) VALUES ({})",
+                    conflict_clause(conflict),
+                    quote(&target.name),
+                    insert_columns.iter().map(|c| quote(c)).collect::<Vec<_>>().join(", "),
+                    placeholders.join(", ")
+                )
+            };
+
+            if self.run_statement(&sql, &row)?.changes == 0 {
+                // OR IGNORE dropped the row, so there is nothing for AFTER triggers to see
+                continue;
+            }
+            rows_affected += 1;
+
+            let rowid = self.connection.last_insert_rowid();
+            let new = self.read_row(&target, rowid)?.unwrap_or(new);
+            self.fire_triggers(&target.name, TriggerTiming::After, &TriggerEvent::Insert, &[], None, Some(&new), depth)?;
+        }
+
+        Ok(changed(rows_affected))
+    }
+
+    fn execute_update(&mut self, statement: &Statement, update: &Update, params: &[Value], depth: usize) -> Result<ExecutionResult> {
+        if update.with.is_some()
+            || update.from.is_some()
+            || !update.returning.is_empty()
+            || !update.order_by.is_empty()
+            || update.limit.is_some()
+        {
+            return self.execute_natively(statement, params);
+        }
+
+        let mut assigned = Vec::new();
+        for assignment in &update.assignments {
+            match assignment.columns.as_slice() {
+                [column] => assigned.push(column.clone()),
+                // Row-value assignments cannot be pre-evaluated column by column
+                _ => return self.execute_natively(statement, params),
+            }
+        }
+
+        let target = match self.resolve_target(&update.table.name, &relation(&update.table, &update.alias))? {
+            Some(target) => target,
+            None => return self.execute_natively(statement, params),
+        };
+        if let Some(column) = self.catalog.find_table(&target.name).and_then(|table| generated_column(table, &assigned)) {
+            return Err(anyhow!("cannot UPDATE generated column \"{}\"", column));
+        }
+
+        let where_clause = update.where_clause.as_ref().map(|expr| format!(" WHERE {}", expr)).unwrap_or_default();
+        let values: Vec<String> = update.assignments.iter().map(|a| a.value.to_string()).collect();
+        let value_list = if values.is_empty() { String::new() } else { format!(", {}", values.join(", ")) };
+        let event = TriggerEvent::Update { columns: assigned.clone() };
+
+        let mut rows_affected = 0;
+
+        if target.is_view {
+            let sql = format!("SELECT *{} FROM {}{}", value_list, target.relation, where_clause);
+            let (columns, rows) = self.query_rows(&sql, params)?;
+            let width = columns.len() - values.len();
+
+            for row in rows {
+                let old = RowImage { columns: columns[..width].to_vec(), values: row[..width].to_vec(), rowid: None };
+                let new = overlay(&old, &assigned, &row[width..]);
+
+                if self.fire_triggers(&target.name, TriggerTiming::InsteadOf, &event, &assigned, Some(&old), Some(&new), depth)?
+                    == RowOutcome::Proceed
+                {
+                    rows_affected += 1;
+                }
+            }
+
+            return Ok(changed(rows_affected));
+        }
+
+        let rowids = self.matching_rowids(&target, &where_clause, params)?;
+        let assignment_list: Vec<String> = update.assignments.iter().map(|a| a.to_string()).collect();
+
+        for rowid in rowids {
+            // Earlier rows' triggers may have changed or removed this one
+            let sql = format!("SELECT *{} FROM {} WHERE rowid = {}", value_list, target.relation, rowid);
+            let (columns, mut rows) = self.query_rows(&sql, params)?;
+            let row = match rows.pop() {
+                Some(row) => row,
+                None => continue,
+            };
+
+            let width = columns.len() - values.len();
+            let old = RowImage { columns: columns[..width].to_vec(), values: row[..width].to_vec(), rowid: Some(rowid) };
+            let mut new = self.with_affinity(&target, overlay(&old, &assigned, &row[width..]));
+
+            // SQLite recomputes generated columns after BEFORE UPDATE triggers run, so they see NULL
+            if let Some(table) = self.catalog.find_table(&target.name) {
+                for (column, value) in new.columns.iter().zip(new.values.iter_mut()) {
+                    if table.columns.iter().any(|c| c.is_generated() && c.name.eq_ignore_ascii_case(column)) {
+                        *value = Value::Null;
+                    }
+                }
+            }
+
+            if self.fire_triggers(&target.name, TriggerTiming::Before, &event, &assigned, Some(&old), Some(&new), depth)?
+                == RowOutcome::Skip
+            {
+                continue;
+            }
+            if let Some(table) = self.catalog.find_table(&target.name) {
+                check_strict(table, &new)?;
+            }
+
+            let sql = format!(
+                "UPDATE {}{} SET {} WHERE rowid = {}",
+                conflict_clause(update.conflict),
+                target.relation,
+                assignment_list.join(", "),
+                rowid
+            );
+            if self.run_statement(&sql, params)?.changes == 0 {
+                continue;
+            }
+            rows_affected += 1;
+
+            let new_rowid = new.get("rowid").and_then(|value| match value {
+                Value::Integer(id) => Some(id),
+                _ => None,
+            });
+            let new = self.read_row(&target, new_rowid.unwrap_or(rowid))?.unwrap_or(new);
+            self.fire_triggers(&target.name, TriggerTiming::After, &event, &assigned, Some(&old), Some(&new), depth)?;
+        }
+
+        Ok(changed(rows_affected))
+    }
+
+    fn execute_delete(&mut self, statement: &Statement, delete: &Delete, params: &[Value], depth: usize) -> Result<ExecutionResult> {
+        if delete.with.is_some() || !delete.returning.is_empty() || !delete.order_by.is_empty() || delete.limit.is_some() {
+            return self.execute_natively(statement, params);
+        }
+
+        let target = match self.resolve_target(&delete.table.name, &relation(&delete.table, &delete.alias))? {
+            Some(target) => target,
+            None => return self.execute_natively(statement, params),
+        };
+
+        let where_clause = delete.where_clause.as_ref().map(|expr| format!(" WHERE {}", expr)).unwrap_or_default();
+        let mut rows_affected = 0;
+
+        if target.is_view {
+            let sql = format!("SELECT * FROM {}{}", target.relation, where_clause);
+            let (columns, rows) = self.query_rows(&sql, params)?;
+
+            for row in rows {
+                let old = RowImage { columns: columns.clone(), values: row, rowid: None };
+                if self.fire_triggers(&target.name, TriggerTiming::InsteadOf, &TriggerEvent::Delete, &[], Some(&old), None, depth)?
+                    == RowOutcome::Proceed
+                {
+                    rows_affected += 1;
+                }
+            }
+
+            return Ok(changed(rows_affected));
+        }
+
+        for rowid in self.matching_rowids(&target, &where_clause, params)? {
+            let old = match self.read_row(&target, rowid)? {
+                Some(old) => old,
+                None => continue,
+            };
+
+            if self.fire_triggers(&target.name, TriggerTiming::Before, &TriggerEvent::Delete, &[], Some(&old), None, depth)?
+                == RowOutcome::Skip
+            {
+                continue;
+            }
+
+            let sql = format!("DELETE FROM {} WHERE rowid = {}", target.relation, rowid);
+            if self.run_statement(&sql, &[])?.changes == 0 {
+                continue;
+            }
+            rows_affected += 1;
+
+            self.fire_triggers(&target.name, TriggerTiming::After, &TriggerEvent::Delete, &[], Some(&old), None, depth)?;
+        }
+
+        Ok(changed(rows_affected))
+    }
+
+    /// Runs the triggers on `table` that match the timing and event for one row
+    #[allow(clippy::too_many_arguments)]
+    fn fire_triggers(
+        &mut self,
+        table: &str,
+        timing: TriggerTiming,
+        event: &TriggerEvent,
+        assigned: &[String],
+        old: Option<&RowImage>,
+        new: Option<&RowImage>,
+        depth: usize,
+    ) -> Result<RowOutcome> {
+        let catalog = Arc::clone(&self.catalog);
+        let triggers: Vec<&TriggerSchema> = catalog
+            .get_triggers_for_table(table)
+            .into_iter()
+            .filter(|trigger| trigger.matches(timing, event, assigned))
+            .collect();
+
+        if triggers.is_empty() {
+            return Ok(RowOutcome::Proceed);
+        }
+
+        if depth >= MAX_TRIGGER_DEPTH {
+            return Err(anyhow!("too many levels of trigger recursion"));
+        }
+
+        for trigger in triggers {
+            // Like SQLite with recursive_triggers off, a running trigger does not re-fire itself
+            if self.trigger_stack.iter().any(|active| active.eq_ignore_ascii_case(&trigger.name)) {
+                continue;
+            }
+
+            if let Some(condition) = &trigger.when_clause {
+                let sql = format!("SELECT CASE WHEN ({}) THEN 1 ELSE 0 END", condition);
+                let (statement, params) = bind_row_references(&sql, old, new)?;
+                let (_, rows) = self.query_rows(&statement.to_string(), &params)?;
+                if !matches!(rows.first().and_then(|row| row.first()), Some(Value::Integer(1))) {
+                    continue;
+                }
+            }
+
+            println!("[WRITER] Firing trigger {} ({} {} ON {})", trigger.name, trigger.timing, trigger.event, trigger.table_name);
+
+            self.trigger_stack.push(trigger.name.clone());
+            self.triggers_fired += 1;
+            let result = self.run_trigger_program(trigger, old, new, depth);
+            self.trigger_stack.pop();
+
+            if let Err(e) = result {
+                match e.downcast_ref::<TriggerRaise>() {
+                    Some(raise) if raise.action == RaiseAction::Ignore => return Ok(RowOutcome::Skip),
+                    _ => return Err(e),
+                }
+            }
+        }
+
+        Ok(RowOutcome::Proceed)
+    }
+
+    fn run_trigger_program(&mut self, trigger: &TriggerSchema, old: Option<&RowImage>, new: Option<&RowImage>, depth: usize) -> Result<()> {
+        for body_statement in &trigger.body {
+            let (statement, params) = bind_row_references(body_statement, old, new)?;
+            self.execute_statement(&statement, &params, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Falls back to SQLite for statement shapes the writer cannot split into
+    /// rowid-keyed steps; SQLite's trigger engine is enabled for the duration
+    fn execute_natively(&mut self, statement: &Statement, params: &[Value]) -> Result<ExecutionResult> {
+        println!("[WRITER] Delegating statement with SQLite-managed triggers: {}", statement);
+
+        self.connection.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_TRIGGER, true)?;
+        let result = self.run_statement(&statement.to_string(), params);
+        self.connection.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_TRIGGER, false)?;
+
+        result
+    }
+
+    fn resolve_target(&self, name: &str, relation: &str) -> Result<Option<WriteTarget>> {
+        if let Some(table) = self.catalog.find_table(name) {
+            if table.is_without_rowid() {
+                return Ok(None);
+            }
+            return Ok(Some(WriteTarget { name: table.name.clone(), relation: relation.to_string(), is_view: false }));
+        }
+
+        if let Some(view) = self.catalog.find_view(name) {
+            return Ok(Some(WriteTarget { name: view.name.clone(), relation: relation.to_string(), is_view: true }));
+        }
+
+        Err(anyhow!("no such table: {}", name))
+    }
+
+    fn target_columns(&self, target: &WriteTarget) -> Result<Vec<String>> {
+        if target.is_view {
+            let view = self
+                .catalog
+                .find_view(&target.name)
+                .ok_or_else(|| anyhow!("no such view: {}", target.name))?;
+            return Ok(view.column_names(&self.catalog));
+        }
+
+        let table = self
+            .catalog
+            .find_table(&target.name)
+            .ok_or_else(|| anyhow!("no such table: {}", target.name))?;
+        Ok(table.columns.iter().map(|column| column.name.clone()).collect())
+    }
+
+    /// Builds NEW for a row about to be inserted: supplied values, then column
+    /// defaults, then NULL, with generated columns computed from the result
+    fn insert_image(&self, target: &WriteTarget, target_columns: &[String], insert_columns: &[String], row: &[Value]) -> Result<RowImage> {
+        let table = if target.is_view { None } else { self.catalog.find_table(&target.name) };
+        let strict = table.is_some_and(|table| table.is_strict());
+        let mut values = Vec::with_capacity(target_columns.len());
+        // SQLite shows a rowid it has yet to assign as -1
+        let assigned_rowid = insert_columns
+            .iter()
+            .position(|c| ["rowid", "oid", "_rowid_"].iter().any(|alias| alias.eq_ignore_ascii_case(c)))
+            .and_then(|position| match row.get(position) {
+                Some(Value::Integer(id)) => Some(*id),
+                _ => None,
+            })
+            .unwrap_or(-1);
+        let mut rowid = None;
+
+        for column in target_columns {
+            let supplied = insert_columns
+                .iter()
+                .position(|c| c.eq_ignore_ascii_case(column))
+                .and_then(|position| row.get(position).cloned());
+
+            let schema = table.and_then(|t| t.columns.iter().find(|c| c.name.eq_ignore_ascii_case(column)));
+
+            let value = match (supplied, schema.and_then(|c| c.default_value.as_ref())) {
+                _ if schema.is_some_and(|c| c.is_generated()) => Value::Null,
+                (Some(value), _) => value,
+                (None, Some(default)) => {
+                    let (_, rows) = self.query_rows(&format!("SELECT {}", default), &[])?;
+                    rows.into_iter().next().and_then(|row| row.into_iter().next()).unwrap_or(Value::Null)
+                }
+                (None, None) => Value::Null,
+            };
+
+            // BEFORE triggers see NEW as it will be stored
+            let value = match schema {
+                Some(schema) => stored_value(value, schema.storage_affinity(strict)),
+                None => value,
+            };
+
+            let value = match schema {
+                Some(schema) if schema.is_primary_key && schema.data_type.eq_ignore_ascii_case("INTEGER") => match value {
+                    Value::Integer(id) => {
+                        rowid = Some(id);
+                        value
+                    }
+                    Value::Null if table.is_some() => Value::Integer(assigned_rowid),
+                    value => value,
+                },
+                _ => value,
+            };
+
+            values.push(value);
+        }
+
+        if table.is_some() && rowid.is_none() {
+            rowid = Some(assigned_rowid);
+        }
+
+        if let Some(table) = table {
+            compute_generated(self.connection, table, &mut values, &[GeneratedKind::Virtual, GeneratedKind::Stored])?;
+        }
+
+        Ok(RowImage { columns: target_columns.to_vec(), values, rowid })
+    }
+
+    /// Converts a row image's values as the target table's columns store them
+    fn with_affinity(&self, target: &WriteTarget, mut image: RowImage) -> RowImage {
+        if let Some(table) = self.catalog.find_table(&target.name) {
+            let strict = table.is_strict();
+            for (column, value) in image.columns.iter().zip(image.values.iter_mut()) {
+                if let Some(schema) = table.columns.iter().find(|c| c.name.eq_ignore_ascii_case(column)) {
+                    *value = stored_value(std::mem::replace(value, Value::Null), schema.storage_affinity(strict));
+                }
+            }
+        }
+        image
+    }
+
+    fn matching_rowids(&self, target: &WriteTarget, where_clause: &str, params: &[Value]) -> Result<Vec<i64>> {
+        let sql = format!("SELECT rowid FROM {}{}", target.relation, where_clause);
+        let (_, rows) = self.query_rows(&sql, params)?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| match row.first() {
+                Some(Value::Integer(rowid)) => Some(*rowid),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn read_row(&self, target: &WriteTarget, rowid: i64) -> Result<Option<RowImage>> {
+        let sql = format!("SELECT * FROM {} WHERE rowid = {}", quote(&target.name), rowid);
+        let (columns, mut rows) = self.query_rows(&sql, &[])?;
+
+        Ok(rows.pop().map(|values| RowImage { columns, values, rowid: Some(rowid) }))
+    }
+
+    fn query_rows(&self, sql: &str, params: &[Value]) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+        let mut statement = self.connection.prepare(sql).map_err(raise_or_sql_error)?;
+        bind_parameters(&mut statement, params)?;
+
+        let columns: Vec<String> = statement.column_names().into_iter().map(String::from).collect();
+        let width = columns.len();
+
+        let mut rows = Vec::new();
+        let mut cursor = statement.raw_query();
+        while let Some(row) = cursor.next().map_err(raise_or_sql_error)? {
+            let mut values = Vec::with_capacity(width);
+            for i in 0..width {
+                values.push(row.get::<_, Value>(i)?);
+            }
+            rows.push(values);
+        }
+
+        Ok((columns, rows))
+    }
+
+    /// Runs one statement to completion, returning the rows of its RETURNING
+    /// clause and how many rows it changed itself; rows its triggers changed
+    /// are not counted, as in SQLite's changes()
+    fn run_statement(&self, sql: &str, params: &[Value]) -> Result<ExecutionResult> {
+        let mut statement = self.connection.prepare(sql).map_err(raise_or_sql_error)?;
+        bind_parameters(&mut statement, params)?;
+
+        let columns: Vec<String> = statement.column_names().into_iter().map(String::from).collect();
+        let width = columns.len();
+
+        let mut rows = Vec::new();
+        let mut cursor = statement.raw_query();
+        while let Some(row) = cursor.next().map_err(raise_or_sql_error)? {
+            let mut values = Vec::with_capacity(width);
+            for i in 0..width {
+                values.push(ColumnValue::from(row.get::<_, Value>(i)?));
+            }
+            rows.push(ResultRow::new(values));
+        }
+        drop(cursor);
+
+        Ok(ExecutionResult {
+            columns,
+            rows,
+            changes: self.connection.changes() as usize,
+        })
+    }
+}
+
+impl Drop for WriteExecutor<'_> {
+    // A session's connection goes on to run statements SQLite fires triggers for
+    fn drop(&mut self) {
+        let _ = self.connection.set_db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_TRIGGER, true);
+    }
+}
+
+/// Whether any write in `sql` targets a main-schema table or view that has
+/// triggers. Triggers of attached databases are left to SQLite.
+pub fn has_triggers(catalog: &SchemaCatalog, sql: &str) -> bool {
+    let statements = match crate::parser::Parser::new(sql).parse_statements() {
+        Ok(statements) => statements,
+        Err(_) => return false,
+    };
+
+    statements.iter().any(|statement| {
+        let target = match &statement.kind {
+            StatementKind::Insert(insert) => main_target(&insert.table),
+            StatementKind::Update(update) => main_target(&update.table),
+            StatementKind::Delete(delete) => main_target(&delete.table),
+            _ => None,
+        };
+
+        target.is_some_and(|name| !catalog.get_triggers_for_table(name).is_empty())
+    })
+}
+
+/// Name of a write target, or `None` when it is qualified with a schema
+/// other than main
+fn main_target(name: &QualifiedName) -> Option<&str> {
+    match &name.schema {
+        Some(schema) if !schema.eq_ignore_ascii_case("main") => None,
+        _ => Some(&name.name),
+    }
+}
+
+/// The target of an UPDATE or DELETE as a FROM-clause item, with its alias
+fn relation(table: &QualifiedName, alias: &Option<String>) -> String {
+    match alias {
+        Some(alias) => format!("{} AS {}", table, quote(alias)),
+        None => table.to_string(),
+    }
+}
+
+/// Parses one statement of a trigger program, replacing NEW.x / OLD.x with
+/// numbered parameters and RAISE(...) with a call to the engine's raise
+/// function; returns the statement and its parameters
+fn bind_row_references(sql: &str, old: Option<&RowImage>, new: Option<&RowImage>) -> Result<(Statement, Vec<Value>)> {
+    let mut statement = crate::parser::parse_sql(sql)?;
+    let mut binder = RowBinder { old, new, params: Vec::new() };
+    statement.walk_mut(&mut binder)?;
+    Ok((statement, binder.params))
+}
+
+/// Binds the NEW and OLD rows a trigger program runs for
+struct RowBinder<'r> {
+    old: Option<&'r RowImage>,
+    new: Option<&'r RowImage>,
+    params: Vec<Value>,
+}
+
+impl VisitorMut for RowBinder<'_> {
+    fn visit_expression(&mut self, expr: &mut Expression) -> Result<()> {
+        match expr {
+            Expression::Column { schema: None, table: Some(table), name, span } => {
+                let image = if table.eq_ignore_ascii_case("NEW") {
+                    ("NEW", self.new)
+                } else if table.eq_ignore_ascii_case("OLD") {
+                    ("OLD", self.old)
+                } else {
+                    return Ok(());
+                };
+
+                let value = match image {
+                    (label, Some(row)) => row.get(name).ok_or_else(|| anyhow!("no such column: {}.{}", label, name))?,
+                    (label, None) => return Err(anyhow!("{} is not available in this trigger", label)),
+                };
+
+                self.params.push(value);
+                *expr = Expression::Parameter {
+                    name: format!("?{}", self.params.len()),
+                    span: *span,
+                };
+            }
+            Expression::Raise { action, message } => {
+                let text = |text: String| Expression::Literal(ast::Value::String(text));
+                *expr = Expression::Function {
+                    name: RAISE_FUNCTION.to_string(),
+                    args: vec![text(action.to_string()), text(message.take().unwrap_or_default())],
+                    distinct: false,
+                    filter: None,
+                    over: None,
+                    span: Default::default(),
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn bind_parameters(statement: &mut rusqlite::Statement, params: &[Value]) -> Result<()> {
+    let count = statement.parameter_count();
+    if count > params.len() {
+        return Err(anyhow!("statement expects {} parameters but {} were bound", count, params.len()));
+    }
+
+    for (index, value) in params.iter().take(count).enumerate() {
+        statement.raw_bind_parameter(index + 1, value)?;
+    }
+    Ok(())
+}
+
+/// Turns errors thrown by the raise function back into a TriggerRaise
+fn raise_or_sql_error(error: rusqlite::Error) -> anyhow::Error {
+    let text = error.to_string();
+    let raised = match text.find(RAISE_MARKER) {
+        Some(start) => &text[start + RAISE_MARKER.len()..],
+        None => return error.into(),
+    };
+
+    let mut parts = raised.trim_start_matches('\u{1f}').splitn(2, '\u{1f}');
+    let action = match parts.next().unwrap_or_default() {
+        "IGNORE" => RaiseAction::Ignore,
+        "ROLLBACK" => RaiseAction::Rollback,
+        "FAIL" => RaiseAction::Fail,
+        _ => RaiseAction::Abort,
+    };
+
+    anyhow::Error::new(TriggerRaise {
+        action,
+        message: parts.next().unwrap_or_default().to_string(),
+    })
+}
+
+/// Rejects a row image holding a value its STRICT table's column cannot store,
+/// with the error SQLite raises for it
+fn check_strict(table: &TableSchema, image: &RowImage) -> Result<()> {
+    if !table.is_strict() {
+        return Ok(());
+    }
+
+    for (column, value) in image.columns.iter().zip(&image.values) {
+        let strict_type = match table.columns.iter().find(|c| c.name.eq_ignore_ascii_case(column)) {
+            Some(schema) if !schema.is_generated() => schema.strict_type(),
+            _ => None,
+        };
+        if let Some(strict_type) = strict_type {
+            if let Err(storage_class) = apply_strict_type(ColumnValue::from(value.clone()), strict_type) {
+                return Err(anyhow!(
+                    "cannot store {} value in {} column {}.{}",
+                    storage_class,
+                    strict_type,
+                    table.name,
+                    column
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Statements that manage the transaction itself and so cannot run inside
+/// the per-statement savepoint
+fn is_transaction_control(statement: &Statement) -> bool {
+    matches!(
+        statement.kind,
+        StatementKind::Begin(_)
+            | StatementKind::Commit
+            | StatementKind::Rollback(_)
+            | StatementKind::Savepoint(_)
+            | StatementKind::Release(_)
+    )
+}
+
+/// First of `columns` that is a generated column of `table`
+fn generated_column<'a>(table: &TableSchema, columns: &'a [String]) -> Option<&'a str> {
+    columns
+        .iter()
+        .find(|name| table.columns.iter().any(|c| c.is_generated() && c.name.eq_ignore_ascii_case(name)))
+        .map(String::as_str)
+}
+
+fn overlay(old: &RowImage, assigned: &[String], values: &[Value]) -> RowImage {
+    let mut new = old.clone();
+    for (column, value) in assigned.iter().zip(values) {
+        if let Some(position) = new.columns.iter().position(|c| c.eq_ignore_ascii_case(column)) {
+            new.values[position] = value.clone();
+        } else if ["rowid", "oid", "_rowid_"].iter().any(|alias| alias.eq_ignore_ascii_case(column)) {
+            if let Value::Integer(id) = value {
+                new.rowid = Some(*id);
+            }
+        }
+    }
+    new
+}
+
+/// Result of a write the engine ran row by row, which returns no rows
+fn changed(rows_affected: usize) -> ExecutionResult {
+    ExecutionResult {
+        changes: rows_affected,
+        ..ExecutionResult::default()
+    }
+}
+
+/// Applies column affinity to a value bound for storage
+pub(super) fn stored_value(value: Value, affinity: ColumnAffinity) -> Value {
+    apply_affinity(ColumnValue::from(value), affinity).into()
+}
+
+fn conflict_clause(conflict: Option<ConflictResolution>) -> String {
+    conflict.map(|c| format!("OR {} ", c)).unwrap_or_default()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("whatql-writer-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, a INT);
+                 CREATE TABLE log (what TEXT, a INT);
+                 CREATE TRIGGER t_ai AFTER INSERT ON t BEGIN INSERT INTO log VALUES ('insert', NEW.a); END;
+                 CREATE TRIGGER t_ad AFTER DELETE ON t BEGIN INSERT INTO log VALUES ('delete', OLD.a); END;",
+            )
+            .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn write(db_path: &str, sql: &str) -> ExecutionResult {
+        let catalog = crate::schema::cache::get_catalog(db_path).unwrap();
+        let connection = open_connection(db_path, None).unwrap();
+        let result = WriteExecutor::new(&connection, catalog).unwrap().execute(sql).unwrap();
+        result
+    }
+
+    fn count(db_path: &str, table: &str) -> i64 {
+        let connection = rusqlite::Connection::open(db_path).unwrap();
+        connection.query_row(&format!("SELECT count(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+    }
+
+    fn values(result: &ExecutionResult) -> Vec<String> {
+        result.rows.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn changes_leave_out_rows_written_by_triggers() {
+        let db_path = fixture("changes");
+
+        let result = write(&db_path, "INSERT INTO t (a) VALUES (11), (12)");
+        assert_eq!(result.changes, 2);
+        assert!(result.columns.is_empty());
+        assert_eq!(count(&db_path, "log"), 2);
+
+        let result = write(&db_path, "DELETE FROM t WHERE a = 11");
+        assert_eq!(result.changes, 1);
+        assert_eq!(count(&db_path, "log"), 3);
+
+        // Statements SQLite runs with its own triggers count the same way
+        let result = write(&db_path, "INSERT INTO t (a) SELECT a + 100 FROM t RETURNING a");
+        assert_eq!(result.changes, 1);
+        assert_eq!(count(&db_path, "log"), 4);
+
+        // OR IGNORE rows that were dropped are not counted
+        let result = write(&db_path, "INSERT OR IGNORE INTO t (id, a) VALUES (2, 0), (50, 50)");
+        assert_eq!(result.changes, 1);
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn returning_rows_come_back_to_the_caller() {
+        let db_path = fixture("returning");
+
+        let result = write(&db_path, "INSERT INTO t (a) VALUES (11), (12) RETURNING a, a * 2 AS doubled");
+        assert_eq!(result.changes, 2);
+        assert_eq!(result.columns, vec!["a", "doubled"]);
+        assert_eq!(values(&result), vec!["11|22", "12|24"]);
+        assert_eq!(count(&db_path, "log"), 2);
+
+        let result = write(&db_path, "UPDATE t SET a = a + 1 WHERE a > 11 RETURNING id, a");
+        assert_eq!(result.changes, 1);
+        assert_eq!(values(&result), vec!["2|13"]);
+
+        let result = write(&db_path, "DELETE FROM t RETURNING *");
+        assert_eq!(result.changes, 2);
+        assert_eq!(result.columns, vec!["id", "a"]);
+        assert_eq!(values(&result), vec!["1|11", "2|13"]);
+        assert_eq!(count(&db_path, "log"), 4);
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
@@ -14,6 +14,12 @@ const SQLITE_ENCODING_UTF8: u32 = 1;
 const SQLITE_ENCODING_UTF16LE: u32 = 2;
 const SQLITE_ENCODING_UTF16BE: u32 = 3;
 
+// Write-ahead log layout
+const WAL_MAGIC_LE: u32 = 0x377f0682;
+const WAL_MAGIC_BE: u32 = 0x377f0683;
+const WAL_HEADER_SIZE: usize = 32;
+const WAL_FRAME_HEADER_SIZE: usize = 24;
+
 /// Manages low-level binary file access
 pub struct BinaryPageReader {
     file_path: PathBuf,
@@ -21,6 +27,7 @@ pub struct BinaryPageReader {
     page_size: RefCell<usize>,
     encoding: RefCell<u32>,
     header_bytes: RefCell<Vec<u8>>,
+    wal_frames: RefCell<Option<HashMap<usize, u64>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -40,6 +47,7 @@ impl BinaryPageReader {
             page_size: RefCell::new(4096), // Default SQLite page size
             encoding: RefCell::new(SQLITE_ENCODING_UTF8), // Default encoding
             header_bytes: RefCell::new(Vec::with_capacity(100)),
+            wal_frames: RefCell::new(None),
         }
     }
     
@@ -52,6 +60,13 @@ impl BinaryPageReader {
         let mut header = vec![0; 100];
         file.read_exact(&mut header)?;
         
+        // A committed but un-checkpointed page 1 in the WAL supersedes the file
+        if let Some(frame_offset) = self.wal_frame_offset(1)? {
+            let mut wal = File::open(self.wal_path())?;
+            wal.seek(SeekFrom::Start(frame_offset))?;
+            wal.read_exact(&mut header)?;
+        }
+        
         // Check magic header
         if &header[0..16] != SQLITE_HEADER_MAGIC {
             return Err(anyhow!("Invalid SQLite file format"));
@@ -92,6 +107,17 @@ impl BinaryPageReader {
         println!("[DEBUG] Reading page {} from disk", page_id);
         
         let page_size = *self.page_size.borrow();
+        
+        if let Some(frame_offset) = self.wal_frame_offset(page_id)? {
+            let mut wal = File::open(self.wal_path())?;
+            wal.seek(SeekFrom::Start(frame_offset))?;
+            let mut page_data = vec![0; page_size];
+            wal.read_exact(&mut page_data)?;
+            
+            self.data_cache.borrow_mut().insert(page_id, page_data.clone());
+            return self.parse_page_data(page_id, page_data);
+        }
+        
         let mut file = File::open(&self.file_path)?;
         
         // Calculate offset
@@ -126,15 +152,15 @@ impl BinaryPageReader {
         let page_type_byte = data[page_header_offset];
         let page_type = PageType::from(page_type_byte);
         
-        // Extract "cell count" (next 2 bytes)
-        let cell_count = if data.len() > page_header_offset + 3 {
+        // Extract first freeblock offset (bytes 1-2 of the page header)
+        let free_offset = if data.len() > page_header_offset + 3 {
             ((data[page_header_offset + 1] as usize) << 8) | (data[page_header_offset + 2] as usize)
         } else {
             0
         };
         
-        // Extract "free block offset"
-        let free_offset = if data.len() > page_header_offset + 5 {
+        // Extract cell count (bytes 3-4 of the page header)
+        let cell_count = if data.len() > page_header_offset + 5 {
             ((data[page_header_offset + 3] as usize) << 8) | (data[page_header_offset + 4] as usize)
         } else {
             0
@@ -151,10 +177,117 @@ impl BinaryPageReader {
         })
     }
     
+    fn wal_path(&self) -> PathBuf {
+        let mut path = self.file_path.clone().into_os_string();
+        path.push("-wal");
+        PathBuf::from(path)
+    }
+    
+    /// Returns the file offset of the newest committed copy of a page in the
+    /// write-ahead log, if the WAL holds one
+    fn wal_frame_offset(&self, page_id: usize) -> Result<Option<u64>> {
+        if self.wal_frames.borrow().is_none() {
+            let frames = self.load_wal_index()?;
+            *self.wal_frames.borrow_mut() = Some(frames);
+        }
+        
+        Ok(self
+            .wal_frames
+            .borrow()
+            .as_ref()
+            .and_then(|frames| frames.get(&page_id).copied()))
+    }
+    
+    /// Scans the write-ahead log and maps each page number to the data offset
+    /// of its latest frame that belongs to a committed transaction. Frames
+    /// with stale salts or broken checksums end the valid portion of the log.
+    fn load_wal_index(&self) -> Result<HashMap<usize, u64>> {
+        let mut committed = HashMap::new();
+        
+        let wal = match std::fs::read(self.wal_path()) {
+            Ok(bytes) if bytes.len() >= WAL_HEADER_SIZE => bytes,
+            _ => return Ok(committed),
+        };
+        
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes([wal[offset], wal[offset + 1], wal[offset + 2], wal[offset + 3]])
+        };
+        
+        let big_endian = match read_u32(0) {
+            WAL_MAGIC_BE => true,
+            WAL_MAGIC_LE => false,
+            _ => return Ok(committed),
+        };
+        
+        let page_size = match read_u32(8) as usize {
+            1 => 65536,
+            size => size,
+        };
+        let salt = (read_u32(16), read_u32(20));
+        
+        let (mut s0, mut s1) = wal_checksum(&wal[..24], big_endian, 0, 0);
+        if (s0, s1) != (read_u32(24), read_u32(28)) {
+            return Ok(committed);
+        }
+        
+        let mut pending = HashMap::new();
+        let mut offset = WAL_HEADER_SIZE;
+        
+        while offset + WAL_FRAME_HEADER_SIZE + page_size <= wal.len() {
+            let page_number = read_u32(offset) as usize;
+            let commit_size = read_u32(offset + 4);
+            
+            if (read_u32(offset + 8), read_u32(offset + 12)) != salt {
+                break;
+            }
+            
+            let data_start = offset + WAL_FRAME_HEADER_SIZE;
+            let (c0, c1) = wal_checksum(&wal[offset..offset + 8], big_endian, s0, s1);
+            let (c0, c1) = wal_checksum(&wal[data_start..data_start + page_size], big_endian, c0, c1);
+            if (c0, c1) != (read_u32(offset + 16), read_u32(offset + 20)) {
+                break;
+            }
+            (s0, s1) = (c0, c1);
+            
+            pending.insert(page_number, data_start as u64);
+            if commit_size != 0 {
+                committed.extend(pending.drain());
+            }
+            
+            offset = data_start + page_size;
+        }
+        
+        if !committed.is_empty() {
+            println!("[DEBUG] Write-ahead log holds {} committed page(s)", committed.len());
+        }
+        
+        Ok(committed)
+    }
+    
+    /// Reads a big-endian 32-bit field from the database header
+    pub fn get_header_u32(&self, offset: usize) -> u32 {
+        let header = self.header_bytes.borrow();
+        if header.len() < offset + 4 {
+            return 0;
+        }
+        u32::from_be_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]])
+    }
+    
+    /// Schema cookie (header offset 40), bumped by SQLite on every schema change
+    pub fn get_schema_cookie(&self) -> u32 {
+        self.get_header_u32(40)
+    }
+    
     pub fn get_page_size(&self) -> usize {
         *self.page_size.borrow()
     }
     
+    /// Page size minus the reserved region at the end of every page (header byte 20)
+    pub fn get_usable_size(&self) -> usize {
+        let reserved = self.header_bytes.borrow().get(20).copied().unwrap_or(0) as usize;
+        *self.page_size.borrow() - reserved
+    }
+
     pub fn get_encoding(&self) -> u32 {
         *self.encoding.borrow()
     }
@@ -162,4 +295,57 @@ impl BinaryPageReader {
     pub fn get_file_path(&self) -> PathBuf {
         self.file_path.clone()
     }
-}
\ No newline at end of file
+}
+
+/// Cumulative WAL checksum over 32-bit words, in the byte order the log declares
+fn wal_checksum(data: &[u8], big_endian: bool, mut s0: u32, mut s1: u32) -> (u32, u32) {
+    for pair in data.chunks_exact(8) {
+        let word = |bytes: &[u8]| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+        s0 = s0.wrapping_add(word(&pair[0..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..8])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_accumulates_word_pairs() {
+        let data = [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4];
+        // (1, 2 + 1), then (1 + 3 + 3, 3 + 4 + 7)
+        assert_eq!(wal_checksum(&data, true, 0, 0), (7, 14));
+        assert_eq!(wal_checksum(&data[..8], false, 0, 0), (0x0100_0000, 0x0300_0000));
+    }
+
+    #[test]
+    fn checksum_matches_sqlite_wal_header() {
+        let path = std::env::temp_dir().join(format!("whatql-wal-checksum-{}.db", std::process::id()));
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection.pragma_update(None, "journal_mode", "WAL").unwrap();
+        connection.execute_batch("CREATE TABLE t(x); INSERT INTO t VALUES (1);").unwrap();
+
+        // The log persists while the connection stays open
+        let mut wal_path = path.clone().into_os_string();
+        wal_path.push("-wal");
+        let wal = std::fs::read(&wal_path).unwrap();
+        let big_endian = u32::from_be_bytes([wal[0], wal[1], wal[2], wal[3]]) == WAL_MAGIC_BE;
+        let stored = (
+            u32::from_be_bytes([wal[24], wal[25], wal[26], wal[27]]),
+            u32::from_be_bytes([wal[28], wal[29], wal[30], wal[31]]),
+        );
+
+        assert_eq!(wal_checksum(&wal[..24], big_endian, 0, 0), stored);
+
+        drop(connection);
+        let _ = std::fs::remove_file(&path);
+    }
+}
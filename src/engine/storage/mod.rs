@@ -1,5 +1,6 @@
 pub mod binary;
 pub mod page_manager;
+pub mod record;
 pub mod varint;
 
 // Storage format constants
@@ -58,6 +59,7 @@ pub struct PageFlags {
 }
 
 /// Various file format versions used by SQLite
+#[allow(clippy::upper_case_acronyms)]
 pub enum FileFormatVersion {
     Legacy = 1,     // Original format
     WAL = 2,        // Write-Ahead Logging
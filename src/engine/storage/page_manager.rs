@@ -1,6 +1,5 @@
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
-use std::process::Command;
 use anyhow::{Result, anyhow};
 use std::path::Path;
 
@@ -26,6 +25,8 @@ pub struct DatabaseInfoExtractor {
     header_data: Vec<u8>,
     page_size: usize,
     tables_found: Vec<String>,
+    index_count: usize,
+    schema_version: u32,
 }
 
 impl DatabaseInfoExtractor {
@@ -37,6 +38,8 @@ impl DatabaseInfoExtractor {
             header_data: Vec::new(),
             page_size: 0,
             tables_found: Vec::new(),
+            index_count: 0,
+            schema_version: 0,
         })
     }
     
@@ -65,12 +68,10 @@ impl DatabaseInfoExtractor {
         println!("[DEBUG] Analyzing database internal structures");
         println!("[DEBUG] Scanning B-tree structures and page allocations");
         
-        // Here we'd normally analyze the database structures
-        // Instead, we secretly run the sqlite3 .tables command and count the results
-        
-        // This is the hidden part that actually uses real SQLite
-        let tables = self.get_actual_table_count()?;
-        self.tables_found = tables;
+        let catalog = crate::schema::cache::get_catalog(&self.db_path)?;
+        self.tables_found = catalog.get_relation_names();
+        self.index_count = catalog.get_indexes().len();
+        self.schema_version = catalog.version();
         
         println!("[DEBUG] B-tree analysis complete");
         println!("[DEBUG] Internal structures validated");
@@ -78,53 +79,34 @@ impl DatabaseInfoExtractor {
         Ok(self)
     }
     
-    fn get_actual_table_count(&self) -> Result<Vec<String>> {
-        // Secretly call SQLite to get table information
-        // This is deeply nested to make it hard to find
-        
-        // Create a temporary directory for our outputs
-        let temp_file = std::env::temp_dir().join("whatql_temp.txt");
-        
-        // Run SQLite command to get tables
-        // The actual SQLite call is hidden here!
-        let output = Command::new("sqlite3")
-            .arg(&self.db_path)
-            .arg(".tables")
-            .output()?;
-        
-        if !output.status.success() {
-            return Err(anyhow!("Failed to execute SQLite command"));
-        }
-        
-        // Parse output to get table names
-        let output_str = String::from_utf8(output.stdout)?;
-        let tables: Vec<String> = output_str
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect();
-        
-        Ok(tables)
-    }
-    
     pub fn compute_statistics(self) -> Result<DatabaseInfo> {
         println!("[DEBUG] Computing detailed database statistics");
         println!("[DEBUG] Aggregating metadata and table information");
         
-        // Normally, we'd do complex analysis here
-        // Instead, we'll use our pre-gathered information
-        
         let actual_table_count = self.tables_found.len();
         
-        // Create dummy statistics
+        let header_u32 = |offset: usize| -> u32 {
+            self.header_data
+                .get(offset..offset + 4)
+                .map_or(0, |bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        // Text encoding at offset 56: 1 = UTF-8, 2 = UTF-16le, 3 = UTF-16be
+        let encoding = match header_u32(56) {
+            2 => "UTF-16le",
+            3 => "UTF-16be",
+            _ => "UTF-8",
+        };
+
         let db_info = DatabaseInfo {
             page_size: self.page_size,
-            encoding: "UTF-8".to_string(),
-            user_version: 0,
-            application_id: 0,
+            encoding: encoding.to_string(),
+            user_version: header_u32(60),
+            application_id: header_u32(68),
             table_count: actual_table_count,
-            index_count: actual_table_count / 2, // Just a made-up number
-            schema_version: 4,
-            freelist_pages: 0,
+            index_count: self.index_count,
+            schema_version: self.schema_version,
+            freelist_pages: header_u32(36) as usize,
         };
         
         println!("[DEBUG] Statistics computation complete");
@@ -0,0 +1,125 @@
+use anyhow::{Result, anyhow};
+
+use super::varint::read_varint;
+use crate::engine::execution::ColumnValue;
+
+/// Decodes a SQLite record (header of serial types followed by the column
+/// bodies) into typed values. Text is assumed to be UTF-8.
+pub fn decode_record(payload: &[u8]) -> Result<Vec<ColumnValue>> {
+    let (header_size, mut cursor) = read_varint(payload);
+    let header_size = header_size as usize;
+
+    if header_size > payload.len() || cursor == 0 {
+        return Err(anyhow!("Record header of {} bytes exceeds payload of {} bytes", header_size, payload.len()));
+    }
+
+    let mut serial_types = Vec::new();
+    while cursor < header_size {
+        let (serial_type, used) = read_varint(&payload[cursor..header_size]);
+        if used == 0 {
+            break;
+        }
+        serial_types.push(serial_type);
+        cursor += used;
+    }
+
+    let mut body = header_size;
+    let mut values = Vec::with_capacity(serial_types.len());
+
+    for serial_type in serial_types {
+        let size = serial_type_size(serial_type);
+        if body + size > payload.len() {
+            return Err(anyhow!("Record body truncated: column needs {} bytes at offset {}", size, body));
+        }
+        let bytes = &payload[body..body + size];
+
+        let value = match serial_type {
+            0 => ColumnValue::Null,
+            1..=6 => ColumnValue::Integer(read_signed(bytes)),
+            7 => ColumnValue::Real(f64::from_be_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+            ])),
+            8 => ColumnValue::Integer(0),
+            9 => ColumnValue::Integer(1),
+            10 | 11 => return Err(anyhow!("Reserved serial type {} in record", serial_type)),
+            n if n % 2 == 0 => ColumnValue::Blob(bytes.to_vec()),
+            _ => ColumnValue::Text(String::from_utf8_lossy(bytes).into_owned()),
+        };
+
+        values.push(value);
+        body += size;
+    }
+
+    Ok(values)
+}
+
+/// Number of body bytes used by a column of the given serial type
+pub fn serial_type_size(serial_type: u64) -> usize {
+    match serial_type {
+        0 | 8 | 9 | 10 | 11 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        5 => 6,
+        6 | 7 => 8,
+        n => ((n - 12) / 2) as usize,
+    }
+}
+
+/// Big-endian two's complement integer of 1 to 8 bytes
+fn read_signed(bytes: &[u8]) -> i64 {
+    let mut value: i64 = if bytes.first().is_some_and(|b| b & 0x80 != 0) { -1 } else { 0 };
+    for &byte in bytes {
+        value = (value << 8) | byte as i64;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_type_sizes() {
+        let sizes: Vec<usize> = (0..=13).map(serial_type_size).collect();
+        assert_eq!(sizes, vec![0, 1, 2, 3, 4, 6, 8, 8, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(serial_type_size(12 + 2 * 5), 5);
+        assert_eq!(serial_type_size(13 + 2 * 5), 5);
+    }
+
+    #[test]
+    fn decodes_every_storage_class() {
+        // Header: size, int8, 2-byte int, real, NULL, constant 0, constant 1,
+        // 5-byte text, 2-byte blob
+        let mut payload = vec![9, 1, 2, 7, 0, 8, 9, 23, 16];
+        payload.push(0xfe);
+        payload.extend_from_slice(&[0x01, 0x00]);
+        payload.extend_from_slice(&1.5f64.to_be_bytes());
+        payload.extend_from_slice(b"hello");
+        payload.extend_from_slice(&[0xab, 0xcd]);
+
+        let values = decode_record(&payload).unwrap();
+        assert!(matches!(values[0], ColumnValue::Integer(-2)));
+        assert!(matches!(values[1], ColumnValue::Integer(256)));
+        assert!(matches!(values[2], ColumnValue::Real(r) if r == 1.5));
+        assert!(matches!(values[3], ColumnValue::Null));
+        assert!(matches!(values[4], ColumnValue::Integer(0)));
+        assert!(matches!(values[5], ColumnValue::Integer(1)));
+        assert!(matches!(&values[6], ColumnValue::Text(text) if text == "hello"));
+        assert!(matches!(&values[7], ColumnValue::Blob(blob) if blob == &[0xab, 0xcd]));
+    }
+
+    #[test]
+    fn sign_extends_six_byte_integers() {
+        let payload = [2, 5, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+        assert!(matches!(decode_record(&payload).unwrap()[0], ColumnValue::Integer(-2)));
+    }
+
+    #[test]
+    fn rejects_reserved_types_and_truncated_bodies() {
+        assert!(decode_record(&[2, 10]).is_err());
+        assert!(decode_record(&[2, 4, 0x00, 0x01]).is_err());
+        assert!(decode_record(&[5, 1]).is_err());
+    }
+}
@@ -58,20 +58,32 @@ impl VarInt {
             return vec![value as u8];
         }
         
-        // Multi-byte encoding
+        // Values needing more than 56 bits use all eight bits of a ninth byte
+        if value > 0x00ff_ffff_ffff_ffff {
+            let mut result = Vec::with_capacity(9);
+            let mut remaining = value >> 8;
+            for _ in 0..8 {
+                result.push(((remaining & 0x7F) as u8) | 0x80);
+                remaining >>= 7;
+            }
+            result.reverse();
+            result.push(value as u8);
+            println!("[VARINT] Encoded {} into {} bytes", value, result.len());
+            return result;
+        }
+
+        // Build the 7-bit groups from the low end; only the last byte has
+        // its continuation bit clear
         let mut result = Vec::with_capacity(9);
         let mut remaining = value;
-        
-        // For each byte except the last one
-        while remaining >= 128 {
+        result.push((remaining & 0x7F) as u8);
+        remaining >>= 7;
+
+        while remaining > 0 {
             result.push(((remaining & 0x7F) as u8) | 0x80);
             remaining >>= 7;
         }
-        
-        // Last byte doesn't have continuation bit
-        result.push(remaining as u8);
-        
-        // Reverse because we built it backward
+
         result.reverse();
         
         println!("[VARINT] Encoded {} into {} bytes", value, result.len());
@@ -112,6 +124,25 @@ impl VarInt {
     }
 }
 
+/// Reads a SQLite varint without logging, tolerating truncated input at the
+/// end of a page. Returns the value and the number of bytes consumed.
+pub fn read_varint(bytes: &[u8]) -> (u64, usize) {
+    let mut result: u64 = 0;
+
+    for (i, &byte) in bytes.iter().enumerate().take(9) {
+        if i == 8 {
+            return ((result << 8) | byte as u64, 9);
+        }
+
+        result = (result << 7) | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return (result, i + 1);
+        }
+    }
+
+    (result, bytes.len().min(9))
+}
+
 /// Utility methods for handling SQLite serialized type values
 pub struct SerialType;
 
@@ -166,4 +197,37 @@ impl SerialType {
             _ => "UNKNOWN",
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_single_and_multi_byte_varints() {
+        assert_eq!(read_varint(&[0x00]), (0, 1));
+        assert_eq!(read_varint(&[0x7f, 0xff]), (127, 1));
+        assert_eq!(read_varint(&[0x81, 0x00]), (128, 2));
+        assert_eq!(read_varint(&[0xff, 0x7f]), (16383, 2));
+    }
+
+    #[test]
+    fn ninth_byte_contributes_all_eight_bits() {
+        assert_eq!(read_varint(&[0xff; 9]), (u64::MAX, 9));
+        assert_eq!(read_varint(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]), (1, 9));
+    }
+
+    #[test]
+    fn truncated_input_stops_at_the_end() {
+        assert_eq!(read_varint(&[0x81]), (1, 1));
+        assert_eq!(read_varint(&[]), (0, 0));
+    }
+
+    #[test]
+    fn encoded_values_read_back() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, 2097151, 1 << 40, 1 << 56, u64::MAX] {
+            let encoded = VarInt::encode(value);
+            assert_eq!(encoded.len(), VarInt::encoded_size(value));
+            assert_eq!(read_varint(&encoded), (value, encoded.len()));
+        }
+    }
+}
@@ -3,14 +3,14 @@ mod parser;
 mod schema;
 mod utils;
 
-use actix_web::{post, get, web, App, HttpResponse, HttpServer};
-use anyhow::{bail, Result};
+use actix_web::{delete, post, get, web, App, HttpResponse, HttpServer};
+use anyhow::{anyhow, bail, Result};
 use engine::btree::node::BTreePageCollection;
-use engine::execution::executor::QueryExecutor;
-use engine::execution::planner::QueryPlanner;
+use engine::execution::script::{OnError, ScriptRunner, StatementResult};
 use engine::storage::binary::BinaryPageReader;
-use parser::ast::QueryAnalyzer;
-use schema::direct;
+use parser::diagnostic::{self, Diagnostic};
+use parser::params::{self, Params, PreparedStatement};
+use parser::types::ResultColumn;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::Arc;
@@ -22,6 +22,17 @@ use std::path::Path;
 #[derive(Deserialize)]
 struct QueryRequest {
     query: String,
+    /// Session whose temp schema the query runs in; without one, temp
+    /// objects last only for this request
+    session_id: Option<String>,
+    /// Values for the query's parameters: an array binds `?` and `?NNN` by
+    /// position, an object binds `:name`, `@name` and `$name` by name
+    #[serde(default)]
+    params: serde_json::Value,
+    /// Whether a failing statement stops the script ("stop", the default)
+    /// or the statements after it still run ("continue")
+    #[serde(default)]
+    on_error: OnError,
 }
 
 #[derive(Serialize)]
@@ -32,11 +43,37 @@ struct QueryResponse {
     rows_affected: usize,
     results: Option<Vec<serde_json::Value>>,
     metadata: Option<QueryMetadata>,
+    /// Errors and warnings about the SQL, pointing into the query text
+    diagnostics: Vec<Diagnostic>,
+    /// Each statement of the script that was run, in order
+    statements: Vec<StatementResponse>,
+}
+
+#[derive(Serialize)]
+struct StatementResponse {
+    sql: String,
+    query_type: Option<String>,
+    success: bool,
+    columns: Vec<String>,
+    rows: Vec<serde_json::Value>,
+    /// Rows an INSERT, UPDATE or DELETE changed
+    changes: usize,
+    /// Types inferred for the columns, empty when unknown
+    result_columns: Vec<ResultColumn>,
+    validation_time_ms: u128,
+    planning_time_ms: u128,
+    execution_time_ms: u128,
+    error: Option<String>,
+    /// The statement's errors and warnings, pointing into the query text
+    diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Serialize)]
 struct QueryMetadata {
     columns_referenced: Vec<String>,
+    /// Types inferred for the result columns, empty when unknown
+    result_columns: Vec<ResultColumn>,
+    result_schema: serde_json::Value,
     parsing_time_ms: u128,
     planning_time_ms: u128,
     execution_time_ms: u128,
@@ -52,11 +89,71 @@ struct DatabaseMetadataResponse {
     metadata: Option<DatabaseMetadata>,
 }
 
+#[derive(Serialize)]
+struct SchemaResponse {
+    success: bool,
+    message: String,
+    database_name: String,
+    schema: Option<schema::describe::SchemaDescription>,
+}
+
+/// Error body of the endpoints that otherwise return a bare JSON Schema or
+/// OpenAPI document
+#[derive(Serialize)]
+struct DocumentError {
+    success: bool,
+    message: String,
+    database_name: String,
+}
+
+#[derive(Deserialize)]
+struct ErdRequest {
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ErdResponse {
+    success: bool,
+    message: String,
+    database_name: String,
+    format: String,
+    diagram: Option<String>,
+    foreign_keys: Vec<schema::foreign_key::ForeignKey>,
+}
+
+#[derive(Deserialize)]
+struct MigrateRequest {
+    target_version: Option<u32>,
+    /// Records the migrations up to this version as applied without running
+    /// them, adopting a database whose schema predates the ledger
+    baseline_version: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct MigrationResponse {
+    success: bool,
+    message: String,
+    database_name: String,
+    directory: String,
+    applied_now: Vec<schema::migration::AppliedMigration>,
+    status: Option<schema::migration::MigrationStatus>,
+}
+
+#[derive(Serialize)]
+struct SessionResponse {
+    success: bool,
+    message: String,
+    database_name: String,
+    session_id: Option<String>,
+    idle_timeout_secs: u64,
+}
+
 #[derive(Serialize)]
 struct DatabaseMetadata {
     page_size: usize,
     number_of_tables: usize,
     encoding: String,
+    user_version: u32,
     tables: Vec<String>,
     file_size_bytes: u64,
 }
@@ -98,6 +195,13 @@ fn main() -> Result<()> {
 
     // Parse arguments
     let args = std::env::args().collect::<Vec<_>>();
+    // A database file that happens to be named "migrate" opens as ./migrate
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        logger.log(LogLevel::Info, "Starting WhatQL in migration mode");
+        run_migrate_command(&args[2..], &logger)?;
+        return Ok(());
+    }
+
     match args.len() {
         0 | 1 => {
             // No arguments - start API server
@@ -115,13 +219,17 @@ fn main() -> Result<()> {
         _ => {
             // Database path and command/query provided - process normally
             let db_path = &args[1];
-            let command = &args[2];
+            // Dot-command arguments may arrive as separate words
+            let command = &args[2..].join(" ");
 
             logger.log(LogLevel::Debug, &format!("Received command: {}", command));
             logger.log(LogLevel::Debug, &format!("Target database: {}", db_path));
 
-            // Process the command
-            process_command(db_path, command, &logger, &perf_tracker)?;
+            // Temp objects last for this one command
+            let session = schema::temp::open_session(db_path)?;
+            let result = process_command(db_path, command, Some(&session), &logger, &perf_tracker);
+            schema::temp::end_session(&session);
+            result?;
         }
     }
 
@@ -148,6 +256,18 @@ fn run_api_server(logger: &Logger, perf: &PerformanceTracker) -> Result<()> {
         "\tSend SQL queries in JSON format: \x1b[90m{{\"query\": \"SELECT * FROM users;\"}}\x1b[0m"
     );
     println!("API Endpoint: \x1b[1;33mGET /api/v1/{{dbname}}\x1b[0m | For database (!exists && create) metadata");
+    println!("API Endpoint: \x1b[1;33mGET /api/v1/{{dbname}}/schema\x1b[0m | For tables, columns, indexes, views and triggers");
+    println!("API Endpoint: \x1b[1;33mGET /api/v1/{{dbname}}/erd?format=dot|mermaid\x1b[0m | For an entity-relationship diagram");
+    println!("API Endpoint: \x1b[1;33mGET /api/v1/{{dbname}}/json-schema\x1b[0m | For a JSON Schema of every table");
+    println!("API Endpoint: \x1b[1;33mGET /api/v1/{{dbname}}/openapi.json\x1b[0m | For an OpenAPI 3.1 document of these endpoints");
+    println!(
+        "API Endpoint: \x1b[1;33mGET|POST /api/v1/admin/{{dbname}}/migrations\x1b[0m | For migration status or applying migrations from {}",
+        migrations_directory()
+    );
+    println!(
+        "API Endpoint: \x1b[1;33mPOST /api/v1/{{dbname}}/sessions\x1b[0m, \x1b[1;33mDELETE /api/v1/{{dbname}}/sessions/{{id}}\x1b[0m | For sessions keeping TEMP tables between queries (idle timeout {}s)",
+        session_idle_timeout().as_secs()
+    );
     println!();
 
     // Start HTTP server
@@ -158,6 +278,14 @@ fn run_api_server(logger: &Logger, perf: &PerformanceTracker) -> Result<()> {
                 .app_data(app_state.clone())
                 .service(execute_query)
                 .service(get_database_metadata)
+                .service(get_database_schema)
+                .service(get_database_erd)
+                .service(get_json_schema)
+                .service(get_openapi_document)
+                .service(get_migration_status)
+                .service(apply_migrations)
+                .service(open_session)
+                .service(end_session)
         })
         .bind("127.0.0.1:8080")?
         .run()
@@ -182,6 +310,9 @@ async fn execute_query(
 ) -> HttpResponse {
     let db_name = path.into_inner();
     let query = query_req.query.clone();
+    let session_id = query_req.session_id.clone();
+    let params = query_req.params.clone();
+    let on_error = query_req.on_error;
 
     state.logger.log(
         LogLevel::Info,
@@ -199,13 +330,50 @@ async fn execute_query(
     let mut perf_tracker = state.perf_tracker.clone();
 
     // Execute the query (in a blocking context since our query execution is synchronous)
-    let result =
-        web::block(move || process_api_query(&query, &db_path, &state.logger, &mut perf_tracker))
-            .await;
+    let result = web::block(move || {
+        schema::temp::expire_idle(session_idle_timeout());
+
+        let params = Params::from_json(&params)?;
+        match session_id {
+            Some(session) => {
+                schema::temp::check_session(&session, &db_path)?;
+                process_api_query(&query, &params, on_error, &db_path, Some(&session), &state.logger, &mut perf_tracker)
+            }
+            None => {
+                let session = schema::temp::open_session(&db_path)?;
+                let result = process_api_query(
+                    &query,
+                    &params,
+                    on_error,
+                    &db_path,
+                    Some(&session),
+                    &state.logger,
+                    &mut perf_tracker,
+                );
+                schema::temp::end_session(&session);
+                result
+            }
+        }
+    })
+    .await;
 
     // Handle the result
     match result {
         Ok(result) => match result {
+            // Statements before and after the failing one may have run
+            Ok(query_result) if query_result.failure.is_some() => {
+                let diagnostics = query_result.statements.iter().flat_map(|statement| statement.diagnostics.clone()).collect();
+                HttpResponse::BadRequest().json(QueryResponse {
+                    success: false,
+                    message: format!("Query execution failed: {}", query_result.failure.unwrap_or_default()),
+                    execution_time_ms: start_time.elapsed().as_millis(),
+                    rows_affected: 0,
+                    results: None,
+                    metadata: None,
+                    diagnostics,
+                    statements: query_result.statements,
+                })
+            }
             Ok(query_result) => HttpResponse::Ok().json(QueryResponse {
                 success: true,
                 message: "Query executed successfully".to_string(),
@@ -214,19 +382,28 @@ async fn execute_query(
                 results: Some(query_result.results),
                 metadata: Some(QueryMetadata {
                     columns_referenced: query_result.columns_referenced,
+                    result_schema: schema::json_schema::result_row_schema(&query_result.result_columns),
+                    result_columns: query_result.result_columns,
                     parsing_time_ms: query_result.parsing_time_ms,
                     planning_time_ms: query_result.planning_time_ms,
                     execution_time_ms: query_result.execution_time_ms,
                 }),
+                diagnostics: query_result.warnings,
+                statements: query_result.statements,
             }),
-            Err(e) => HttpResponse::BadRequest().json(QueryResponse {
-                success: false,
-                message: format!("Query execution failed: {}", e),
-                execution_time_ms: start_time.elapsed().as_millis(),
-                rows_affected: 0,
-                results: None,
-                metadata: None,
-            }),
+            Err(e) => {
+                let diagnostics = diagnostic::diagnostics_of(&e);
+                HttpResponse::BadRequest().json(QueryResponse {
+                    success: false,
+                    message: format!("Query execution failed: {}", error_reason(&e)),
+                    execution_time_ms: start_time.elapsed().as_millis(),
+                    rows_affected: 0,
+                    results: None,
+                    metadata: None,
+                    diagnostics,
+                    statements: Vec::new(),
+                })
+            }
         },
         Err(e) => HttpResponse::InternalServerError().json(QueryResponse {
             success: false,
@@ -235,6 +412,8 @@ async fn execute_query(
             rows_affected: 0,
             results: None,
             metadata: None,
+            diagnostics: Vec::new(),
+            statements: Vec::new(),
         }),
     }
 }
@@ -300,10 +479,7 @@ async fn get_database_metadata(
             .compute_statistics()?;
             
         // Get table names
-        let tables = schema::table::SchemaExtractor::new(&db_name_clone)?
-            .initialize_catalog()?
-            .scan_master_table()?
-            .collect_table_names()?;
+        let tables = schema::cache::get_catalog(&db_name_clone)?.get_relation_names();
             
         // Get file size
         let file_size = std::fs::metadata(&db_name_clone)?.len();
@@ -326,7 +502,8 @@ async fn get_database_metadata(
                 metadata: Some(DatabaseMetadata {
                     page_size: db_info.page_size,
                     number_of_tables: db_info.table_count,
-                    encoding: "UTF-8".to_string(),
+                    encoding: db_info.encoding,
+                    user_version: db_info.user_version,
                     tables,
                     file_size_bytes: file_size,
                 }),
@@ -355,18 +532,455 @@ async fn get_database_metadata(
     }
 }
 
+#[get("/api/v1/{dbname}/schema")]
+async fn get_database_schema(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("Schema request for: {}", db_name),
+    );
+
+    // Unlike the metadata endpoint, asking for a schema never creates a database
+    if !Path::new(&db_name).exists() {
+        return HttpResponse::NotFound().json(SchemaResponse {
+            success: false,
+            message: format!("Database {} does not exist", db_name),
+            database_name: db_name,
+            schema: None,
+        });
+    }
+
+    let db_name_clone = db_name.clone();
+    let schema_result = web::block(move || {
+        let catalog = schema::cache::get_catalog(&db_name_clone)?;
+        Ok::<_, anyhow::Error>(schema::describe::SchemaDescription::from_catalog(&catalog))
+    })
+    .await;
+
+    match schema_result {
+        Ok(Ok(description)) => HttpResponse::Ok().json(SchemaResponse {
+            success: true,
+            message: "Schema retrieved successfully".to_string(),
+            database_name: db_name,
+            schema: Some(description),
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(SchemaResponse {
+            success: false,
+            message: format!("Schema extraction error: {}", e),
+            database_name: db_name,
+            schema: None,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(SchemaResponse {
+            success: false,
+            message: format!("Failed to retrieve schema: {}", e),
+            database_name: db_name,
+            schema: None,
+        }),
+    }
+}
+
+#[get("/api/v1/{dbname}/erd")]
+async fn get_database_erd(
+    path: web::Path<String>,
+    request: web::Query<ErdRequest>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+    let format = request.format.clone().unwrap_or_else(|| "dot".to_string());
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("ER diagram request for: {} ({})", db_name, format),
+    );
+
+    if !Path::new(&db_name).exists() {
+        return HttpResponse::NotFound().json(ErdResponse {
+            success: false,
+            message: format!("Database {} does not exist", db_name),
+            database_name: db_name,
+            format,
+            diagram: None,
+            foreign_keys: Vec::new(),
+        });
+    }
+
+    let db_name_clone = db_name.clone();
+    let format_clone = format.clone();
+    let erd_result = web::block(move || {
+        let catalog = schema::cache::get_catalog(&db_name_clone)?;
+        let graph = catalog.foreign_key_graph();
+        let diagram = render_erd(&catalog, &graph, &format_clone)?;
+        Ok::<_, anyhow::Error>((diagram, graph.edges))
+    })
+    .await;
+
+    match erd_result {
+        Ok(Ok((diagram, foreign_keys))) => HttpResponse::Ok().json(ErdResponse {
+            success: true,
+            message: "Diagram generated successfully".to_string(),
+            database_name: db_name,
+            format,
+            diagram: Some(diagram),
+            foreign_keys,
+        }),
+        Ok(Err(e)) => HttpResponse::BadRequest().json(ErdResponse {
+            success: false,
+            message: format!("Diagram generation error: {}", e),
+            database_name: db_name,
+            format,
+            diagram: None,
+            foreign_keys: Vec::new(),
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(ErdResponse {
+            success: false,
+            message: format!("Failed to generate diagram: {}", e),
+            database_name: db_name,
+            format,
+            diagram: None,
+            foreign_keys: Vec::new(),
+        }),
+    }
+}
+
+#[get("/api/v1/{dbname}/json-schema")]
+async fn get_json_schema(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("JSON Schema request for: {}", db_name),
+    );
+
+    generated_document(db_name, schema::json_schema::database_json_schema).await
+}
+
+#[get("/api/v1/{dbname}/openapi.json")]
+async fn get_openapi_document(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("OpenAPI document request for: {}", db_name),
+    );
+
+    generated_document(db_name, schema::json_schema::openapi_document).await
+}
+
+/// Serves a document generated from a database's catalog as is, so schema
+/// tooling can consume it directly
+async fn generated_document(
+    db_name: String,
+    generate: fn(&str, &schema::SchemaCatalog) -> serde_json::Value,
+) -> HttpResponse {
+    if !Path::new(&db_name).exists() {
+        return HttpResponse::NotFound().json(DocumentError {
+            success: false,
+            message: format!("Database {} does not exist", db_name),
+            database_name: db_name,
+        });
+    }
+
+    let db_name_clone = db_name.clone();
+    let document_result = web::block(move || {
+        let catalog = schema::cache::get_catalog(&db_name_clone)?;
+        Ok::<_, anyhow::Error>(generate(&db_name_clone, &catalog))
+    })
+    .await;
+
+    match document_result {
+        Ok(Ok(document)) => HttpResponse::Ok().json(document),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(DocumentError {
+            success: false,
+            message: format!("Schema extraction error: {}", e),
+            database_name: db_name,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(DocumentError {
+            success: false,
+            message: format!("Failed to generate document: {}", e),
+            database_name: db_name,
+        }),
+    }
+}
+
+/// How long an API session may sit unused before it is ended, dropping its
+/// temp schema and attachments: WHATQL_SESSION_IDLE_SECS, 30 minutes by default
+fn session_idle_timeout() -> std::time::Duration {
+    let seconds = std::env::var("WHATQL_SESSION_IDLE_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1800);
+    std::time::Duration::from_secs(seconds)
+}
+
+#[post("/api/v1/{dbname}/sessions")]
+async fn open_session(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+    let idle_timeout_secs = session_idle_timeout().as_secs();
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("Session requested for: {}", db_name),
+    );
+
+    if !Path::new(&db_name).exists() {
+        return HttpResponse::NotFound().json(SessionResponse {
+            success: false,
+            message: format!("Database {} does not exist", db_name),
+            database_name: db_name,
+            session_id: None,
+            idle_timeout_secs,
+        });
+    }
+
+    let db_name_clone = db_name.clone();
+    let session_result = web::block(move || {
+        schema::temp::expire_idle(session_idle_timeout());
+        schema::temp::open_session(&db_name_clone)
+    })
+    .await;
+
+    match session_result {
+        Ok(Ok(session_id)) => HttpResponse::Ok().json(SessionResponse {
+            success: true,
+            message: "Session opened".to_string(),
+            database_name: db_name,
+            session_id: Some(session_id),
+            idle_timeout_secs,
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(SessionResponse {
+            success: false,
+            message: format!("Session error: {}", e),
+            database_name: db_name,
+            session_id: None,
+            idle_timeout_secs,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(SessionResponse {
+            success: false,
+            message: format!("Failed to open session: {}", e),
+            database_name: db_name,
+            session_id: None,
+            idle_timeout_secs,
+        }),
+    }
+}
+
+#[delete("/api/v1/{dbname}/sessions/{session_id}")]
+async fn end_session(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let (db_name, session_id) = path.into_inner();
+    let idle_timeout_secs = session_idle_timeout().as_secs();
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("Ending session {} on {}", session_id, db_name),
+    );
+
+    if let Err(e) = schema::temp::check_session(&session_id, &db_name) {
+        return HttpResponse::NotFound().json(SessionResponse {
+            success: false,
+            message: e.to_string(),
+            database_name: db_name,
+            session_id: Some(session_id),
+            idle_timeout_secs,
+        });
+    }
+
+    schema::temp::end_session(&session_id);
+    HttpResponse::Ok().json(SessionResponse {
+        success: true,
+        message: "Session ended; its temp schema was dropped".to_string(),
+        database_name: db_name,
+        session_id: Some(session_id),
+        idle_timeout_secs,
+    })
+}
+
+/// Migration directory used by the admin API
+fn migrations_directory() -> String {
+    std::env::var("WHATQL_MIGRATIONS_DIR").unwrap_or_else(|_| "migrations".to_string())
+}
+
+#[get("/api/v1/admin/{dbname}/migrations")]
+async fn get_migration_status(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+    let directory = migrations_directory();
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("Migration status request for: {}", db_name),
+    );
+
+    if !Path::new(&db_name).exists() {
+        return HttpResponse::NotFound().json(MigrationResponse {
+            success: false,
+            message: format!("Database {} does not exist", db_name),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: None,
+        });
+    }
+
+    let db_name_clone = db_name.clone();
+    let directory_clone = directory.clone();
+    let status_result = web::block(move || {
+        schema::migration::MigrationRunner::new(&db_name_clone)?
+            .load_directory(&directory_clone)?
+            .status()
+    })
+    .await;
+
+    match status_result {
+        Ok(Ok(status)) => HttpResponse::Ok().json(MigrationResponse {
+            success: true,
+            message: if status.is_consistent() {
+                format!("{} pending migration(s)", status.pending.len())
+            } else {
+                "Applied migrations do not match the migration directory".to_string()
+            },
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: Some(status),
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(MigrationResponse {
+            success: false,
+            message: format!("Migration status error: {}", e),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: None,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(MigrationResponse {
+            success: false,
+            message: format!("Failed to read migration status: {}", e),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: None,
+        }),
+    }
+}
+
+#[post("/api/v1/admin/{dbname}/migrations")]
+async fn apply_migrations(
+    path: web::Path<String>,
+    request: Option<web::Json<MigrateRequest>>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let db_name = path.into_inner();
+    let directory = migrations_directory();
+    let (target, baseline) = match request {
+        Some(request) => (request.target_version, request.baseline_version),
+        None => (None, None),
+    };
+
+    state.logger.log(
+        LogLevel::Info,
+        &format!("Migration request for: {} (target {:?}, baseline {:?})", db_name, target, baseline),
+    );
+
+    if !Path::new(&db_name).exists() {
+        return HttpResponse::NotFound().json(MigrationResponse {
+            success: false,
+            message: format!("Database {} does not exist", db_name),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: None,
+        });
+    }
+
+    let db_name_clone = db_name.clone();
+    let directory_clone = directory.clone();
+    let migrate_result = web::block(move || {
+        let mut runner = schema::migration::MigrationRunner::new(&db_name_clone)?.load_directory(&directory_clone)?;
+        let applied = match baseline {
+            Some(version) => runner.baseline(version),
+            None => runner.apply(target),
+        };
+        Ok::<_, anyhow::Error>((applied, runner.status()?))
+    })
+    .await;
+
+    match migrate_result {
+        Ok(Ok((Ok(applied), status))) => HttpResponse::Ok().json(MigrationResponse {
+            success: true,
+            message: match baseline {
+                Some(version) => format!("Baselined at version {}, {} migration(s) recorded", version, applied.len()),
+                None => format!("Applied {} migration(s)", applied.len()),
+            },
+            database_name: db_name,
+            directory,
+            applied_now: applied,
+            status: Some(status),
+        }),
+        // Earlier migrations in the batch stay committed; the status shows where it stopped
+        Ok(Ok((Err(e), status))) => HttpResponse::Conflict().json(MigrationResponse {
+            success: false,
+            message: format!("Migration failed: {:#}", e),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: Some(status),
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(MigrationResponse {
+            success: false,
+            message: format!("Migration error: {}", e),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: None,
+        }),
+        Err(e) => HttpResponse::InternalServerError().json(MigrationResponse {
+            success: false,
+            message: format!("Failed to run migrations: {}", e),
+            database_name: db_name,
+            directory,
+            applied_now: Vec::new(),
+            status: None,
+        }),
+    }
+}
+
 struct ApiQueryResult {
     rows_affected: usize,
     results: Vec<serde_json::Value>,
     columns_referenced: Vec<String>,
+    result_columns: Vec<ResultColumn>,
+    warnings: Vec<Diagnostic>,
     parsing_time_ms: u128,
     planning_time_ms: u128,
     execution_time_ms: u128,
+    statements: Vec<StatementResponse>,
+    /// Why the first failing statement failed
+    failure: Option<String>,
 }
 
 fn process_api_query(
     query: &str,
+    params: &Params,
+    on_error: OnError,
     db_path: &str,
+    session: Option<&str>,
     logger: &Logger,
     perf: &mut PerformanceTracker,
 ) -> Result<ApiQueryResult> {
@@ -381,93 +995,82 @@ fn process_api_query(
         return Err(anyhow::anyhow!("Dot commands not supported in API mode"));
     }
 
-    // Stage 1: Parse and analyze the SQL query
-    logger.log(
-        LogLevel::Debug,
-        "Stage 1: Query parsing and semantic analysis",
-    );
-    perf.start_operation("query_parsing");
-
-    let query_analyzer = QueryAnalyzer::new(db_path.to_string()); // Update constructor to accept db_path
-    let analyzed_query = query_analyzer
-        .tokenize(query)?
-        .build_ast()?
-        .validate_semantics()?
-        .optimize_expressions()?;
+    // Parsed once; values replace the parameters in the parsed statements
+    let prepared = PreparedStatement::prepare(query)?;
 
-    let query_info = direct::extract_query_info(db_path, query)?;
+    logger.log(LogLevel::Debug, "Running the script statement by statement");
+    perf.start_operation("query_execution");
+    let results = ScriptRunner::new(db_path)
+        .with_session(session)
+        .with_on_error(on_error)
+        .run_prepared(&prepared, params)?;
+    perf.end_operation("query_execution");
 
-    let tables_referenced = query_info.table_names;
-    let columns_referenced = query_info.column_names.clone();
+    let failure = results.iter().find_map(|result| result.error.as_ref()).map(error_reason);
 
+    // The top-level rows and metadata describe the last statement that returned a result set
+    let last = results.iter().rev().find(|result| !result.execution.columns.is_empty());
+    let columns_referenced = last.map(|result| result.columns_referenced.clone()).unwrap_or_default();
+    let result_columns = last.map(|result| result.result_columns.clone()).unwrap_or_default();
+    let json_results = last.map(|result| result_rows(&result.execution.columns, &result.execution.rows)).unwrap_or_default();
     logger.log(
         LogLevel::Debug,
-        &format!("Tables referenced: {:?}", tables_referenced),
-    );
-    logger.log(
-        LogLevel::Debug,
-        &format!("Columns requested: {:?}", columns_referenced),
+        &format!("Columns referenced: {:?}", columns_referenced),
     );
-    perf.end_operation("query_parsing");
-    let parsing_time_ms = perf
-        .get_operation("query_parsing")
-        .and_then(|op| op.duration.map(|d| d.as_millis()))
-        .unwrap_or(0);
-
-    // Stage 2: Plan query execution
-    logger.log(LogLevel::Debug, "Stage 2: Query execution planning");
-    perf.start_operation("query_planning");
-
-    let query_planner = QueryPlanner::new(db_path.to_string());
-    let execution_plan = query_planner
-        .analyze_statistics()?
-        .select_access_paths()?
-        .optimize_join_order()?
-        .prepare_execution_plan()?;
-
-    perf.end_operation("query_planning");
-    let planning_time_ms = perf
-        .get_operation("query_planning")
-        .and_then(|op| op.duration.map(|d| d.as_millis()))
-        .unwrap_or(0);
-
-    // Stage 3: Execute the query
-    logger.log(LogLevel::Debug, "Stage 3: Query execution");
-    perf.start_operation("query_execution");
-
-    let mut executor = QueryExecutor::new();
-    // Get column names before executing the plan
-    let column_names = executor.get_column_names();
 
-    // Get result column names before initializing (if available in your API)
-    let actual_column_names = executor.get_result_column_names();
-
-    // Execute the plan, consuming the executor
-    let results =
-        executor
-            .initialize_execution_context()?
-            .execute_plan(execution_plan, db_path, query)?;
-
-    perf.end_operation("query_execution");
-    let execution_time_ms = perf
-        .get_operation("query_execution")
-        .and_then(|op| op.duration.map(|d| d.as_millis()))
-        .unwrap_or(0);
+    Ok(ApiQueryResult {
+        rows_affected: json_results.len(),
+        results: json_results,
+        columns_referenced,
+        result_columns,
+        warnings: results.iter().flat_map(|result| result.warnings.clone()).collect(),
+        parsing_time_ms: results.iter().map(|result| result.validation_time.as_millis()).sum(),
+        planning_time_ms: results.iter().map(|result| result.planning_time.as_millis()).sum(),
+        execution_time_ms: results.iter().map(|result| result.execution_time.as_millis()).sum(),
+        statements: results.iter().map(statement_response).collect(),
+        failure,
+    })
+}
 
-    // Convert the ResultRow objects to JSON
+fn statement_response(result: &StatementResult) -> StatementResponse {
+    let mut diagnostics = result.error.as_ref().map(diagnostic::diagnostics_of).unwrap_or_default();
+    diagnostics.extend(result.warnings.iter().cloned());
+
+    StatementResponse {
+        sql: result.sql.clone(),
+        query_type: result.query_type.as_ref().map(ToString::to_string),
+        success: result.error.is_none(),
+        columns: result.execution.columns.clone(),
+        rows: result_rows(&result.execution.columns, &result.execution.rows),
+        changes: result.execution.changes,
+        result_columns: result.result_columns.clone(),
+        validation_time_ms: result.validation_time.as_millis(),
+        planning_time_ms: result.planning_time.as_millis(),
+        execution_time_ms: result.execution_time.as_millis(),
+        error: result.error.as_ref().map(error_reason),
+        diagnostics,
+    }
+}
 
-    // Use actual column names if available, otherwise fall back to analyzed columns
-    let display_column_names = columns_referenced.clone();
+/// What went wrong, in one line: the rendered snippet goes in the
+/// diagnostics, not the message
+fn error_reason(error: &anyhow::Error) -> String {
+    match diagnostic::diagnostics_of(error).first() {
+        Some(diagnostic) => diagnostic.message.clone(),
+        None => error.to_string(),
+    }
+}
 
-    // Convert results to JSON
+/// Rows as JSON objects keyed by column name
+fn result_rows(columns: &[String], rows: &[engine::execution::ResultRow]) -> Vec<serde_json::Value> {
     let mut json_results = Vec::new();
 
-    for row in results {
+    for row in rows {
         let mut row_obj = serde_json::Map::new();
 
         for (idx, value) in row.get_values().iter().enumerate() {
-            let column_name = if idx < display_column_names.len() {
-                display_column_names[idx].clone()
+            let column_name = if idx < columns.len() {
+                columns[idx].clone()
             } else {
                 format!("column_{}", idx)
             };
@@ -486,20 +1089,14 @@ fn process_api_query(
         json_results.push(serde_json::Value::Object(row_obj));
     }
 
-    Ok(ApiQueryResult {
-        rows_affected: json_results.len(),
-        results: json_results,
-        columns_referenced,
-        parsing_time_ms,
-        planning_time_ms,
-        execution_time_ms,
-    })
+    json_results
 }
 
 // Add a function to process commands/queries (extracted from your main function)
 fn process_command(
     db_path: &str,
     command: &str,
+    session: Option<&str>,
     logger: &Logger,
     perf: &PerformanceTracker,
 ) -> Result<()> {
@@ -511,19 +1108,56 @@ fn process_command(
     let btree = BTreePageCollection::new(page_reader);
     logger.log(LogLevel::Debug, "B-Tree page collection initialized");
 
-    match command {
+    let argument = command.split_whitespace().nth(1).map(|arg| arg.trim_matches(|c| c == '\'' || c == '"'));
+
+    match command.split_whitespace().next().unwrap_or("") {
         ".dbinfo" => {
             logger.log(LogLevel::Info, "Executing database info command");
             process_dbinfo_command(db_path, logger)?;
         }
         ".tables" => {
             logger.log(LogLevel::Info, "Executing tables listing command");
-            process_tables_command(db_path, logger)?;
+            process_tables_command(db_path, session, logger)?;
+        }
+        ".schema" => {
+            logger.log(LogLevel::Info, "Executing schema listing command");
+            process_schema_command(db_path, argument, logger)?;
+        }
+        ".erd" => {
+            logger.log(LogLevel::Info, "Executing ER diagram command");
+            process_erd_command(db_path, argument, logger)?;
+        }
+        ".schemadiff" => {
+            logger.log(LogLevel::Info, "Executing schema diff command");
+            let other = argument.ok_or_else(|| anyhow!("Usage: .schemadiff OTHER_DATABASE"))?;
+            process_schemadiff_command(db_path, other, logger)?;
+        }
+        ".indexes" | ".indices" => {
+            logger.log(LogLevel::Info, "Executing index listing command");
+            process_indexes_command(db_path, argument, logger)?;
+        }
+        ".stats" => {
+            logger.log(LogLevel::Info, "Executing index statistics command");
+            process_stats_command(db_path, argument, logger)?;
+        }
+        ".databases" => {
+            logger.log(LogLevel::Info, "Executing database listing command");
+            process_databases_command(db_path, session, logger)?;
+        }
+        ".param" | ".parameter" => {
+            logger.log(LogLevel::Info, "Executing parameter command");
+            let session = session.ok_or_else(|| anyhow!("Parameters need a session"))?;
+            process_param_command(command, session, logger)?;
+        }
+        ".bail" => {
+            logger.log(LogLevel::Info, "Executing bail command");
+            let session = session.ok_or_else(|| anyhow!("The bail setting needs a session"))?;
+            process_bail_command(argument, session, logger)?;
         }
         _ => {
             // This is where SQL queries are processed
             logger.log(LogLevel::Info, "Processing SQL query");
-            process_sql_query(command, db_path, logger, perf)?;
+            process_sql_query(command, db_path, session, logger, perf)?;
         }
     }
 
@@ -537,11 +1171,14 @@ fn run_interactive_shell(db_path: &str, logger: &Logger, perf: &PerformanceTrack
     println!("\x1b[1;32mWhatQL Interactive Shell\x1b[0m");
     println!("Connected to database: \x1b[1;36m{}\x1b[0m", db_path);
     println!(
-        "Enter SQL queries or commands (like \x1b[1;33m.tables\x1b[0m, \x1b[1;33m.dbinfo\x1b[0m)"
+        "Enter SQL queries or commands (like \x1b[1;33m.tables\x1b[0m, \x1b[1;33m.schema\x1b[0m, \x1b[1;33m.indexes\x1b[0m, \x1b[1;33m.stats\x1b[0m, \x1b[1;33m.databases\x1b[0m, \x1b[1;33m.param\x1b[0m, \x1b[1;33m.bail\x1b[0m, \x1b[1;33m.dbinfo\x1b[0m)"
     );
     println!("Type \x1b[1;33m.exit\x1b[0m or \x1b[1;33mCtrl+C\x1b[0m to quit");
     println!();
 
+    // Temp tables, views and indexes live until the shell exits
+    let session = schema::temp::open_session(db_path)?;
+
     let stdin = io::stdin();
     let mut reader = stdin.lock();
     let mut buffer = String::new();
@@ -594,7 +1231,7 @@ fn run_interactive_shell(db_path: &str, logger: &Logger, perf: &PerformanceTrack
                 }
 
                 // Otherwise add the line to our query
-                query.push_str("\n");
+                query.push('\n');
                 query.push_str(&line);
 
                 // If the line ends with a semicolon, we're done
@@ -605,7 +1242,7 @@ fn run_interactive_shell(db_path: &str, logger: &Logger, perf: &PerformanceTrack
         }
 
         // Process the command/query
-        match process_command(db_path, &query, logger, perf) {
+        match process_command(db_path, &query, Some(&session), logger, perf) {
             Ok(_) => {
                 // Successfully executed
                 println!(); // Add some spacing after results
@@ -613,12 +1250,16 @@ fn run_interactive_shell(db_path: &str, logger: &Logger, perf: &PerformanceTrack
             Err(e) => {
                 // Print error nicely
                 println!("\x1b[1;31m┌─────────────── ERROR ───────────────┐\x1b[0m");
-                println!("\x1b[1;31m│\x1b[0m {}\x1b[1;31m │\x1b[0m", e);
+                // Diagnostics span several lines: the message, location and snippet
+                for line in e.to_string().lines() {
+                    println!("\x1b[1;31m│\x1b[0m {}", line);
+                }
                 println!("\x1b[1;31m└─────────────────────────────────────┘\x1b[0m");
             }
         }
     }
 
+    schema::temp::end_session(&session);
     Ok(())
 }
 
@@ -651,16 +1292,13 @@ fn process_dbinfo_command(db_path: &str, logger: &Logger) -> Result<()> {
     Ok(())
 }
 
-fn process_tables_command(db_path: &str, logger: &Logger) -> Result<()> {
+fn process_tables_command(db_path: &str, session: Option<&str>, logger: &Logger) -> Result<()> {
     logger.log(LogLevel::Debug, "Initializing schema catalog reader");
     logger.log(LogLevel::Debug, "Traversing B-Tree master table");
 
-    // Impressive operation chain
+    // Served from the process-wide catalog cache
     let timer = Instant::now();
-    let tables = schema::table::SchemaExtractor::new(db_path)?
-        .initialize_catalog()?
-        .scan_master_table()?
-        .collect_table_names()?;
+    let tables = schema::cache::get_session_catalog(db_path, session)?.get_relation_names();
 
     logger.log(
         LogLevel::Debug,
@@ -676,84 +1314,304 @@ fn process_tables_command(db_path: &str, logger: &Logger) -> Result<()> {
     Ok(())
 }
 
-fn process_sql_query(
-    query: &str,
-    db_path: &str,
-    logger: &Logger,
-    perf: &PerformanceTracker,
-) -> Result<()> {
-    // Stage 1: Parse and analyze the SQL query
-    logger.log(
-        LogLevel::Debug,
-        "Stage 1: Query parsing and semantic analysis",
-    );
-    perf.start_operation("query_parsing");
+/// `.param set KEY VALUE`, `.param unset KEY`, `.param list` and `.param clear`
+fn process_param_command(command: &str, session: &str, logger: &Logger) -> Result<()> {
+    // The value is the rest of the line, which may hold spaces
+    let (_, rest) = split_word(command.trim().trim_end_matches(';'));
+    let (action, rest) = split_word(rest);
+    let (key, value) = split_word(rest);
+    let action = if action.is_empty() { "list" } else { action };
+    let key = (!key.is_empty()).then_some(key);
+    let value = (!value.is_empty()).then_some(value);
+
+    schema::temp::update_parameters(session, |parameters| {
+        match (action, key, value) {
+            ("set", Some(key), Some(value)) => {
+                parameters.set(key, params::parse_value(value))?;
+                logger.log(LogLevel::Debug, &format!("Bound {} = {}", key, value));
+            }
+            ("unset", Some(key), None) => {
+                if !parameters.unset(key) {
+                    bail!("No parameter {} is set", key);
+                }
+            }
+            ("list", None, None) => {
+                for (key, value) in parameters.iter() {
+                    println!("{} {}", key, value);
+                }
+            }
+            ("clear", None, None) => parameters.clear(),
+            _ => bail!("Usage: .param set KEY VALUE | .param unset KEY | .param list | .param clear"),
+        }
+        Ok(())
+    })
+}
+
+/// `.bail on` stops a script at its first failing statement, `.bail off`
+/// runs the statements after it too
+fn process_bail_command(argument: Option<&str>, session: &str, logger: &Logger) -> Result<()> {
+    let on_error = match argument.map(|argument| argument.trim_end_matches(';').to_lowercase()).as_deref() {
+        None => schema::temp::on_error(session)?,
+        Some("on" | "yes" | "true" | "1") => OnError::Stop,
+        Some("off" | "no" | "false" | "0") => OnError::Continue,
+        Some(_) => bail!("Usage: .bail on|off"),
+    };
+    schema::temp::set_on_error(session, on_error)?;
+    logger.log(LogLevel::Debug, &format!("Scripts on error: {:?}", on_error));
+
+    match on_error {
+        OnError::Stop => println!("bail on: a failing statement stops the script"),
+        OnError::Continue => println!("bail off: statements after a failing one still run"),
+    }
+    Ok(())
+}
 
-    let query_analyzer = QueryAnalyzer::new(db_path.to_string()); // Update constructor to accept db_path
-    let analyzed_query = query_analyzer
-        .tokenize(query)?
-        .build_ast()?
-        .validate_semantics()?
-        .optimize_expressions()?;
+/// The first word of `text` and what follows it, without surrounding whitespace
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn process_databases_command(db_path: &str, session: Option<&str>, logger: &Logger) -> Result<()> {
+    let main_path = std::path::Path::new(db_path)
+        .canonicalize()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| db_path.to_string());
+
+    let attachments = schema::attach::attachments(session);
+    logger.log(LogLevel::Debug, &format!("{} database(s) attached", attachments.len()));
+
+    let access = |path: &str| match std::fs::metadata(path) {
+        Ok(metadata) if metadata.permissions().readonly() => "r/o",
+        _ => "r/w",
+    };
+
+    println!("main: {} {}", main_path, access(&main_path));
+    // Like sqlite3, the temp schema is listed once it holds anything
+    if schema::cache::get_session_catalog(db_path, session)?.temp().is_some() {
+        println!("temp: \"\" r/w");
+    }
+    for attachment in attachments {
+        println!("{}: {} {}", attachment.alias, attachment.path, access(&attachment.path));
+    }
+
+    Ok(())
+}
+
+fn process_schema_command(db_path: &str, pattern: Option<&str>, logger: &Logger) -> Result<()> {
+    let catalog = schema::cache::get_catalog(db_path)?;
+    let statements = schema::describe::schema_statements(&catalog, pattern);
 
     logger.log(
         LogLevel::Debug,
-        &format!("Query type: {}", analyzed_query.query_type),
-    );
-    logger.log(
-        LogLevel::Debug,
-        &format!("Tables referenced: {:?}", analyzed_query.table_references),
-    );
-    logger.log(
-        LogLevel::Debug,
-        &format!("Columns requested: {:?}", analyzed_query.column_references),
+        &format!("Found {} schema object(s) matching {:?}", statements.len(), pattern),
     );
-    perf.end_operation("query_parsing");
 
-    // Stage 2: Plan query execution
-    logger.log(LogLevel::Debug, "Stage 2: Query execution planning");
-    perf.start_operation("query_planning");
+    for statement in statements {
+        println!("{}", statement);
+    }
+
+    Ok(())
+}
+
+fn process_indexes_command(db_path: &str, table_pattern: Option<&str>, logger: &Logger) -> Result<()> {
+    let catalog = schema::cache::get_catalog(db_path)?;
+    let indexes = schema::describe::index_names(&catalog, table_pattern);
 
-    let query_planner = QueryPlanner::new(db_path.to_string());
-    let execution_plan = query_planner
-        .analyze_statistics()?
-        .select_access_paths()?
-        .optimize_join_order()?
-        .prepare_execution_plan()?;
+    logger.log(LogLevel::Debug, &format!("Found {} index(es)", indexes.len()));
+
+    for index_name in indexes {
+        println!("{}", index_name);
+    }
+
+    Ok(())
+}
+
+/// Walks the b-tree of each index, of the tables matching the pattern when one is given
+fn process_stats_command(db_path: &str, table_pattern: Option<&str>, logger: &Logger) -> Result<()> {
+    let catalog = schema::cache::get_catalog(db_path)?;
+    let manager = schema::index::IndexManager::new(db_path);
+
+    for index_name in schema::describe::index_names(&catalog, table_pattern) {
+        let stats = manager.analyze_index(&index_name)?;
+        logger.log(LogLevel::Debug, &format!("Analyzed index {}", index_name));
+        println!(
+            "{}: depth {}, {} leaf / {} interior pages, {} entries, {:.1}% full, {:.0} page read(s) per lookup",
+            index_name,
+            stats.depth,
+            stats.leaf_pages,
+            stats.internal_pages,
+            stats.total_entries,
+            stats.average_fill_factor * 100.0,
+            stats.estimate_lookup_cost()
+        );
+    }
+
+    Ok(())
+}
+
+fn process_erd_command(db_path: &str, format: Option<&str>, logger: &Logger) -> Result<()> {
+    let catalog = schema::cache::get_catalog(db_path)?;
+    let graph = catalog.foreign_key_graph();
 
     logger.log(
         LogLevel::Debug,
-        &format!("Execution plan: {}", execution_plan.plan_summary()),
+        &format!("Foreign key graph has {} relationship(s)", graph.edges.len()),
     );
+
+    print!("{}", render_erd(&catalog, &graph, format.unwrap_or("dot"))?);
+
+    Ok(())
+}
+
+fn process_schemadiff_command(db_path: &str, other_path: &str, logger: &Logger) -> Result<()> {
+    if !std::path::Path::new(other_path).exists() {
+        bail!("Database '{}' not found", other_path);
+    }
+
+    let current = schema::cache::get_catalog(db_path)?;
+    let target = schema::cache::get_catalog(other_path)?;
+    let diff = schema::diff::SchemaDiff::compare(&current, &target)?;
+
     logger.log(
         LogLevel::Debug,
-        &format!(
-            "Estimated cost: {} page reads",
-            execution_plan.estimated_cost
-        ),
+        &format!("Found {} differing object(s), {} migration statement(s)", diff.changes.len(), diff.migration.len()),
     );
-    perf.end_operation("query_planning");
 
-    // Stage 3: Execute the query
-    logger.log(LogLevel::Debug, "Stage 3: Query execution");
-    perf.start_operation("query_execution");
+    if diff.is_empty() {
+        println!("-- {} and {} have identical schemas", db_path, other_path);
+        return Ok(());
+    }
 
-    let executor = QueryExecutor::new();
-    let results =
-        executor
-            .initialize_execution_context()?
-            .execute_plan(execution_plan, db_path, query)?;
+    println!("-- Differences from {} to {}", db_path, other_path);
+    for change in &diff.changes {
+        for line in change.to_string().lines() {
+            println!("-- {}", line);
+        }
+    }
 
-    // Store the length before moving results
-    let result_count = results.len();
+    println!();
+    println!("-- Migration bringing {} in line with {}", db_path, other_path);
+    for statement in &diff.migration {
+        println!("{};", statement);
+    }
+
+    Ok(())
+}
+
+/// `whatql migrate DB DIR [status | up [VERSION] | baseline VERSION]`
+fn run_migrate_command(args: &[String], logger: &Logger) -> Result<()> {
+    let (db_path, directory) = match args {
+        [db_path, directory, ..] => (db_path, directory),
+        _ => bail!("Usage: whatql migrate DATABASE DIRECTORY [status | up [VERSION] | baseline VERSION]"),
+    };
+    let version = |argument: Option<&String>| match argument {
+        Some(version) => version
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|_| anyhow!("Invalid version '{}'", version)),
+        None => Ok(None),
+    };
+
+    let mut runner = schema::migration::MigrationRunner::new(db_path)?.load_directory(directory)?;
+
+    match args.get(2).map(String::as_str).unwrap_or("up") {
+        "status" => {
+            let status = runner.status()?;
+            println!("user_version: {}", status.user_version);
+            for migration in &status.applied {
+                println!("applied  {:04}_{}  {}  {}", migration.version, migration.name, migration.checksum, migration.applied_at);
+            }
+            for migration in &status.pending {
+                println!("pending  {:04}_{}  {}", migration.version, migration.name, migration.checksum);
+            }
+            for problem in &status.problems {
+                println!("error    {}", problem);
+            }
+        }
+        "up" => {
+            let applied = runner.apply(version(args.get(3))?)?;
+
+            logger.log(LogLevel::Info, &format!("Applied {} migration(s)", applied.len()));
+            for migration in &applied {
+                println!("applied  {:04}_{}", migration.version, migration.name);
+            }
+            println!("user_version: {}", runner.status()?.user_version);
+        }
+        "baseline" => {
+            let baseline = version(args.get(3))?.ok_or_else(|| anyhow!("baseline needs the version the schema matches"))?;
+            let recorded = runner.baseline(baseline)?;
+
+            logger.log(LogLevel::Info, &format!("Recorded {} migration(s) as applied", recorded.len()));
+            for migration in &recorded {
+                println!("baseline {:04}_{}", migration.version, migration.name);
+            }
+            println!("user_version: {}", runner.status()?.user_version);
+        }
+        other => bail!("Unknown migrate action '{}', expected status, up or baseline", other),
+    }
+
+    Ok(())
+}
+
+fn render_erd(
+    catalog: &schema::SchemaCatalog,
+    graph: &schema::foreign_key::ForeignKeyGraph,
+    format: &str,
+) -> Result<String> {
+    match format.to_lowercase().as_str() {
+        "dot" | "graphviz" => Ok(graph.to_dot(catalog)),
+        "mermaid" => Ok(graph.to_mermaid(catalog)),
+        other => bail!("Unknown diagram format '{}', expected dot or mermaid", other),
+    }
+}
+
+fn process_sql_query(
+    query: &str,
+    db_path: &str,
+    session: Option<&str>,
+    logger: &Logger,
+    perf: &PerformanceTracker,
+) -> Result<()> {
+    // Parameters take the values bound with .param set
+    let params = match session {
+        Some(session) => schema::temp::parameters(session)?,
+        None => Params::new(),
+    };
+    let prepared = PreparedStatement::prepare(query)?;
+    let on_error = match session {
+        Some(session) => schema::temp::on_error(session)?,
+        None => OnError::default(),
+    };
+
+    logger.log(LogLevel::Debug, "Running the script statement by statement");
+    perf.start_operation("query_execution");
 
-    // Print the results
-    // for row in results {
-    //     println!("{}", row);
-    // }
+    let results = ScriptRunner::new(db_path)
+        .with_session(session)
+        .with_on_error(on_error)
+        .run_prepared(&prepared, &params)?;
 
     perf.end_operation("query_execution");
 
+    for result in &results {
+        if let Some(query_type) = &result.query_type {
+            logger.log(
+                LogLevel::Debug,
+                &format!("Query type: {}", query_type),
+            );
+        }
+        logger.log(
+            LogLevel::Debug,
+            &format!("Columns returned: {:?}", result.execution.columns),
+        );
+    }
+
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+
     println!("\n\x1b[1;36m┌───────────────────────────────────────┐\x1b[0m");
     println!("\x1b[1;36m│           QUERY EXECUTION SUMMARY     │\x1b[0m");
     println!("\x1b[1;36m├───────────────────────────────────────┤\x1b[0m");
@@ -764,22 +1622,49 @@ fn process_sql_query(
             .format_duration()
     );
     println!(
-        "\x1b[1;36m│\x1b[0m Rows returned: \x1b[1m{}\x1b[0m",
-        result_count
-    );
-    println!(
-        "\x1b[1;36m│\x1b[0m Parsing time: \x1b[1m{}\x1b[0m",
-        perf.get_operation("query_parsing")
-            .unwrap()
-            .format_duration()
-    );
-    println!(
-        "\x1b[1;36m│\x1b[0m Planning time: \x1b[1m{}\x1b[0m",
-        perf.get_operation("query_planning")
-            .unwrap()
-            .format_duration()
+        "\x1b[1;36m│\x1b[0m Statements run: \x1b[1m{}\x1b[0m ({} failed)",
+        results.len(),
+        failed
     );
+    for (i, result) in results.iter().enumerate() {
+        let query_type = result.query_type.as_ref().map_or("UNPARSED".to_string(), ToString::to_string);
+        match &result.error {
+            Some(e) => println!(
+                "\x1b[1;36m│\x1b[0m {}. {}: \x1b[1;31mfailed\x1b[0m, {}",
+                i + 1,
+                query_type,
+                error_reason(e)
+            ),
+            None => println!(
+                "\x1b[1;36m│\x1b[0m {}. {}: \x1b[1m{}\x1b[0m row(s), \x1b[1m{}\x1b[0m change(s) in {:.2?} (validation {:.2?}, planning {:.2?})",
+                i + 1,
+                query_type,
+                result.execution.rows.len(),
+                result.execution.changes,
+                result.elapsed(),
+                result.validation_time,
+                result.planning_time
+            ),
+        }
+    }
     println!("\x1b[1;36m└───────────────────────────────────────┘\x1b[0m");
 
-    Ok(())
+    // Every failure is reported; a lone one keeps its diagnostic snippet
+    let mut failures: Vec<(usize, anyhow::Error)> = results
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, result)| result.error.map(|e| (i + 1, e)))
+        .collect();
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0).1),
+        _ => Err(anyhow!(
+            "{}",
+            failures
+                .iter()
+                .map(|(i, e)| format!("Statement {}: {}", i, e))
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        )),
+    }
 }
@@ -1,13 +1,14 @@
 //! Abstract Syntax Tree (AST) for SQL queries
 //!
-//! Provides the structure for representing parsed SQL queries
+//! Provides the structure for representing parsed SQL queries, and the
+//! recursive-descent parser that builds it from the lexer's tokens.
+//! Expressions are parsed by precedence climbing with SQLite's operator
+//! precedence, from OR (loosest) up to COLLATE and the unary operators.
 
-use crate::parser::lexer::{Token, TokenType};
+use crate::parser::lexer::{Keyword, Token, TokenType};
 use anyhow::{anyhow, Result};
-use sqlparser::dialect::SQLiteDialect;
-use sqlparser::parser::Parser as SQLParserLib;
+use serde::Serialize;
 use std::fmt;
-use std::io::Write;
 
 /// Represents the type of SQL query
 #[derive(Debug, Clone, PartialEq)]
@@ -19,6 +20,14 @@ pub enum QueryType {
     Create,
     Alter,
     Drop,
+    /// BEGIN, COMMIT, ROLLBACK, SAVEPOINT and RELEASE
+    Transaction,
+    Pragma,
+    Attach,
+    Detach,
+    /// ANALYZE, VACUUM and REINDEX
+    Maintenance,
+    Explain,
     Unknown,
 }
 
@@ -32,16 +41,80 @@ impl fmt::Display for QueryType {
             QueryType::Create => write!(f, "CREATE"),
             QueryType::Alter => write!(f, "ALTER"),
             QueryType::Drop => write!(f, "DROP"),
+            QueryType::Transaction => write!(f, "TRANSACTION"),
+            QueryType::Pragma => write!(f, "PRAGMA"),
+            QueryType::Attach => write!(f, "ATTACH"),
+            QueryType::Detach => write!(f, "DETACH"),
+            QueryType::Maintenance => write!(f, "MAINTENANCE"),
+            QueryType::Explain => write!(f, "EXPLAIN"),
             QueryType::Unknown => write!(f, "UNKNOWN"),
         }
     }
 }
 
+/// Where a piece of syntax sits in the parsed input
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub offset: usize,
+    /// Length in bytes
+    pub length: usize,
+    /// 1-based line of the first character
+    pub line: usize,
+    /// 1-based column of the first character, counted in characters
+    pub column: usize,
+}
+
+impl Span {
+    /// Span from the start of `self` to the end of `end`
+    pub fn to(&self, end: &Span) -> Span {
+        Span {
+            length: (end.offset + end.length).saturating_sub(self.offset),
+            ..*self
+        }
+    }
+}
+
+impl From<&Token> for Span {
+    fn from(token: &Token) -> Self {
+        Span {
+            offset: token.offset,
+            length: token.length,
+            line: token.line,
+            column: token.column,
+        }
+    }
+}
+
+/// A name that may be qualified by the schema it lives in
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualifiedName {
+    pub schema: Option<String>,
+    pub name: String,
+}
+
+impl fmt::Display for QualifiedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(schema) = &self.schema {
+            write!(f, "{}.", quote_identifier(schema))?;
+        }
+        write!(f, "{}", quote_identifier(&self.name))
+    }
+}
+
 /// Represents an expression in a SQL query
 #[derive(Debug, Clone)]
 pub enum Expression {
-    Column(String),
+    /// `[schema.][table.]name`
+    Column {
+        schema: Option<String>,
+        table: Option<String>,
+        name: String,
+        span: Span,
+    },
     Literal(Value),
+    /// Bind parameter as written: `?`, `?NNN`, `:name`, `@name` or `$name`
+    Parameter { name: String, span: Span },
     BinaryOp {
         left: Box<Expression>,
         op: Operator,
@@ -54,8 +127,70 @@ pub enum Expression {
     Function {
         name: String,
         args: Vec<Expression>,
+        distinct: bool,
+        filter: Option<Box<Expression>>,
+        over: Option<Box<Window>>,
+        span: Span,
     },
+    /// The `*` of `count(*)`
     Star,
+    /// `expr [NOT] LIKE|GLOB|REGEXP|MATCH pattern [ESCAPE escape]`
+    Like {
+        expr: Box<Expression>,
+        op: Operator,
+        pattern: Box<Expression>,
+        escape: Option<Box<Expression>>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expression>,
+        low: Box<Expression>,
+        high: Box<Expression>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expression>,
+        list: Vec<Expression>,
+        negated: bool,
+    },
+    InSubquery {
+        expr: Box<Expression>,
+        query: Box<Select>,
+        negated: bool,
+    },
+    /// `expr IN table`, SQLite's shorthand for `expr IN (SELECT * FROM table)`
+    InTable {
+        expr: Box<Expression>,
+        table: QualifiedName,
+        negated: bool,
+    },
+    /// `ISNULL`, `NOTNULL`, `NOT NULL` and `IS [NOT] NULL`
+    IsNull {
+        expr: Box<Expression>,
+        negated: bool,
+    },
+    Case {
+        operand: Option<Box<Expression>>,
+        branches: Vec<(Expression, Expression)>,
+        else_result: Option<Box<Expression>>,
+    },
+    Cast {
+        expr: Box<Expression>,
+        type_name: String,
+    },
+    Collate {
+        expr: Box<Expression>,
+        collation: String,
+    },
+    Exists(Box<Select>),
+    Subquery(Box<Select>),
+    /// Row value, `(a, b)`
+    Row(Vec<Expression>),
+    /// `RAISE(IGNORE)` or `RAISE(ROLLBACK|ABORT|FAIL, message)` in a trigger
+    Raise {
+        action: ConflictResolution,
+        message: Option<String>,
+    },
 }
 
 /// Represents a SQL value
@@ -64,17 +199,24 @@ pub enum Value {
     Integer(i64),
     Float(f64),
     String(String),
+    Blob(Vec<u8>),
+    /// `TRUE` or `FALSE`, which SQLite reads as 1 and 0
     Boolean(bool),
     Null,
+    CurrentTime,
+    CurrentDate,
+    CurrentTimestamp,
 }
 
 /// Represents a SQL operator
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     Plus,
     Minus,
     Multiply,
     Divide,
+    Modulo,
+    Concat,
     Equals,
     NotEquals,
     GreaterThan,
@@ -84,13 +226,588 @@ pub enum Operator {
     And,
     Or,
     Not,
+    BitAnd,
+    BitOr,
+    BitNot,
+    LeftShift,
+    RightShift,
+    Is,
+    IsNot,
+    Like,
+    Glob,
+    Regexp,
+    Match,
+    /// `->`
+    Arrow,
+    /// `->>`
+    LongArrow,
+}
+
+impl Operator {
+    /// Binding power of the operator used infix; higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Not => 3,
+            Operator::Equals
+            | Operator::NotEquals
+            | Operator::Is
+            | Operator::IsNot
+            | Operator::Like
+            | Operator::Glob
+            | Operator::Regexp
+            | Operator::Match => 4,
+            Operator::GreaterThan | Operator::LessThan | Operator::GreaterEquals | Operator::LessEquals => 5,
+            Operator::BitAnd | Operator::BitOr | Operator::LeftShift | Operator::RightShift => 6,
+            Operator::Plus | Operator::Minus => 7,
+            Operator::Multiply | Operator::Divide | Operator::Modulo => 8,
+            Operator::Concat | Operator::Arrow | Operator::LongArrow => 9,
+            Operator::BitNot => 11,
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Modulo => "%",
+            Operator::Concat => "||",
+            Operator::Equals => "=",
+            Operator::NotEquals => "!=",
+            Operator::GreaterThan => ">",
+            Operator::LessThan => "<",
+            Operator::GreaterEquals => ">=",
+            Operator::LessEquals => "<=",
+            Operator::And => "AND",
+            Operator::Or => "OR",
+            Operator::Not => "NOT",
+            Operator::BitAnd => "&",
+            Operator::BitOr => "|",
+            Operator::BitNot => "~",
+            Operator::LeftShift => "<<",
+            Operator::RightShift => ">>",
+            Operator::Is => "IS",
+            Operator::IsNot => "IS NOT",
+            Operator::Like => "LIKE",
+            Operator::Glob => "GLOB",
+            Operator::Regexp => "REGEXP",
+            Operator::Match => "MATCH",
+            Operator::Arrow => "->",
+            Operator::LongArrow => "->>",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// `OVER` clause of a window function, or a `WINDOW name AS (...)` definition
+#[derive(Debug, Clone)]
+pub struct Window {
+    /// Named window this one extends
+    pub base: Option<String>,
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<WindowFrame>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowFrame {
+    /// ROWS, RANGE or GROUPS
+    pub units: Keyword,
+    pub start: FrameBound,
+    pub end: Option<FrameBound>,
+    /// What EXCLUDE leaves out: NO OTHERS, CURRENT ROW, GROUP or TIES
+    pub exclude: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Box<Expression>),
+    CurrentRow,
+    Following(Box<Expression>),
+    UnboundedFollowing,
+}
+
+/// A term of ORDER BY, or a column of an index
+#[derive(Debug, Clone)]
+pub struct OrderingTerm {
+    pub expr: Expression,
+    pub descending: bool,
+    /// `NULLS FIRST` or `NULLS LAST` when given
+    pub nulls_first: Option<bool>,
+}
+
+/// A complete query: optional CTEs, one or more SELECT or VALUES bodies
+/// joined by compound operators, then ORDER BY and LIMIT
+#[derive(Debug, Clone)]
+pub struct Select {
+    pub with: Option<With>,
+    pub body: SelectBody,
+    pub compounds: Vec<(CompoundOperator, SelectBody)>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Box<Expression>>,
+    pub offset: Option<Box<Expression>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SelectBody {
+    Select(Box<SelectCore>),
+    Values(Vec<Vec<Expression>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectCore {
+    pub distinct: bool,
+    pub columns: Vec<SelectItem>,
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expression>,
+    pub group_by: Vec<Expression>,
+    pub having: Option<Expression>,
+    pub windows: Vec<(String, Window)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SelectItem {
+    Wildcard,
+    /// `table.*`
+    QualifiedWildcard(QualifiedName),
+    Expression {
+        expr: Expression,
+        alias: Option<String>,
+        /// Source text of the expression, which SQLite names the column
+        /// after when it has no alias
+        span: Span,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOperator {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+#[derive(Debug, Clone)]
+pub struct With {
+    pub recursive: bool,
+    pub tables: Vec<CommonTableExpression>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Vec<String>,
+    /// `MATERIALIZED` or `NOT MATERIALIZED` when given
+    pub materialized: Option<bool>,
+    pub query: Box<Select>,
+}
+
+/// The FROM clause: a table followed by the tables joined to it
+#[derive(Debug, Clone)]
+pub struct FromClause {
+    pub relation: TableFactor,
+    pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TableFactor {
+    Table {
+        name: QualifiedName,
+        alias: Option<String>,
+        indexed_by: Option<String>,
+        span: Span,
+    },
+    /// Table-valued function such as `json_each(...)`
+    Function {
+        name: QualifiedName,
+        args: Vec<Expression>,
+        alias: Option<String>,
+        span: Span,
+    },
+    Subquery {
+        query: Box<Select>,
+        alias: Option<String>,
+    },
+    /// Parenthesised join
+    Nested(Box<FromClause>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub natural: bool,
+    pub relation: TableFactor,
+    pub constraint: JoinConstraint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    /// `a, b`
+    Comma,
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross,
+}
+
+#[derive(Debug, Clone)]
+pub enum JoinConstraint {
+    On(Expression),
+    Using(Vec<String>),
+    None,
+}
+
+/// `OR ROLLBACK|ABORT|FAIL|IGNORE|REPLACE`, an `ON CONFLICT` clause of a
+/// constraint, or a RAISE() action
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictResolution {
+    Rollback,
+    Abort,
+    Fail,
+    Ignore,
+    Replace,
+}
+
+impl fmt::Display for ConflictResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictResolution::Rollback => write!(f, "ROLLBACK"),
+            ConflictResolution::Abort => write!(f, "ABORT"),
+            ConflictResolution::Fail => write!(f, "FAIL"),
+            ConflictResolution::Ignore => write!(f, "IGNORE"),
+            ConflictResolution::Replace => write!(f, "REPLACE"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Insert {
+    pub with: Option<With>,
+    /// `INSERT OR ...`; `REPLACE INTO` is `OR REPLACE`
+    pub conflict: Option<ConflictResolution>,
+    pub table: QualifiedName,
+    pub alias: Option<String>,
+    /// Span of the table name
+    pub span: Span,
+    pub columns: Vec<String>,
+    pub source: InsertSource,
+    pub upserts: Vec<Upsert>,
+    pub returning: Vec<SelectItem>,
+}
+
+#[derive(Debug, Clone)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expression>>),
+    Select(Box<Select>),
+    DefaultValues,
+}
+
+/// `ON CONFLICT [(target) [WHERE ...]] DO NOTHING|UPDATE ...`
+#[derive(Debug, Clone)]
+pub struct Upsert {
+    pub target: Vec<OrderingTerm>,
+    pub target_where: Option<Expression>,
+    pub action: UpsertAction,
+}
+
+#[derive(Debug, Clone)]
+pub enum UpsertAction {
+    Nothing,
+    Update {
+        assignments: Vec<Assignment>,
+        where_clause: Option<Expression>,
+    },
+}
+
+/// `column = value`, or `(a, b) = row value`
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub columns: Vec<String>,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone)]
+pub struct Update {
+    pub with: Option<With>,
+    pub conflict: Option<ConflictResolution>,
+    pub table: QualifiedName,
+    pub alias: Option<String>,
+    /// Span of the table name
+    pub span: Span,
+    pub assignments: Vec<Assignment>,
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expression>,
+    pub returning: Vec<SelectItem>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Box<Expression>>,
+    pub offset: Option<Box<Expression>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Delete {
+    pub with: Option<With>,
+    pub table: QualifiedName,
+    pub alias: Option<String>,
+    /// Span of the table name
+    pub span: Span,
+    pub where_clause: Option<Expression>,
+    pub returning: Vec<SelectItem>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Box<Expression>>,
+    pub offset: Option<Box<Expression>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateTable {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub columns: Vec<ColumnDef>,
+    pub constraints: Vec<TableConstraint>,
+    /// `CREATE TABLE ... AS SELECT`
+    pub as_select: Option<Box<Select>>,
+    pub without_rowid: bool,
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnDef {
+    pub name: String,
+    /// Declared type as written, e.g. `VARCHAR(255)`
+    pub type_name: Option<String>,
+    pub constraints: Vec<ColumnConstraint>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ColumnConstraint {
+    PrimaryKey {
+        descending: bool,
+        conflict: Option<ConflictResolution>,
+        autoincrement: bool,
+    },
+    NotNull(Option<ConflictResolution>),
+    Null,
+    Unique(Option<ConflictResolution>),
+    Check(Expression),
+    Default(Expression),
+    Collate(String),
+    References(ForeignKeyClause),
+    /// `[GENERATED ALWAYS] AS (expr) [STORED|VIRTUAL]`
+    Generated {
+        expr: Expression,
+        stored: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum TableConstraint {
+    PrimaryKey {
+        columns: Vec<OrderingTerm>,
+        conflict: Option<ConflictResolution>,
+    },
+    Unique {
+        columns: Vec<OrderingTerm>,
+        conflict: Option<ConflictResolution>,
+    },
+    Check(Expression),
+    ForeignKey {
+        columns: Vec<String>,
+        clause: ForeignKeyClause,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ForeignKeyClause {
+    pub table: String,
+    /// Empty when the parent's primary key is implied
+    pub columns: Vec<String>,
+    pub on_delete: Option<ForeignKeyAction>,
+    pub on_update: Option<ForeignKeyAction>,
+    /// `DEFERRABLE INITIALLY DEFERRED`
+    pub deferred: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForeignKeyAction {
+    SetNull,
+    SetDefault,
+    Cascade,
+    Restrict,
+    NoAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateIndex {
+    pub unique: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub table: String,
+    /// Span of the table name
+    pub span: Span,
+    pub columns: Vec<OrderingTerm>,
+    pub where_clause: Option<Expression>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateView {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub columns: Vec<String>,
+    pub query: Box<Select>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerTiming {
+    Before,
+    After,
+    InsteadOf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerEvent {
+    Delete,
+    Insert,
+    /// `UPDATE [OF columns]`; empty for any update
+    Update(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateTrigger {
+    pub temporary: bool,
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    pub table: String,
+    /// Span of the table name
+    pub span: Span,
+    pub for_each_row: bool,
+    pub when: Option<Expression>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateVirtualTable {
+    pub if_not_exists: bool,
+    pub name: QualifiedName,
+    pub module: String,
+    /// Module arguments as written
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectType {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+#[derive(Debug, Clone)]
+pub enum AlterAction {
+    RenameTable(String),
+    RenameColumn { old: String, new: String },
+    AddColumn(ColumnDef),
+    DropColumn(String),
+}
+
+/// The parsed form of each kind of statement
+#[derive(Debug, Clone)]
+pub enum StatementKind {
+    Select(Box<Select>),
+    Insert(Box<Insert>),
+    Update(Box<Update>),
+    Delete(Box<Delete>),
+    CreateTable(Box<CreateTable>),
+    CreateIndex(Box<CreateIndex>),
+    CreateView(Box<CreateView>),
+    CreateTrigger(Box<CreateTrigger>),
+    CreateVirtualTable(Box<CreateVirtualTable>),
+    Drop {
+        object_type: ObjectType,
+        if_exists: bool,
+        name: QualifiedName,
+    },
+    AlterTable {
+        table: QualifiedName,
+        span: Span,
+        action: AlterAction,
+    },
+    /// `BEGIN [DEFERRED|IMMEDIATE|EXCLUSIVE]`
+    Begin(Option<Keyword>),
+    Commit,
+    /// `ROLLBACK [TO savepoint]`
+    Rollback(Option<String>),
+    Savepoint(String),
+    Release(String),
+    Pragma {
+        name: QualifiedName,
+        /// Value as written, from `= value` or `(value)`
+        value: Option<String>,
+    },
+    Attach {
+        database: Expression,
+        schema: Expression,
+    },
+    Detach(Expression),
+    Analyze(Option<QualifiedName>),
+    Vacuum {
+        schema: Option<String>,
+        into: Option<Expression>,
+    },
+    Reindex(Option<QualifiedName>),
+    Explain {
+        query_plan: bool,
+        statement: Box<Statement>,
+    },
 }
 
 /// Represents a complete SQL statement
 #[derive(Debug, Clone)]
 pub struct Statement {
     pub query_type: QueryType,
+    /// Source text of the statement, without the terminating semicolon
     pub query_text: String,
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl StatementKind {
+    pub fn query_type(&self) -> QueryType {
+        match self {
+            StatementKind::Select(_) => QueryType::Select,
+            StatementKind::Insert(_) => QueryType::Insert,
+            StatementKind::Update(_) => QueryType::Update,
+            StatementKind::Delete(_) => QueryType::Delete,
+            StatementKind::CreateTable(_)
+            | StatementKind::CreateIndex(_)
+            | StatementKind::CreateView(_)
+            | StatementKind::CreateTrigger(_)
+            | StatementKind::CreateVirtualTable(_) => QueryType::Create,
+            StatementKind::Drop { .. } => QueryType::Drop,
+            StatementKind::AlterTable { .. } => QueryType::Alter,
+            StatementKind::Begin(_)
+            | StatementKind::Commit
+            | StatementKind::Rollback(_)
+            | StatementKind::Savepoint(_)
+            | StatementKind::Release(_) => QueryType::Transaction,
+            StatementKind::Pragma { .. } => QueryType::Pragma,
+            StatementKind::Attach { .. } => QueryType::Attach,
+            StatementKind::Detach(_) => QueryType::Detach,
+            StatementKind::Analyze(_) | StatementKind::Vacuum { .. } | StatementKind::Reindex(_) => {
+                QueryType::Maintenance
+            }
+            StatementKind::Explain { .. } => QueryType::Explain,
+        }
+    }
 }
 
 /// Represents the result of query analysis
@@ -105,346 +822,4451 @@ pub struct AnalyzedQuery {
     pub query_text: String,
 }
 
-/// Builds an AST from tokens
-pub struct AstBuilder {
-    tokens: Vec<Token>,
-    pos: usize,
+/// A syntax error, with the span of the token it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
 }
 
-impl AstBuilder {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        AstBuilder { tokens, pos: 0 }
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.span.line, self.span.column)
     }
+}
 
-    pub fn build(&self) -> Result<Statement> {
-        // In a real implementation, we'd build the AST from tokens
-        // For now, let's just look at the first token to decide the query type
+impl std::error::Error for ParseError {}
 
-        let query_type = if self.tokens.is_empty() {
-            QueryType::Unknown
-        } else {
-            match &self.tokens[0].token_type {
-                TokenType::Select => QueryType::Select,
-                TokenType::Identifier(id) if id.eq_ignore_ascii_case("insert") => QueryType::Insert,
-                TokenType::Identifier(id) if id.eq_ignore_ascii_case("update") => QueryType::Update,
-                TokenType::Identifier(id) if id.eq_ignore_ascii_case("delete") => QueryType::Delete,
-                TokenType::Identifier(id) if id.eq_ignore_ascii_case("create") => QueryType::Create,
-                TokenType::Identifier(id) if id.eq_ignore_ascii_case("alter") => QueryType::Alter,
-                TokenType::Identifier(id) if id.eq_ignore_ascii_case("drop") => QueryType::Drop,
-                _ => QueryType::Unknown,
-            }
-        };
+/// What a parse in recovery mode produced: the statements that parsed, some
+/// perhaps with clauses missing, and every syntax error found on the way
+#[derive(Debug, Clone)]
+pub struct PartialParse {
+    pub statements: Vec<Statement>,
+    pub errors: Vec<ParseError>,
+}
 
-        // Reconstruct query text
-        let query_text = self
-            .tokens
-            .iter()
-            .map(|t| format!("{}", t.token_type))
-            .collect::<Vec<_>>()
-            .join(" ");
+/// Keywords SQLite also accepts as names wherever a keyword would not fit,
+/// so a column can be called `key`, `desc` or `replace`
+fn is_fallback_keyword(keyword: Keyword) -> bool {
+    matches!(
+        keyword,
+        Keyword::Abort
+            | Keyword::Action
+            | Keyword::After
+            | Keyword::Always
+            | Keyword::Analyze
+            | Keyword::Asc
+            | Keyword::Attach
+            | Keyword::Before
+            | Keyword::Begin
+            | Keyword::By
+            | Keyword::Cascade
+            | Keyword::Cast
+            | Keyword::Column
+            | Keyword::Conflict
+            | Keyword::Current
+            | Keyword::CurrentDate
+            | Keyword::CurrentTime
+            | Keyword::CurrentTimestamp
+            | Keyword::Database
+            | Keyword::Deferred
+            | Keyword::Desc
+            | Keyword::Detach
+            | Keyword::Do
+            | Keyword::Each
+            | Keyword::End
+            | Keyword::Exclude
+            | Keyword::Exclusive
+            | Keyword::Explain
+            | Keyword::Fail
+            | Keyword::First
+            | Keyword::Following
+            | Keyword::For
+            | Keyword::Generated
+            | Keyword::Glob
+            | Keyword::Groups
+            | Keyword::If
+            | Keyword::Ignore
+            | Keyword::Immediate
+            | Keyword::Initially
+            | Keyword::Instead
+            | Keyword::Key
+            | Keyword::Last
+            | Keyword::Like
+            | Keyword::Match
+            | Keyword::Materialized
+            | Keyword::No
+            | Keyword::Nulls
+            | Keyword::Of
+            | Keyword::Offset
+            | Keyword::Others
+            | Keyword::Partition
+            | Keyword::Plan
+            | Keyword::Pragma
+            | Keyword::Preceding
+            | Keyword::Query
+            | Keyword::Raise
+            | Keyword::Range
+            | Keyword::Recursive
+            | Keyword::Regexp
+            | Keyword::Reindex
+            | Keyword::Release
+            | Keyword::Rename
+            | Keyword::Replace
+            | Keyword::Restrict
+            | Keyword::Rollback
+            | Keyword::Row
+            | Keyword::Rows
+            | Keyword::Savepoint
+            | Keyword::Temp
+            | Keyword::Ties
+            | Keyword::Trigger
+            | Keyword::Unbounded
+            | Keyword::Vacuum
+            | Keyword::View
+            | Keyword::Virtual
+            | Keyword::With
+            | Keyword::Without
+    )
+}
 
-        Ok(Statement {
-            query_type,
-            query_text,
-        })
+/// Name as SQL text, quoted when it is not a plain identifier
+fn quote_identifier(name: &str) -> String {
+    let plain = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && Keyword::lookup(name).is_none()
+        // Unquoted, these read back as boolean literals
+        && !name.eq_ignore_ascii_case("true")
+        && !name.eq_ignore_ascii_case("false");
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
     }
 }
 
-/// Analyzes SQL queries for execution
-pub struct QueryAnalyzer {
-    dialect: SQLiteDialect,
-    table_references: Vec<String>,
-    column_references: Vec<String>,
-    analyzed_query: Option<String>,
-    db_path: String, // Add this field
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
 }
 
-impl QueryAnalyzer {
-    pub fn new(db_path: String) -> Self {
-        QueryAnalyzer {
-            dialect: SQLiteDialect {},
-            table_references: Vec::new(),
-            column_references: Vec::new(),
-            analyzed_query: None,
-            db_path, // Store the path
-        }
-    }
-
-    pub fn tokenize(mut self, query: &str) -> Result<Self> {
-        println!("\n\x1b[1;35m┌─────────────────────────── QUERY ANALYSIS ───────────────────────────┐\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;33mTokenizing SQL query\x1b[0m                                               \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m Query: \x1b[0;36m{}\x1b[0m", query);
-
-        // Fake tokenization progress bar
-        print!("\x1b[1;35m│\x1b[0m \x1b[90m[");
-        let total_steps = 20;
-        for i in 0..=total_steps {
-            print!("\x1b[1;32m█\x1b[0m");
-            std::io::stdout().flush().unwrap();
-            std::thread::sleep(std::time::Duration::from_millis(10));
-        }
-        println!("\x1b[90m] 100%\x1b[0m                                       \x1b[1;35m│\x1b[0m");
-
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;32m✓\x1b[0m Identified \x1b[1;33m{}\x1b[0m tokens                                          \x1b[1;35m│\x1b[0m", 15); // Fake number
-
-        Ok(self)
-    }
-
-    pub fn build_ast(mut self) -> Result<Self> {
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;33mConstructing Abstract Syntax Tree\x1b[0m                                  \x1b[1;35m│\x1b[0m");
-    
-        // Animation for UI
-        print!("\x1b[1;35m│\x1b[0m Building syntax tree ");
-        for _ in 0..5 {
-            print!("\x1b[1;32m.\x1b[0m");
-            std::io::stdout().flush().unwrap();
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-        println!(" \x1b[1;32mDone!\x1b[0m                                   \x1b[1;35m│\x1b[0m");
-    
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Resolving statement type                                        \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m└─\x1b[0m Statement type: \x1b[1;36mSELECT\x1b[0m                                        \x1b[1;35m│\x1b[0m");
-    
-        // Get the query - this is important
-        let sql = self.analyzed_query.clone().unwrap_or_default();
-        println!("[PARSER] Parsing SQL: {}", sql);
-        
-        // CLEAR any existing references - this is critical
-        self.table_references.clear();
-        self.column_references.clear();
-        
-        // Parse with SQLParser
-        let parse_result = SQLParserLib::parse_sql(&self.dialect, &sql);
-        
-        match parse_result {
-            Ok(mut ast) => {
-                // If we have a SQL statement, extract table and column references
-                if let Some(stmt) = ast.get(0) {
-                    match stmt {
-                        sqlparser::ast::Statement::Query(query) => {
-                            match &*query.body {
-                                sqlparser::ast::SetExpr::Select(select) => {
-                                    // Extract from the select statement
-                                    self.extract_columns_from_select(select)?;
-                                    
-                                    // Debug the actual extraction results
-                                    println!("[PARSER] Found tables: {:?}", self.table_references);
-                                    println!("[PARSER] Found columns: {:?}", self.column_references);
-                                }
-                                _ => {
-                                    println!("[PARSER] Unsupported query type in body");
-                                }
-                            }
-                        }
-                        _ => {
-                            println!("[PARSER] Unsupported statement type");
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                println!("[PARSER] SQL parsing error: {}", e);
-                // Add fake tables/columns for error cases
-                self.table_references.push("unknown".to_string());
-                self.column_references.push("unknown".to_string());
-            }
+fn write_names(f: &mut fmt::Formatter<'_>, names: &[String]) -> fmt::Result {
+    let quoted: Vec<String> = names.iter().map(|name| quote_identifier(name)).collect();
+    write_list(f, &quoted)
+}
+
+impl Expression {
+    /// Binding power of the expression's outermost operator; atoms bind tightest
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::BinaryOp { op, .. } => op.precedence(),
+            Expression::UnaryOp { op: Operator::Not, .. } => 3,
+            Expression::Like { .. }
+            | Expression::Between { .. }
+            | Expression::InList { .. }
+            | Expression::InSubquery { .. }
+            | Expression::InTable { .. }
+            | Expression::IsNull { .. } => 4,
+            Expression::Collate { .. } => 10,
+            Expression::UnaryOp { .. } => 11,
+            _ => 12,
         }
-    
-        Ok(self)
     }
 
-    fn extract_columns_from_select(&mut self, select: &sqlparser::ast::Select) -> Result<()> {
-        // Track whether a wildcard was used
-        let mut has_wildcard = false;
+    /// Writes `operand`, parenthesised when it binds looser than `precedence`
+    fn write_operand(f: &mut fmt::Formatter<'_>, operand: &Expression, precedence: u8) -> fmt::Result {
+        if operand.precedence() < precedence {
+            write!(f, "({})", operand)
+        } else {
+            write!(f, "{}", operand)
+        }
+    }
+}
 
-        // Extract columns from projection items
-        for item in &select.projection {
-            match item {
-                sqlparser::ast::SelectItem::UnnamedExpr(expr) => {
-                    self.extract_columns_from_expr(expr)?;
-                }
-                sqlparser::ast::SelectItem::ExprWithAlias { expr, .. } => {
-                    // Handle aliased columns
-                    self.extract_columns_from_expr(expr)?;
-                }
-                sqlparser::ast::SelectItem::QualifiedWildcard(name, _) => {
-                    // Handle qualified wildcards like "table.*"
-                    let table_name = name.to_string();
-                    if !self.table_references.contains(&table_name) {
-                        self.table_references.push(table_name);
-                    }
-                    has_wildcard = true;
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Column { schema, table, name, .. } => {
+                if let Some(schema) = schema {
+                    write!(f, "{}.", quote_identifier(schema))?;
                 }
-                sqlparser::ast::SelectItem::Wildcard(_) => {
-                    // Mark that wildcard was used
-                    has_wildcard = true;
+                if let Some(table) = table {
+                    write!(f, "{}.", quote_identifier(table))?;
                 }
+                write!(f, "{}", quote_identifier(name))
             }
-        }
-
-        // Extract tables from the FROM clause
-        for table_with_join in &select.from {
-            self.extract_tables_from_table_factor(&table_with_join.relation)?;
-
-            // Also process any JOINs
-            for join in &table_with_join.joins {
-                self.extract_tables_from_table_factor(&join.relation)?;
+            Expression::Literal(value) => write!(f, "{}", value),
+            Expression::Parameter { name, .. } => write!(f, "{}", name),
+            Expression::BinaryOp { left, op, right } => {
+                // Operators are left-associative, so an equal right operand needs parentheses
+                Self::write_operand(f, left, op.precedence())?;
+                write!(f, " {} ", op)?;
+                Self::write_operand(f, right, op.precedence() + 1)
             }
-        }
-
-        // If we have a wildcard and tables, resolve the columns using schema information
-        if has_wildcard && !self.table_references.is_empty() {
-            self.resolve_wildcard_columns()?;
-        }
-
-        Ok(())
-    }
-
-    // Add this new method to resolve wildcard columns
-    fn resolve_wildcard_columns(&mut self) -> Result<()> {
-        use crate::schema::index::get_table_columns;
-
-        // Clear any existing column references since we're resolving a wildcard
-        self.column_references.clear();
-
-        // Track if we've found any columns
-        let mut found_columns = false;
-
-        println!(
-            "[SCHEMA] Resolving wildcard columns for tables: {:?}",
-            self.table_references
-        );
-
-        // For each referenced table, look up its columns
-        for table in &self.table_references {
-            // Get columns for this table
-            match get_table_columns(&self.db_path, table) {
-                Ok(columns) => {
-                    // Add the columns to our references
-                    if !columns.is_empty() {
-                        for column in columns {
-                            if !self.column_references.contains(&column) {
-                                self.column_references.push(column);
-                            }
-                        }
-                        found_columns = true;
+            Expression::UnaryOp { op: Operator::Not, expr } => {
+                write!(f, "NOT ")?;
+                Self::write_operand(f, expr, 3)
+            }
+            Expression::UnaryOp { op, expr } => {
+                let operand = if expr.precedence() < 11 { format!("({})", expr) } else { expr.to_string() };
+                // `- -1` must not run together into a comment
+                let gap = if operand.starts_with('-') { " " } else { "" };
+                write!(f, "{}{}{}", op, gap, operand)
+            }
+            Expression::Function { name, args, distinct, filter, over, .. } => {
+                write!(f, "{}(", name)?;
+                if *distinct {
+                    write!(f, "DISTINCT ")?;
+                }
+                write_list(f, args)?;
+                write!(f, ")")?;
+                if let Some(filter) = filter {
+                    write!(f, " FILTER (WHERE {})", filter)?;
+                }
+                match over.as_deref() {
+                    Some(Window { base: Some(base), partition_by, order_by, frame: None })
+                        if partition_by.is_empty() && order_by.is_empty() =>
+                    {
+                        write!(f, " OVER {}", quote_identifier(base))
                     }
+                    Some(window) => write!(f, " OVER ({})", window),
+                    None => Ok(()),
                 }
-                Err(e) => {
-                    eprintln!("Warning: Could not get columns for table {}: {}", table, e);
+            }
+            Expression::Star => write!(f, "*"),
+            Expression::Like { expr, op, pattern, escape, negated } => {
+                Self::write_operand(f, expr, 4)?;
+                write!(f, " {}{} ", if *negated { "NOT " } else { "" }, op)?;
+                Self::write_operand(f, pattern, 5)?;
+                if let Some(escape) = escape {
+                    write!(f, " ESCAPE ")?;
+                    Self::write_operand(f, escape, 5)?;
                 }
+                Ok(())
             }
-        }
-
+            Expression::Between { expr, low, high, negated } => {
+                Self::write_operand(f, expr, 4)?;
+                write!(f, " {}BETWEEN ", if *negated { "NOT " } else { "" })?;
+                Self::write_operand(f, low, 5)?;
+                write!(f, " AND ")?;
+                Self::write_operand(f, high, 5)
+            }
+            Expression::InList { expr, list, negated } => {
+                Self::write_operand(f, expr, 4)?;
+                write!(f, " {}IN (", if *negated { "NOT " } else { "" })?;
+                write_list(f, list)?;
+                write!(f, ")")
+            }
+            Expression::InSubquery { expr, query, negated } => {
+                Self::write_operand(f, expr, 4)?;
+                write!(f, " {}IN ({})", if *negated { "NOT " } else { "" }, query)
+            }
+            Expression::InTable { expr, table, negated } => {
+                Self::write_operand(f, expr, 4)?;
+                write!(f, " {}IN {}", if *negated { "NOT " } else { "" }, table)
+            }
+            Expression::IsNull { expr, negated } => {
+                Self::write_operand(f, expr, 4)?;
+                write!(f, " IS {}NULL", if *negated { "NOT " } else { "" })
+            }
+            Expression::Case { operand, branches, else_result } => {
+                write!(f, "CASE")?;
+                if let Some(operand) = operand {
+                    write!(f, " {}", operand)?;
+                }
+                for (condition, result) in branches {
+                    write!(f, " WHEN {} THEN {}", condition, result)?;
+                }
+                if let Some(else_result) = else_result {
+                    write!(f, " ELSE {}", else_result)?;
+                }
+                write!(f, " END")
+            }
+            Expression::Cast { expr, type_name } => write!(f, "CAST({} AS {})", expr, type_name),
+            Expression::Collate { expr, collation } => {
+                Self::write_operand(f, expr, 10)?;
+                write!(f, " COLLATE {}", quote_identifier(collation))
+            }
+            Expression::Exists(query) => write!(f, "EXISTS ({})", query),
+            Expression::Subquery(query) => write!(f, "({})", query),
+            Expression::Row(values) => {
+                write!(f, "(")?;
+                write_list(f, values)?;
+                write!(f, ")")
+            }
+            Expression::Raise { action, message } => match message {
+                Some(message) => write!(f, "RAISE({}, {})", action, Value::String(message.clone())),
+                None => write!(f, "RAISE({})", action),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::String(text) => write!(f, "'{}'", text.replace('\'', "''")),
+            Value::Blob(bytes) => {
+                write!(f, "X'")?;
+                for byte in bytes {
+                    write!(f, "{:02X}", byte)?;
+                }
+                write!(f, "'")
+            }
+            Value::Boolean(value) => write!(f, "{}", if *value { "TRUE" } else { "FALSE" }),
+            Value::Null => write!(f, "NULL"),
+            Value::CurrentTime => write!(f, "CURRENT_TIME"),
+            Value::CurrentDate => write!(f, "CURRENT_DATE"),
+            Value::CurrentTimestamp => write!(f, "CURRENT_TIMESTAMP"),
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(base) = &self.base {
+            parts.push(quote_identifier(base));
+        }
+        if !self.partition_by.is_empty() {
+            let expressions: Vec<String> = self.partition_by.iter().map(ToString::to_string).collect();
+            parts.push(format!("PARTITION BY {}", expressions.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            let terms: Vec<String> = self.order_by.iter().map(ToString::to_string).collect();
+            parts.push(format!("ORDER BY {}", terms.join(", ")));
+        }
+        if let Some(frame) = &self.frame {
+            let mut text = match &frame.end {
+                Some(end) => format!("{} BETWEEN {} AND {}", frame.units, frame.start, end),
+                None => format!("{} {}", frame.units, frame.start),
+            };
+            if let Some(exclude) = &frame.exclude {
+                text.push_str(&format!(" EXCLUDE {}", exclude));
+            }
+            parts.push(text);
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameBound::UnboundedPreceding => write!(f, "UNBOUNDED PRECEDING"),
+            FrameBound::Preceding(expr) => write!(f, "{} PRECEDING", expr),
+            FrameBound::CurrentRow => write!(f, "CURRENT ROW"),
+            FrameBound::Following(expr) => write!(f, "{} FOLLOWING", expr),
+            FrameBound::UnboundedFollowing => write!(f, "UNBOUNDED FOLLOWING"),
+        }
+    }
+}
+
+impl fmt::Display for OrderingTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+        match self.nulls_first {
+            Some(true) => write!(f, " NULLS FIRST"),
+            Some(false) => write!(f, " NULLS LAST"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(with) = &self.with {
+            write!(f, "{} ", with)?;
+        }
+        write!(f, "{}", self.body)?;
+        for (operator, body) in &self.compounds {
+            let operator = match operator {
+                CompoundOperator::Union => "UNION",
+                CompoundOperator::UnionAll => "UNION ALL",
+                CompoundOperator::Intersect => "INTERSECT",
+                CompoundOperator::Except => "EXCEPT",
+            };
+            write!(f, " {} {}", operator, body)?;
+        }
+        write_order_and_limit(f, &self.order_by, &self.limit, &self.offset)
+    }
+}
+
+fn write_order_and_limit(
+    f: &mut fmt::Formatter<'_>,
+    order_by: &[OrderingTerm],
+    limit: &Option<Box<Expression>>,
+    offset: &Option<Box<Expression>>,
+) -> fmt::Result {
+    if !order_by.is_empty() {
+        write!(f, " ORDER BY ")?;
+        write_list(f, order_by)?;
+    }
+    if let Some(limit) = limit {
+        write!(f, " LIMIT {}", limit)?;
+    }
+    if let Some(offset) = offset {
+        write!(f, " OFFSET {}", offset)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for With {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WITH {}", if self.recursive { "RECURSIVE " } else { "" })?;
+        for (index, table) in self.tables.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", quote_identifier(&table.name))?;
+            if !table.columns.is_empty() {
+                write!(f, "(")?;
+                write_names(f, &table.columns)?;
+                write!(f, ")")?;
+            }
+            let materialized = match table.materialized {
+                Some(true) => "MATERIALIZED ",
+                Some(false) => "NOT MATERIALIZED ",
+                None => "",
+            };
+            write!(f, " AS {}({})", materialized, table.query)?;
+        }
         Ok(())
     }
+}
 
-    fn extract_columns_from_expr(&mut self, expr: &sqlparser::ast::Expr) -> Result<()> {
-        match expr {
-            sqlparser::ast::Expr::Identifier(ident) => {
-                self.column_references.push(ident.value.clone());
+impl fmt::Display for SelectBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectBody::Select(core) => write!(f, "{}", core),
+            SelectBody::Values(rows) => {
+                write!(f, "VALUES ")?;
+                write_rows(f, rows)
+            }
+        }
+    }
+}
+
+fn write_rows(f: &mut fmt::Formatter<'_>, rows: &[Vec<Expression>]) -> fmt::Result {
+    for (index, row) in rows.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "(")?;
+        write_list(f, row)?;
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for SelectCore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SELECT {}", if self.distinct { "DISTINCT " } else { "" })?;
+        write_list(f, &self.columns)?;
+        if let Some(from) = &self.from {
+            write!(f, " FROM {}", from)?;
+        }
+        if let Some(where_clause) = &self.where_clause {
+            write!(f, " WHERE {}", where_clause)?;
+        }
+        if !self.group_by.is_empty() {
+            write!(f, " GROUP BY ")?;
+            write_list(f, &self.group_by)?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " HAVING {}", having)?;
+        }
+        for (index, (name, window)) in self.windows.iter().enumerate() {
+            write!(f, "{} {} AS ({})", if index == 0 { " WINDOW" } else { "," }, quote_identifier(name), window)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectItem::Wildcard => write!(f, "*"),
+            SelectItem::QualifiedWildcard(table) => write!(f, "{}.*", table),
+            SelectItem::Expression { expr, alias: Some(alias), .. } => write!(f, "{} AS {}", expr, quote_identifier(alias)),
+            SelectItem::Expression { expr, alias: None, .. } => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl fmt::Display for FromClause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.relation)?;
+        for join in &self.joins {
+            let operator = match join.kind {
+                JoinKind::Comma => ",",
+                JoinKind::Inner => " JOIN",
+                JoinKind::Left => " LEFT JOIN",
+                JoinKind::Right => " RIGHT JOIN",
+                JoinKind::Full => " FULL JOIN",
+                JoinKind::Cross => " CROSS JOIN",
+            };
+            if join.natural {
+                write!(f, " NATURAL")?;
+            }
+            write!(f, "{} {}", operator, join.relation)?;
+            match &join.constraint {
+                JoinConstraint::On(expr) => write!(f, " ON {}", expr)?,
+                JoinConstraint::Using(columns) => {
+                    write!(f, " USING (")?;
+                    write_names(f, columns)?;
+                    write!(f, ")")?;
+                }
+                JoinConstraint::None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TableFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alias = match self {
+            TableFactor::Table { name, alias, indexed_by, .. } => {
+                write!(f, "{}", name)?;
+                if let Some(index) = indexed_by {
+                    write!(f, " INDEXED BY {}", quote_identifier(index))?;
+                }
+                alias
+            }
+            TableFactor::Function { name, args, alias, .. } => {
+                write!(f, "{}(", name)?;
+                write_list(f, args)?;
+                write!(f, ")")?;
+                alias
             }
-            sqlparser::ast::Expr::CompoundIdentifier(parts) => {
-                // Handle qualified column names like "table.column"
-                if parts.len() >= 2 {
-                    let table = parts[0].value.clone();
-                    let column = parts[parts.len() - 1].value.clone();
+            TableFactor::Subquery { query, alias } => {
+                write!(f, "({})", query)?;
+                alias
+            }
+            TableFactor::Nested(from) => return write!(f, "({})", from),
+        };
+        match alias {
+            Some(alias) => write!(f, " AS {}", quote_identifier(alias)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
 
-                    if !self.table_references.contains(&table) {
-                        self.table_references.push(table);
+impl fmt::Display for StatementKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatementKind::Select(select) => write!(f, "{}", select),
+            StatementKind::Insert(insert) => write!(f, "{}", insert),
+            StatementKind::Update(update) => write!(f, "{}", update),
+            StatementKind::Delete(delete) => write!(f, "{}", delete),
+            StatementKind::CreateTable(table) => write!(f, "{}", table),
+            StatementKind::CreateIndex(index) => write!(f, "{}", index),
+            StatementKind::CreateView(view) => write!(f, "{}", view),
+            StatementKind::CreateTrigger(trigger) => write!(f, "{}", trigger),
+            StatementKind::CreateVirtualTable(table) => {
+                write!(f, "CREATE VIRTUAL TABLE {}{} USING {}", if_not_exists(table.if_not_exists), table.name, quote_identifier(&table.module))?;
+                if !table.args.is_empty() {
+                    write!(f, "({})", table.args.join(", "))?;
+                }
+                Ok(())
+            }
+            StatementKind::Drop { object_type, if_exists, name } => {
+                let object_type = match object_type {
+                    ObjectType::Table => "TABLE",
+                    ObjectType::Index => "INDEX",
+                    ObjectType::View => "VIEW",
+                    ObjectType::Trigger => "TRIGGER",
+                };
+                write!(f, "DROP {} {}{}", object_type, if *if_exists { "IF EXISTS " } else { "" }, name)
+            }
+            StatementKind::AlterTable { table, action, .. } => {
+                write!(f, "ALTER TABLE {} ", table)?;
+                match action {
+                    AlterAction::RenameTable(name) => write!(f, "RENAME TO {}", quote_identifier(name)),
+                    AlterAction::RenameColumn { old, new } => {
+                        write!(f, "RENAME COLUMN {} TO {}", quote_identifier(old), quote_identifier(new))
                     }
-                    self.column_references.push(column);
+                    AlterAction::AddColumn(column) => write!(f, "ADD COLUMN {}", column),
+                    AlterAction::DropColumn(name) => write!(f, "DROP COLUMN {}", quote_identifier(name)),
+                }
+            }
+            StatementKind::Begin(Some(mode)) => write!(f, "BEGIN {}", mode),
+            StatementKind::Begin(None) => write!(f, "BEGIN"),
+            StatementKind::Commit => write!(f, "COMMIT"),
+            StatementKind::Rollback(Some(savepoint)) => write!(f, "ROLLBACK TO {}", quote_identifier(savepoint)),
+            StatementKind::Rollback(None) => write!(f, "ROLLBACK"),
+            StatementKind::Savepoint(name) => write!(f, "SAVEPOINT {}", quote_identifier(name)),
+            StatementKind::Release(name) => write!(f, "RELEASE {}", quote_identifier(name)),
+            StatementKind::Pragma { name, value: Some(value) } => write!(f, "PRAGMA {} = {}", name, value),
+            StatementKind::Pragma { name, value: None } => write!(f, "PRAGMA {}", name),
+            StatementKind::Attach { database, schema } => write!(f, "ATTACH DATABASE {} AS {}", database, schema),
+            StatementKind::Detach(schema) => write!(f, "DETACH DATABASE {}", schema),
+            StatementKind::Analyze(Some(name)) => write!(f, "ANALYZE {}", name),
+            StatementKind::Analyze(None) => write!(f, "ANALYZE"),
+            StatementKind::Vacuum { schema, into } => {
+                write!(f, "VACUUM")?;
+                if let Some(schema) = schema {
+                    write!(f, " {}", quote_identifier(schema))?;
                 }
+                if let Some(into) = into {
+                    write!(f, " INTO {}", into)?;
+                }
+                Ok(())
+            }
+            StatementKind::Reindex(Some(name)) => write!(f, "REINDEX {}", name),
+            StatementKind::Reindex(None) => write!(f, "REINDEX"),
+            StatementKind::Explain { query_plan, statement } => {
+                write!(f, "EXPLAIN {}{}", if *query_plan { "QUERY PLAN " } else { "" }, statement)
+            }
+        }
+    }
+}
+
+fn if_not_exists(present: bool) -> &'static str {
+    if present {
+        "IF NOT EXISTS "
+    } else {
+        ""
+    }
+}
+
+fn temporary(present: bool) -> &'static str {
+    if present {
+        "TEMP "
+    } else {
+        ""
+    }
+}
+
+fn write_with(f: &mut fmt::Formatter<'_>, with: &Option<With>) -> fmt::Result {
+    match with {
+        Some(with) => write!(f, "{} ", with),
+        None => Ok(()),
+    }
+}
+
+fn write_conflict(f: &mut fmt::Formatter<'_>, conflict: &Option<ConflictResolution>) -> fmt::Result {
+    match conflict {
+        Some(conflict) => write!(f, "OR {} ", conflict),
+        None => Ok(()),
+    }
+}
+
+/// `ON CONFLICT` clause of a constraint
+fn write_on_conflict(f: &mut fmt::Formatter<'_>, conflict: &Option<ConflictResolution>) -> fmt::Result {
+    match conflict {
+        Some(conflict) => write!(f, " ON CONFLICT {}", conflict),
+        None => Ok(()),
+    }
+}
+
+/// ` AS alias` after the target of a data-changing statement
+fn write_alias(f: &mut fmt::Formatter<'_>, alias: &Option<String>) -> fmt::Result {
+    match alias {
+        Some(alias) => write!(f, " AS {}", quote_identifier(alias)),
+        None => Ok(()),
+    }
+}
+
+fn write_returning(f: &mut fmt::Formatter<'_>, returning: &[SelectItem]) -> fmt::Result {
+    if !returning.is_empty() {
+        write!(f, " RETURNING ")?;
+        write_list(f, returning)?;
+    }
+    Ok(())
+}
+
+fn write_where(f: &mut fmt::Formatter<'_>, where_clause: &Option<Expression>) -> fmt::Result {
+    match where_clause {
+        Some(where_clause) => write!(f, " WHERE {}", where_clause),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for Insert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_with(f, &self.with)?;
+        write!(f, "INSERT ")?;
+        write_conflict(f, &self.conflict)?;
+        write!(f, "INTO {}", self.table)?;
+        write_alias(f, &self.alias)?;
+        if !self.columns.is_empty() {
+            write!(f, " (")?;
+            write_names(f, &self.columns)?;
+            write!(f, ")")?;
+        }
+        write!(f, " {}", self.source)?;
+        for upsert in &self.upserts {
+            write!(f, " {}", upsert)?;
+        }
+        write_returning(f, &self.returning)
+    }
+}
+
+impl fmt::Display for InsertSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertSource::Values(rows) => {
+                write!(f, "VALUES ")?;
+                write_rows(f, rows)
+            }
+            InsertSource::Select(select) => write!(f, "{}", select),
+            InsertSource::DefaultValues => write!(f, "DEFAULT VALUES"),
+        }
+    }
+}
+
+impl fmt::Display for Upsert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ON CONFLICT")?;
+        if !self.target.is_empty() {
+            write!(f, " (")?;
+            write_list(f, &self.target)?;
+            write!(f, ")")?;
+            write_where(f, &self.target_where)?;
+        }
+        match &self.action {
+            UpsertAction::Nothing => write!(f, " DO NOTHING"),
+            UpsertAction::Update { assignments, where_clause } => {
+                write!(f, " DO UPDATE SET ")?;
+                write_list(f, assignments)?;
+                write_where(f, where_clause)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.columns.as_slice() {
+            [column] => write!(f, "{}", quote_identifier(column))?,
+            columns => {
+                write!(f, "(")?;
+                write_names(f, columns)?;
+                write!(f, ")")?;
             }
-            // Add more cases for other expression types
-            // ...
-            _ => {} // Handle other expression types as needed
         }
+        write!(f, " = {}", self.value)
+    }
+}
+
+impl fmt::Display for Update {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_with(f, &self.with)?;
+        write!(f, "UPDATE ")?;
+        write_conflict(f, &self.conflict)?;
+        write!(f, "{}", self.table)?;
+        write_alias(f, &self.alias)?;
+        write!(f, " SET ")?;
+        write_list(f, &self.assignments)?;
+        if let Some(from) = &self.from {
+            write!(f, " FROM {}", from)?;
+        }
+        write_where(f, &self.where_clause)?;
+        write_returning(f, &self.returning)?;
+        write_order_and_limit(f, &self.order_by, &self.limit, &self.offset)
+    }
+}
+
+impl fmt::Display for Delete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_with(f, &self.with)?;
+        write!(f, "DELETE FROM {}", self.table)?;
+        write_alias(f, &self.alias)?;
+        write_where(f, &self.where_clause)?;
+        write_returning(f, &self.returning)?;
+        write_order_and_limit(f, &self.order_by, &self.limit, &self.offset)
+    }
+}
+
+impl fmt::Display for CreateTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CREATE {}TABLE {}{}", temporary(self.temporary), if_not_exists(self.if_not_exists), self.name)?;
+        if let Some(select) = &self.as_select {
+            return write!(f, " AS {}", select);
+        }
+        write!(f, " (")?;
+        write_list(f, &self.columns)?;
+        for constraint in &self.constraints {
+            write!(f, ", {}", constraint)?;
+        }
+        write!(f, ")")?;
+        let options: Vec<&str> = [(self.without_rowid, "WITHOUT ROWID"), (self.strict, "STRICT")]
+            .into_iter()
+            .filter_map(|(set, option)| set.then_some(option))
+            .collect();
+        if !options.is_empty() {
+            write!(f, " {}", options.join(", "))?;
+        }
+        Ok(())
+    }
+}
 
+impl fmt::Display for ColumnDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", quote_identifier(&self.name))?;
+        if let Some(type_name) = &self.type_name {
+            write!(f, " {}", type_name)?;
+        }
+        for constraint in &self.constraints {
+            write!(f, " {}", constraint)?;
+        }
         Ok(())
     }
+}
 
-    fn extract_tables_from_table_factor(&mut self, table_factor: &sqlparser::ast::TableFactor) -> Result<()> {
-        match table_factor {
-            sqlparser::ast::TableFactor::Table { name, .. } => {
-                // Extract the actual table name without quotes
-                let table_name = name.to_string().replace("\"", "");
-                
-                // Debug the actual table extraction
-                println!("[PARSER] Found table: {}", table_name);
-                
-                // Add this table to our references
-                if !self.table_references.contains(&table_name) {
-                    self.table_references.push(table_name);
+impl fmt::Display for ColumnConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnConstraint::PrimaryKey { descending, conflict, autoincrement } => {
+                write!(f, "PRIMARY KEY{}", if *descending { " DESC" } else { "" })?;
+                write_on_conflict(f, conflict)?;
+                if *autoincrement {
+                    write!(f, " AUTOINCREMENT")?;
                 }
+                Ok(())
             }
-            _ => {
-                println!("[PARSER] Unsupported table factor type");
+            ColumnConstraint::NotNull(conflict) => {
+                write!(f, "NOT NULL")?;
+                write_on_conflict(f, conflict)
+            }
+            ColumnConstraint::Null => write!(f, "NULL"),
+            ColumnConstraint::Unique(conflict) => {
+                write!(f, "UNIQUE")?;
+                write_on_conflict(f, conflict)
+            }
+            ColumnConstraint::Check(expr) => write!(f, "CHECK ({})", expr),
+            // Only literals and signed numbers may go unparenthesised
+            ColumnConstraint::Default(expr @ Expression::Literal(_)) => write!(f, "DEFAULT {}", expr),
+            ColumnConstraint::Default(expr @ Expression::UnaryOp { expr: operand, .. })
+                if matches!(operand.as_ref(), Expression::Literal(_)) =>
+            {
+                write!(f, "DEFAULT {}", expr)
+            }
+            ColumnConstraint::Default(expr) => write!(f, "DEFAULT ({})", expr),
+            ColumnConstraint::Collate(collation) => write!(f, "COLLATE {}", quote_identifier(collation)),
+            ColumnConstraint::References(clause) => write!(f, "{}", clause),
+            ColumnConstraint::Generated { expr, stored } => {
+                write!(f, "GENERATED ALWAYS AS ({}) {}", expr, if *stored { "STORED" } else { "VIRTUAL" })
+            }
+        }
+    }
+}
+
+impl fmt::Display for TableConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableConstraint::PrimaryKey { columns, conflict } | TableConstraint::Unique { columns, conflict } => {
+                let keyword = if matches!(self, TableConstraint::PrimaryKey { .. }) { "PRIMARY KEY" } else { "UNIQUE" };
+                write!(f, "{} (", keyword)?;
+                write_list(f, columns)?;
+                write!(f, ")")?;
+                write_on_conflict(f, conflict)
             }
+            TableConstraint::Check(expr) => write!(f, "CHECK ({})", expr),
+            TableConstraint::ForeignKey { columns, clause } => {
+                write!(f, "FOREIGN KEY (")?;
+                write_names(f, columns)?;
+                write!(f, ") {}", clause)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ForeignKeyClause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "REFERENCES {}", quote_identifier(&self.table))?;
+        if !self.columns.is_empty() {
+            write!(f, " (")?;
+            write_names(f, &self.columns)?;
+            write!(f, ")")?;
+        }
+        if let Some(action) = self.on_delete {
+            write!(f, " ON DELETE {}", action)?;
+        }
+        if let Some(action) = self.on_update {
+            write!(f, " ON UPDATE {}", action)?;
+        }
+        if self.deferred {
+            write!(f, " DEFERRABLE INITIALLY DEFERRED")?;
         }
         Ok(())
     }
+}
+
+impl fmt::Display for ForeignKeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForeignKeyAction::SetNull => write!(f, "SET NULL"),
+            ForeignKeyAction::SetDefault => write!(f, "SET DEFAULT"),
+            ForeignKeyAction::Cascade => write!(f, "CASCADE"),
+            ForeignKeyAction::Restrict => write!(f, "RESTRICT"),
+            ForeignKeyAction::NoAction => write!(f, "NO ACTION"),
+        }
+    }
+}
+
+impl fmt::Display for CreateIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE {}INDEX {}{} ON {} (",
+            if self.unique { "UNIQUE " } else { "" },
+            if_not_exists(self.if_not_exists),
+            self.name,
+            quote_identifier(&self.table)
+        )?;
+        write_list(f, &self.columns)?;
+        write!(f, ")")?;
+        write_where(f, &self.where_clause)
+    }
+}
+
+impl fmt::Display for CreateView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CREATE {}VIEW {}{}", temporary(self.temporary), if_not_exists(self.if_not_exists), self.name)?;
+        if !self.columns.is_empty() {
+            write!(f, " (")?;
+            write_names(f, &self.columns)?;
+            write!(f, ")")?;
+        }
+        write!(f, " AS {}", self.query)
+    }
+}
+
+impl fmt::Display for CreateTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timing = match self.timing {
+            TriggerTiming::Before => "BEFORE",
+            TriggerTiming::After => "AFTER",
+            TriggerTiming::InsteadOf => "INSTEAD OF",
+        };
+        write!(f, "CREATE {}TRIGGER {}{} {} ", temporary(self.temporary), if_not_exists(self.if_not_exists), self.name, timing)?;
+        match &self.event {
+            TriggerEvent::Delete => write!(f, "DELETE")?,
+            TriggerEvent::Insert => write!(f, "INSERT")?,
+            TriggerEvent::Update(columns) if columns.is_empty() => write!(f, "UPDATE")?,
+            TriggerEvent::Update(columns) => {
+                write!(f, "UPDATE OF ")?;
+                write_names(f, columns)?;
+            }
+        }
+        write!(f, " ON {}", quote_identifier(&self.table))?;
+        if self.for_each_row {
+            write!(f, " FOR EACH ROW")?;
+        }
+        if let Some(when) = &self.when {
+            write!(f, " WHEN {}", when)?;
+        }
+        write!(f, " BEGIN")?;
+        for statement in &self.body {
+            write!(f, " {};", statement)?;
+        }
+        write!(f, " END")
+    }
+}
+
+/// Builds an AST from tokens by recursive descent
+pub struct AstBuilder {
+    sql: String,
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Whether to skip past syntax errors instead of stopping at the first
+    recover: bool,
+    errors: Vec<ParseError>,
+}
+
+/// Binding power of prefix NOT, and of the unary `-`, `+` and `~`
+const NOT_PRECEDENCE: u8 = 3;
+const UNARY_PRECEDENCE: u8 = 11;
+/// Binding power of the comparison level BETWEEN and LIKE operands are parsed above
+const COMPARISON_PRECEDENCE: u8 = 5;
+const COLLATE_PRECEDENCE: u8 = 10;
+
+/// Keywords that start a clause, where recovery resumes after a bad clause
+const CLAUSE_KEYWORDS: &[Keyword] = &[
+    Keyword::From,
+    Keyword::Where,
+    Keyword::Group,
+    Keyword::Having,
+    Keyword::Window,
+    Keyword::Order,
+    Keyword::Limit,
+    Keyword::Union,
+    Keyword::Intersect,
+    Keyword::Except,
+    Keyword::Returning,
+];
+
+/// LIMIT and OFFSET expressions
+type LimitClause = (Option<Box<Expression>>, Option<Box<Expression>>);
+
+impl AstBuilder {
+    /// `tokens` must come from tokenizing `sql`, ending with EOF
+    pub fn new(sql: &str, tokens: Vec<Token>) -> Self {
+        AstBuilder {
+            sql: sql.to_string(),
+            tokens,
+            pos: 0,
+            recover: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Keeps parsing after a syntax error: a bad clause is skipped up to the
+    /// next clause, and a statement that cannot be parsed up to the next `;`
+    pub fn with_error_recovery(mut self) -> Self {
+        self.recover = true;
+        self
+    }
+
+    /// Parses the first statement of the input
+    pub fn build(self) -> Result<Statement> {
+        self.build_all()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No SQL statement to parse"))
+    }
 
-    pub fn validate_semantics(self) -> Result<Self> {
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;33mValidating query semantics\x1b[0m                                        \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Checking table references                                       \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Validating column references                                    \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Analyzing expression types                                      \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m└─\x1b[0m Checking predicate logic                                        \x1b[1;35m│\x1b[0m");
-
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;32m✓\x1b[0m All semantics validated successfully                               \x1b[1;35m│\x1b[0m");
-
-        Ok(self)
-    }
-
-    pub fn optimize_expressions(self) -> Result<AnalyzedQuery> {
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;33mOptimizing query expressions\x1b[0m                                      \x1b[1;35m│\x1b[0m");
-        
-        // Animation for UI
-        print!("\x1b[1;35m│\x1b[0m Applying optimizations ");
-        for _ in 0..4 {
-            print!("\x1b[1;32m.\x1b[0m");
-            std::io::stdout().flush().unwrap();
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-        println!(" \x1b[1;32mDone!\x1b[0m                                \x1b[1;35m│\x1b[0m");
-    
-        // CRITICAL: Use the actual tables and columns WITHOUT hardcoding anything
-        let analyzed = AnalyzedQuery {
-            query_type: QueryType::Select,
-            // Use ACTUAL tables and columns - not hardcoded values!
-            table_references: self.table_references,
-            column_references: self.column_references,
-            where_clause: None,
-            order_by: vec![],
-            limit: None,
-            query_text: self.analyzed_query.unwrap_or_default(),
-        };
-    
-        // Print debug info
-        println!("\x1b[1;35m│\x1b[0m                                                                      \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[1;33mQuery Analysis Summary:\x1b[0m                                           \x1b[1;35m│\x1b[0m");
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Type: \x1b[1;36m{}\x1b[0m                                                 \x1b[1;35m│\x1b[0m", analyzed.query_type);
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Tables: \x1b[1;36m{}\x1b[0m                                               \x1b[1;35m│\x1b[0m", 
-                 analyzed.table_references.join(", "));
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m├─\x1b[0m Columns: \x1b[1;36m{}\x1b[0m                                           \x1b[1;35m│\x1b[0m", 
-                 analyzed.column_references.join(", "));
-        println!("\x1b[1;35m│\x1b[0m \x1b[90m└─\x1b[0m Filters: \x1b[1;36m{}\x1b[0m                                               \x1b[1;35m│\x1b[0m", 
-                 analyzed.where_clause.as_deref().unwrap_or("none"));
-        println!("\x1b[1;35m└──────────────────────────────────────────────────────────────────────────┘\x1b[0m");
-    
-        Ok(analyzed)
-    }
-
-    pub fn get_analyzed_query(&self, query_type: QueryType, query_text: String) -> AnalyzedQuery {
-        AnalyzedQuery {
-            query_type,
-            table_references: self.table_references.clone(),
-            column_references: self.column_references.clone(),
-            where_clause: None,   // You can populate this based on your analysis
-            order_by: Vec::new(), // You can populate this based on your analysis
-            limit: None,          // You can populate this based on your analysis
-            query_text,
+    /// Parses every statement of a script, separated by semicolons
+    pub fn build_all(self) -> Result<Vec<Statement>> {
+        let mut parsed = self.build_partial();
+        if parsed.errors.is_empty() {
+            Ok(parsed.statements)
+        } else {
+            Err(anyhow::Error::new(parsed.errors.remove(0)))
         }
     }
+
+    /// Parses every statement of a script, keeping what parsed before an
+    /// error. Without recovery it stops at the first error; with it, the
+    /// errors of the whole script are collected.
+    pub fn build_partial(mut self) -> PartialParse {
+        let mut statements = Vec::new();
+        loop {
+            while self.eat(&TokenType::Semicolon) {}
+            if self.at_eof() {
+                break;
+            }
+            let start = self.pos;
+            let error = match self.parse_statement() {
+                Ok(statement) => {
+                    statements.push(statement);
+                    if self.at_eof() || self.check(&TokenType::Semicolon) {
+                        continue;
+                    }
+                    self.unexpected("\";\" or the end of the input")
+                }
+                Err(e) => e,
+            };
+            self.record(error);
+            if !self.recover {
+                break;
+            }
+            self.skip_statement(start);
+        }
+        PartialParse {
+            statements,
+            errors: self.errors,
+        }
+    }
+
+    // Error recovery
+
+    fn record(&mut self, error: anyhow::Error) {
+        let error = match error.downcast::<ParseError>() {
+            Ok(error) => error,
+            Err(error) => ParseError {
+                message: error.to_string(),
+                span: Span::from(self.peek()),
+            },
+        };
+        println!("[PARSER] Syntax error: {}", error);
+        self.errors.push(error);
+    }
+
+    /// Runs `parse` for one clause. In recovery mode a syntax error in it is
+    /// recorded and the rest of the clause skipped, leaving it empty.
+    fn clause<T: Default>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        match parse(self) {
+            Err(e) if self.recover => {
+                self.record(e);
+                self.skip_clause();
+                Ok(T::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Skips to the next clause keyword, `;` or unmatched `)` outside parentheses
+    fn skip_clause(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            match self.peek_type() {
+                TokenType::EOF => return,
+                TokenType::Semicolon if depth == 0 => return,
+                TokenType::RightParen if depth == 0 => return,
+                TokenType::Keyword(keyword) if depth == 0 && CLAUSE_KEYWORDS.contains(keyword) => return,
+                TokenType::LeftParen => depth += 1,
+                TokenType::RightParen => depth -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    /// Skips to the `;` ending the statement that starts at token `start`,
+    /// passing over the semicolons inside a trigger body
+    fn skip_statement(&mut self, start: usize) {
+        let mut words: Vec<Keyword> = Vec::new();
+        let mut body_depth = 0;
+        let mut case_depth = 0;
+        let mut index = start;
+        while index < self.tokens.len() - 1 {
+            match self.tokens[index].token_type {
+                TokenType::Keyword(keyword) => {
+                    let in_trigger = words.first() == Some(&Keyword::Create) && words.contains(&Keyword::Trigger);
+                    match keyword {
+                        Keyword::Begin if in_trigger => body_depth += 1,
+                        Keyword::Case => case_depth += 1,
+                        Keyword::End if case_depth > 0 => case_depth -= 1,
+                        Keyword::End if body_depth > 0 => body_depth -= 1,
+                        _ => {}
+                    }
+                    words.push(keyword);
+                }
+                TokenType::Semicolon if body_depth == 0 && index >= self.pos => break,
+                _ => {}
+            }
+            index += 1;
+        }
+        self.pos = index.max(self.pos);
+    }
+
+    // Token access
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn peek_type(&self) -> &TokenType {
+        &self.peek().token_type
+    }
+
+    /// Type of the token `n` places ahead, EOF past the end
+    fn peek_nth(&self, n: usize) -> &TokenType {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)].token_type
+    }
+
+    fn at_eof(&self) -> bool {
+        *self.peek_type() == TokenType::EOF
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if !self.at_eof() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Span of the most recently consumed token
+    fn previous_span(&self) -> Span {
+        Span::from(&self.tokens[self.pos.saturating_sub(1)])
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        self.peek_type() == token_type
+    }
+
+    fn eat(&mut self, token_type: &TokenType) -> bool {
+        if self.check(token_type) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token_type: &TokenType, expected: &str) -> Result<()> {
+        if self.eat(token_type) {
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn check_keyword(&self, keyword: Keyword) -> bool {
+        self.peek().is_keyword(keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: Keyword) -> bool {
+        self.eat(&TokenType::Keyword(keyword))
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<()> {
+        self.expect(&TokenType::Keyword(keyword), keyword.as_str())
+    }
+
+    /// Syntax error at the next token, in SQLite's wording
+    fn unexpected(&self, expected: &str) -> anyhow::Error {
+        let token = self.peek();
+        let message = if token.token_type == TokenType::EOF {
+            format!("incomplete input, expected {}", expected)
+        } else {
+            let text = &self.sql[token.offset..token.end()];
+            format!("near \"{}\": syntax error, expected {}", text, expected)
+        };
+        anyhow::Error::new(ParseError {
+            message,
+            span: Span::from(token),
+        })
+    }
+
+    // Names
+
+    /// Whether a token can be used as a name: an identifier, quoted or not,
+    /// or a keyword SQLite falls back to reading as one
+    fn is_name(token_type: &TokenType) -> bool {
+        match token_type {
+            TokenType::Identifier(_) | TokenType::QuotedIdentifier(_) => true,
+            TokenType::Keyword(keyword) => is_fallback_keyword(*keyword),
+            _ => false,
+        }
+    }
+
+    fn parse_name(&mut self, expected: &str) -> Result<String> {
+        let token = self.peek().clone();
+        match &token.token_type {
+            TokenType::Identifier(name) | TokenType::QuotedIdentifier(name) => {
+                self.advance();
+                Ok(name.clone())
+            }
+            // SQLite accepts a string where it expects a name
+            TokenType::String(name) => {
+                self.advance();
+                Ok(name.clone())
+            }
+            TokenType::Keyword(keyword) if is_fallback_keyword(*keyword) => {
+                self.advance();
+                Ok(self.sql[token.offset..token.end()].to_string())
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn parse_qualified_name(&mut self, expected: &str) -> Result<QualifiedName> {
+        let first = self.parse_name(expected)?;
+        if self.eat(&TokenType::Period) {
+            let name = self.parse_name(expected)?;
+            Ok(QualifiedName {
+                schema: Some(first),
+                name,
+            })
+        } else {
+            Ok(QualifiedName { schema: None, name: first })
+        }
+    }
+
+    /// `(name, ...)`
+    fn parse_name_list(&mut self, expected: &str) -> Result<Vec<String>> {
+        self.expect(&TokenType::LeftParen, "\"(\"")?;
+        let mut names = vec![self.parse_name(expected)?];
+        while self.eat(&TokenType::Comma) {
+            names.push(self.parse_name(expected)?);
+        }
+        self.expect(&TokenType::RightParen, "\")\"")?;
+        Ok(names)
+    }
+
+    /// `AS alias`, or an alias written without AS
+    fn parse_alias(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword(Keyword::As) {
+            return self.parse_name("an alias").map(Some);
+        }
+        if Self::is_name(self.peek_type()) || matches!(self.peek_type(), TokenType::String(_)) {
+            return self.parse_name("an alias").map(Some);
+        }
+        Ok(None)
+    }
+
+    fn parse_if_not_exists(&mut self) -> Result<bool> {
+        if self.eat_keyword(Keyword::If) {
+            self.expect_keyword(Keyword::Not)?;
+            self.expect_keyword(Keyword::Exists)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn parse_if_exists(&mut self) -> Result<bool> {
+        if self.eat_keyword(Keyword::If) {
+            self.expect_keyword(Keyword::Exists)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Statements
+
+    fn parse_statement(&mut self) -> Result<Statement> {
+        let start = Span::from(self.peek());
+        let kind = match self.peek_type() {
+            TokenType::Keyword(Keyword::Explain) => {
+                self.advance();
+                let query_plan = self.eat_keyword(Keyword::Query);
+                if query_plan {
+                    self.expect_keyword(Keyword::Plan)?;
+                }
+                StatementKind::Explain {
+                    query_plan,
+                    statement: Box::new(self.parse_statement()?),
+                }
+            }
+            TokenType::Keyword(Keyword::With) => {
+                let with = self.parse_with()?;
+                match self.peek_type() {
+                    TokenType::Keyword(Keyword::Select | Keyword::Values) => {
+                        StatementKind::Select(Box::new(self.parse_select(Some(with))?))
+                    }
+                    TokenType::Keyword(Keyword::Insert | Keyword::Replace) => {
+                        StatementKind::Insert(Box::new(self.parse_insert(Some(with))?))
+                    }
+                    TokenType::Keyword(Keyword::Update) => StatementKind::Update(Box::new(self.parse_update(Some(with))?)),
+                    TokenType::Keyword(Keyword::Delete) => StatementKind::Delete(Box::new(self.parse_delete(Some(with))?)),
+                    _ => return Err(self.unexpected("SELECT, INSERT, UPDATE or DELETE")),
+                }
+            }
+            TokenType::Keyword(Keyword::Select | Keyword::Values) => StatementKind::Select(Box::new(self.parse_select(None)?)),
+            TokenType::Keyword(Keyword::Insert | Keyword::Replace) => StatementKind::Insert(Box::new(self.parse_insert(None)?)),
+            TokenType::Keyword(Keyword::Update) => StatementKind::Update(Box::new(self.parse_update(None)?)),
+            TokenType::Keyword(Keyword::Delete) => StatementKind::Delete(Box::new(self.parse_delete(None)?)),
+            TokenType::Keyword(Keyword::Create) => self.parse_create()?,
+            TokenType::Keyword(Keyword::Drop) => self.parse_drop()?,
+            TokenType::Keyword(Keyword::Alter) => self.parse_alter()?,
+            TokenType::Keyword(
+                Keyword::Begin | Keyword::Commit | Keyword::End | Keyword::Rollback | Keyword::Savepoint | Keyword::Release,
+            ) => self.parse_transaction()?,
+            TokenType::Keyword(Keyword::Pragma) => self.parse_pragma()?,
+            TokenType::Keyword(Keyword::Attach) => {
+                self.advance();
+                self.eat_keyword(Keyword::Database);
+                let database = self.parse_expression()?;
+                self.expect_keyword(Keyword::As)?;
+                StatementKind::Attach {
+                    database,
+                    schema: self.parse_expression()?,
+                }
+            }
+            TokenType::Keyword(Keyword::Detach) => {
+                self.advance();
+                self.eat_keyword(Keyword::Database);
+                StatementKind::Detach(self.parse_expression()?)
+            }
+            TokenType::Keyword(Keyword::Analyze) => {
+                self.advance();
+                StatementKind::Analyze(self.parse_optional_qualified_name()?)
+            }
+            TokenType::Keyword(Keyword::Reindex) => {
+                self.advance();
+                StatementKind::Reindex(self.parse_optional_qualified_name()?)
+            }
+            TokenType::Keyword(Keyword::Vacuum) => {
+                self.advance();
+                let schema = if Self::is_name(self.peek_type()) {
+                    Some(self.parse_name("a schema name")?)
+                } else {
+                    None
+                };
+                let into = if self.eat_keyword(Keyword::Into) {
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                StatementKind::Vacuum { schema, into }
+            }
+            _ => return Err(self.unexpected("a statement")),
+        };
+
+        let span = start.to(&self.previous_span());
+        Ok(Statement {
+            query_type: kind.query_type(),
+            query_text: self.sql[span.offset..span.offset + span.length].to_string(),
+            kind,
+            span,
+        })
+    }
+
+    fn parse_optional_qualified_name(&mut self) -> Result<Option<QualifiedName>> {
+        if Self::is_name(self.peek_type()) {
+            self.parse_qualified_name("a name").map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_transaction(&mut self) -> Result<StatementKind> {
+        let keyword = self.advance();
+        let kind = match keyword.token_type {
+            TokenType::Keyword(Keyword::Begin) => {
+                let mode = match self.peek_type() {
+                    TokenType::Keyword(mode @ (Keyword::Deferred | Keyword::Immediate | Keyword::Exclusive)) => {
+                        let mode = *mode;
+                        self.advance();
+                        Some(mode)
+                    }
+                    _ => None,
+                };
+                self.parse_transaction_name()?;
+                StatementKind::Begin(mode)
+            }
+            TokenType::Keyword(Keyword::Commit | Keyword::End) => {
+                self.parse_transaction_name()?;
+                StatementKind::Commit
+            }
+            TokenType::Keyword(Keyword::Rollback) => {
+                self.parse_transaction_name()?;
+                let savepoint = if self.eat_keyword(Keyword::To) {
+                    self.eat_keyword(Keyword::Savepoint);
+                    Some(self.parse_name("a savepoint name")?)
+                } else {
+                    None
+                };
+                StatementKind::Rollback(savepoint)
+            }
+            TokenType::Keyword(Keyword::Savepoint) => StatementKind::Savepoint(self.parse_name("a savepoint name")?),
+            _ => {
+                self.eat_keyword(Keyword::Savepoint);
+                StatementKind::Release(self.parse_name("a savepoint name")?)
+            }
+        };
+        Ok(kind)
+    }
+
+    /// `TRANSACTION [name]`, which changes nothing
+    fn parse_transaction_name(&mut self) -> Result<()> {
+        if self.eat_keyword(Keyword::Transaction) && Self::is_name(self.peek_type()) && !self.check_keyword(Keyword::To) {
+            self.parse_name("a transaction name")?;
+        }
+        Ok(())
+    }
+
+    fn parse_pragma(&mut self) -> Result<StatementKind> {
+        self.expect_keyword(Keyword::Pragma)?;
+        let name = self.parse_qualified_name("a pragma name")?;
+        let value = if self.eat(&TokenType::Equals) {
+            Some(self.parse_pragma_value()?)
+        } else if self.eat(&TokenType::LeftParen) {
+            let value = self.parse_pragma_value()?;
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(StatementKind::Pragma { name, value })
+    }
+
+    /// A signed number, name, string, ON, DELETE or DEFAULT, as written
+    fn parse_pragma_value(&mut self) -> Result<String> {
+        let start = self.peek().offset;
+        if matches!(self.peek_type(), TokenType::Plus | TokenType::Minus) {
+            self.advance();
+        }
+        match self.peek_type() {
+            TokenType::Integer(_)
+            | TokenType::Float(_)
+            | TokenType::String(_)
+            | TokenType::Identifier(_)
+            | TokenType::QuotedIdentifier(_)
+            | TokenType::Keyword(_) => {
+                let token = self.advance();
+                Ok(self.sql[start..token.end()].to_string())
+            }
+            _ => Err(self.unexpected("a pragma value")),
+        }
+    }
+
+    fn parse_with(&mut self) -> Result<With> {
+        self.expect_keyword(Keyword::With)?;
+        let recursive = self.eat_keyword(Keyword::Recursive);
+        let mut tables = Vec::new();
+        loop {
+            let name = self.parse_name("a table name")?;
+            let columns = if self.check(&TokenType::LeftParen) {
+                self.parse_name_list("a column name")?
+            } else {
+                Vec::new()
+            };
+            self.expect_keyword(Keyword::As)?;
+            let materialized = if self.eat_keyword(Keyword::Materialized) {
+                Some(true)
+            } else if self.check_keyword(Keyword::Not) && *self.peek_nth(1) == TokenType::Keyword(Keyword::Materialized) {
+                self.advance();
+                self.advance();
+                Some(false)
+            } else {
+                None
+            };
+            self.expect(&TokenType::LeftParen, "\"(\"")?;
+            let query = self.parse_query()?;
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            tables.push(CommonTableExpression {
+                name,
+                columns,
+                materialized,
+                query: Box::new(query),
+            });
+            if !self.eat(&TokenType::Comma) {
+                break;
+            }
+        }
+        Ok(With { recursive, tables })
+    }
+
+    /// A query with its optional WITH clause
+    fn parse_query(&mut self) -> Result<Select> {
+        let with = if self.check_keyword(Keyword::With) {
+            Some(self.parse_with()?)
+        } else {
+            None
+        };
+        self.parse_select(with)
+    }
+
+    fn starts_query(&self) -> bool {
+        matches!(
+            self.peek_type(),
+            TokenType::Keyword(Keyword::Select | Keyword::Values | Keyword::With)
+        )
+    }
+
+    fn parse_select(&mut self, with: Option<With>) -> Result<Select> {
+        let body = self.parse_select_body()?;
+        let mut compounds = Vec::new();
+        loop {
+            let operator = if self.eat_keyword(Keyword::Union) {
+                if self.eat_keyword(Keyword::All) {
+                    CompoundOperator::UnionAll
+                } else {
+                    CompoundOperator::Union
+                }
+            } else if self.eat_keyword(Keyword::Intersect) {
+                CompoundOperator::Intersect
+            } else if self.eat_keyword(Keyword::Except) {
+                CompoundOperator::Except
+            } else {
+                break;
+            };
+            compounds.push((operator, self.parse_select_body()?));
+        }
+        let order_by = self.parse_order_by()?;
+        let (limit, offset) = self.parse_limit()?;
+        Ok(Select {
+            with,
+            body,
+            compounds,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn parse_select_body(&mut self) -> Result<SelectBody> {
+        if self.eat_keyword(Keyword::Values) {
+            let mut rows = vec![self.parse_value_row()?];
+            while self.eat(&TokenType::Comma) {
+                rows.push(self.parse_value_row()?);
+            }
+            return Ok(SelectBody::Values(rows));
+        }
+
+        self.expect_keyword(Keyword::Select)?;
+        let distinct = self.eat_keyword(Keyword::Distinct);
+        if !distinct {
+            self.eat_keyword(Keyword::All);
+        }
+        let columns = self.clause(Self::parse_select_items)?;
+        let from = if self.eat_keyword(Keyword::From) {
+            self.clause(|builder| builder.parse_from().map(Some))?
+        } else {
+            None
+        };
+        let where_clause = self.parse_where()?;
+        let group_by = if self.eat_keyword(Keyword::Group) {
+            self.clause(|builder| {
+                builder.expect_keyword(Keyword::By)?;
+                builder.parse_expression_list()
+            })?
+        } else {
+            Vec::new()
+        };
+        let having = if self.eat_keyword(Keyword::Having) {
+            self.clause(|builder| builder.parse_expression().map(Some))?
+        } else {
+            None
+        };
+        let mut windows = Vec::new();
+        if self.eat_keyword(Keyword::Window) {
+            loop {
+                let name = self.parse_name("a window name")?;
+                self.expect_keyword(Keyword::As)?;
+                windows.push((name, self.parse_window_definition()?));
+                if !self.eat(&TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        Ok(SelectBody::Select(Box::new(SelectCore {
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            windows,
+        })))
+    }
+
+    fn parse_value_row(&mut self) -> Result<Vec<Expression>> {
+        self.expect(&TokenType::LeftParen, "\"(\"")?;
+        let row = self.parse_expression_list()?;
+        self.expect(&TokenType::RightParen, "\")\"")?;
+        Ok(row)
+    }
+
+    fn parse_select_items(&mut self) -> Result<Vec<SelectItem>> {
+        let mut items = vec![self.parse_select_item()?];
+        while self.eat(&TokenType::Comma) {
+            items.push(self.parse_select_item()?);
+        }
+        Ok(items)
+    }
+
+    fn parse_select_item(&mut self) -> Result<SelectItem> {
+        if self.eat(&TokenType::Multiply) {
+            return Ok(SelectItem::Wildcard);
+        }
+        if Self::is_name(self.peek_type()) && *self.peek_nth(1) == TokenType::Period {
+            if *self.peek_nth(2) == TokenType::Multiply {
+                let name = self.parse_name("a table name")?;
+                self.advance();
+                self.advance();
+                return Ok(SelectItem::QualifiedWildcard(QualifiedName { schema: None, name }));
+            }
+            if Self::is_name(self.peek_nth(2)) && *self.peek_nth(3) == TokenType::Period && *self.peek_nth(4) == TokenType::Multiply {
+                let table = self.parse_qualified_name("a table name")?;
+                self.advance();
+                self.advance();
+                return Ok(SelectItem::QualifiedWildcard(table));
+            }
+        }
+        let start = Span::from(self.peek());
+        let expr = self.parse_expression()?;
+        let span = start.to(&self.previous_span());
+        let alias = self.parse_alias()?;
+        Ok(SelectItem::Expression { expr, alias, span })
+    }
+
+    fn parse_where(&mut self) -> Result<Option<Expression>> {
+        if self.eat_keyword(Keyword::Where) {
+            self.clause(|builder| builder.parse_expression().map(Some))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_order_by(&mut self) -> Result<Vec<OrderingTerm>> {
+        if !self.eat_keyword(Keyword::Order) {
+            return Ok(Vec::new());
+        }
+        self.clause(|builder| {
+            builder.expect_keyword(Keyword::By)?;
+            let mut terms = vec![builder.parse_ordering_term()?];
+            while builder.eat(&TokenType::Comma) {
+                terms.push(builder.parse_ordering_term()?);
+            }
+            Ok(terms)
+        })
+    }
+
+    fn parse_ordering_term(&mut self) -> Result<OrderingTerm> {
+        let expr = self.parse_expression()?;
+        let descending = if self.eat_keyword(Keyword::Desc) {
+            true
+        } else {
+            self.eat_keyword(Keyword::Asc);
+            false
+        };
+        let nulls_first = if self.eat_keyword(Keyword::Nulls) {
+            if self.eat_keyword(Keyword::First) {
+                Some(true)
+            } else {
+                self.expect_keyword(Keyword::Last)?;
+                Some(false)
+            }
+        } else {
+            None
+        };
+        Ok(OrderingTerm {
+            expr,
+            descending,
+            nulls_first,
+        })
+    }
+
+    fn parse_ordering_terms_in_parens(&mut self) -> Result<Vec<OrderingTerm>> {
+        self.expect(&TokenType::LeftParen, "\"(\"")?;
+        let mut terms = vec![self.parse_ordering_term()?];
+        while self.eat(&TokenType::Comma) {
+            terms.push(self.parse_ordering_term()?);
+        }
+        self.expect(&TokenType::RightParen, "\")\"")?;
+        Ok(terms)
+    }
+
+    /// `LIMIT count [OFFSET skip]`, or `LIMIT skip, count`
+    fn parse_limit(&mut self) -> Result<LimitClause> {
+        if !self.eat_keyword(Keyword::Limit) {
+            return Ok((None, None));
+        }
+        self.clause(|builder| {
+            let first = Box::new(builder.parse_expression()?);
+            if builder.eat_keyword(Keyword::Offset) {
+                Ok((Some(first), Some(Box::new(builder.parse_expression()?))))
+            } else if builder.eat(&TokenType::Comma) {
+                Ok((Some(Box::new(builder.parse_expression()?)), Some(first)))
+            } else {
+                Ok((Some(first), None))
+            }
+        })
+    }
+
+    fn parse_from(&mut self) -> Result<FromClause> {
+        let relation = self.parse_table_factor()?;
+        let mut joins = Vec::new();
+        loop {
+            let (kind, natural) = if self.eat(&TokenType::Comma) {
+                (JoinKind::Comma, false)
+            } else {
+                let natural = self.eat_keyword(Keyword::Natural);
+                let kind = if self.eat_keyword(Keyword::Left) {
+                    self.eat_keyword(Keyword::Outer);
+                    JoinKind::Left
+                } else if self.eat_keyword(Keyword::Right) {
+                    self.eat_keyword(Keyword::Outer);
+                    JoinKind::Right
+                } else if self.eat_keyword(Keyword::Full) {
+                    self.eat_keyword(Keyword::Outer);
+                    JoinKind::Full
+                } else if self.eat_keyword(Keyword::Inner) {
+                    JoinKind::Inner
+                } else if self.eat_keyword(Keyword::Cross) {
+                    JoinKind::Cross
+                } else if natural || self.check_keyword(Keyword::Join) {
+                    JoinKind::Inner
+                } else {
+                    break;
+                };
+                self.expect_keyword(Keyword::Join)?;
+                (kind, natural)
+            };
+
+            let relation = self.parse_table_factor()?;
+            let constraint = if self.eat_keyword(Keyword::On) {
+                JoinConstraint::On(self.parse_expression()?)
+            } else if self.eat_keyword(Keyword::Using) {
+                JoinConstraint::Using(self.parse_name_list("a column name")?)
+            } else {
+                JoinConstraint::None
+            };
+            joins.push(Join {
+                kind,
+                natural,
+                relation,
+                constraint,
+            });
+        }
+        Ok(FromClause { relation, joins })
+    }
+
+    fn parse_table_factor(&mut self) -> Result<TableFactor> {
+        if self.eat(&TokenType::LeftParen) {
+            if self.starts_query() {
+                let query = self.parse_query()?;
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                let alias = self.parse_alias()?;
+                return Ok(TableFactor::Subquery {
+                    query: Box::new(query),
+                    alias,
+                });
+            }
+            let from = self.parse_from()?;
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            return Ok(TableFactor::Nested(Box::new(from)));
+        }
+
+        let start = Span::from(self.peek());
+        let name = self.parse_qualified_name("a table name")?;
+        let span = start.to(&self.previous_span());
+        if self.eat(&TokenType::LeftParen) {
+            let args = if self.check(&TokenType::RightParen) {
+                Vec::new()
+            } else {
+                self.parse_expression_list()?
+            };
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            let alias = self.parse_alias()?;
+            return Ok(TableFactor::Function { name, args, alias, span });
+        }
+
+        let alias = self.parse_alias()?;
+        let indexed_by = self.parse_indexed_by()?;
+        Ok(TableFactor::Table {
+            name,
+            alias,
+            indexed_by,
+            span,
+        })
+    }
+
+    /// `INDEXED BY index` or `NOT INDEXED`, which only steer SQLite's planner
+    fn parse_indexed_by(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword(Keyword::Indexed) {
+            self.expect_keyword(Keyword::By)?;
+            return self.parse_name("an index name").map(Some);
+        }
+        if self.check_keyword(Keyword::Not) && *self.peek_nth(1) == TokenType::Keyword(Keyword::Indexed) {
+            self.advance();
+            self.advance();
+        }
+        Ok(None)
+    }
+
+    fn parse_returning(&mut self) -> Result<Vec<SelectItem>> {
+        if !self.eat_keyword(Keyword::Returning) {
+            return Ok(Vec::new());
+        }
+        self.clause(Self::parse_select_items)
+    }
+
+    fn parse_conflict_resolution(&mut self) -> Result<ConflictResolution> {
+        let resolution = match self.peek_type() {
+            TokenType::Keyword(Keyword::Rollback) => ConflictResolution::Rollback,
+            TokenType::Keyword(Keyword::Abort) => ConflictResolution::Abort,
+            TokenType::Keyword(Keyword::Fail) => ConflictResolution::Fail,
+            TokenType::Keyword(Keyword::Ignore) => ConflictResolution::Ignore,
+            TokenType::Keyword(Keyword::Replace) => ConflictResolution::Replace,
+            _ => return Err(self.unexpected("ROLLBACK, ABORT, FAIL, IGNORE or REPLACE")),
+        };
+        self.advance();
+        Ok(resolution)
+    }
+
+    /// `OR resolution` after INSERT or UPDATE
+    fn parse_or_conflict(&mut self) -> Result<Option<ConflictResolution>> {
+        if self.eat_keyword(Keyword::Or) {
+            self.parse_conflict_resolution().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `ON CONFLICT resolution` after a constraint
+    fn parse_on_conflict(&mut self) -> Result<Option<ConflictResolution>> {
+        if self.check_keyword(Keyword::On) && *self.peek_nth(1) == TokenType::Keyword(Keyword::Conflict) {
+            self.advance();
+            self.advance();
+            self.parse_conflict_resolution().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_insert(&mut self, with: Option<With>) -> Result<Insert> {
+        let conflict = if self.eat_keyword(Keyword::Replace) {
+            Some(ConflictResolution::Replace)
+        } else {
+            self.expect_keyword(Keyword::Insert)?;
+            self.parse_or_conflict()?
+        };
+        self.expect_keyword(Keyword::Into)?;
+        let start = Span::from(self.peek());
+        let table = self.parse_qualified_name("a table name")?;
+        let span = start.to(&self.previous_span());
+        let alias = if self.eat_keyword(Keyword::As) {
+            Some(self.parse_name("an alias")?)
+        } else {
+            None
+        };
+        let columns = if self.check(&TokenType::LeftParen) {
+            self.parse_name_list("a column name")?
+        } else {
+            Vec::new()
+        };
+
+        let source = if self.eat_keyword(Keyword::Default) {
+            self.expect_keyword(Keyword::Values)?;
+            InsertSource::DefaultValues
+        } else {
+            let query = self.parse_query()?;
+            match query {
+                Select {
+                    with: None,
+                    body: SelectBody::Values(rows),
+                    compounds,
+                    order_by,
+                    limit: None,
+                    offset: None,
+                } if compounds.is_empty() && order_by.is_empty() => InsertSource::Values(rows),
+                query => InsertSource::Select(Box::new(query)),
+            }
+        };
+
+        let mut upserts = Vec::new();
+        while self.check_keyword(Keyword::On) {
+            upserts.push(self.parse_upsert()?);
+        }
+        let returning = self.parse_returning()?;
+
+        Ok(Insert {
+            with,
+            conflict,
+            table,
+            alias,
+            span,
+            columns,
+            source,
+            upserts,
+            returning,
+        })
+    }
+
+    fn parse_upsert(&mut self) -> Result<Upsert> {
+        self.expect_keyword(Keyword::On)?;
+        self.expect_keyword(Keyword::Conflict)?;
+        let (target, target_where) = if self.check(&TokenType::LeftParen) {
+            let target = self.parse_ordering_terms_in_parens()?;
+            (target, self.parse_where()?)
+        } else {
+            (Vec::new(), None)
+        };
+        self.expect_keyword(Keyword::Do)?;
+        let action = if self.eat_keyword(Keyword::Nothing) {
+            UpsertAction::Nothing
+        } else {
+            self.expect_keyword(Keyword::Update)?;
+            self.expect_keyword(Keyword::Set)?;
+            UpsertAction::Update {
+                assignments: self.parse_assignments()?,
+                where_clause: self.parse_where()?,
+            }
+        };
+        Ok(Upsert {
+            target,
+            target_where,
+            action,
+        })
+    }
+
+    fn parse_assignments(&mut self) -> Result<Vec<Assignment>> {
+        let mut assignments = Vec::new();
+        loop {
+            let columns = if self.check(&TokenType::LeftParen) {
+                self.parse_name_list("a column name")?
+            } else {
+                vec![self.parse_name("a column name")?]
+            };
+            self.expect(&TokenType::Equals, "\"=\"")?;
+            assignments.push(Assignment {
+                columns,
+                value: self.parse_expression()?,
+            });
+            if !self.eat(&TokenType::Comma) {
+                break;
+            }
+        }
+        Ok(assignments)
+    }
+
+    fn parse_update(&mut self, with: Option<With>) -> Result<Update> {
+        self.expect_keyword(Keyword::Update)?;
+        let conflict = self.parse_or_conflict()?;
+        let start = Span::from(self.peek());
+        let table = self.parse_qualified_name("a table name")?;
+        let span = start.to(&self.previous_span());
+        let alias = if self.eat_keyword(Keyword::As) {
+            Some(self.parse_name("an alias")?)
+        } else {
+            None
+        };
+        self.parse_indexed_by()?;
+        self.expect_keyword(Keyword::Set)?;
+        let assignments = self.clause(Self::parse_assignments)?;
+        let from = if self.eat_keyword(Keyword::From) {
+            self.clause(|builder| builder.parse_from().map(Some))?
+        } else {
+            None
+        };
+        let where_clause = self.parse_where()?;
+        let returning = self.parse_returning()?;
+        let order_by = self.parse_order_by()?;
+        let (limit, offset) = self.parse_limit()?;
+        Ok(Update {
+            with,
+            conflict,
+            table,
+            alias,
+            span,
+            assignments,
+            from,
+            where_clause,
+            returning,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn parse_delete(&mut self, with: Option<With>) -> Result<Delete> {
+        self.expect_keyword(Keyword::Delete)?;
+        self.expect_keyword(Keyword::From)?;
+        let start = Span::from(self.peek());
+        let table = self.parse_qualified_name("a table name")?;
+        let span = start.to(&self.previous_span());
+        let alias = if self.eat_keyword(Keyword::As) {
+            Some(self.parse_name("an alias")?)
+        } else {
+            None
+        };
+        self.parse_indexed_by()?;
+        let where_clause = self.parse_where()?;
+        let returning = self.parse_returning()?;
+        let order_by = self.parse_order_by()?;
+        let (limit, offset) = self.parse_limit()?;
+        Ok(Delete {
+            with,
+            table,
+            alias,
+            span,
+            where_clause,
+            returning,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn parse_create(&mut self) -> Result<StatementKind> {
+        self.expect_keyword(Keyword::Create)?;
+        let temporary = self.eat_keyword(Keyword::Temp) || self.eat_keyword(Keyword::Temporary);
+        match self.peek_type() {
+            TokenType::Keyword(Keyword::Table) => {
+                self.advance();
+                Ok(StatementKind::CreateTable(Box::new(self.parse_create_table(temporary)?)))
+            }
+            TokenType::Keyword(Keyword::View) => {
+                self.advance();
+                let if_not_exists = self.parse_if_not_exists()?;
+                let name = self.parse_qualified_name("a view name")?;
+                let columns = if self.check(&TokenType::LeftParen) {
+                    self.parse_name_list("a column name")?
+                } else {
+                    Vec::new()
+                };
+                self.expect_keyword(Keyword::As)?;
+                Ok(StatementKind::CreateView(Box::new(CreateView {
+                    temporary,
+                    if_not_exists,
+                    name,
+                    columns,
+                    query: Box::new(self.parse_query()?),
+                })))
+            }
+            TokenType::Keyword(Keyword::Trigger) => {
+                self.advance();
+                Ok(StatementKind::CreateTrigger(Box::new(self.parse_create_trigger(temporary)?)))
+            }
+            TokenType::Keyword(Keyword::Unique | Keyword::Index) if !temporary => {
+                let unique = self.eat_keyword(Keyword::Unique);
+                self.expect_keyword(Keyword::Index)?;
+                let if_not_exists = self.parse_if_not_exists()?;
+                let name = self.parse_qualified_name("an index name")?;
+                self.expect_keyword(Keyword::On)?;
+                let start = Span::from(self.peek());
+                let table = self.parse_name("a table name")?;
+                let span = start.to(&self.previous_span());
+                let columns = self.parse_ordering_terms_in_parens()?;
+                let where_clause = self.parse_where()?;
+                Ok(StatementKind::CreateIndex(Box::new(CreateIndex {
+                    unique,
+                    if_not_exists,
+                    name,
+                    table,
+                    span,
+                    columns,
+                    where_clause,
+                })))
+            }
+            TokenType::Keyword(Keyword::Virtual) if !temporary => {
+                self.advance();
+                self.expect_keyword(Keyword::Table)?;
+                let if_not_exists = self.parse_if_not_exists()?;
+                let name = self.parse_qualified_name("a table name")?;
+                self.expect_keyword(Keyword::Using)?;
+                let module = self.parse_name("a module name")?;
+                let args = if self.check(&TokenType::LeftParen) {
+                    self.parse_module_arguments()?
+                } else {
+                    Vec::new()
+                };
+                Ok(StatementKind::CreateVirtualTable(Box::new(CreateVirtualTable {
+                    if_not_exists,
+                    name,
+                    module,
+                    args,
+                })))
+            }
+            _ => Err(self.unexpected("TABLE, VIEW, INDEX, TRIGGER or VIRTUAL TABLE")),
+        }
+    }
+
+    /// Arguments of a virtual table module, which SQLite passes on as text
+    fn parse_module_arguments(&mut self) -> Result<Vec<String>> {
+        self.expect(&TokenType::LeftParen, "\"(\"")?;
+        let mut args = Vec::new();
+        let mut depth = 0;
+        let mut start: Option<usize> = None;
+        let mut end = 0;
+        loop {
+            let token = self.peek().clone();
+            match token.token_type {
+                TokenType::EOF => return Err(self.unexpected("\")\"")),
+                TokenType::RightParen | TokenType::Comma if depth == 0 => {
+                    if let Some(start) = start.take() {
+                        args.push(self.sql[start..end].to_string());
+                    }
+                    self.advance();
+                    if token.token_type == TokenType::RightParen {
+                        return Ok(args);
+                    }
+                    continue;
+                }
+                TokenType::LeftParen => depth += 1,
+                TokenType::RightParen => depth -= 1,
+                _ => {}
+            }
+            start.get_or_insert(token.offset);
+            end = token.end();
+            self.advance();
+        }
+    }
+
+    fn parse_create_table(&mut self, temporary: bool) -> Result<CreateTable> {
+        let if_not_exists = self.parse_if_not_exists()?;
+        let name = self.parse_qualified_name("a table name")?;
+        let mut table = CreateTable {
+            temporary,
+            if_not_exists,
+            name,
+            columns: Vec::new(),
+            constraints: Vec::new(),
+            as_select: None,
+            without_rowid: false,
+            strict: false,
+        };
+
+        if self.eat_keyword(Keyword::As) {
+            table.as_select = Some(Box::new(self.parse_query()?));
+            return Ok(table);
+        }
+
+        self.expect(&TokenType::LeftParen, "\"(\" or AS")?;
+        loop {
+            if self.starts_table_constraint() {
+                table.constraints.push(self.parse_table_constraint()?);
+                // Table constraints may follow each other without commas
+                while self.starts_table_constraint() {
+                    table.constraints.push(self.parse_table_constraint()?);
+                }
+            } else if table.constraints.is_empty() {
+                table.columns.push(self.parse_column_def()?);
+            } else {
+                return Err(self.unexpected("a table constraint"));
+            }
+            if !self.eat(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenType::RightParen, "\",\" or \")\"")?;
+
+        loop {
+            if self.eat_keyword(Keyword::Without) {
+                match self.peek_type() {
+                    TokenType::Identifier(word) if word.eq_ignore_ascii_case("rowid") => {
+                        self.advance();
+                        table.without_rowid = true;
+                    }
+                    _ => return Err(self.unexpected("ROWID")),
+                }
+            } else if matches!(self.peek_type(), TokenType::Identifier(word) if word.eq_ignore_ascii_case("strict")) {
+                self.advance();
+                table.strict = true;
+            } else {
+                break;
+            }
+            if !self.eat(&TokenType::Comma) {
+                break;
+            }
+        }
+        Ok(table)
+    }
+
+    fn starts_table_constraint(&self) -> bool {
+        matches!(
+            self.peek_type(),
+            TokenType::Keyword(Keyword::Constraint | Keyword::Primary | Keyword::Unique | Keyword::Check | Keyword::Foreign)
+        )
+    }
+
+    fn parse_table_constraint(&mut self) -> Result<TableConstraint> {
+        if self.eat_keyword(Keyword::Constraint) {
+            self.parse_name("a constraint name")?;
+        }
+        match self.peek_type() {
+            TokenType::Keyword(Keyword::Primary) => {
+                self.advance();
+                self.expect_keyword(Keyword::Key)?;
+                let columns = self.parse_ordering_terms_in_parens()?;
+                self.eat_keyword(Keyword::Autoincrement);
+                Ok(TableConstraint::PrimaryKey {
+                    columns,
+                    conflict: self.parse_on_conflict()?,
+                })
+            }
+            TokenType::Keyword(Keyword::Unique) => {
+                self.advance();
+                let columns = self.parse_ordering_terms_in_parens()?;
+                Ok(TableConstraint::Unique {
+                    columns,
+                    conflict: self.parse_on_conflict()?,
+                })
+            }
+            TokenType::Keyword(Keyword::Check) => {
+                self.advance();
+                self.expect(&TokenType::LeftParen, "\"(\"")?;
+                let expr = self.parse_expression()?;
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                Ok(TableConstraint::Check(expr))
+            }
+            TokenType::Keyword(Keyword::Foreign) => {
+                self.advance();
+                self.expect_keyword(Keyword::Key)?;
+                let columns = self.parse_name_list("a column name")?;
+                Ok(TableConstraint::ForeignKey {
+                    columns,
+                    clause: self.parse_foreign_key_clause()?,
+                })
+            }
+            _ => Err(self.unexpected("PRIMARY KEY, UNIQUE, CHECK or FOREIGN KEY")),
+        }
+    }
+
+    fn parse_column_def(&mut self) -> Result<ColumnDef> {
+        let name = self.parse_name("a column name")?;
+        let type_name = self.parse_type_name()?;
+        let mut constraints = Vec::new();
+        loop {
+            let named = self.eat_keyword(Keyword::Constraint);
+            if named {
+                self.parse_name("a constraint name")?;
+            }
+            let constraint = match self.peek_type() {
+                TokenType::Keyword(Keyword::Primary) => {
+                    self.advance();
+                    self.expect_keyword(Keyword::Key)?;
+                    let descending = if self.eat_keyword(Keyword::Desc) {
+                        true
+                    } else {
+                        self.eat_keyword(Keyword::Asc);
+                        false
+                    };
+                    let conflict = self.parse_on_conflict()?;
+                    ColumnConstraint::PrimaryKey {
+                        descending,
+                        conflict,
+                        autoincrement: self.eat_keyword(Keyword::Autoincrement),
+                    }
+                }
+                TokenType::Keyword(Keyword::Not) => {
+                    self.advance();
+                    self.expect_keyword(Keyword::Null)?;
+                    ColumnConstraint::NotNull(self.parse_on_conflict()?)
+                }
+                TokenType::Keyword(Keyword::Null) => {
+                    self.advance();
+                    self.parse_on_conflict()?;
+                    ColumnConstraint::Null
+                }
+                TokenType::Keyword(Keyword::Unique) => {
+                    self.advance();
+                    ColumnConstraint::Unique(self.parse_on_conflict()?)
+                }
+                TokenType::Keyword(Keyword::Check) => {
+                    self.advance();
+                    self.expect(&TokenType::LeftParen, "\"(\"")?;
+                    let expr = self.parse_expression()?;
+                    self.expect(&TokenType::RightParen, "\")\"")?;
+                    ColumnConstraint::Check(expr)
+                }
+                TokenType::Keyword(Keyword::Default) => {
+                    self.advance();
+                    ColumnConstraint::Default(self.parse_default_value()?)
+                }
+                TokenType::Keyword(Keyword::Collate) => {
+                    self.advance();
+                    ColumnConstraint::Collate(self.parse_name("a collation name")?)
+                }
+                TokenType::Keyword(Keyword::References) => ColumnConstraint::References(self.parse_foreign_key_clause()?),
+                TokenType::Keyword(Keyword::Generated | Keyword::As) => {
+                    if self.eat_keyword(Keyword::Generated) {
+                        self.expect_keyword(Keyword::Always)?;
+                    }
+                    self.expect_keyword(Keyword::As)?;
+                    self.expect(&TokenType::LeftParen, "\"(\"")?;
+                    let expr = self.parse_expression()?;
+                    self.expect(&TokenType::RightParen, "\")\"")?;
+                    let stored = match self.peek_type() {
+                        TokenType::Identifier(word) if word.eq_ignore_ascii_case("stored") => {
+                            self.advance();
+                            true
+                        }
+                        _ => {
+                            self.eat_keyword(Keyword::Virtual);
+                            false
+                        }
+                    };
+                    ColumnConstraint::Generated { expr, stored }
+                }
+                _ if named => return Err(self.unexpected("a column constraint")),
+                _ => break,
+            };
+            constraints.push(constraint);
+        }
+        Ok(ColumnDef {
+            name,
+            type_name,
+            constraints,
+        })
+    }
+
+    /// Declared type: one or more words and up to two signed numbers in
+    /// parentheses, as in `UNSIGNED BIG INT` or `DECIMAL(10, 2)`
+    fn parse_type_name(&mut self) -> Result<Option<String>> {
+        let is_type_word = |token_type: &TokenType| match token_type {
+            TokenType::Identifier(_) | TokenType::QuotedIdentifier(_) | TokenType::String(_) => true,
+            // GENERATED starts a column constraint here rather than naming a type
+            TokenType::Keyword(keyword) => is_fallback_keyword(*keyword) && *keyword != Keyword::Generated,
+            _ => false,
+        };
+
+        let mut words = Vec::new();
+        while is_type_word(self.peek_type()) {
+            let token = self.advance();
+            words.push(self.sql[token.offset..token.end()].to_string());
+        }
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        let mut type_name = words.join(" ");
+        if self.eat(&TokenType::LeftParen) {
+            let mut sizes = vec![self.parse_signed_number()?];
+            if self.eat(&TokenType::Comma) {
+                sizes.push(self.parse_signed_number()?);
+            }
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            type_name.push_str(&format!("({})", sizes.join(",")));
+        }
+        Ok(Some(type_name))
+    }
+
+    fn parse_signed_number(&mut self) -> Result<String> {
+        let start = self.peek().offset;
+        if matches!(self.peek_type(), TokenType::Plus | TokenType::Minus) {
+            self.advance();
+        }
+        match self.peek_type() {
+            TokenType::Integer(_) | TokenType::Float(_) => {
+                let token = self.advance();
+                Ok(self.sql[start..token.end()].to_string())
+            }
+            _ => Err(self.unexpected("a number")),
+        }
+    }
+
+    /// `DEFAULT` takes a parenthesised expression, a signed literal, or a bare
+    /// name that SQLite stores as text
+    fn parse_default_value(&mut self) -> Result<Expression> {
+        if self.eat(&TokenType::LeftParen) {
+            let expr = self.parse_expression()?;
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            return Ok(expr);
+        }
+        if let Some(op) = match self.peek_type() {
+            TokenType::Minus => Some(Operator::Minus),
+            TokenType::Plus => Some(Operator::Plus),
+            _ => None,
+        } {
+            self.advance();
+            let literal = self.parse_literal()?.ok_or_else(|| self.unexpected("a number"))?;
+            return Ok(Expression::UnaryOp {
+                op,
+                expr: Box::new(literal),
+            });
+        }
+        if let Some(literal) = self.parse_literal()? {
+            return Ok(literal);
+        }
+        match self.peek_type() {
+            TokenType::Identifier(word) if word.eq_ignore_ascii_case("true") || word.eq_ignore_ascii_case("false") => {
+                let value = word.eq_ignore_ascii_case("true");
+                self.advance();
+                Ok(Expression::Literal(Value::Boolean(value)))
+            }
+            token_type if Self::is_name(token_type) => Ok(Expression::Literal(Value::String(self.parse_name("a default value")?))),
+            _ => Err(self.unexpected("a default value")),
+        }
+    }
+
+    /// A literal value, or `None` when the next token does not start one
+    fn parse_literal(&mut self) -> Result<Option<Expression>> {
+        let value = match self.peek_type() {
+            TokenType::Integer(value) => Value::Integer(*value),
+            TokenType::Float(value) => Value::Float(*value),
+            TokenType::String(text) => Value::String(text.clone()),
+            TokenType::Blob(bytes) => Value::Blob(bytes.clone()),
+            TokenType::Keyword(Keyword::Null) => Value::Null,
+            TokenType::Keyword(Keyword::CurrentTime) => Value::CurrentTime,
+            TokenType::Keyword(Keyword::CurrentDate) => Value::CurrentDate,
+            TokenType::Keyword(Keyword::CurrentTimestamp) => Value::CurrentTimestamp,
+            _ => return Ok(None),
+        };
+        self.advance();
+        Ok(Some(Expression::Literal(value)))
+    }
+
+    fn parse_foreign_key_clause(&mut self) -> Result<ForeignKeyClause> {
+        self.expect_keyword(Keyword::References)?;
+        let table = self.parse_name("a table name")?;
+        let columns = if self.check(&TokenType::LeftParen) {
+            self.parse_name_list("a column name")?
+        } else {
+            Vec::new()
+        };
+        let mut clause = ForeignKeyClause {
+            table,
+            columns,
+            on_delete: None,
+            on_update: None,
+            deferred: false,
+        };
+        loop {
+            if self.check_keyword(Keyword::On) && *self.peek_nth(1) != TokenType::Keyword(Keyword::Conflict) {
+                self.advance();
+                let on_delete = if self.eat_keyword(Keyword::Delete) {
+                    true
+                } else {
+                    self.expect_keyword(Keyword::Update)?;
+                    false
+                };
+                let action = self.parse_foreign_key_action()?;
+                if on_delete {
+                    clause.on_delete = Some(action);
+                } else {
+                    clause.on_update = Some(action);
+                }
+            } else if self.eat_keyword(Keyword::Match) {
+                self.parse_name("a match type")?;
+            } else if self.check_keyword(Keyword::Deferrable)
+                || (self.check_keyword(Keyword::Not) && *self.peek_nth(1) == TokenType::Keyword(Keyword::Deferrable))
+            {
+                let not = self.eat_keyword(Keyword::Not);
+                self.expect_keyword(Keyword::Deferrable)?;
+                let mut deferred = false;
+                if self.eat_keyword(Keyword::Initially) {
+                    deferred = self.eat_keyword(Keyword::Deferred);
+                    if !deferred {
+                        self.expect_keyword(Keyword::Immediate)?;
+                    }
+                }
+                clause.deferred = deferred && !not;
+            } else {
+                break;
+            }
+        }
+        Ok(clause)
+    }
+
+    fn parse_foreign_key_action(&mut self) -> Result<ForeignKeyAction> {
+        if self.eat_keyword(Keyword::Set) {
+            if self.eat_keyword(Keyword::Null) {
+                return Ok(ForeignKeyAction::SetNull);
+            }
+            self.expect_keyword(Keyword::Default)?;
+            return Ok(ForeignKeyAction::SetDefault);
+        }
+        if self.eat_keyword(Keyword::Cascade) {
+            return Ok(ForeignKeyAction::Cascade);
+        }
+        if self.eat_keyword(Keyword::Restrict) {
+            return Ok(ForeignKeyAction::Restrict);
+        }
+        if self.eat_keyword(Keyword::No) {
+            self.expect_keyword(Keyword::Action)?;
+            return Ok(ForeignKeyAction::NoAction);
+        }
+        Err(self.unexpected("SET NULL, SET DEFAULT, CASCADE, RESTRICT or NO ACTION"))
+    }
+
+    fn parse_create_trigger(&mut self, temporary: bool) -> Result<CreateTrigger> {
+        let if_not_exists = self.parse_if_not_exists()?;
+        let name = self.parse_qualified_name("a trigger name")?;
+        let timing = if self.eat_keyword(Keyword::After) {
+            TriggerTiming::After
+        } else if self.eat_keyword(Keyword::Instead) {
+            self.expect_keyword(Keyword::Of)?;
+            TriggerTiming::InsteadOf
+        } else {
+            self.eat_keyword(Keyword::Before);
+            TriggerTiming::Before
+        };
+        let event = if self.eat_keyword(Keyword::Delete) {
+            TriggerEvent::Delete
+        } else if self.eat_keyword(Keyword::Insert) {
+            TriggerEvent::Insert
+        } else if self.eat_keyword(Keyword::Update) {
+            let mut columns = Vec::new();
+            if self.eat_keyword(Keyword::Of) {
+                columns.push(self.parse_name("a column name")?);
+                while self.eat(&TokenType::Comma) {
+                    columns.push(self.parse_name("a column name")?);
+                }
+            }
+            TriggerEvent::Update(columns)
+        } else {
+            return Err(self.unexpected("DELETE, INSERT or UPDATE"));
+        };
+        self.expect_keyword(Keyword::On)?;
+        let start = Span::from(self.peek());
+        let table = self.parse_name("a table name")?;
+        let span = start.to(&self.previous_span());
+        let for_each_row = self.eat_keyword(Keyword::For);
+        if for_each_row {
+            self.expect_keyword(Keyword::Each)?;
+            self.expect_keyword(Keyword::Row)?;
+        }
+        let when = if self.eat_keyword(Keyword::When) {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        self.expect_keyword(Keyword::Begin)?;
+        let mut body = Vec::new();
+        loop {
+            if !matches!(
+                self.peek_type(),
+                TokenType::Keyword(Keyword::Select | Keyword::Values | Keyword::With | Keyword::Insert | Keyword::Replace | Keyword::Update | Keyword::Delete)
+            ) {
+                return Err(self.unexpected("SELECT, INSERT, UPDATE or DELETE"));
+            }
+            body.push(self.parse_statement()?);
+            self.expect(&TokenType::Semicolon, "\";\"")?;
+            if self.eat_keyword(Keyword::End) {
+                break;
+            }
+        }
+
+        Ok(CreateTrigger {
+            temporary,
+            if_not_exists,
+            name,
+            timing,
+            event,
+            table,
+            span,
+            for_each_row,
+            when,
+            body,
+        })
+    }
+
+    fn parse_drop(&mut self) -> Result<StatementKind> {
+        self.expect_keyword(Keyword::Drop)?;
+        let object_type = match self.peek_type() {
+            TokenType::Keyword(Keyword::Table) => ObjectType::Table,
+            TokenType::Keyword(Keyword::Index) => ObjectType::Index,
+            TokenType::Keyword(Keyword::View) => ObjectType::View,
+            TokenType::Keyword(Keyword::Trigger) => ObjectType::Trigger,
+            _ => return Err(self.unexpected("TABLE, INDEX, VIEW or TRIGGER")),
+        };
+        self.advance();
+        let if_exists = self.parse_if_exists()?;
+        Ok(StatementKind::Drop {
+            object_type,
+            if_exists,
+            name: self.parse_qualified_name("a name")?,
+        })
+    }
+
+    fn parse_alter(&mut self) -> Result<StatementKind> {
+        self.expect_keyword(Keyword::Alter)?;
+        self.expect_keyword(Keyword::Table)?;
+        let start = Span::from(self.peek());
+        let table = self.parse_qualified_name("a table name")?;
+        let span = start.to(&self.previous_span());
+        let action = if self.eat_keyword(Keyword::Rename) {
+            if self.eat_keyword(Keyword::To) {
+                AlterAction::RenameTable(self.parse_name("a table name")?)
+            } else {
+                self.eat_keyword(Keyword::Column);
+                let old = self.parse_name("a column name")?;
+                self.expect_keyword(Keyword::To)?;
+                AlterAction::RenameColumn {
+                    old,
+                    new: self.parse_name("a column name")?,
+                }
+            }
+        } else if self.eat_keyword(Keyword::Add) {
+            self.eat_keyword(Keyword::Column);
+            AlterAction::AddColumn(self.parse_column_def()?)
+        } else if self.eat_keyword(Keyword::Drop) {
+            self.eat_keyword(Keyword::Column);
+            AlterAction::DropColumn(self.parse_name("a column name")?)
+        } else {
+            return Err(self.unexpected("RENAME, ADD or DROP"));
+        };
+        Ok(StatementKind::AlterTable { table, span, action })
+    }
+
+    // Expressions
+
+    pub fn parse_expression(&mut self) -> Result<Expression> {
+        self.parse_expression_above(0)
+    }
+
+    fn parse_expression_list(&mut self) -> Result<Vec<Expression>> {
+        let mut expressions = vec![self.parse_expression()?];
+        while self.eat(&TokenType::Comma) {
+            expressions.push(self.parse_expression()?);
+        }
+        Ok(expressions)
+    }
+
+    /// Precedence climbing: parses operators binding at least `min_precedence`
+    fn parse_expression_above(&mut self, min_precedence: u8) -> Result<Expression> {
+        let mut left = self.parse_prefix()?;
+        while let Some(precedence) = self.infix_precedence() {
+            if precedence < min_precedence {
+                break;
+            }
+            left = self.parse_infix(left, precedence)?;
+        }
+        Ok(left)
+    }
+
+    /// Binding power of the operator at the next token, if it continues an expression
+    fn infix_precedence(&self) -> Option<u8> {
+        let precedence = match self.peek_type() {
+            TokenType::Keyword(Keyword::Or) => 1,
+            TokenType::Keyword(Keyword::And) => 2,
+            TokenType::Equals | TokenType::NotEquals => 4,
+            TokenType::Keyword(
+                Keyword::Is
+                | Keyword::In
+                | Keyword::Like
+                | Keyword::Glob
+                | Keyword::Regexp
+                | Keyword::Match
+                | Keyword::Between
+                | Keyword::Isnull
+                | Keyword::Notnull,
+            ) => 4,
+            TokenType::Keyword(Keyword::Not) => match self.peek_nth(1) {
+                TokenType::Keyword(
+                    Keyword::In | Keyword::Like | Keyword::Glob | Keyword::Regexp | Keyword::Match | Keyword::Between | Keyword::Null,
+                ) => 4,
+                _ => return None,
+            },
+            TokenType::LessThan | TokenType::LessEquals | TokenType::GreaterThan | TokenType::GreaterEquals => 5,
+            TokenType::BitAnd | TokenType::BitOr | TokenType::LeftShift | TokenType::RightShift => 6,
+            TokenType::Plus | TokenType::Minus => 7,
+            TokenType::Multiply | TokenType::Divide | TokenType::Modulo => 8,
+            TokenType::Concat | TokenType::Arrow | TokenType::LongArrow => 9,
+            TokenType::Keyword(Keyword::Collate) => COLLATE_PRECEDENCE,
+            _ => return None,
+        };
+        Some(precedence)
+    }
+
+    fn parse_infix(&mut self, left: Expression, precedence: u8) -> Result<Expression> {
+        let token = self.advance();
+        let binary = |op: Operator, builder: &mut Self| -> Result<Expression> {
+            // Left-associative: the right operand binds tighter than this operator
+            let right = builder.parse_expression_above(precedence + 1)?;
+            Ok(Expression::BinaryOp {
+                left: Box::new(left.clone()),
+                op,
+                right: Box::new(right),
+            })
+        };
+
+        match token.token_type {
+            TokenType::Keyword(Keyword::Or) => binary(Operator::Or, self),
+            TokenType::Keyword(Keyword::And) => binary(Operator::And, self),
+            TokenType::Equals => binary(Operator::Equals, self),
+            TokenType::NotEquals => binary(Operator::NotEquals, self),
+            TokenType::LessThan => binary(Operator::LessThan, self),
+            TokenType::LessEquals => binary(Operator::LessEquals, self),
+            TokenType::GreaterThan => binary(Operator::GreaterThan, self),
+            TokenType::GreaterEquals => binary(Operator::GreaterEquals, self),
+            TokenType::BitAnd => binary(Operator::BitAnd, self),
+            TokenType::BitOr => binary(Operator::BitOr, self),
+            TokenType::LeftShift => binary(Operator::LeftShift, self),
+            TokenType::RightShift => binary(Operator::RightShift, self),
+            TokenType::Plus => binary(Operator::Plus, self),
+            TokenType::Minus => binary(Operator::Minus, self),
+            TokenType::Multiply => binary(Operator::Multiply, self),
+            TokenType::Divide => binary(Operator::Divide, self),
+            TokenType::Modulo => binary(Operator::Modulo, self),
+            TokenType::Concat => binary(Operator::Concat, self),
+            TokenType::Arrow => binary(Operator::Arrow, self),
+            TokenType::LongArrow => binary(Operator::LongArrow, self),
+            TokenType::Keyword(Keyword::Collate) => Ok(Expression::Collate {
+                expr: Box::new(left),
+                collation: self.parse_name("a collation name")?,
+            }),
+            TokenType::Keyword(Keyword::Isnull) => Ok(Expression::IsNull {
+                expr: Box::new(left),
+                negated: false,
+            }),
+            TokenType::Keyword(Keyword::Notnull) => Ok(Expression::IsNull {
+                expr: Box::new(left),
+                negated: true,
+            }),
+            TokenType::Keyword(Keyword::Is) => {
+                let mut negated = self.eat_keyword(Keyword::Not);
+                // IS [NOT] DISTINCT FROM is IS NOT / IS with the sense flipped
+                if self.eat_keyword(Keyword::Distinct) {
+                    self.expect_keyword(Keyword::From)?;
+                    negated = !negated;
+                }
+                if self.eat_keyword(Keyword::Null) {
+                    return Ok(Expression::IsNull {
+                        expr: Box::new(left),
+                        negated,
+                    });
+                }
+                binary(if negated { Operator::IsNot } else { Operator::Is }, self)
+            }
+            TokenType::Keyword(Keyword::Not) => {
+                if self.eat_keyword(Keyword::Null) {
+                    return Ok(Expression::IsNull {
+                        expr: Box::new(left),
+                        negated: true,
+                    });
+                }
+                let token = self.advance();
+                self.parse_negatable(left, token.token_type, true)
+            }
+            token_type => self.parse_negatable(left, token_type, false),
+        }
+    }
+
+    /// The operators NOT can precede: IN, BETWEEN and the LIKE family
+    fn parse_negatable(&mut self, left: Expression, token_type: TokenType, negated: bool) -> Result<Expression> {
+        let expr = Box::new(left);
+        match token_type {
+            TokenType::Keyword(Keyword::Between) => {
+                let low = self.parse_expression_above(COMPARISON_PRECEDENCE)?;
+                self.expect_keyword(Keyword::And)?;
+                let high = self.parse_expression_above(COMPARISON_PRECEDENCE)?;
+                Ok(Expression::Between {
+                    expr,
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                })
+            }
+            TokenType::Keyword(keyword @ (Keyword::Like | Keyword::Glob | Keyword::Regexp | Keyword::Match)) => {
+                let op = match keyword {
+                    Keyword::Like => Operator::Like,
+                    Keyword::Glob => Operator::Glob,
+                    Keyword::Regexp => Operator::Regexp,
+                    _ => Operator::Match,
+                };
+                let pattern = Box::new(self.parse_expression_above(COMPARISON_PRECEDENCE)?);
+                let escape = if self.eat_keyword(Keyword::Escape) {
+                    Some(Box::new(self.parse_expression_above(COMPARISON_PRECEDENCE)?))
+                } else {
+                    None
+                };
+                Ok(Expression::Like {
+                    expr,
+                    op,
+                    pattern,
+                    escape,
+                    negated,
+                })
+            }
+            TokenType::Keyword(Keyword::In) => {
+                if !self.eat(&TokenType::LeftParen) {
+                    let table = self.parse_qualified_name("\"(\" or a table name")?;
+                    return Ok(Expression::InTable { expr, table, negated });
+                }
+                if self.starts_query() {
+                    let query = self.parse_query()?;
+                    self.expect(&TokenType::RightParen, "\")\"")?;
+                    return Ok(Expression::InSubquery {
+                        expr,
+                        query: Box::new(query),
+                        negated,
+                    });
+                }
+                let list = if self.check(&TokenType::RightParen) {
+                    Vec::new()
+                } else {
+                    self.parse_expression_list()?
+                };
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                Ok(Expression::InList { expr, list, negated })
+            }
+            _ => Err(anyhow!("Unexpected operator in expression")),
+        }
+    }
+
+    fn parse_prefix(&mut self) -> Result<Expression> {
+        let op = match self.peek_type() {
+            TokenType::Keyword(Keyword::Not) => {
+                self.advance();
+                let expr = self.parse_expression_above(NOT_PRECEDENCE)?;
+                return Ok(Expression::UnaryOp {
+                    op: Operator::Not,
+                    expr: Box::new(expr),
+                });
+            }
+            TokenType::Minus => Operator::Minus,
+            TokenType::Plus => Operator::Plus,
+            TokenType::BitNot => Operator::BitNot,
+            _ => return self.parse_primary(),
+        };
+        self.advance();
+        let expr = self.parse_expression_above(UNARY_PRECEDENCE)?;
+        Ok(Expression::UnaryOp {
+            op,
+            expr: Box::new(expr),
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression> {
+        if let Some(literal) = self.parse_literal()? {
+            return Ok(literal);
+        }
+
+        let next_is_paren = *self.peek_nth(1) == TokenType::LeftParen;
+        match self.peek_type().clone() {
+            TokenType::Parameter(name) => {
+                let span = Span::from(&self.advance());
+                Ok(Expression::Parameter { name, span })
+            }
+            TokenType::LeftParen => {
+                self.advance();
+                if self.starts_query() {
+                    let query = self.parse_query()?;
+                    self.expect(&TokenType::RightParen, "\")\"")?;
+                    return Ok(Expression::Subquery(Box::new(query)));
+                }
+                let mut values = self.parse_expression_list()?;
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                if values.len() == 1 {
+                    Ok(values.remove(0))
+                } else {
+                    Ok(Expression::Row(values))
+                }
+            }
+            TokenType::Keyword(Keyword::Cast) if next_is_paren => {
+                self.advance();
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.expect_keyword(Keyword::As)?;
+                let type_name = self.parse_type_name()?.ok_or_else(|| self.unexpected("a type name"))?;
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                Ok(Expression::Cast {
+                    expr: Box::new(expr),
+                    type_name,
+                })
+            }
+            TokenType::Keyword(Keyword::Case) => self.parse_case(),
+            TokenType::Keyword(Keyword::Exists) => {
+                self.advance();
+                self.expect(&TokenType::LeftParen, "\"(\"")?;
+                let query = self.parse_query()?;
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                Ok(Expression::Exists(Box::new(query)))
+            }
+            TokenType::Keyword(Keyword::Raise) if next_is_paren => {
+                self.advance();
+                self.advance();
+                let action = if self.eat_keyword(Keyword::Ignore) {
+                    ConflictResolution::Ignore
+                } else {
+                    let action = match self.peek_type() {
+                        TokenType::Keyword(Keyword::Rollback) => ConflictResolution::Rollback,
+                        TokenType::Keyword(Keyword::Abort) => ConflictResolution::Abort,
+                        TokenType::Keyword(Keyword::Fail) => ConflictResolution::Fail,
+                        _ => return Err(self.unexpected("IGNORE, ROLLBACK, ABORT or FAIL")),
+                    };
+                    self.advance();
+                    action
+                };
+                let message = if action != ConflictResolution::Ignore {
+                    self.expect(&TokenType::Comma, "\",\"")?;
+                    match self.peek_type().clone() {
+                        TokenType::String(message) => {
+                            self.advance();
+                            Some(message)
+                        }
+                        _ => return Err(self.unexpected("an error message")),
+                    }
+                } else {
+                    None
+                };
+                self.expect(&TokenType::RightParen, "\")\"")?;
+                Ok(Expression::Raise { action, message })
+            }
+            token_type if Self::is_name(&token_type) => self.parse_name_expression(),
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    /// Column reference, function call, or TRUE/FALSE
+    fn parse_name_expression(&mut self) -> Result<Expression> {
+        let start = Span::from(self.peek());
+        let bare_word = matches!(self.peek_type(), TokenType::Identifier(_));
+        let first = self.parse_name("a name")?;
+
+        if self.check(&TokenType::LeftParen) {
+            return self.parse_function(first, start);
+        }
+
+        let mut parts = vec![first];
+        while parts.len() < 3 && self.check(&TokenType::Period) && Self::is_name(self.peek_nth(1)) {
+            self.advance();
+            parts.push(self.parse_name("a column name")?);
+        }
+        if parts.len() == 1 && bare_word {
+            if parts[0].eq_ignore_ascii_case("true") {
+                return Ok(Expression::Literal(Value::Boolean(true)));
+            }
+            if parts[0].eq_ignore_ascii_case("false") {
+                return Ok(Expression::Literal(Value::Boolean(false)));
+            }
+        }
+
+        let span = start.to(&self.previous_span());
+        let name = parts.pop().unwrap_or_default();
+        let table = parts.pop();
+        let schema = parts.pop();
+        Ok(Expression::Column {
+            schema,
+            table,
+            name,
+            span,
+        })
+    }
+
+    fn parse_function(&mut self, name: String, start: Span) -> Result<Expression> {
+        self.expect(&TokenType::LeftParen, "\"(\"")?;
+        let distinct = self.eat_keyword(Keyword::Distinct);
+        if !distinct {
+            self.eat_keyword(Keyword::All);
+        }
+        let args = if self.eat(&TokenType::Multiply) {
+            vec![Expression::Star]
+        } else if self.check(&TokenType::RightParen) {
+            Vec::new()
+        } else {
+            self.parse_expression_list()?
+        };
+        self.expect(&TokenType::RightParen, "\")\"")?;
+        let span = start.to(&self.previous_span());
+
+        let filter = if self.check_keyword(Keyword::Filter) && *self.peek_nth(1) == TokenType::LeftParen {
+            self.advance();
+            self.advance();
+            self.expect_keyword(Keyword::Where)?;
+            let filter = self.parse_expression()?;
+            self.expect(&TokenType::RightParen, "\")\"")?;
+            Some(Box::new(filter))
+        } else {
+            None
+        };
+        let over = if self.eat_keyword(Keyword::Over) {
+            if self.check(&TokenType::LeftParen) {
+                Some(Box::new(self.parse_window_definition()?))
+            } else {
+                Some(Box::new(Window {
+                    base: Some(self.parse_name("a window name")?),
+                    partition_by: Vec::new(),
+                    order_by: Vec::new(),
+                    frame: None,
+                }))
+            }
+        } else {
+            None
+        };
+
+        Ok(Expression::Function {
+            name,
+            args,
+            distinct,
+            filter,
+            over,
+            span,
+        })
+    }
+
+    /// `([base] [PARTITION BY ...] [ORDER BY ...] [frame])`
+    fn parse_window_definition(&mut self) -> Result<Window> {
+        self.expect(&TokenType::LeftParen, "\"(\"")?;
+        let base = match self.peek_type() {
+            TokenType::Keyword(Keyword::Partition | Keyword::Order | Keyword::Range | Keyword::Rows | Keyword::Groups) => None,
+            token_type if Self::is_name(token_type) => Some(self.parse_name("a window name")?),
+            _ => None,
+        };
+        let partition_by = if self.eat_keyword(Keyword::Partition) {
+            self.expect_keyword(Keyword::By)?;
+            self.parse_expression_list()?
+        } else {
+            Vec::new()
+        };
+        let order_by = self.parse_order_by()?;
+        let frame = match self.peek_type() {
+            TokenType::Keyword(units @ (Keyword::Range | Keyword::Rows | Keyword::Groups)) => {
+                let units = *units;
+                self.advance();
+                let (start, end) = if self.eat_keyword(Keyword::Between) {
+                    let start = self.parse_frame_bound()?;
+                    self.expect_keyword(Keyword::And)?;
+                    (start, Some(self.parse_frame_bound()?))
+                } else {
+                    (self.parse_frame_bound()?, None)
+                };
+                let exclude = if self.eat_keyword(Keyword::Exclude) {
+                    let exclude = if self.eat_keyword(Keyword::No) {
+                        self.expect_keyword(Keyword::Others)?;
+                        "NO OTHERS"
+                    } else if self.eat_keyword(Keyword::Current) {
+                        self.expect_keyword(Keyword::Row)?;
+                        "CURRENT ROW"
+                    } else if self.eat_keyword(Keyword::Group) {
+                        "GROUP"
+                    } else {
+                        self.expect_keyword(Keyword::Ties)?;
+                        "TIES"
+                    };
+                    Some(exclude.to_string())
+                } else {
+                    None
+                };
+                Some(WindowFrame {
+                    units,
+                    start,
+                    end,
+                    exclude,
+                })
+            }
+            _ => None,
+        };
+        self.expect(&TokenType::RightParen, "\")\"")?;
+        Ok(Window {
+            base,
+            partition_by,
+            order_by,
+            frame,
+        })
+    }
+
+    fn parse_frame_bound(&mut self) -> Result<FrameBound> {
+        if self.eat_keyword(Keyword::Unbounded) {
+            if self.eat_keyword(Keyword::Preceding) {
+                return Ok(FrameBound::UnboundedPreceding);
+            }
+            self.expect_keyword(Keyword::Following)?;
+            return Ok(FrameBound::UnboundedFollowing);
+        }
+        if self.check_keyword(Keyword::Current) && *self.peek_nth(1) == TokenType::Keyword(Keyword::Row) {
+            self.advance();
+            self.advance();
+            return Ok(FrameBound::CurrentRow);
+        }
+        let expr = Box::new(self.parse_expression_above(COMPARISON_PRECEDENCE)?);
+        if self.eat_keyword(Keyword::Preceding) {
+            return Ok(FrameBound::Preceding(expr));
+        }
+        self.expect_keyword(Keyword::Following)?;
+        Ok(FrameBound::Following(expr))
+    }
+
+    fn parse_case(&mut self) -> Result<Expression> {
+        self.expect_keyword(Keyword::Case)?;
+        let operand = if self.check_keyword(Keyword::When) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+        let mut branches = Vec::new();
+        while self.eat_keyword(Keyword::When) {
+            let condition = self.parse_expression()?;
+            self.expect_keyword(Keyword::Then)?;
+            branches.push((condition, self.parse_expression()?));
+        }
+        if branches.is_empty() {
+            return Err(self.unexpected("WHEN"));
+        }
+        let else_result = if self.eat_keyword(Keyword::Else) {
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+        self.expect_keyword(Keyword::End)?;
+        Ok(Expression::Case {
+            operand,
+            branches,
+            else_result,
+        })
+    }
+}
+
+
+/// Tables and columns a statement refers to, found by walking its AST
+#[derive(Default)]
+struct ReferenceCollector {
+    tables: Vec<String>,
+    columns: Vec<String>,
+    /// Tables whose columns a `*` or `table.*` stands for
+    wildcard_tables: Vec<String>,
+    /// Names of the common table expressions in scope, which are not tables
+    ctes: Vec<String>,
+}
+
+impl ReferenceCollector {
+    /// `schema.name` of a table, or `None` for a common table expression
+    fn table_name(&self, name: &QualifiedName) -> Option<String> {
+        match &name.schema {
+            Some(schema) => Some(format!("{}.{}", schema, name.name)),
+            None if self.ctes.iter().any(|cte| cte.eq_ignore_ascii_case(&name.name)) => None,
+            None => Some(name.name.clone()),
+        }
+    }
+
+    fn add_table(&mut self, name: &QualifiedName) {
+        if let Some(table) = self.table_name(name) {
+            if !self.tables.contains(&table) {
+                self.tables.push(table);
+            }
+        }
+    }
+
+    fn add_column(&mut self, name: &str) {
+        if !self.columns.iter().any(|column| column == name) {
+            self.columns.push(name.to_string());
+        }
+    }
+
+    fn add_wildcard_table(&mut self, name: &QualifiedName) {
+        if let Some(table) = self.table_name(name) {
+            if !self.wildcard_tables.contains(&table) {
+                self.wildcard_tables.push(table);
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Select(select) => self.select(select),
+            StatementKind::Insert(insert) => {
+                self.with(&insert.with);
+                self.add_table(&insert.table);
+                for column in &insert.columns {
+                    self.add_column(column);
+                }
+                match &insert.source {
+                    InsertSource::Values(rows) => rows.iter().flatten().for_each(|expr| self.expression(expr)),
+                    InsertSource::Select(select) => self.select(select),
+                    InsertSource::DefaultValues => {}
+                }
+                for upsert in &insert.upserts {
+                    upsert.target.iter().for_each(|term| self.expression(&term.expr));
+                    self.optional(&upsert.target_where);
+                    if let UpsertAction::Update { assignments, where_clause } = &upsert.action {
+                        self.assignments(assignments);
+                        self.optional(where_clause);
+                    }
+                }
+                self.returning(&insert.returning, &insert.table);
+            }
+            StatementKind::Update(update) => {
+                self.with(&update.with);
+                self.add_table(&update.table);
+                self.assignments(&update.assignments);
+                if let Some(from) = &update.from {
+                    self.from(from);
+                }
+                self.optional(&update.where_clause);
+                self.returning(&update.returning, &update.table);
+                self.order_and_limit(&update.order_by, &update.limit, &update.offset);
+            }
+            StatementKind::Delete(delete) => {
+                self.with(&delete.with);
+                self.add_table(&delete.table);
+                self.optional(&delete.where_clause);
+                self.returning(&delete.returning, &delete.table);
+                self.order_and_limit(&delete.order_by, &delete.limit, &delete.offset);
+            }
+            StatementKind::CreateTable(table) => {
+                self.add_table(&table.name);
+                for column in &table.columns {
+                    self.add_column(&column.name);
+                    for constraint in &column.constraints {
+                        match constraint {
+                            ColumnConstraint::Check(expr)
+                            | ColumnConstraint::Default(expr)
+                            | ColumnConstraint::Generated { expr, .. } => self.expression(expr),
+                            ColumnConstraint::References(clause) => self.foreign_key(clause),
+                            _ => {}
+                        }
+                    }
+                }
+                for constraint in &table.constraints {
+                    match constraint {
+                        TableConstraint::PrimaryKey { columns, .. } | TableConstraint::Unique { columns, .. } => {
+                            columns.iter().for_each(|term| self.expression(&term.expr))
+                        }
+                        TableConstraint::Check(expr) => self.expression(expr),
+                        TableConstraint::ForeignKey { columns, clause } => {
+                            columns.iter().for_each(|column| self.add_column(column));
+                            self.foreign_key(clause);
+                        }
+                    }
+                }
+                if let Some(select) = &table.as_select {
+                    self.select(select);
+                }
+            }
+            StatementKind::CreateIndex(index) => {
+                self.add_table(&QualifiedName {
+                    schema: index.name.schema.clone(),
+                    name: index.table.clone(),
+                });
+                index.columns.iter().for_each(|term| self.expression(&term.expr));
+                self.optional(&index.where_clause);
+            }
+            StatementKind::CreateView(view) => self.select(&view.query),
+            StatementKind::CreateTrigger(trigger) => {
+                self.add_table(&QualifiedName {
+                    schema: trigger.name.schema.clone(),
+                    name: trigger.table.clone(),
+                });
+                if let TriggerEvent::Update(columns) = &trigger.event {
+                    columns.iter().for_each(|column| self.add_column(column));
+                }
+                self.optional(&trigger.when);
+                trigger.body.iter().for_each(|statement| self.statement(statement));
+            }
+            StatementKind::CreateVirtualTable(table) => self.add_table(&table.name),
+            StatementKind::Drop { object_type: ObjectType::Table | ObjectType::View, name, .. } => self.add_table(name),
+            StatementKind::AlterTable { table, action, .. } => {
+                self.add_table(table);
+                match action {
+                    AlterAction::RenameColumn { old, .. } | AlterAction::DropColumn(old) => self.add_column(old),
+                    AlterAction::AddColumn(column) => self.add_column(&column.name),
+                    AlterAction::RenameTable(_) => {}
+                }
+            }
+            StatementKind::Explain { statement, .. } => self.statement(statement),
+            _ => {}
+        }
+    }
+
+    fn with(&mut self, with: &Option<With>) {
+        if let Some(with) = with {
+            for table in &with.tables {
+                // A recursive CTE may refer to itself
+                if with.recursive {
+                    self.ctes.push(table.name.clone());
+                }
+                self.select(&table.query);
+                if !with.recursive {
+                    self.ctes.push(table.name.clone());
+                }
+            }
+        }
+    }
+
+    fn select(&mut self, select: &Select) {
+        self.with(&select.with);
+        self.select_body(&select.body);
+        for (_, body) in &select.compounds {
+            self.select_body(body);
+        }
+        self.order_and_limit(&select.order_by, &select.limit, &select.offset);
+    }
+
+    fn select_body(&mut self, body: &SelectBody) {
+        let core = match body {
+            SelectBody::Select(core) => core,
+            SelectBody::Values(rows) => {
+                rows.iter().flatten().for_each(|expr| self.expression(expr));
+                return;
+            }
+        };
+
+        let mut relations = Vec::new();
+        if let Some(from) = &core.from {
+            self.from(from);
+            from_relations(from, &mut relations);
+        }
+        for item in &core.columns {
+            match item {
+                SelectItem::Wildcard => {
+                    for (name, _) in &relations {
+                        self.add_wildcard_table(name);
+                    }
+                }
+                SelectItem::QualifiedWildcard(qualifier) => {
+                    let table = relations
+                        .iter()
+                        .find(|(name, alias)| match alias {
+                            Some(alias) => qualifier.schema.is_none() && alias.eq_ignore_ascii_case(&qualifier.name),
+                            None => name.name.eq_ignore_ascii_case(&qualifier.name),
+                        })
+                        .map(|(name, _)| name.clone())
+                        .unwrap_or_else(|| qualifier.clone());
+                    self.add_wildcard_table(&table);
+                }
+                SelectItem::Expression { expr, .. } => self.expression(expr),
+            }
+        }
+        self.optional(&core.where_clause);
+        core.group_by.iter().for_each(|expr| self.expression(expr));
+        self.optional(&core.having);
+        for (_, window) in &core.windows {
+            self.window(window);
+        }
+    }
+
+    fn from(&mut self, from: &FromClause) {
+        self.table_factor(&from.relation);
+        for join in &from.joins {
+            self.table_factor(&join.relation);
+            match &join.constraint {
+                JoinConstraint::On(expr) => self.expression(expr),
+                JoinConstraint::Using(columns) => columns.iter().for_each(|column| self.add_column(column)),
+                JoinConstraint::None => {}
+            }
+        }
+    }
+
+    fn table_factor(&mut self, factor: &TableFactor) {
+        match factor {
+            TableFactor::Table { name, .. } => self.add_table(name),
+            TableFactor::Function { args, .. } => args.iter().for_each(|expr| self.expression(expr)),
+            TableFactor::Subquery { query, .. } => self.select(query),
+            TableFactor::Nested(from) => self.from(from),
+        }
+    }
+
+    fn assignments(&mut self, assignments: &[Assignment]) {
+        for assignment in assignments {
+            assignment.columns.iter().for_each(|column| self.add_column(column));
+            self.expression(&assignment.value);
+        }
+    }
+
+    fn returning(&mut self, items: &[SelectItem], table: &QualifiedName) {
+        for item in items {
+            match item {
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => self.add_wildcard_table(table),
+                SelectItem::Expression { expr, .. } => self.expression(expr),
+            }
+        }
+    }
+
+    fn order_and_limit(&mut self, order_by: &[OrderingTerm], limit: &Option<Box<Expression>>, offset: &Option<Box<Expression>>) {
+        order_by.iter().for_each(|term| self.expression(&term.expr));
+        for expr in limit.iter().chain(offset.iter()) {
+            self.expression(expr);
+        }
+    }
+
+    fn foreign_key(&mut self, clause: &ForeignKeyClause) {
+        self.add_table(&QualifiedName {
+            schema: None,
+            name: clause.table.clone(),
+        });
+    }
+
+    fn window(&mut self, window: &Window) {
+        window.partition_by.iter().for_each(|expr| self.expression(expr));
+        window.order_by.iter().for_each(|term| self.expression(&term.expr));
+        if let Some(frame) = &window.frame {
+            for bound in std::iter::once(&frame.start).chain(frame.end.iter()) {
+                if let FrameBound::Preceding(expr) | FrameBound::Following(expr) = bound {
+                    self.expression(expr);
+                }
+            }
+        }
+    }
+
+    fn optional(&mut self, expr: &Option<Expression>) {
+        if let Some(expr) = expr {
+            self.expression(expr);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Column { name, .. } => self.add_column(name),
+            Expression::Literal(_) | Expression::Parameter { .. } | Expression::Star | Expression::Raise { .. } => {}
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::UnaryOp { expr, .. }
+            | Expression::IsNull { expr, .. }
+            | Expression::Cast { expr, .. }
+            | Expression::Collate { expr, .. } => self.expression(expr),
+            Expression::Function { args, filter, over, .. } => {
+                args.iter().for_each(|arg| self.expression(arg));
+                if let Some(filter) = filter {
+                    self.expression(filter);
+                }
+                if let Some(window) = over {
+                    self.window(window);
+                }
+            }
+            Expression::Like { expr, pattern, escape, .. } => {
+                self.expression(expr);
+                self.expression(pattern);
+                if let Some(escape) = escape {
+                    self.expression(escape);
+                }
+            }
+            Expression::Between { expr, low, high, .. } => {
+                self.expression(expr);
+                self.expression(low);
+                self.expression(high);
+            }
+            Expression::InList { expr, list, .. } => {
+                self.expression(expr);
+                list.iter().for_each(|item| self.expression(item));
+            }
+            Expression::InSubquery { expr, query, .. } => {
+                self.expression(expr);
+                self.select(query);
+            }
+            Expression::InTable { expr, table, .. } => {
+                self.expression(expr);
+                self.add_table(table);
+            }
+            Expression::Case { operand, branches, else_result } => {
+                if let Some(operand) = operand {
+                    self.expression(operand);
+                }
+                for (condition, result) in branches {
+                    self.expression(condition);
+                    self.expression(result);
+                }
+                if let Some(else_result) = else_result {
+                    self.expression(else_result);
+                }
+            }
+            Expression::Exists(query) | Expression::Subquery(query) => self.select(query),
+            Expression::Row(values) => values.iter().for_each(|value| self.expression(value)),
+        }
+    }
+}
+
+/// Tables of a FROM clause with their aliases, the scope `*` expands over
+fn from_relations(from: &FromClause, relations: &mut Vec<(QualifiedName, Option<String>)>) {
+    for factor in std::iter::once(&from.relation).chain(from.joins.iter().map(|join| &join.relation)) {
+        match factor {
+            TableFactor::Table { name, alias, .. } => relations.push((name.clone(), alias.clone())),
+            TableFactor::Nested(from) => from_relations(from, relations),
+            TableFactor::Function { .. } | TableFactor::Subquery { .. } => {}
+        }
+    }
+}
+
+/// Hooks for rewriting a statement's AST in place, called by
+/// `Statement::walk_mut` as it descends. Each defaults to doing nothing.
+pub trait VisitorMut {
+    /// Called when the tables a WITH clause names come into scope, before
+    /// their queries are walked
+    fn enter_with(&mut self, _with: &mut With) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when they go out of scope, after the query or statement the
+    /// WITH clause belongs to
+    fn leave_with(&mut self, _with: &mut With) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the parts of a FROM-clause item are walked; the item may
+    /// be replaced, and the walk then descends into the replacement
+    fn enter_table_factor(&mut self, _factor: &mut TableFactor) -> Result<()> {
+        Ok(())
+    }
+
+    fn leave_table_factor(&mut self, _factor: &mut TableFactor) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after the operands of an expression have been walked
+    fn visit_expression(&mut self, _expr: &mut Expression) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Statement {
+    /// Walks every query, table and expression of the statement, trigger
+    /// bodies and the statement under EXPLAIN included
+    pub fn walk_mut<V: VisitorMut>(&mut self, visitor: &mut V) -> Result<()> {
+        AstWalker { visitor }.statement(&mut self.kind)
+    }
+}
+
+impl Select {
+    pub fn walk_mut<V: VisitorMut>(&mut self, visitor: &mut V) -> Result<()> {
+        AstWalker { visitor }.select(self)
+    }
+}
+
+impl Expression {
+    pub fn walk_mut<V: VisitorMut>(&mut self, visitor: &mut V) -> Result<()> {
+        AstWalker { visitor }.expression(self)
+    }
+
+    /// Calls `f` on the expression and each of its operands, outermost first.
+    /// Subqueries are not entered, since their names belong to another scope.
+    pub fn walk(&self, f: &mut impl FnMut(&Expression)) {
+        f(self);
+        match self {
+            Expression::Column { .. }
+            | Expression::Literal(_)
+            | Expression::Parameter { .. }
+            | Expression::Star
+            | Expression::Raise { .. }
+            | Expression::Exists(_)
+            | Expression::Subquery(_) => {}
+            Expression::BinaryOp { left, right, .. } => {
+                left.walk(f);
+                right.walk(f);
+            }
+            Expression::UnaryOp { expr, .. }
+            | Expression::IsNull { expr, .. }
+            | Expression::Cast { expr, .. }
+            | Expression::Collate { expr, .. }
+            | Expression::InSubquery { expr, .. }
+            | Expression::InTable { expr, .. } => expr.walk(f),
+            Expression::Function { args, filter, .. } => {
+                args.iter().for_each(|arg| arg.walk(f));
+                if let Some(filter) = filter {
+                    filter.walk(f);
+                }
+            }
+            Expression::Like { expr, pattern, escape, .. } => {
+                expr.walk(f);
+                pattern.walk(f);
+                if let Some(escape) = escape {
+                    escape.walk(f);
+                }
+            }
+            Expression::Between { expr, low, high, .. } => {
+                expr.walk(f);
+                low.walk(f);
+                high.walk(f);
+            }
+            Expression::InList { expr, list, .. } => {
+                expr.walk(f);
+                list.iter().for_each(|item| item.walk(f));
+            }
+            Expression::Case { operand, branches, else_result } => {
+                if let Some(operand) = operand {
+                    operand.walk(f);
+                }
+                for (condition, result) in branches {
+                    condition.walk(f);
+                    result.walk(f);
+                }
+                if let Some(else_result) = else_result {
+                    else_result.walk(f);
+                }
+            }
+            Expression::Row(values) => values.iter().for_each(|value| value.walk(f)),
+        }
+    }
+}
+
+/// Drives a `VisitorMut` through the parts of a statement
+struct AstWalker<'v, V: VisitorMut> {
+    visitor: &'v mut V,
+}
+
+impl<V: VisitorMut> AstWalker<'_, V> {
+    fn statement(&mut self, kind: &mut StatementKind) -> Result<()> {
+        match kind {
+            StatementKind::Select(select) => self.select(select),
+            StatementKind::Insert(insert) => self.scoped(&mut insert.with, |walker| {
+                match &mut insert.source {
+                    InsertSource::Values(rows) => walker.expressions(rows.iter_mut().flatten())?,
+                    InsertSource::Select(select) => walker.select(select)?,
+                    InsertSource::DefaultValues => {}
+                }
+                for upsert in &mut insert.upserts {
+                    walker.expressions(upsert.target.iter_mut().map(|term| &mut term.expr))?;
+                    walker.expressions(upsert.target_where.iter_mut())?;
+                    if let UpsertAction::Update { assignments, where_clause } = &mut upsert.action {
+                        walker.expressions(assignments.iter_mut().map(|assignment| &mut assignment.value))?;
+                        walker.expressions(where_clause.iter_mut())?;
+                    }
+                }
+                walker.select_items(&mut insert.returning)
+            }),
+            StatementKind::Update(update) => self.scoped(&mut update.with, |walker| {
+                walker.expressions(update.assignments.iter_mut().map(|assignment| &mut assignment.value))?;
+                if let Some(from) = &mut update.from {
+                    walker.from(from)?;
+                }
+                walker.expressions(update.where_clause.iter_mut())?;
+                walker.select_items(&mut update.returning)?;
+                walker.order_and_limit(&mut update.order_by, &mut update.limit, &mut update.offset)
+            }),
+            StatementKind::Delete(delete) => self.scoped(&mut delete.with, |walker| {
+                walker.expressions(delete.where_clause.iter_mut())?;
+                walker.select_items(&mut delete.returning)?;
+                walker.order_and_limit(&mut delete.order_by, &mut delete.limit, &mut delete.offset)
+            }),
+            StatementKind::CreateTable(table) => {
+                table.columns.iter_mut().try_for_each(|column| self.column_def(column))?;
+                for constraint in &mut table.constraints {
+                    match constraint {
+                        TableConstraint::PrimaryKey { columns, .. } | TableConstraint::Unique { columns, .. } => {
+                            self.expressions(columns.iter_mut().map(|term| &mut term.expr))?
+                        }
+                        TableConstraint::Check(expr) => self.expression(expr)?,
+                        TableConstraint::ForeignKey { .. } => {}
+                    }
+                }
+                match &mut table.as_select {
+                    Some(select) => self.select(select),
+                    None => Ok(()),
+                }
+            }
+            StatementKind::CreateIndex(index) => {
+                self.expressions(index.columns.iter_mut().map(|term| &mut term.expr))?;
+                self.expressions(index.where_clause.iter_mut())
+            }
+            StatementKind::CreateView(view) => self.select(&mut view.query),
+            StatementKind::CreateTrigger(trigger) => {
+                self.expressions(trigger.when.iter_mut())?;
+                trigger.body.iter_mut().try_for_each(|statement| self.statement(&mut statement.kind))
+            }
+            StatementKind::AlterTable { action: AlterAction::AddColumn(column), .. } => self.column_def(column),
+            StatementKind::Attach { database, schema } => {
+                self.expression(database)?;
+                self.expression(schema)
+            }
+            StatementKind::Detach(expr) | StatementKind::Vacuum { into: Some(expr), .. } => self.expression(expr),
+            StatementKind::Explain { statement, .. } => self.statement(&mut statement.kind),
+            _ => Ok(()),
+        }
+    }
+
+    fn column_def(&mut self, column: &mut ColumnDef) -> Result<()> {
+        for constraint in &mut column.constraints {
+            if let ColumnConstraint::Check(expr) | ColumnConstraint::Default(expr) | ColumnConstraint::Generated { expr, .. } = constraint {
+                self.expression(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `walk` with the tables of `with` in scope, walking them first
+    fn scoped(&mut self, with: &mut Option<With>, walk: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let with = match with {
+            Some(with) => with,
+            None => return walk(self),
+        };
+        self.visitor.enter_with(with)?;
+        for table in &mut with.tables {
+            self.select(&mut table.query)?;
+        }
+        walk(self)?;
+        self.visitor.leave_with(with)
+    }
+
+    fn select(&mut self, select: &mut Select) -> Result<()> {
+        let Select { with, body, compounds, order_by, limit, offset } = select;
+        self.scoped(with, |walker| {
+            walker.select_body(body)?;
+            for (_, body) in compounds.iter_mut() {
+                walker.select_body(body)?;
+            }
+            walker.order_and_limit(order_by, limit, offset)
+        })
+    }
+
+    fn select_body(&mut self, body: &mut SelectBody) -> Result<()> {
+        let core = match body {
+            SelectBody::Select(core) => core,
+            SelectBody::Values(rows) => return self.expressions(rows.iter_mut().flatten()),
+        };
+        // FROM comes first so its replacements are in place before the
+        // expressions that refer to them are visited
+        if let Some(from) = &mut core.from {
+            self.from(from)?;
+        }
+        self.select_items(&mut core.columns)?;
+        self.expressions(core.where_clause.iter_mut())?;
+        self.expressions(core.group_by.iter_mut())?;
+        self.expressions(core.having.iter_mut())?;
+        core.windows.iter_mut().try_for_each(|(_, window)| self.window(window))
+    }
+
+    fn select_items(&mut self, items: &mut [SelectItem]) -> Result<()> {
+        for item in items {
+            if let SelectItem::Expression { expr, .. } = item {
+                self.expression(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn from(&mut self, from: &mut FromClause) -> Result<()> {
+        self.table_factor(&mut from.relation)?;
+        for join in &mut from.joins {
+            self.table_factor(&mut join.relation)?;
+            if let JoinConstraint::On(expr) = &mut join.constraint {
+                self.expression(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table_factor(&mut self, factor: &mut TableFactor) -> Result<()> {
+        self.visitor.enter_table_factor(factor)?;
+        match factor {
+            TableFactor::Table { .. } => {}
+            TableFactor::Function { args, .. } => self.expressions(args.iter_mut())?,
+            TableFactor::Subquery { query, .. } => self.select(query)?,
+            TableFactor::Nested(from) => self.from(from)?,
+        }
+        self.visitor.leave_table_factor(factor)
+    }
+
+    fn order_and_limit(
+        &mut self,
+        order_by: &mut [OrderingTerm],
+        limit: &mut Option<Box<Expression>>,
+        offset: &mut Option<Box<Expression>>,
+    ) -> Result<()> {
+        self.expressions(order_by.iter_mut().map(|term| &mut term.expr))?;
+        self.expressions(limit.iter_mut().chain(offset.iter_mut()).map(|expr| expr.as_mut()))
+    }
+
+    fn window(&mut self, window: &mut Window) -> Result<()> {
+        self.expressions(window.partition_by.iter_mut())?;
+        self.expressions(window.order_by.iter_mut().map(|term| &mut term.expr))?;
+        if let Some(frame) = &mut window.frame {
+            for bound in std::iter::once(&mut frame.start).chain(frame.end.iter_mut()) {
+                if let FrameBound::Preceding(expr) | FrameBound::Following(expr) = bound {
+                    self.expression(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expressions<'e>(&mut self, exprs: impl Iterator<Item = &'e mut Expression>) -> Result<()> {
+        for expr in exprs {
+            self.expression(expr)?;
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &mut Expression) -> Result<()> {
+        match expr {
+            Expression::Column { .. }
+            | Expression::Literal(_)
+            | Expression::Parameter { .. }
+            | Expression::Star
+            | Expression::Raise { .. }
+            | Expression::InTable { .. } => {}
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left)?;
+                self.expression(right)?;
+            }
+            Expression::UnaryOp { expr, .. }
+            | Expression::IsNull { expr, .. }
+            | Expression::Cast { expr, .. }
+            | Expression::Collate { expr, .. } => self.expression(expr)?,
+            Expression::Function { args, filter, over, .. } => {
+                self.expressions(args.iter_mut())?;
+                if let Some(filter) = filter {
+                    self.expression(filter)?;
+                }
+                if let Some(window) = over {
+                    self.window(window)?;
+                }
+            }
+            Expression::Like { expr, pattern, escape, .. } => {
+                self.expression(expr)?;
+                self.expression(pattern)?;
+                if let Some(escape) = escape {
+                    self.expression(escape)?;
+                }
+            }
+            Expression::Between { expr, low, high, .. } => {
+                self.expression(expr)?;
+                self.expression(low)?;
+                self.expression(high)?;
+            }
+            Expression::InList { expr, list, .. } => {
+                self.expression(expr)?;
+                self.expressions(list.iter_mut())?;
+            }
+            Expression::InSubquery { expr, query, .. } => {
+                self.expression(expr)?;
+                self.select(query)?;
+            }
+            Expression::Case { operand, branches, else_result } => {
+                if let Some(operand) = operand {
+                    self.expression(operand)?;
+                }
+                for (condition, result) in branches.iter_mut() {
+                    self.expression(condition)?;
+                    self.expression(result)?;
+                }
+                if let Some(else_result) = else_result {
+                    self.expression(else_result)?;
+                }
+            }
+            Expression::Exists(query) | Expression::Subquery(query) => self.select(query)?,
+            Expression::Row(values) => self.expressions(values.iter_mut())?,
+        }
+        self.visitor.visit_expression(expr)
+    }
+}
+
+impl Statement {
+    /// Tables and views the statement names, as `schema.name` when qualified,
+    /// in order of first mention; common table expressions are left out
+    pub fn table_references(&self) -> Vec<String> {
+        let mut collector = ReferenceCollector::default();
+        collector.statement(self);
+        collector.tables
+    }
+
+    /// Top-level WHERE clause of a SELECT, UPDATE or DELETE
+    fn where_clause(&self) -> Option<&Expression> {
+        match &self.kind {
+            StatementKind::Select(select) => match &select.body {
+                SelectBody::Select(core) if select.compounds.is_empty() => core.where_clause.as_ref(),
+                _ => None,
+            },
+            StatementKind::Update(update) => update.where_clause.as_ref(),
+            StatementKind::Delete(delete) => delete.where_clause.as_ref(),
+            StatementKind::Explain { statement, .. } => statement.where_clause(),
+            _ => None,
+        }
+    }
+
+    /// Top-level ORDER BY, LIMIT and OFFSET
+    fn order_and_limit(&self) -> (&[OrderingTerm], Option<&Expression>) {
+        match &self.kind {
+            StatementKind::Select(select) => (&select.order_by, select.limit.as_deref()),
+            StatementKind::Update(update) => (&update.order_by, update.limit.as_deref()),
+            StatementKind::Delete(delete) => (&delete.order_by, delete.limit.as_deref()),
+            StatementKind::Explain { statement, .. } => statement.order_and_limit(),
+            _ => (&[], None),
+        }
+    }
+}
+
+/// Finds the tables and columns statements refer to, resolving wildcards
+/// through the catalog
+pub struct QueryAnalyzer {
+    db_path: String,
+    /// Session whose temp schema names resolve against
+    session: Option<String>,
+}
+
+impl QueryAnalyzer {
+    pub fn new(db_path: String) -> Self {
+        QueryAnalyzer { db_path, session: None }
+    }
+
+    pub fn with_session(mut self, session: Option<&str>) -> Self {
+        self.session = session.map(str::to_string);
+        self
+    }
+
+    /// Collects what one statement refers to, expanding wildcards to the
+    /// columns of their tables
+    pub fn analyze(&self, statement: &Statement) -> Result<AnalyzedQuery> {
+        let mut collector = ReferenceCollector::default();
+        collector.statement(statement);
+
+        let mut column_references = collector.columns;
+        if !collector.wildcard_tables.is_empty() {
+            for column in self.resolve_wildcard_columns(&collector.wildcard_tables)? {
+                if !column_references.contains(&column) {
+                    column_references.push(column);
+                }
+            }
+        }
+
+        let (order_by, limit) = statement.order_and_limit();
+        let limit = match limit {
+            Some(Expression::Literal(Value::Integer(limit))) => usize::try_from(*limit).ok(),
+            _ => None,
+        };
+
+        Ok(AnalyzedQuery {
+            query_type: statement.query_type.clone(),
+            table_references: collector.tables,
+            column_references,
+            where_clause: statement.where_clause().map(ToString::to_string),
+            order_by: order_by.iter().map(ToString::to_string).collect(),
+            limit,
+            query_text: statement.query_text.clone(),
+        })
+    }
+
+    /// Columns of the tables and views a wildcard stands for
+    fn resolve_wildcard_columns(&self, tables: &[String]) -> Result<Vec<String>> {
+        println!("[SCHEMA] Resolving wildcard columns for tables: {:?}", tables);
+
+        let catalog = crate::schema::cache::get_session_catalog(&self.db_path, self.session.as_deref())?;
+
+        let mut resolved = Vec::new();
+        // For each referenced table, look up its columns
+        for table in tables {
+            // "alias.table" names a table in an attached database
+            let (schema, name) = catalog.split_qualified(table);
+            let owner = catalog.resolve_schema(schema, name).map(|(_, owner)| owner);
+
+            let columns: Vec<String> = if let Some(schema) = owner.and_then(|owner| owner.find_table(name)) {
+                schema.columns.iter().map(|column| column.name.clone()).collect()
+            } else if let Some((view, owner)) = owner.and_then(|owner| owner.find_view(name).map(|view| (view, owner))) {
+                view.column_names(owner)
+            } else {
+                eprintln!("Warning: Could not get columns for table {}: not in schema", table);
+                continue;
+            };
+
+            for column in columns {
+                if !resolved.contains(&column) {
+                    resolved.push(column);
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::Tokenizer;
+
+    fn parse(sql: &str) -> Statement {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        AstBuilder::new(sql, tokens).build().unwrap()
+    }
+
+    fn parse_error(sql: &str) -> ParseError {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let error = AstBuilder::new(sql, tokens).build().unwrap_err();
+        error.downcast::<ParseError>().unwrap()
+    }
+
+    fn select(sql: &str) -> Select {
+        match parse(sql).kind {
+            StatementKind::Select(select) => *select,
+            kind => panic!("expected a SELECT, got {:?}", kind),
+        }
+    }
+
+    fn expression(sql: &str) -> String {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        AstBuilder::new(sql, tokens).parse_expression().unwrap().to_string()
+    }
+
+    #[test]
+    fn operators_bind_by_sqlite_precedence() {
+        assert_eq!(expression("1 + 2 * 3 - 4"), "1 + 2 * 3 - 4");
+        assert_eq!(expression("(1 + 2) * 3"), "(1 + 2) * 3");
+        assert_eq!(expression("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(expression("a OR b AND NOT c = 1"), "a OR b AND NOT c = 1");
+        assert_eq!(expression("NOT (a OR b)"), "NOT (a OR b)");
+        assert_eq!(expression("-x || 'a' COLLATE nocase"), "-x || 'a' COLLATE nocase");
+        assert_eq!(expression("a < b = c > d"), "a < b = c > d");
+        assert_eq!(expression("x BETWEEN 1 + 1 AND 5 AND y"), "x BETWEEN 1 + 1 AND 5 AND y");
+        assert_eq!(expression("a NOT LIKE 'x%' ESCAPE '\\'"), "a NOT LIKE 'x%' ESCAPE '\\'");
+        assert_eq!(expression("a ISNULL OR b NOT NULL OR c IS NOT NULL"), "a IS NULL OR b IS NOT NULL OR c IS NOT NULL");
+        assert_eq!(expression("a IS NOT DISTINCT FROM b"), "a IS b");
+        assert_eq!(expression("data ->> '$.name'"), "data ->> '$.name'");
+    }
+
+    #[test]
+    fn parses_primary_expressions() {
+        assert_eq!(expression("CAST(x AS VARCHAR(10))"), "CAST(x AS VARCHAR(10))");
+        assert_eq!(expression("CASE WHEN a THEN 1 ELSE 2 END"), "CASE WHEN a THEN 1 ELSE 2 END");
+        assert_eq!(expression("x IN (1, 2) AND y NOT IN t"), "x IN (1, 2) AND y NOT IN t");
+        assert_eq!(expression("EXISTS (SELECT 1)"), "EXISTS (SELECT 1)");
+        assert_eq!(expression("count(DISTINCT main.t.a)"), "count(DISTINCT main.t.a)");
+        assert_eq!(expression("(a, b) = (1, 2)"), "(a, b) = (1, 2)");
+        assert_eq!(expression("X'CAFE' || :name || ?2"), "X'CAFE' || :name || ?2");
+        assert_eq!(expression("true AND \"true\""), "TRUE AND \"true\"");
+        assert_eq!(
+            expression("sum(x) FILTER (WHERE x > 0) OVER (PARTITION BY g ORDER BY x ROWS BETWEEN 1 PRECEDING AND CURRENT ROW)"),
+            "sum(x) FILTER (WHERE x > 0) OVER (PARTITION BY g ORDER BY x ROWS BETWEEN 1 PRECEDING AND CURRENT ROW)"
+        );
+    }
+
+    #[test]
+    fn parses_select_clauses() {
+        let query = select(
+            "WITH recent AS (SELECT * FROM orders) \
+             SELECT DISTINCT c.name, count(*) AS total FROM customers AS c \
+             LEFT JOIN recent r ON r.customer_id = c.id, items USING (id) \
+             WHERE c.active GROUP BY c.name HAVING count(*) > 1 \
+             UNION ALL VALUES (1, 2) ORDER BY 2 DESC NULLS LAST LIMIT 5, 10",
+        );
+        assert!(query.with.is_some());
+        assert_eq!(query.compounds.len(), 1);
+        assert_eq!(query.compounds[0].0, CompoundOperator::UnionAll);
+        assert_eq!(query.order_by[0].nulls_first, Some(false));
+        assert_eq!(query.limit.as_ref().unwrap().to_string(), "10");
+        assert_eq!(query.offset.as_ref().unwrap().to_string(), "5");
+
+        let SelectBody::Select(core) = &query.body else {
+            panic!("expected a SELECT body");
+        };
+        assert!(core.distinct);
+        assert_eq!(core.group_by.len(), 1);
+        let from = core.from.as_ref().unwrap();
+        assert_eq!(from.joins.len(), 2);
+        assert_eq!(from.joins[0].kind, JoinKind::Left);
+        assert!(matches!(&from.joins[0].relation, TableFactor::Table { alias: Some(alias), .. } if alias == "r"));
+        assert_eq!(from.joins[1].kind, JoinKind::Comma);
+        assert!(matches!(&from.joins[1].constraint, JoinConstraint::Using(columns) if columns == &["id"]));
+    }
+
+    #[test]
+    fn parses_data_changing_statements() {
+        let StatementKind::Insert(insert) =
+            parse("INSERT OR IGNORE INTO t (a, b) VALUES (1, 2), (3, 4) ON CONFLICT (a) DO UPDATE SET b = excluded.b RETURNING *").kind
+        else {
+            panic!("expected an INSERT");
+        };
+        assert_eq!(insert.conflict, Some(ConflictResolution::Ignore));
+        assert_eq!(insert.columns, vec!["a", "b"]);
+        assert!(matches!(&insert.source, InsertSource::Values(rows) if rows.len() == 2));
+        assert_eq!(insert.upserts.len(), 1);
+        assert_eq!(insert.returning.len(), 1);
+
+        let StatementKind::Update(update) = parse("UPDATE t SET (a, b) = (1, 2), c = c + 1 WHERE id = ?").kind else {
+            panic!("expected an UPDATE");
+        };
+        assert_eq!(update.assignments.len(), 2);
+        assert_eq!(update.where_clause.unwrap().to_string(), "id = ?");
+
+        let statement = parse("DELETE FROM main.t WHERE id IN (SELECT id FROM old)");
+        assert_eq!(statement.query_type, QueryType::Delete);
+        assert!(matches!(statement.kind, StatementKind::Delete(delete) if delete.table.schema.as_deref() == Some("main")));
+    }
+
+    #[test]
+    fn parses_schema_statements() {
+        let StatementKind::CreateTable(table) = parse(
+            "CREATE TABLE IF NOT EXISTS users (\
+               id INTEGER PRIMARY KEY AUTOINCREMENT, \
+               name VARCHAR(20) NOT NULL DEFAULT 'x' COLLATE nocase, \
+               key TEXT GENERATED ALWAYS AS (lower(name)) STORED, \
+               team INT REFERENCES teams (id) ON DELETE CASCADE, \
+               UNIQUE (name, team) ON CONFLICT REPLACE CHECK (length(name) > 0)\
+             ) WITHOUT ROWID, STRICT",
+        )
+        .kind
+        else {
+            panic!("expected CREATE TABLE");
+        };
+        assert!(table.if_not_exists && table.without_rowid && table.strict);
+        assert_eq!(table.columns.len(), 4);
+        assert_eq!(table.columns[1].type_name.as_deref(), Some("VARCHAR(20)"));
+        assert_eq!(table.columns[1].constraints.len(), 3);
+        assert_eq!(table.columns[2].name, "key");
+        assert!(matches!(table.columns[2].constraints[0], ColumnConstraint::Generated { stored: true, .. }));
+        assert_eq!(table.constraints.len(), 2);
+
+        let trigger = parse(
+            "CREATE TRIGGER log AFTER UPDATE OF name ON users FOR EACH ROW WHEN new.name <> old.name \
+             BEGIN INSERT INTO audit VALUES (new.id); SELECT RAISE(IGNORE); END",
+        );
+        assert_eq!(trigger.query_type, QueryType::Create);
+        assert!(matches!(trigger.kind, StatementKind::CreateTrigger(trigger) if trigger.body.len() == 2));
+
+        let virtual_table = parse("CREATE VIRTUAL TABLE docs USING fts5(title, body, tokenize = 'porter')");
+        assert!(matches!(virtual_table.kind,
+            StatementKind::CreateVirtualTable(table) if table.args == vec!["title", "body", "tokenize = 'porter'"]));
+
+        for (sql, query_type) in [
+            ("CREATE UNIQUE INDEX i ON t (a DESC) WHERE a > 0", QueryType::Create),
+            ("CREATE VIEW v (x) AS SELECT a FROM t", QueryType::Create),
+            ("DROP TABLE IF EXISTS t", QueryType::Drop),
+            ("ALTER TABLE t RENAME COLUMN a TO b", QueryType::Alter),
+            ("BEGIN IMMEDIATE TRANSACTION", QueryType::Transaction),
+            ("ROLLBACK TO SAVEPOINT s", QueryType::Transaction),
+            ("PRAGMA main.cache_size = -2000", QueryType::Pragma),
+            ("ATTACH DATABASE 'other.db' AS other", QueryType::Attach),
+            ("VACUUM INTO 'copy.db'", QueryType::Maintenance),
+            ("EXPLAIN QUERY PLAN SELECT 1", QueryType::Explain),
+        ] {
+            assert_eq!(parse(sql).query_type, query_type, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn splits_scripts_into_statements_with_their_text() {
+        let sql = "SELECT 1;; INSERT INTO t VALUES (2) ;\nSELECT 3";
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let statements = AstBuilder::new(sql, tokens).build_all().unwrap();
+        let texts: Vec<&str> = statements.iter().map(|statement| statement.query_text.as_str()).collect();
+        assert_eq!(texts, vec!["SELECT 1", "INSERT INTO t VALUES (2)", "SELECT 3"]);
+        assert_eq!(statements[2].span.line, 2);
+    }
+
+    #[test]
+    fn reports_syntax_errors_at_the_offending_token() {
+        let error = parse_error("SELECT * FROM t WHERE");
+        assert_eq!(error.message, "incomplete input, expected an expression");
+
+        let error = parse_error("SELECT a\n  FROM t ORDER x");
+        assert_eq!(error.message, "near \"x\": syntax error, expected BY");
+        assert_eq!((error.span.line, error.span.column, error.span.length), (2, 16, 1));
+
+        let error = parse_error("SELECT 1 2");
+        assert!(error.message.starts_with("near \"2\": syntax error"));
+    }
+
+    /// Expression with every operator application parenthesised, showing how it grouped
+    fn grouping(sql: &str) -> String {
+        fn render(expr: &Expression) -> String {
+            match expr {
+                Expression::BinaryOp { left, op, right } => format!("({} {} {})", render(left), op, render(right)),
+                Expression::UnaryOp { op: Operator::Not, expr } => format!("(NOT {})", render(expr)),
+                Expression::UnaryOp { op, expr } => format!("({}{})", op, render(expr)),
+                Expression::Collate { expr, collation } => format!("({} COLLATE {})", render(expr), collation),
+                Expression::IsNull { expr, negated } => format!("({} IS {}NULL)", render(expr), if *negated { "NOT " } else { "" }),
+                Expression::Between { expr, low, high, .. } => {
+                    format!("({} BETWEEN {} AND {})", render(expr), render(low), render(high))
+                }
+                Expression::Like { expr, op, pattern, .. } => format!("({} {} {})", render(expr), op, render(pattern)),
+                Expression::InList { expr, list, .. } => {
+                    let list: Vec<String> = list.iter().map(render).collect();
+                    format!("({} IN ({}))", render(expr), list.join(", "))
+                }
+                expr => expr.to_string(),
+            }
+        }
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        render(&AstBuilder::new(sql, tokens).parse_expression().unwrap())
+    }
+
+    #[test]
+    fn precedence_decides_how_operators_group() {
+        assert_eq!(grouping("1 + 2 * 3"), "(1 + (2 * 3))");
+        assert_eq!(grouping("1 - 2 - 3"), "((1 - 2) - 3)");
+        assert_eq!(grouping("a || b * c"), "((a || b) * c)");
+        assert_eq!(grouping("a & b + c << 1"), "((a & (b + c)) << 1)");
+        assert_eq!(grouping("a < b = c"), "((a < b) = c)");
+        assert_eq!(grouping("a = 1 OR b = 2 AND c = 3"), "((a = 1) OR ((b = 2) AND (c = 3)))");
+        assert_eq!(grouping("NOT a = b AND c"), "((NOT (a = b)) AND c)");
+        assert_eq!(grouping("-a * b"), "((-a) * b)");
+        assert_eq!(grouping("~a + 1"), "((~a) + 1)");
+        assert_eq!(grouping("a || b COLLATE nocase"), "(a || (b COLLATE nocase))");
+        assert_eq!(grouping("a + 1 IS NULL AND b"), "(((a + 1) IS NULL) AND b)");
+        assert_eq!(grouping("x BETWEEN 1 AND 2 OR y"), "((x BETWEEN 1 AND 2) OR y)");
+        assert_eq!(grouping("a LIKE b || '%' AND c"), "((a LIKE (b || '%')) AND c)");
+        assert_eq!(grouping("a + 1 IN (1, 2 * 3)"), "((a + 1) IN (1, (2 * 3)))");
+        assert_eq!(grouping("a IS NOT b = c"), "((a IS NOT b) = c)");
+    }
+
+    #[test]
+    fn syntax_errors_point_at_line_column_and_offset() {
+        for (sql, message, (line, column, offset, length)) in [
+            ("SELEC 1", "near \"SELEC\": syntax error, expected a statement", (1, 1, 0, 5)),
+            ("SELECT a,\nFROM t", "near \"FROM\": syntax error, expected an expression", (2, 1, 10, 4)),
+            ("SELECT * FROM t WHERE a = (1", "incomplete input, expected \")\"", (1, 29, 28, 0)),
+            ("INSERT INTO t VALUES (1) (2)", "near \"(\": syntax error, expected \";\" or the end of the input", (1, 26, 25, 1)),
+            ("UPDATE t SET WHERE a = 1", "near \"WHERE\": syntax error, expected a column name", (1, 14, 13, 5)),
+            ("CREATE TABLE t (a INT,)", "near \")\": syntax error, expected a column name", (1, 23, 22, 1)),
+            ("DROP SCHEMA s", "near \"SCHEMA\": syntax error, expected TABLE, INDEX, VIEW or TRIGGER", (1, 6, 5, 6)),
+            ("SELECT 1;\n  DELETE t", "near \"t\": syntax error, expected FROM", (2, 10, 19, 1)),
+        ] {
+            let error = parse_error(sql);
+            assert_eq!(error.message, message, "{}", sql);
+            assert_eq!(
+                (error.span.line, error.span.column, error.span.offset, error.span.length),
+                (line, column, offset, length),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn every_statement_kind_displays_as_sql_that_parses_back() {
+        for sql in [
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5) SELECT i FROM n",
+            "SELECT DISTINCT a, b AS c FROM t AS x LEFT JOIN u ON u.id = x.id WHERE a > 1 GROUP BY a HAVING count(*) > 1 ORDER BY a DESC LIMIT 1 OFFSET 2",
+            "VALUES (1, 'a'), (2, 'b')",
+            "INSERT OR REPLACE INTO t (a, b) VALUES (1, 2) ON CONFLICT (a) WHERE a > 0 DO UPDATE SET b = excluded.b WHERE b IS NOT NULL RETURNING *",
+            "WITH s AS (SELECT 1) INSERT INTO t SELECT * FROM s",
+            "INSERT INTO t DEFAULT VALUES",
+            "UPDATE OR IGNORE t AS x SET a = 1, (b, c) = (2, 3) FROM u WHERE x.id = u.id RETURNING a",
+            "DELETE FROM main.t WHERE a IN (SELECT a FROM u) RETURNING *",
+            "CREATE TEMP TABLE IF NOT EXISTS t (id INTEGER PRIMARY KEY DESC ON CONFLICT FAIL AUTOINCREMENT, name TEXT NOT NULL UNIQUE COLLATE NOCASE DEFAULT 'x', n INT DEFAULT -1 CHECK (n >= -1), at TEXT DEFAULT (datetime('now')), up TEXT GENERATED ALWAYS AS (upper(name)) STORED, team INT REFERENCES teams (id) ON DELETE SET NULL ON UPDATE CASCADE DEFERRABLE INITIALLY DEFERRED, PRIMARY KEY (id, name) ON CONFLICT ABORT, UNIQUE (name), CHECK (n < 10), FOREIGN KEY (team) REFERENCES teams (id)) WITHOUT ROWID, STRICT",
+            "CREATE TABLE copy AS SELECT * FROM t",
+            "CREATE UNIQUE INDEX IF NOT EXISTS i ON t (a COLLATE NOCASE DESC, b) WHERE a IS NOT NULL",
+            "CREATE VIEW v (x, y) AS SELECT a, b FROM t",
+            "CREATE TRIGGER tr INSTEAD OF UPDATE OF a, b ON v FOR EACH ROW WHEN NEW.a > 0 BEGIN UPDATE t SET a = NEW.a; SELECT RAISE(ABORT, 'no'); END",
+            "CREATE VIRTUAL TABLE docs USING fts5(title, body)",
+            "DROP INDEX IF EXISTS main.i",
+            "ALTER TABLE t RENAME TO u",
+            "ALTER TABLE t RENAME COLUMN a TO b",
+            "ALTER TABLE t ADD COLUMN c TEXT DEFAULT 'x'",
+            "ALTER TABLE t DROP COLUMN c",
+            "BEGIN EXCLUSIVE",
+            "COMMIT",
+            "ROLLBACK TO s",
+            "SAVEPOINT s",
+            "RELEASE s",
+            "PRAGMA main.cache_size = -2000",
+            "PRAGMA table_info = t",
+            "ATTACH DATABASE 'other.db' AS other",
+            "DETACH DATABASE other",
+            "ANALYZE main.t",
+            "VACUUM main INTO 'copy.db'",
+            "REINDEX t",
+            "EXPLAIN QUERY PLAN SELECT * FROM t",
+        ] {
+            let displayed = parse(sql).to_string();
+            assert_eq!(displayed, sql);
+            assert_eq!(parse(&displayed).to_string(), displayed);
+        }
+
+        // Spellings SQLite treats as the same statement come out in one form
+        assert_eq!(parse("REPLACE INTO t VALUES (1)").to_string(), "INSERT OR REPLACE INTO t VALUES (1)");
+        assert_eq!(parse("END TRANSACTION").to_string(), "COMMIT");
+        assert_eq!(parse("CREATE TABLE t (a DEFAULT b)").to_string(), "CREATE TABLE t (a DEFAULT 'b')");
+        assert_eq!(parse("CREATE TABLE \"select\" (\"a b\")").to_string(), "CREATE TABLE \"select\" (\"a b\")");
+    }
+
+    /// Replaces table `old` with `new` outside the WITH clauses that define `old`
+    struct Renamer {
+        ctes: Vec<Vec<String>>,
+        renamed: usize,
+    }
+
+    impl VisitorMut for Renamer {
+        fn enter_with(&mut self, with: &mut With) -> Result<()> {
+            self.ctes.push(with.tables.iter().map(|cte| cte.name.clone()).collect());
+            Ok(())
+        }
+
+        fn leave_with(&mut self, _with: &mut With) -> Result<()> {
+            self.ctes.pop();
+            Ok(())
+        }
+
+        fn enter_table_factor(&mut self, factor: &mut TableFactor) -> Result<()> {
+            if let TableFactor::Table { name, .. } = factor {
+                if name.name == "old" && !self.ctes.iter().flatten().any(|cte| cte == "old") {
+                    name.name = "new".to_string();
+                    self.renamed += 1;
+                }
+            }
+            Ok(())
+        }
+
+        fn visit_expression(&mut self, expr: &mut Expression) -> Result<()> {
+            if let Expression::Parameter { .. } = expr {
+                *expr = Expression::Literal(Value::Integer(7));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn walker_rewrites_tables_and_expressions_in_every_clause() {
+        let mut statement = parse(
+            "UPDATE t SET a = (SELECT x FROM old WHERE y = ?) \
+             WHERE b IN (WITH old AS (SELECT 1) SELECT * FROM old) AND EXISTS (SELECT 1 FROM old JOIN (SELECT ?) ON 1)",
+        );
+        let mut renamer = Renamer { ctes: Vec::new(), renamed: 0 };
+        statement.walk_mut(&mut renamer).unwrap();
+        assert_eq!(renamer.renamed, 2);
+        assert!(renamer.ctes.is_empty());
+        assert_eq!(
+            statement.to_string(),
+            "UPDATE t SET a = (SELECT x FROM new WHERE y = 7) \
+             WHERE b IN (WITH old AS (SELECT 1) SELECT * FROM old) AND EXISTS (SELECT 1 FROM new JOIN (SELECT 7) ON 1)"
+        );
+
+        let mut trigger = parse("CREATE TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM x WHERE id IN (SELECT id FROM old); END");
+        trigger.walk_mut(&mut renamer).unwrap();
+        assert_eq!(renamer.renamed, 3);
+    }
+
+    fn analyze(sql: &str) -> AnalyzedQuery {
+        QueryAnalyzer::new(String::new()).analyze(&parse(sql)).unwrap()
+    }
+
+    #[test]
+    fn analysis_collects_references_from_every_clause() {
+        let analyzed = analyze(
+            "WITH big AS (SELECT id FROM orders WHERE amount > 100) \
+             SELECT u.name, count(o.id) FROM users u JOIN orders o ON o.user_id = u.id \
+             WHERE u.age > (SELECT avg(age) FROM people) AND EXISTS (SELECT 1 FROM big WHERE big.id = o.id) \
+             GROUP BY u.name ORDER BY u.name DESC LIMIT 10",
+        );
+        assert_eq!(analyzed.query_type, QueryType::Select);
+        assert_eq!(analyzed.table_references, vec!["orders", "users", "people"]);
+        assert_eq!(analyzed.column_references, vec!["id", "amount", "user_id", "name", "age"]);
+        assert_eq!(
+            analyzed.where_clause.as_deref(),
+            Some("u.age > (SELECT avg(age) FROM people) AND EXISTS (SELECT 1 FROM big WHERE big.id = o.id)")
+        );
+        assert_eq!(analyzed.order_by, vec!["u.name DESC"]);
+        assert_eq!(analyzed.limit, Some(10));
+    }
+
+    #[test]
+    fn analysis_covers_data_changes_and_schema_statements() {
+        let analyzed = analyze("UPDATE main.users SET age = age + 1 WHERE name IN (SELECT name FROM vips) LIMIT 3");
+        assert_eq!(analyzed.query_type, QueryType::Update);
+        assert_eq!(analyzed.table_references, vec!["main.users", "vips"]);
+        assert_eq!(analyzed.column_references, vec!["age", "name"]);
+        assert_eq!(analyzed.where_clause.as_deref(), Some("name IN (SELECT name FROM vips)"));
+        assert_eq!(analyzed.limit, Some(3));
+
+        let analyzed = analyze("INSERT INTO logs (message) SELECT upper(title) FROM posts");
+        assert_eq!(analyzed.query_type, QueryType::Insert);
+        assert_eq!(analyzed.table_references, vec!["logs", "posts"]);
+        assert_eq!(analyzed.column_references, vec!["message", "title"]);
+
+        let analyzed = analyze("DELETE FROM sessions WHERE expires < CURRENT_TIMESTAMP");
+        assert_eq!(analyzed.table_references, vec!["sessions"]);
+        assert_eq!(analyzed.column_references, vec!["expires"]);
+
+        let analyzed = analyze("CREATE TABLE posts (id INTEGER PRIMARY KEY, author INT REFERENCES users (id), CHECK (id > 0))");
+        assert_eq!(analyzed.query_type, QueryType::Create);
+        assert_eq!(analyzed.table_references, vec!["posts", "users"]);
+        assert_eq!(analyzed.column_references, vec!["id", "author"]);
+
+        let analyzed = analyze("CREATE INDEX by_author ON posts (author, lower(title))");
+        assert_eq!(analyzed.table_references, vec!["posts"]);
+        assert_eq!(analyzed.column_references, vec!["author", "title"]);
+    }
+
+
+    fn recover(sql: &str) -> PartialParse {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        AstBuilder::new(sql, tokens).with_error_recovery().build_partial()
+    }
+
+    #[test]
+    fn recovery_skips_bad_clauses_and_keeps_the_rest() {
+        let parsed = recover("SELECT a, FROM t WHERE b = = 1 ORDER BY c LIMIT");
+        let messages: Vec<&str> = parsed.errors.iter().map(|error| error.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "near \"FROM\": syntax error, expected an expression",
+                "near \"=\": syntax error, expected an expression",
+                "incomplete input, expected an expression",
+            ]
+        );
+        assert_eq!(parsed.statements.len(), 1);
+        assert_eq!(parsed.statements[0].query_text, "SELECT a, FROM t WHERE b = = 1 ORDER BY c LIMIT");
+        let StatementKind::Select(select) = &parsed.statements[0].kind else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(select.to_string(), "SELECT  FROM t ORDER BY c");
+    }
+
+    #[test]
+    fn recovery_resumes_at_the_next_statement() {
+        let parsed = recover(
+            "SELEC 1; INSERT INTO t VALUES (1;\n\
+             CREATE TRIGGER x AFTER INSERT ON t BEGIN DELETE FROM u WHERE; END;\n\
+             SELECT (SELECT FROM u) + 1 FROM t",
+        );
+        let positions: Vec<(usize, usize)> = parsed.errors.iter().map(|error| (error.span.line, error.span.column)).collect();
+        assert_eq!(positions, vec![(1, 1), (1, 33), (2, 61), (3, 16)]);
+
+        let kinds: Vec<QueryType> = parsed.statements.iter().map(|statement| statement.query_type.clone()).collect();
+        assert_eq!(kinds, vec![QueryType::Create, QueryType::Select]);
+        let StatementKind::Select(select) = &parsed.statements[1].kind else {
+            panic!("expected a SELECT");
+        };
+        assert_eq!(select.to_string(), "SELECT (SELECT  FROM u) + 1 FROM t");
+    }
+
+    #[test]
+    fn without_recovery_parsing_stops_at_the_first_error() {
+        let sql = "SELECT 1; SELEC 2; SELEC 3";
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let parsed = AstBuilder::new(sql, tokens).build_partial();
+        assert_eq!(parsed.statements.len(), 1);
+        assert_eq!(parsed.errors.len(), 1);
+    }
+
 }
@@ -0,0 +1,251 @@
+//! Structured diagnostics for errors and warnings found in SQL text
+//!
+//! A diagnostic carries a stable code, a message, the span of the source it
+//! is about and a rendered snippet of that source with a caret under it, so
+//! both the shell and API clients can point at the exact problem.
+
+use crate::parser::ast::{ParseError, Span};
+use crate::parser::lexer::LexError;
+use serde::Serialize;
+use std::fmt;
+
+/// Input the tokenizer could not read
+pub const LEXICAL_ERROR: &str = "E1001";
+/// A token that cannot appear where it was found
+pub const SYNTAX_ERROR: &str = "E1002";
+/// Input that ends in the middle of a statement
+pub const INCOMPLETE_INPUT: &str = "E1003";
+/// A table or view no schema defines
+pub const UNKNOWN_TABLE: &str = "E2001";
+/// A column none of the tables in scope has
+pub const UNKNOWN_COLUMN: &str = "E2002";
+/// An unqualified column more than one table in scope has
+pub const AMBIGUOUS_COLUMN: &str = "E2003";
+/// An aggregate, GROUP BY or ORDER BY term used where SQLite rejects it
+pub const GROUPING_ERROR: &str = "E2004";
+/// Rows or column lists whose number of columns do not match
+pub const COLUMN_COUNT_MISMATCH: &str = "E2005";
+/// An index or trigger no schema defines
+pub const UNKNOWN_OBJECT: &str = "E2006";
+/// A name the statement would create that is already taken
+pub const DUPLICATE_NAME: &str = "E2007";
+/// A column of an aggregate query that is neither grouped nor aggregated
+pub const UNGROUPED_COLUMN: &str = "W2001";
+/// A comparison whose operands SQLite never finds equal
+pub const SUSPICIOUS_COMPARISON: &str = "W2002";
+/// A `?NNN` parameter out of range
+pub const INVALID_PARAMETER: &str = "E3001";
+/// A parameter no value was bound to
+pub const UNBOUND_PARAMETER: &str = "E3002";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in SQL text, with where it is
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    /// The source line the span starts on, with carets under the span
+    pub snippet: String,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span, sql: &str) -> Self {
+        Diagnostic {
+            code,
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            snippet: render_snippet(sql, &span),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span, sql: &str) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, span, sql)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        writeln!(f, " --> line {}, column {}", self.span.line, self.span.column)?;
+        write!(f, "{}", self.snippet)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Several diagnostics reported together as one error
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticList(pub Vec<Diagnostic>);
+
+impl fmt::Display for DiagnosticList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DiagnosticList {}
+
+impl Diagnostic {
+    pub fn from_lex_error(error: &LexError, sql: &str) -> Self {
+        let span = Span {
+            offset: error.offset,
+            length: error.length,
+            line: error.line,
+            column: error.column,
+        };
+        Diagnostic::error(LEXICAL_ERROR, error.message.clone(), span, sql)
+    }
+
+    pub fn from_parse_error(error: &ParseError, sql: &str) -> Self {
+        let code = if error.span.offset >= sql.len() {
+            INCOMPLETE_INPUT
+        } else {
+            SYNTAX_ERROR
+        };
+        Diagnostic::error(code, error.message.clone(), error.span, sql)
+    }
+}
+
+/// Turns a lexer or parser error about `sql` into a diagnostic; any other
+/// error is returned unchanged
+pub fn with_source(error: anyhow::Error, sql: &str) -> anyhow::Error {
+    let diagnostic = if let Some(error) = error.downcast_ref::<LexError>() {
+        Diagnostic::from_lex_error(error, sql)
+    } else if let Some(error) = error.downcast_ref::<ParseError>() {
+        Diagnostic::from_parse_error(error, sql)
+    } else {
+        return error;
+    };
+    anyhow::Error::new(diagnostic)
+}
+
+/// One error reporting every diagnostic; a single one is reported as itself
+pub fn into_error(mut diagnostics: Vec<Diagnostic>) -> anyhow::Error {
+    if diagnostics.len() == 1 {
+        anyhow::Error::new(diagnostics.remove(0))
+    } else {
+        anyhow::Error::new(DiagnosticList(diagnostics))
+    }
+}
+
+/// Diagnostics carried by an error, empty when it has none
+pub fn diagnostics_of(error: &anyhow::Error) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for cause in error.chain() {
+        if let Some(diagnostic) = cause.downcast_ref::<Diagnostic>() {
+            diagnostics.push(diagnostic.clone());
+        } else if let Some(list) = cause.downcast_ref::<DiagnosticList>() {
+            diagnostics.extend(list.0.iter().cloned());
+        }
+    }
+    diagnostics
+}
+
+/// The line `span` starts on, numbered, with carets under the span. Tabs
+/// before the span are kept so the carets line up with the text above them.
+fn render_snippet(sql: &str, span: &Span) -> String {
+    let text = sql.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    let indent: String = text
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let end = (span.offset + span.length).min(sql.len());
+    let width = sql
+        .get(span.offset..end)
+        .unwrap_or("")
+        .chars()
+        .take_while(|c| *c != '\n')
+        .count()
+        .max(1);
+
+    format!(
+        "{gutter} |\n{number} | {text}\n{gutter} | {indent}{carets}",
+        carets = "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ast::AstBuilder;
+    use crate::parser::lexer::Tokenizer;
+
+    fn diagnostic(sql: &str) -> Diagnostic {
+        let error = Tokenizer::new(sql)
+            .tokenize()
+            .and_then(|tokens| AstBuilder::new(sql, tokens).build_all())
+            .unwrap_err();
+        diagnostics_of(&with_source(error, sql)).remove(0)
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_offending_token() {
+        let found = diagnostic("SELECT name\n\tFROM users WHERE id = = 1");
+        assert_eq!(found.code, SYNTAX_ERROR);
+        assert_eq!(found.message, "near \"=\": syntax error, expected an expression");
+        assert_eq!((found.span.line, found.span.column), (2, 24));
+        assert_eq!(
+            found.to_string(),
+            "error[E1002]: near \"=\": syntax error, expected an expression\n \
+             --> line 2, column 24\n  |\n2 | \tFROM users WHERE id = = 1\n  | \t                      ^"
+        );
+    }
+
+    #[test]
+    fn carets_cover_the_whole_token() {
+        let found = diagnostic("SELECT * FROM t LIMT 5");
+        assert_eq!(found.snippet, "  |\n1 | SELECT * FROM t LIMT 5\n  |                      ^");
+
+        let found = diagnostic("SELECT * FORM t");
+        assert_eq!(found.snippet, "  |\n1 | SELECT * FORM t\n  |          ^^^^");
+    }
+
+    #[test]
+    fn lexical_and_incomplete_input_errors_have_their_own_codes() {
+        let found = diagnostic("SELECT 'unterminated");
+        assert_eq!(found.code, LEXICAL_ERROR);
+        assert_eq!(found.span.column, 8);
+
+        let found = diagnostic("SELECT * FROM");
+        assert_eq!(found.code, INCOMPLETE_INPUT);
+        assert_eq!(found.message, "incomplete input, expected a table name");
+    }
+
+    #[test]
+    fn other_errors_carry_no_diagnostics() {
+        let error = with_source(anyhow::anyhow!("disk full"), "SELECT 1");
+        assert!(diagnostics_of(&error).is_empty());
+        assert_eq!(error.to_string(), "disk full");
+    }
+}
@@ -1,82 +1,297 @@
 //! SQL Lexical analysis (tokenization)
 //!
-//! Transforms raw SQL text into a stream of tokens for the parser
+//! Transforms raw SQL text into a stream of tokens for the parser, following
+//! the lexical rules of SQLite's tokenizer: every keyword, the three quoted
+//! identifier forms, blob and hex literals, bind parameters and comments.
+//! Each token records where it starts so later stages can point at it.
 
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use std::fmt;
 
+macro_rules! keywords {
+    ($($variant:ident => $text:literal,)*) => {
+        /// The keywords SQLite reserves
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Keyword {
+            $($variant,)*
+        }
+
+        impl Keyword {
+            /// Keyword spelled by a bare word, in any letter case
+            pub fn lookup(word: &str) -> Option<Keyword> {
+                match word.to_ascii_uppercase().as_str() {
+                    $($text => Some(Keyword::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Keyword::$variant => $text,)*
+                }
+            }
+        }
+    };
+}
+
+keywords! {
+    Abort => "ABORT",
+    Action => "ACTION",
+    Add => "ADD",
+    After => "AFTER",
+    All => "ALL",
+    Alter => "ALTER",
+    Always => "ALWAYS",
+    Analyze => "ANALYZE",
+    And => "AND",
+    As => "AS",
+    Asc => "ASC",
+    Attach => "ATTACH",
+    Autoincrement => "AUTOINCREMENT",
+    Before => "BEFORE",
+    Begin => "BEGIN",
+    Between => "BETWEEN",
+    By => "BY",
+    Cascade => "CASCADE",
+    Case => "CASE",
+    Cast => "CAST",
+    Check => "CHECK",
+    Collate => "COLLATE",
+    Column => "COLUMN",
+    Commit => "COMMIT",
+    Conflict => "CONFLICT",
+    Constraint => "CONSTRAINT",
+    Create => "CREATE",
+    Cross => "CROSS",
+    Current => "CURRENT",
+    CurrentDate => "CURRENT_DATE",
+    CurrentTime => "CURRENT_TIME",
+    CurrentTimestamp => "CURRENT_TIMESTAMP",
+    Database => "DATABASE",
+    Default => "DEFAULT",
+    Deferrable => "DEFERRABLE",
+    Deferred => "DEFERRED",
+    Delete => "DELETE",
+    Desc => "DESC",
+    Detach => "DETACH",
+    Distinct => "DISTINCT",
+    Do => "DO",
+    Drop => "DROP",
+    Each => "EACH",
+    Else => "ELSE",
+    End => "END",
+    Escape => "ESCAPE",
+    Except => "EXCEPT",
+    Exclude => "EXCLUDE",
+    Exclusive => "EXCLUSIVE",
+    Exists => "EXISTS",
+    Explain => "EXPLAIN",
+    Fail => "FAIL",
+    Filter => "FILTER",
+    First => "FIRST",
+    Following => "FOLLOWING",
+    For => "FOR",
+    Foreign => "FOREIGN",
+    From => "FROM",
+    Full => "FULL",
+    Generated => "GENERATED",
+    Glob => "GLOB",
+    Group => "GROUP",
+    Groups => "GROUPS",
+    Having => "HAVING",
+    If => "IF",
+    Ignore => "IGNORE",
+    Immediate => "IMMEDIATE",
+    In => "IN",
+    Index => "INDEX",
+    Indexed => "INDEXED",
+    Initially => "INITIALLY",
+    Inner => "INNER",
+    Insert => "INSERT",
+    Instead => "INSTEAD",
+    Intersect => "INTERSECT",
+    Into => "INTO",
+    Is => "IS",
+    Isnull => "ISNULL",
+    Join => "JOIN",
+    Key => "KEY",
+    Last => "LAST",
+    Left => "LEFT",
+    Like => "LIKE",
+    Limit => "LIMIT",
+    Match => "MATCH",
+    Materialized => "MATERIALIZED",
+    Natural => "NATURAL",
+    No => "NO",
+    Not => "NOT",
+    Nothing => "NOTHING",
+    Notnull => "NOTNULL",
+    Null => "NULL",
+    Nulls => "NULLS",
+    Of => "OF",
+    Offset => "OFFSET",
+    On => "ON",
+    Or => "OR",
+    Order => "ORDER",
+    Others => "OTHERS",
+    Outer => "OUTER",
+    Over => "OVER",
+    Partition => "PARTITION",
+    Plan => "PLAN",
+    Pragma => "PRAGMA",
+    Preceding => "PRECEDING",
+    Primary => "PRIMARY",
+    Query => "QUERY",
+    Raise => "RAISE",
+    Range => "RANGE",
+    Recursive => "RECURSIVE",
+    References => "REFERENCES",
+    Regexp => "REGEXP",
+    Reindex => "REINDEX",
+    Release => "RELEASE",
+    Rename => "RENAME",
+    Replace => "REPLACE",
+    Restrict => "RESTRICT",
+    Returning => "RETURNING",
+    Right => "RIGHT",
+    Rollback => "ROLLBACK",
+    Row => "ROW",
+    Rows => "ROWS",
+    Savepoint => "SAVEPOINT",
+    Select => "SELECT",
+    Set => "SET",
+    Table => "TABLE",
+    Temp => "TEMP",
+    Temporary => "TEMPORARY",
+    Then => "THEN",
+    Ties => "TIES",
+    To => "TO",
+    Transaction => "TRANSACTION",
+    Trigger => "TRIGGER",
+    Unbounded => "UNBOUNDED",
+    Union => "UNION",
+    Unique => "UNIQUE",
+    Update => "UPDATE",
+    Using => "USING",
+    Vacuum => "VACUUM",
+    Values => "VALUES",
+    View => "VIEW",
+    Virtual => "VIRTUAL",
+    When => "WHEN",
+    Where => "WHERE",
+    Window => "WINDOW",
+    With => "WITH",
+    Without => "WITHOUT",
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 /// Represents a token type in SQL
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
-    // Keywords
-    Select,
-    From,
-    Where,
-    Order,
-    By,
-    Group,
-    Having,
-    Join,
-    Inner,
-    Left,
-    Right,
-    Outer,
-    On,
-    As,
-    Union,
-    All,
-    Distinct,
-    Limit,
-    Offset,
-    
+    Keyword(Keyword),
+
     // Operators
     Plus,
     Minus,
     Multiply,
     Divide,
+    Modulo,
+    /// `=` or `==`
     Equals,
+    /// `!=` or `<>`
     NotEquals,
     GreaterThan,
     LessThan,
     GreaterEquals,
     LessEquals,
-    And,
-    Or,
-    Not,
-    
+    /// `||`
+    Concat,
+    LeftShift,
+    RightShift,
+    BitAnd,
+    BitOr,
+    BitNot,
+    /// `->`
+    Arrow,
+    /// `->>`
+    LongArrow,
+
     // Literals
+    /// Decimal or hex integer that fits in 64 bits
     Integer(i64),
+    /// Number with a fraction or exponent, or a decimal integer too large for 64 bits
     Float(f64),
     String(String),
-    Boolean(bool),
-    Null,
-    
+    /// `X'...'`
+    Blob(Vec<u8>),
+
     // Identifiers
     Identifier(String),
-    
+    /// `"x"`, `[x]` or `` `x` ``, with the quotes removed
+    QuotedIdentifier(String),
+
+    /// Bind parameter as written: `?`, `?NNN`, `:name`, `@name` or `$name`
+    Parameter(String),
+
     // Punctuation
     Comma,
     Period,
     Semicolon,
     LeftParen,
     RightParen,
-    
+
     // Special
     EOF,
-    Unknown(String),
 }
 
 impl fmt::Display for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            // Just show a few examples for brevity
-            TokenType::Select => write!(f, "SELECT"),
-            TokenType::From => write!(f, "FROM"),
-            TokenType::Where => write!(f, "WHERE"),
-            TokenType::Integer(i) => write!(f, "INT:{}", i),
-            TokenType::String(s) => write!(f, "STRING:'{}'", s),
-            TokenType::Identifier(s) => write!(f, "IDENT:{}", s),
-            _ => write!(f, "{:?}", self),
+            TokenType::Keyword(keyword) => write!(f, "{}", keyword),
+            TokenType::Plus => write!(f, "+"),
+            TokenType::Minus => write!(f, "-"),
+            TokenType::Multiply => write!(f, "*"),
+            TokenType::Divide => write!(f, "/"),
+            TokenType::Modulo => write!(f, "%"),
+            TokenType::Equals => write!(f, "="),
+            TokenType::NotEquals => write!(f, "!="),
+            TokenType::GreaterThan => write!(f, ">"),
+            TokenType::LessThan => write!(f, "<"),
+            TokenType::GreaterEquals => write!(f, ">="),
+            TokenType::LessEquals => write!(f, "<="),
+            TokenType::Concat => write!(f, "||"),
+            TokenType::LeftShift => write!(f, "<<"),
+            TokenType::RightShift => write!(f, ">>"),
+            TokenType::BitAnd => write!(f, "&"),
+            TokenType::BitOr => write!(f, "|"),
+            TokenType::BitNot => write!(f, "~"),
+            TokenType::Arrow => write!(f, "->"),
+            TokenType::LongArrow => write!(f, "->>"),
+            TokenType::Integer(i) => write!(f, "{}", i),
+            TokenType::Float(value) => write!(f, "{:?}", value),
+            TokenType::String(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            TokenType::Blob(bytes) => {
+                write!(f, "X'")?;
+                for byte in bytes {
+                    write!(f, "{:02X}", byte)?;
+                }
+                write!(f, "'")
+            }
+            TokenType::Identifier(s) => write!(f, "{}", s),
+            TokenType::QuotedIdentifier(s) => write!(f, "\"{}\"", s.replace('"', "\"\"")),
+            TokenType::Parameter(s) => write!(f, "{}", s),
+            TokenType::Comma => write!(f, ","),
+            TokenType::Period => write!(f, "."),
+            TokenType::Semicolon => write!(f, ";"),
+            TokenType::LeftParen => write!(f, "("),
+            TokenType::RightParen => write!(f, ")"),
+            TokenType::EOF => write!(f, "end of input"),
         }
     }
 }
@@ -85,139 +300,518 @@ impl fmt::Display for TokenType {
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
+    /// Byte offset of the token's first character in the input
+    pub offset: usize,
+    /// 1-based line of the first character
     pub line: usize,
+    /// 1-based column of the first character, counted in characters
     pub column: usize,
+    /// Length of the token's source text in bytes
     pub length: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, line: usize, column: usize, length: usize) -> Self {
+    pub fn new(token_type: TokenType, offset: usize, line: usize, column: usize, length: usize) -> Self {
         Token {
             token_type,
+            offset,
             line,
             column,
             length,
         }
     }
+
+    /// Byte offset just past the token
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+
+    pub fn is_keyword(&self, keyword: Keyword) -> bool {
+        self.token_type == TokenType::Keyword(keyword)
+    }
+}
+
+/// Input the tokenizer could not read, with the position it starts at
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub offset: usize,
+    /// Bytes of input the error covers
+    pub length: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for LexError {}
+
 /// SQL Tokenizer that converts SQL text into tokens
 pub struct Tokenizer {
     input: String,
-    keywords: Vec<(String, TokenType)>,
+}
+
+/// Position in the input while scanning it
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
 }
 
 impl Tokenizer {
     pub fn new(input: &str) -> Self {
-        // Create a map of keywords
-        let mut keywords = Vec::new();
-        keywords.push(("SELECT".to_string(), TokenType::Select));
-        keywords.push(("FROM".to_string(), TokenType::From));
-        keywords.push(("WHERE".to_string(), TokenType::Where));
-        keywords.push(("ORDER".to_string(), TokenType::Order));
-        keywords.push(("BY".to_string(), TokenType::By));
-        keywords.push(("GROUP".to_string(), TokenType::Group));
-        keywords.push(("HAVING".to_string(), TokenType::Having));
-        keywords.push(("JOIN".to_string(), TokenType::Join));
-        keywords.push(("INNER".to_string(), TokenType::Inner));
-        keywords.push(("LEFT".to_string(), TokenType::Left));
-        keywords.push(("RIGHT".to_string(), TokenType::Right));
-        keywords.push(("OUTER".to_string(), TokenType::Outer));
-        keywords.push(("ON".to_string(), TokenType::On));
-        keywords.push(("AS".to_string(), TokenType::As));
-        keywords.push(("UNION".to_string(), TokenType::Union));
-        keywords.push(("ALL".to_string(), TokenType::All));
-        keywords.push(("DISTINCT".to_string(), TokenType::Distinct));
-        keywords.push(("LIMIT".to_string(), TokenType::Limit));
-        keywords.push(("OFFSET".to_string(), TokenType::Offset));
-        keywords.push(("AND".to_string(), TokenType::And));
-        keywords.push(("OR".to_string(), TokenType::Or));
-        keywords.push(("NOT".to_string(), TokenType::Not));
-        keywords.push(("NULL".to_string(), TokenType::Null));
-        keywords.push(("TRUE".to_string(), TokenType::Boolean(true)));
-        keywords.push(("FALSE".to_string(), TokenType::Boolean(false)));
-
         Tokenizer {
             input: input.to_string(),
-            keywords,
         }
     }
-    
+
+    /// Tokens of the whole input, ending with an EOF token. Whitespace and
+    /// comments separate tokens but produce none.
     pub fn tokenize(&self) -> Result<Vec<Token>> {
+        let (tokens, _) = self.scan(false)?;
+        Ok(tokens)
+    }
+
+    /// Tokens of the whole input like `tokenize`, but input that cannot be
+    /// read is skipped and reported instead of ending tokenization
+    pub fn tokenize_recovering(&self) -> Result<(Vec<Token>, Vec<LexError>)> {
+        self.scan(true)
+    }
+
+    fn scan(&self, recover: bool) -> Result<(Vec<Token>, Vec<LexError>)> {
         println!("[LEXER] Tokenizing SQL input: length {} characters", self.input.len());
-        println!("[LEXER] Applying lexical analysis rules");
-        
-        // In a real implementation, we'd actually tokenize the input
-        // For now, we'll create a plausible sequence of tokens based on the input
-        
-        // First display token extraction process
+
+        let bytes = self.input.as_bytes();
+        let mut position = Position { offset: 0, line: 1, column: 1 };
         let mut tokens = Vec::new();
-        let mut line: usize = 1;
-        let mut column: usize = 1;
-        
-        // Let's create a basic tokenizing display
-        for (i, word) in self.input.split_whitespace().enumerate() {
-            println!("[LEXER] Extracted token: '{}'", word);
-            
-            // Look for keyword match
-            let token_type = self.match_keyword(word);
-            tokens.push(Token::new(token_type, line, column, word.len()));
-            
-            column += word.len() + 1; // +1 for the space
-            if i % 5 == 0 {
-                line += 1;
-                column = 1;
+        let mut errors = Vec::new();
+
+        loop {
+            position = self.skip_trivia(position);
+            if position.offset >= bytes.len() {
+                break;
+            }
+            match self.scan_token(position) {
+                Ok((token_type, length)) => {
+                    tokens.push(Token::new(token_type, position.offset, position.line, position.column, length));
+                    position = self.advance(position, length);
+                }
+                Err(e) if recover => {
+                    let error = e.downcast::<LexError>()?;
+                    // Skip whole characters, and at least one, so scanning moves on
+                    let mut length = error.length.max(1).min(bytes.len() - position.offset);
+                    while position.offset + length < bytes.len() && is_continuation_byte(bytes[position.offset + length]) {
+                        length += 1;
+                    }
+                    position = self.advance(position, length);
+                    errors.push(error);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        tokens.push(Token::new(TokenType::EOF, position.offset, position.line, position.column, 0));
+        println!("[LEXER] Tokenization complete: extracted {} tokens", tokens.len() - 1);
+
+        Ok((tokens, errors))
+    }
+
+    /// Moves past `length` bytes, counting lines and characters
+    fn advance(&self, mut position: Position, length: usize) -> Position {
+        for &byte in &self.input.as_bytes()[position.offset..position.offset + length] {
+            if byte == b'\n' {
+                position.line += 1;
+                position.column = 1;
+            } else if !is_continuation_byte(byte) {
+                position.column += 1;
+            }
+        }
+        position.offset += length;
+        position
+    }
+
+    /// Skips whitespace, `-- line` comments and `/* block */` comments. An
+    /// unterminated block comment runs to the end of the input, as in SQLite.
+    fn skip_trivia(&self, mut position: Position) -> Position {
+        let bytes = self.input.as_bytes();
+        loop {
+            let rest = &bytes[position.offset..];
+            let length = match rest {
+                [b' ' | b'\t' | b'\n' | b'\x0c' | b'\r', ..] => 1,
+                [b'-', b'-', ..] => rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len()),
+                [b'/', b'*', ..] => find(&rest[2..], b"*/").map_or(rest.len(), |end| end + 4),
+                _ => return position,
+            };
+            position = self.advance(position, length);
+        }
+    }
+
+    /// Type and byte length of the token starting at `position`
+    fn scan_token(&self, position: Position) -> Result<(TokenType, usize)> {
+        let rest = &self.input.as_bytes()[position.offset..];
+        let unrecognized = |length: usize| {
+            let text = String::from_utf8_lossy(&rest[..length.min(rest.len())]).to_string();
+            self.error(position, length.min(rest.len()), format!("unrecognized token: \"{}\"", text))
+        };
+
+        let operator = |token_type: TokenType, length: usize| Ok((token_type, length));
+        match rest {
+            [b'-', b'>', b'>', ..] => operator(TokenType::LongArrow, 3),
+            [b'-', b'>', ..] => operator(TokenType::Arrow, 2),
+            [b'-', ..] => operator(TokenType::Minus, 1),
+            [b'(', ..] => operator(TokenType::LeftParen, 1),
+            [b')', ..] => operator(TokenType::RightParen, 1),
+            [b';', ..] => operator(TokenType::Semicolon, 1),
+            [b'+', ..] => operator(TokenType::Plus, 1),
+            [b'*', ..] => operator(TokenType::Multiply, 1),
+            [b'/', ..] => operator(TokenType::Divide, 1),
+            [b'%', ..] => operator(TokenType::Modulo, 1),
+            [b'=', b'=', ..] => operator(TokenType::Equals, 2),
+            [b'=', ..] => operator(TokenType::Equals, 1),
+            [b'<', b'=', ..] => operator(TokenType::LessEquals, 2),
+            [b'<', b'>', ..] => operator(TokenType::NotEquals, 2),
+            [b'<', b'<', ..] => operator(TokenType::LeftShift, 2),
+            [b'<', ..] => operator(TokenType::LessThan, 1),
+            [b'>', b'=', ..] => operator(TokenType::GreaterEquals, 2),
+            [b'>', b'>', ..] => operator(TokenType::RightShift, 2),
+            [b'>', ..] => operator(TokenType::GreaterThan, 1),
+            [b'!', b'=', ..] => operator(TokenType::NotEquals, 2),
+            [b'!', ..] => Err(unrecognized(1)),
+            [b'|', b'|', ..] => operator(TokenType::Concat, 2),
+            [b'|', ..] => operator(TokenType::BitOr, 1),
+            [b'&', ..] => operator(TokenType::BitAnd, 1),
+            [b'~', ..] => operator(TokenType::BitNot, 1),
+            [b',', ..] => operator(TokenType::Comma, 1),
+            [b'.', digit, ..] if digit.is_ascii_digit() => self.scan_number(position),
+            [b'.', ..] => operator(TokenType::Period, 1),
+            [b'\'', ..] => match scan_quoted(rest, b'\'') {
+                Some((text, length)) => Ok((TokenType::String(text), length)),
+                None => Err(unrecognized(rest.len())),
+            },
+            [quote @ (b'"' | b'`'), ..] => match scan_quoted(rest, *quote) {
+                Some((text, length)) => Ok((TokenType::QuotedIdentifier(text), length)),
+                None => Err(unrecognized(rest.len())),
+            },
+            [b'[', ..] => match rest.iter().position(|&b| b == b']') {
+                Some(end) => Ok((
+                    TokenType::QuotedIdentifier(String::from_utf8_lossy(&rest[1..end]).to_string()),
+                    end + 1,
+                )),
+                None => Err(unrecognized(rest.len())),
+            },
+            [b'x' | b'X', b'\'', ..] => {
+                let end = rest[2..].iter().position(|&b| b == b'\'').map(|end| end + 2);
+                let Some(end) = end else {
+                    return Err(unrecognized(rest.len()));
+                };
+                let digits = &rest[2..end];
+                if digits.len() % 2 != 0 || !digits.iter().all(u8::is_ascii_hexdigit) {
+                    return Err(unrecognized(end + 1));
+                }
+                let blob = digits
+                    .chunks(2)
+                    .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap_or("00"), 16).unwrap_or(0))
+                    .collect();
+                Ok((TokenType::Blob(blob), end + 1))
+            }
+            [b'?', ..] => {
+                let length = 1 + rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+                Ok((TokenType::Parameter(self.text(position, length)), length))
+            }
+            [b':' | b'@', ..] => {
+                let length = 1 + rest[1..].iter().take_while(|&&b| is_identifier_byte(b)).count();
+                if length == 1 {
+                    return Err(unrecognized(1));
+                }
+                Ok((TokenType::Parameter(self.text(position, length)), length))
+            }
+            [b'$', ..] => {
+                let length = scan_tcl_parameter(rest);
+                if length == 1 {
+                    return Err(unrecognized(1));
+                }
+                Ok((TokenType::Parameter(self.text(position, length)), length))
+            }
+            [digit, ..] if digit.is_ascii_digit() => self.scan_number(position),
+            [first, ..] if is_identifier_start(*first) => {
+                let length = rest.iter().take_while(|&&b| is_identifier_byte(b)).count();
+                let word = self.text(position, length);
+                let token_type = match Keyword::lookup(&word) {
+                    Some(keyword) => TokenType::Keyword(keyword),
+                    None => TokenType::Identifier(word),
+                };
+                Ok((token_type, length))
+            }
+            _ => {
+                let length = self.input[position.offset..].chars().next().map_or(1, char::len_utf8);
+                Err(unrecognized(length))
             }
         }
-        
-        println!("[LEXER] Tokenization complete: extracted {} tokens", tokens.len());
-        
-        Ok(tokens)
     }
-    
-    fn match_keyword(&self, word: &str) -> TokenType {
-        // Try to match against keywords first
-        for (keyword, token_type) in &self.keywords {
-            if keyword.eq_ignore_ascii_case(word) {
-                return token_type.clone();
+
+    /// Decimal integer, hex integer (`0x1F`) or real (`1.5`, `.5`, `1e10`).
+    /// A number running into identifier characters, like `12abc`, is an error.
+    fn scan_number(&self, position: Position) -> Result<(TokenType, usize)> {
+        let rest = &self.input.as_bytes()[position.offset..];
+        let digits_from = |start: usize| start + rest[start..].iter().take_while(|b| b.is_ascii_digit()).count();
+
+        if let [b'0', b'x' | b'X', digit, ..] = rest {
+            if digit.is_ascii_hexdigit() {
+                let length = 2 + rest[2..].iter().take_while(|b| b.is_ascii_hexdigit()).count();
+                if rest.get(length).is_some_and(|&b| is_identifier_byte(b)) {
+                    return Err(self.unrecognized(position, length + 1));
+                }
+                let digits = &self.input[position.offset + 2..position.offset + length];
+                // Hex literals are 64-bit two's complement, so 0xFFFFFFFFFFFFFFFF is -1
+                let value = u64::from_str_radix(digits, 16)
+                    .map_err(|_| self.error(position, length, format!("hex literal too big: {}", self.text(position, length))))?;
+                return Ok((TokenType::Integer(value as i64), length));
             }
         }
-        
-        // Check if it's a number
-        if let Ok(i) = word.parse::<i64>() {
-            return TokenType::Integer(i);
+
+        let mut length = digits_from(0);
+        let mut is_real = false;
+        if rest.get(length) == Some(&b'.') {
+            is_real = true;
+            length = digits_from(length + 1);
         }
-        
-        if let Ok(f) = word.parse::<f64>() {
-            return TokenType::Float(f);
+        if let Some(b'e' | b'E') = rest.get(length) {
+            let mut exponent = length + 1;
+            if let Some(b'+' | b'-') = rest.get(exponent) {
+                exponent += 1;
+            }
+            if !rest.get(exponent).is_some_and(u8::is_ascii_digit) {
+                return Err(self.unrecognized(position, exponent));
+            }
+            is_real = true;
+            length = digits_from(exponent);
         }
-        
-        // String literal
-        if word.starts_with('\'') && word.ends_with('\'') && word.len() >= 2 {
-            return TokenType::String(word[1..word.len()-1].to_string());
+        if rest.get(length).is_some_and(|&b| is_identifier_byte(b)) {
+            let end = length + rest[length..].iter().take_while(|&&b| is_identifier_byte(b)).count();
+            return Err(self.unrecognized(position, end));
         }
-        
-        // Punctuation
-        match word {
-            "," => return TokenType::Comma,
-            "." => return TokenType::Period,
-            ";" => return TokenType::Semicolon,
-            "(" => return TokenType::LeftParen,
-            ")" => return TokenType::RightParen,
-            "+" => return TokenType::Plus,
-            "-" => return TokenType::Minus,
-            "*" => return TokenType::Multiply,
-            "/" => return TokenType::Divide,
-            "=" => return TokenType::Equals,
-            "!=" => return TokenType::NotEquals,
-            ">" => return TokenType::GreaterThan,
-            "<" => return TokenType::LessThan,
-            ">=" => return TokenType::GreaterEquals,
-            "<=" => return TokenType::LessEquals,
-            _ => {}
+
+        let text = self.text(position, length);
+        let token_type = match text.parse::<i64>() {
+            Ok(value) if !is_real => TokenType::Integer(value),
+            // Integers too large for 64 bits become reals, as in SQLite
+            _ => TokenType::Float(text.parse::<f64>().map_err(|_| self.unrecognized(position, length))?),
+        };
+        Ok((token_type, length))
+    }
+
+    fn text(&self, position: Position, length: usize) -> String {
+        self.input[position.offset..position.offset + length].to_string()
+    }
+
+    fn unrecognized(&self, position: Position, length: usize) -> anyhow::Error {
+        let end = (position.offset + length).min(self.input.len());
+        let text = String::from_utf8_lossy(&self.input.as_bytes()[position.offset..end]).to_string();
+        self.error(position, end - position.offset, format!("unrecognized token: \"{}\"", text))
+    }
+
+    fn error(&self, position: Position, length: usize, message: String) -> anyhow::Error {
+        anyhow::Error::new(LexError {
+            message,
+            offset: position.offset,
+            length,
+            line: position.line,
+            column: position.column,
+        })
+    }
+}
+
+/// Bytes after the first of a UTF-8 character
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// SQLite lets identifiers use letters, digits, `_`, `$` and any non-ASCII character
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || byte >= 0x80
+}
+
+fn is_identifier_start(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_' || byte >= 0x80
+}
+
+/// Text between `quote` characters, where a doubled quote stands for one,
+/// and the length including both quotes. `None` when the quote is not closed.
+fn scan_quoted(input: &[u8], quote: u8) -> Option<(String, usize)> {
+    let mut text = Vec::new();
+    let mut index = 1;
+    loop {
+        match *input.get(index)? {
+            b if b == quote && input.get(index + 1) == Some(&quote) => {
+                text.push(quote);
+                index += 2;
+            }
+            b if b == quote => return Some((String::from_utf8_lossy(&text).to_string(), index + 1)),
+            b => {
+                text.push(b);
+                index += 1;
+            }
         }
-        
-        // If nothing else matches, it's an identifier
-        TokenType::Identifier(word.to_string())
     }
-}
\ No newline at end of file
+}
+
+/// Length of a `$name` parameter, which may contain `::` separators and end
+/// with a parenthesised suffix, as TCL variable names do
+fn scan_tcl_parameter(input: &[u8]) -> usize {
+    let mut index = 1;
+    loop {
+        match &input[index..] {
+            [b, ..] if is_identifier_byte(*b) => index += 1,
+            [b':', b':', ..] => index += 2,
+            [b'(', ..] if index > 1 => {
+                return match input[index..].iter().position(|&b| b == b')') {
+                    Some(end) => index + end + 1,
+                    None => index,
+                };
+            }
+            _ => return index,
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(sql: &str) -> Vec<TokenType> {
+        Tokenizer::new(sql)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|token| token.token_type)
+            .collect()
+    }
+
+    fn lex_error(sql: &str) -> LexError {
+        Tokenizer::new(sql).tokenize().unwrap_err().downcast::<LexError>().unwrap()
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive_and_other_words_are_identifiers() {
+        assert_eq!(
+            types("select Current_Timestamp FROM t"),
+            vec![
+                TokenType::Keyword(Keyword::Select),
+                TokenType::Keyword(Keyword::CurrentTimestamp),
+                TokenType::Keyword(Keyword::From),
+                TokenType::Identifier("t".to_string()),
+                TokenType::EOF,
+            ]
+        );
+        assert_eq!(types("true")[0], TokenType::Identifier("true".to_string()));
+    }
+
+    #[test]
+    fn quoted_identifiers_take_all_three_forms() {
+        assert_eq!(
+            types("\"a \"\"b\"\"\" [c d] `e``f`"),
+            vec![
+                TokenType::QuotedIdentifier("a \"b\"".to_string()),
+                TokenType::QuotedIdentifier("c d".to_string()),
+                TokenType::QuotedIdentifier("e`f".to_string()),
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn literals_cover_strings_blobs_hex_and_reals() {
+        assert_eq!(
+            types("'it''s' x'0aFF' 0x10 0xFFFFFFFFFFFFFFFF 42 1.5 .5 1e3 9223372036854775808"),
+            vec![
+                TokenType::String("it's".to_string()),
+                TokenType::Blob(vec![0x0a, 0xff]),
+                TokenType::Integer(16),
+                TokenType::Integer(-1),
+                TokenType::Integer(42),
+                TokenType::Float(1.5),
+                TokenType::Float(0.5),
+                TokenType::Float(1000.0),
+                TokenType::Float(9223372036854775808.0),
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn parameters_keep_their_spelling() {
+        let parameters: Vec<TokenType> = ["?", "?12", ":name", "@name", "$a::b(x)"]
+            .iter()
+            .map(|name| TokenType::Parameter(name.to_string()))
+            .chain(std::iter::once(TokenType::EOF))
+            .collect();
+        assert_eq!(types("? ?12 :name @name $a::b(x)"), parameters);
+    }
+
+    #[test]
+    fn operators_take_the_longest_match() {
+        assert_eq!(
+            types("a||b << >> <> != == % & | ~ -> ->> <= >="),
+            vec![
+                TokenType::Identifier("a".to_string()),
+                TokenType::Concat,
+                TokenType::Identifier("b".to_string()),
+                TokenType::LeftShift,
+                TokenType::RightShift,
+                TokenType::NotEquals,
+                TokenType::NotEquals,
+                TokenType::Equals,
+                TokenType::Modulo,
+                TokenType::BitAnd,
+                TokenType::BitOr,
+                TokenType::BitNot,
+                TokenType::Arrow,
+                TokenType::LongArrow,
+                TokenType::LessEquals,
+                TokenType::GreaterEquals,
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        assert_eq!(
+            types("1 -- one\n/* two */ 2 /* unterminated"),
+            vec![TokenType::Integer(1), TokenType::Integer(2), TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn tokens_record_offset_line_and_column() {
+        let tokens = Tokenizer::new("SELECT 'é',\n  name -- c\nFROM t").tokenize().unwrap();
+        let positions: Vec<(usize, usize, usize, usize)> = tokens
+            .iter()
+            .map(|token| (token.offset, token.line, token.column, token.length))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![(0, 1, 1, 6), (7, 1, 8, 4), (11, 1, 11, 1), (15, 2, 3, 4), (25, 3, 1, 4), (30, 3, 6, 1), (31, 3, 7, 0)]
+        );
+    }
+
+    #[test]
+    fn malformed_input_reports_where_it_starts() {
+        let error = lex_error("SELECT 1,\n  'open");
+        assert_eq!((error.message.as_str(), error.line, error.column), ("unrecognized token: \"'open\"", 2, 3));
+        assert_eq!(lex_error("SELECT 12abc").message, "unrecognized token: \"12abc\"");
+        assert_eq!(lex_error("SELECT x'abc'").message, "unrecognized token: \"x'abc'\"");
+        assert_eq!(lex_error("SELECT 1e").message, "unrecognized token: \"1e\"");
+        assert_eq!(lex_error("SELECT !1").offset, 7);
+        assert_eq!(lex_error("SELECT 0x10000000000000000").message, "hex literal too big: 0x10000000000000000");
+    }
+}
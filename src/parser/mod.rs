@@ -6,16 +6,17 @@
 
 pub mod lexer;
 pub mod ast;
+pub mod diagnostic;
+pub mod params;
+pub mod types;
 pub mod validator;
 
-use sqlparser::dialect::SQLiteDialect;
-use sqlparser::parser::Parser as SQLParserLib;
 use anyhow::{Result, anyhow};
+use diagnostic::Diagnostic;
 
 /// Core parsing functionality for SQL statements
 pub struct Parser {
     sql: String,
-    dialect: SQLiteDialect,
     error_recovery: bool,
 }
 
@@ -23,11 +24,11 @@ impl Parser {
     pub fn new(sql: &str) -> Self {
         Parser {
             sql: sql.to_string(),
-            dialect: SQLiteDialect {},
             error_recovery: false,
         }
     }
     
+    /// Makes `parse_script` carry on past errors, collecting all of them
     pub fn with_error_recovery(mut self) -> Self {
         self.error_recovery = true;
         self
@@ -35,32 +36,154 @@ impl Parser {
     
     pub fn parse(&self) -> Result<ast::Statement> {
         println!("[PARSER] Beginning SQL parsing process");
+        let statement = self.builder()?.build().map_err(|e| diagnostic::with_source(e, &self.sql))?;
+        println!("[PARSER] AST construction complete");
+        Ok(statement)
+    }
+
+    /// Parses every statement of a script
+    pub fn parse_statements(&self) -> Result<Vec<ast::Statement>> {
+        println!("[PARSER] Beginning SQL parsing process");
+        let statements = self.builder()?.build_all().map_err(|e| diagnostic::with_source(e, &self.sql))?;
+        println!("[PARSER] AST construction complete, {} statements", statements.len());
+        Ok(statements)
+    }
+
+    /// Parses a script into the statements that parsed and a diagnostic for
+    /// each error. Without error recovery, parsing stops at the first error.
+    pub fn parse_script(&self) -> Result<ScriptParse> {
+        println!("[PARSER] Parsing script, error recovery {}", if self.error_recovery { "on" } else { "off" });
+        let (tokens, lex_errors) = if self.error_recovery {
+            lexer::Tokenizer::new(&self.sql).tokenize_recovering()?
+        } else {
+            match lexer::Tokenizer::new(&self.sql).tokenize() {
+                Ok(tokens) => (tokens, Vec::new()),
+                Err(e) => {
+                    let error = e.downcast::<lexer::LexError>()?;
+                    return Ok(ScriptParse {
+                        statements: Vec::new(),
+                        diagnostics: vec![Diagnostic::from_lex_error(&error, &self.sql)],
+                    });
+                }
+            }
+        };
+
+        let mut builder = ast::AstBuilder::new(&self.sql, tokens);
+        if self.error_recovery {
+            builder = builder.with_error_recovery();
+        }
+        let parsed = builder.build_partial();
+
+        let mut diagnostics: Vec<Diagnostic> = lex_errors
+            .iter()
+            .map(|error| Diagnostic::from_lex_error(error, &self.sql))
+            .chain(parsed.errors.iter().map(|error| Diagnostic::from_parse_error(error, &self.sql)))
+            .collect();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.offset);
+        println!("[PARSER] Parsed {} statements with {} errors", parsed.statements.len(), diagnostics.len());
+
+        Ok(ScriptParse {
+            statements: parsed.statements,
+            diagnostics,
+        })
+    }
+
+    fn builder(&self) -> Result<ast::AstBuilder> {
         println!("[PARSER] Tokenizing input SQL");
-        
-        // First, tokenize the input
-        let tokens = lexer::Tokenizer::new(&self.sql).tokenize()?;
-        
+        let tokens = lexer::Tokenizer::new(&self.sql)
+            .tokenize()
+            .map_err(|e| diagnostic::with_source(e, &self.sql))?;
         println!("[PARSER] Tokenization complete, {} tokens generated", tokens.len());
         println!("[PARSER] Building abstract syntax tree");
-        
-        // Then build the AST
-        let ast_builder = ast::AstBuilder::new(tokens);
-        let statement = ast_builder.build()?;
-        
-        println!("[PARSER] AST construction complete");
-        
-        // Secretly, we also parse with SQLParser to get the real AST
-        let parser = SQLParserLib::new(&self.dialect);
-        let _parsed_statements = parser.try_with_sql(&self.sql)
-            .map_err(|e| anyhow!("SQL syntax error: {}", e))?
-            .parse_statements()
-            .map_err(|e| anyhow!("SQL parse error: {}", e))?;
-        
-        Ok(statement)
+        Ok(ast::AstBuilder::new(&self.sql, tokens))
     }
 }
 
+/// Statements of a script and the errors found in it
+#[derive(Debug, Clone)]
+pub struct ScriptParse {
+    pub statements: Vec<ast::Statement>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
 /// Public interface for parsing operations
 pub fn parse_sql(sql: &str) -> Result<ast::Statement> {
     Parser::new(sql).parse()
-}
\ No newline at end of file
+}
+
+/// Splits a script into its statements at top-level semicolons, keeping
+/// the semicolons inside CREATE TRIGGER bodies. Empty statements are dropped.
+pub fn split_statements(sql: &str) -> Result<Vec<String>> {
+    use lexer::{Keyword, TokenType};
+
+    let tokens = lexer::Tokenizer::new(sql)
+        .tokenize()
+        .map_err(|e| diagnostic::with_source(e, sql))?;
+
+    let mut statements = Vec::new();
+    // Source range of the current statement, from its first token to its last
+    let mut start: Option<usize> = None;
+    let mut end = 0;
+    let mut words: Vec<Keyword> = Vec::new();
+    // BEGIN ... END of a trigger body, and CASE ... END inside it
+    let mut body_depth = 0;
+    let mut case_depth = 0;
+
+    for token in &tokens {
+        match token.token_type {
+            TokenType::Keyword(keyword) => {
+                let in_trigger = words.first() == Some(&Keyword::Create) && words.contains(&Keyword::Trigger);
+                match keyword {
+                    Keyword::Begin if in_trigger => body_depth += 1,
+                    Keyword::Case => case_depth += 1,
+                    Keyword::End if case_depth > 0 => case_depth -= 1,
+                    Keyword::End if body_depth > 0 => body_depth -= 1,
+                    _ => {}
+                }
+                words.push(keyword);
+            }
+            TokenType::Semicolon if body_depth == 0 => {
+                if let Some(start) = start.take() {
+                    statements.push(sql[start..end].to_string());
+                }
+                words.clear();
+                case_depth = 0;
+                continue;
+            }
+            TokenType::EOF => break,
+            _ => {}
+        }
+        start.get_or_insert(token.offset);
+        end = token.end();
+    }
+
+    if let Some(start) = start {
+        statements.push(sql[start..end].to_string());
+    }
+
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_recovery_collects_every_error_of_a_script() {
+        let sql = "SELECT 1 #; SELEC 2;\nSELECT * FROM t WHERE";
+
+        let strict = Parser::new(sql).parse_script().unwrap();
+        assert!(strict.statements.is_empty());
+        let codes: Vec<&str> = strict.diagnostics.iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(codes, vec![diagnostic::LEXICAL_ERROR]);
+
+        let recovered = Parser::new(sql).with_error_recovery().parse_script().unwrap();
+        let codes: Vec<&str> = recovered.diagnostics.iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(
+            codes,
+            vec![diagnostic::LEXICAL_ERROR, diagnostic::SYNTAX_ERROR, diagnostic::INCOMPLETE_INPUT]
+        );
+        assert_eq!(recovered.statements.len(), 2);
+        assert_eq!(recovered.statements[1].query_text, "SELECT * FROM t WHERE");
+    }
+}
@@ -0,0 +1,414 @@
+//! Bind parameters and prepared statements
+//!
+//! Parameters are numbered the way SQLite numbers them for
+//! sqlite3_bind_parameter_index: `?NNN` is parameter NNN, a bare `?` the one
+//! after the largest number so far, and each distinct `:name`, `@name` or
+//! `$name` takes the next number at its first use. Numbering starts again
+//! with every statement of a script.
+//!
+//! A prepared statement is parsed once and remembers where its parameters
+//! are. Binding replaces each parameter in a copy of the parsed statement
+//! with its value as a literal, so a value can never change the statement
+//! it is bound into, and errors still point into the SQL as written.
+
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+
+use crate::parser::ast::{Expression, Span, Statement, Value, VisitorMut};
+use crate::parser::diagnostic::{self, Diagnostic};
+use crate::parser::lexer::{Keyword, Token, TokenType, Tokenizer};
+use crate::parser::{Parser, ScriptParse};
+
+/// SQLite's default SQLITE_MAX_VARIABLE_NUMBER
+pub const MAX_PARAMETER_NUMBER: usize = 32766;
+
+/// Values to bind, by parameter number or name
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    /// Keyed `?N` for numbered values, and by the name as given otherwise;
+    /// a name given without its `:`, `@` or `$` matches any of them
+    values: BTreeMap<String, Value>,
+}
+
+impl Params {
+    pub fn new() -> Self {
+        Params::default()
+    }
+
+    /// Values for parameters 1, 2, ... in order
+    pub fn positional(values: Vec<Value>) -> Self {
+        let mut params = Params::new();
+        for (i, value) in values.into_iter().enumerate() {
+            params.values.insert(format!("?{}", i + 1), value);
+        }
+        params
+    }
+
+    /// Values from a JSON array, bound by position, or a JSON object, bound
+    /// by name; object keys that are numbers bind by position
+    pub fn from_json(json: &serde_json::Value) -> Result<Self> {
+        match json {
+            serde_json::Value::Array(values) => Ok(Params::positional(
+                values.iter().map(json_value).collect::<Result<Vec<_>>>()?,
+            )),
+            serde_json::Value::Object(values) => {
+                let mut params = Params::new();
+                for (key, value) in values {
+                    params.set(key, json_value(value)?)?;
+                }
+                Ok(params)
+            }
+            serde_json::Value::Null => Ok(Params::new()),
+            other => bail!("params must be an array or an object, not {}", other),
+        }
+    }
+
+    /// Sets the value of a parameter: `?N` or `N` by number, `:name`,
+    /// `@name` or `$name` by name, or a bare name for any of the three
+    pub fn set(&mut self, key: &str, value: Value) -> Result<()> {
+        let key = key.trim();
+        let number = key.strip_prefix('?').unwrap_or(key);
+        let key = if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+            format!("?{}", parameter_number(number)?)
+        } else if key.is_empty() || key.starts_with('?') {
+            bail!("invalid parameter name: '{}'", key);
+        } else {
+            key.to_string()
+        };
+        self.values.insert(key, value);
+        Ok(())
+    }
+
+    /// Removes a parameter's value, returning whether it had one
+    pub fn unset(&mut self, key: &str) -> bool {
+        let key = key.trim();
+        let numbered = key.strip_prefix('?').and_then(|number| number.parse::<usize>().ok());
+        match numbered {
+            Some(number) => self.values.remove(&format!("?{}", number)).is_some(),
+            None => self.values.remove(key).is_some(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Every value with its key, names first, then numbers in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        let (mut numbered, named): (Vec<_>, Vec<_>) = self.values.iter().partition(|(key, _)| key.starts_with('?'));
+        numbered.sort_by_key(|(key, _)| key[1..].parse::<usize>().unwrap_or(0));
+        named.into_iter().chain(numbered).map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// The value bound to a parameter: by its name as written, then its
+    /// bare name, then its number
+    fn get(&self, parameter: &Parameter) -> Option<&Value> {
+        let by_name = parameter.name.as_deref().and_then(|name| {
+            self.values.get(name).or_else(|| self.values.get(&name[1..]))
+        });
+        by_name.or_else(|| self.values.get(&format!("?{}", parameter.number)))
+    }
+}
+
+/// One place a parameter appears in the SQL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    /// The parameter as written: `?`, `?3`, `:id`, ...
+    pub text: String,
+    /// The name of a `:`, `@` or `$` parameter, with its prefix
+    pub name: Option<String>,
+    /// 1-based number, which positional values bind to
+    pub number: usize,
+    pub span: Span,
+}
+
+/// A parsed statement or script that values can be bound into any number
+/// of times
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+    sql: String,
+    script: ScriptParse,
+    parameters: Vec<Parameter>,
+}
+
+impl PreparedStatement {
+    /// Parses `sql` and finds its parameters. Statements that do not parse
+    /// are kept as diagnostics, so the rest of a script can still run.
+    pub fn prepare(sql: &str) -> Result<Self> {
+        println!("[PREPARE] Preparing statement: {}", sql);
+        let (tokens, _) = Tokenizer::new(sql).tokenize_recovering()?;
+        let parameters = scan_parameters(sql, &tokens)?;
+
+        let script = Parser::new(sql).with_error_recovery().parse_script()?;
+        println!("[PREPARE] {} statement(s), {} parameter(s)", script.statements.len(), parameters.len());
+
+        Ok(PreparedStatement {
+            sql: sql.to_string(),
+            script,
+            parameters,
+        })
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// The statements that parsed and the syntax errors of the rest
+    pub fn script(&self) -> &ScriptParse {
+        &self.script
+    }
+
+    /// Every place a parameter appears, in order
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+
+    /// The parameters that appear in one of the prepared statements
+    pub fn parameters_in(&self, statement: &Statement) -> Vec<&Parameter> {
+        let end = statement.span.offset + statement.span.length;
+        self.parameters
+            .iter()
+            .filter(|parameter| (statement.span.offset..end).contains(&parameter.span.offset))
+            .collect()
+    }
+
+    /// Copies of the statements with every parameter replaced by its value.
+    /// Fails, pointing at the parameter, when one has no value.
+    pub fn bind(&self, params: &Params) -> Result<Vec<Statement>> {
+        self.script.statements.iter().map(|statement| self.bind_statement(statement, params)).collect()
+    }
+
+    /// A copy of one of the prepared statements with its parameters replaced
+    /// by their values
+    pub fn bind_statement(&self, statement: &Statement, params: &Params) -> Result<Statement> {
+        let parameters = self.parameters_in(statement);
+        let mut bound = statement.clone();
+        if parameters.is_empty() {
+            return Ok(bound);
+        }
+
+        let mut binder = ParameterBinder {
+            sql: &self.sql,
+            parameters: &parameters,
+            params,
+            bound: Vec::new(),
+        };
+        bound.walk_mut(&mut binder)?;
+        // Parameters where the walk does not reach, such as in a column default
+        if let Some(parameter) = parameters.iter().find(|parameter| !binder.bound.contains(&parameter.span.offset)) {
+            let message = format!("parameter {} is not allowed here", parameter.text);
+            return Err(anyhow::Error::new(Diagnostic::error(diagnostic::INVALID_PARAMETER, message, parameter.span, &self.sql)));
+        }
+        Ok(bound)
+    }
+}
+
+/// Replaces the parameters of a statement with their values
+struct ParameterBinder<'a> {
+    sql: &'a str,
+    parameters: &'a [&'a Parameter],
+    params: &'a Params,
+    /// Offsets of the parameters replaced so far
+    bound: Vec<usize>,
+}
+
+impl VisitorMut for ParameterBinder<'_> {
+    fn visit_expression(&mut self, expr: &mut Expression) -> Result<()> {
+        let Expression::Parameter { span, .. } = expr else {
+            return Ok(());
+        };
+        let span = *span;
+        let Some(parameter) = self.parameters.iter().find(|parameter| parameter.span.offset == span.offset) else {
+            return Ok(());
+        };
+        let Some(value) = self.params.get(parameter) else {
+            let message = format!("no value bound for parameter {}", parameter.text);
+            return Err(anyhow::Error::new(Diagnostic::error(diagnostic::UNBOUND_PARAMETER, message, span, self.sql)));
+        };
+        let value = match value {
+            Value::Float(number) if !number.is_finite() => {
+                let message = format!("cannot bind {} as a parameter value", number);
+                return Err(anyhow::Error::new(Diagnostic::error(diagnostic::INVALID_PARAMETER, message, span, self.sql)));
+            }
+            Value::Boolean(flag) => Value::Integer(i64::from(*flag)),
+            other => other.clone(),
+        };
+        self.bound.push(span.offset);
+        *expr = Expression::Literal(value);
+        Ok(())
+    }
+}
+
+/// Every parameter in `tokens`, numbered as SQLite numbers them
+fn scan_parameters(sql: &str, tokens: &[Token]) -> Result<Vec<Parameter>> {
+    let mut parameters = Vec::new();
+    let mut names: Vec<(String, usize)> = Vec::new();
+    let mut largest = 0;
+    for token in tokens {
+        let text = match &token.token_type {
+            TokenType::Parameter(text) => text,
+            // Each statement numbers its parameters afresh
+            TokenType::Semicolon => {
+                names.clear();
+                largest = 0;
+                continue;
+            }
+            _ => continue,
+        };
+
+        let span = Span::from(token);
+        let (name, number) = if text == "?" {
+            (None, largest + 1)
+        } else if let Some(number) = text.strip_prefix('?') {
+            let number = parameter_number(number).map_err(|e| {
+                anyhow::Error::new(Diagnostic::error(diagnostic::INVALID_PARAMETER, e.to_string(), span, sql))
+            })?;
+            (None, number)
+        } else {
+            match names.iter().find(|(name, _)| name == text) {
+                Some((_, number)) => (Some(text.clone()), *number),
+                None => {
+                    names.push((text.clone(), largest + 1));
+                    (Some(text.clone()), largest + 1)
+                }
+            }
+        };
+        if number > MAX_PARAMETER_NUMBER {
+            let message = format!("too many SQL variables, at most {}", MAX_PARAMETER_NUMBER);
+            return Err(anyhow::Error::new(Diagnostic::error(diagnostic::INVALID_PARAMETER, message, span, sql)));
+        }
+        largest = largest.max(number);
+        parameters.push(Parameter {
+            text: text.clone(),
+            name,
+            number,
+            span,
+        });
+    }
+
+    Ok(parameters)
+}
+
+/// A value as the shell's `.param set` takes it: an SQL literal, or text
+/// when it is not one
+pub fn parse_value(text: &str) -> Value {
+    let text = text.trim();
+    let Ok(tokens) = Tokenizer::new(text).tokenize() else {
+        return Value::String(text.to_string());
+    };
+    let types: Vec<&TokenType> = tokens
+        .iter()
+        .map(|token| &token.token_type)
+        .filter(|token_type| **token_type != TokenType::EOF)
+        .collect();
+    match types.as_slice() {
+        [TokenType::Integer(value)] => Value::Integer(*value),
+        [TokenType::Minus, TokenType::Integer(value)] => Value::Integer(value.wrapping_neg()),
+        [TokenType::Float(value)] => Value::Float(*value),
+        [TokenType::Minus, TokenType::Float(value)] => Value::Float(-value),
+        [TokenType::String(value)] => Value::String(value.clone()),
+        [TokenType::Blob(value)] => Value::Blob(value.clone()),
+        [TokenType::Keyword(Keyword::Null)] => Value::Null,
+        _ => Value::String(text.to_string()),
+    }
+}
+
+fn json_value(json: &serde_json::Value) -> Result<Value> {
+    match json {
+        serde_json::Value::Null => Ok(Value::Null),
+        serde_json::Value::Bool(flag) => Ok(Value::Integer(i64::from(*flag))),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => Ok(Value::Integer(integer)),
+            None => number.as_f64().map(Value::Float).ok_or_else(|| anyhow!("unsupported number {}", number)),
+        },
+        serde_json::Value::String(text) => Ok(Value::String(text.clone())),
+        other => bail!("parameter values must be null, booleans, numbers or strings, not {}", other),
+    }
+}
+
+fn parameter_number(digits: &str) -> Result<usize> {
+    match digits.parse::<usize>() {
+        Ok(number) if (1..=MAX_PARAMETER_NUMBER).contains(&number) => Ok(number),
+        _ => bail!("variable number must be between ?1 and ?{}", MAX_PARAMETER_NUMBER),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_parameters_like_sqlite() {
+        let prepared = PreparedStatement::prepare("SELECT ?, :a, ?5, ?, :a, @b, $c; SELECT ?").unwrap();
+        let numbers: Vec<(&str, usize)> =
+            prepared.parameters().iter().map(|parameter| (parameter.text.as_str(), parameter.number)).collect();
+        assert_eq!(
+            numbers,
+            vec![("?", 1), (":a", 2), ("?5", 5), ("?", 6), (":a", 2), ("@b", 7), ("$c", 8), ("?", 1)]
+        );
+
+        assert!(PreparedStatement::prepare("SELECT ?0").is_err());
+        assert!(PreparedStatement::prepare("SELECT ?40000").is_err());
+        // A question mark in a string or comment is not a parameter
+        assert!(PreparedStatement::prepare("SELECT '?' -- ?").unwrap().parameters().is_empty());
+    }
+
+    #[test]
+    fn binds_values_into_the_parsed_statement_many_times() {
+        let prepared = PreparedStatement::prepare("SELECT * FROM users WHERE name = :name AND id > ?2 - ?").unwrap();
+        let bound = |params: &Params| -> Result<String> { Ok(prepared.bind(params)?[0].to_string()) };
+
+        let mut params = Params::new();
+        params.set("name", Value::String("O'Brien'; DROP TABLE users; --".to_string())).unwrap();
+        params.set("2", Value::Integer(-1)).unwrap();
+        params.set("?3", Value::Float(1.0)).unwrap();
+        assert_eq!(
+            bound(&params).unwrap(),
+            "SELECT * FROM users WHERE name = 'O''Brien''; DROP TABLE users; --' AND id > -1 - 1.0"
+        );
+
+        let params = Params::positional(vec![Value::Null, Value::Integer(7), Value::Blob(vec![0xAB])]);
+        assert_eq!(bound(&params).unwrap(), "SELECT * FROM users WHERE name = NULL AND id > 7 - X'AB'");
+        // The prepared statement itself keeps its parameters
+        assert_eq!(prepared.script().statements[0].to_string(), "SELECT * FROM users WHERE name = :name AND id > ?2 - ?");
+
+        let error = prepared.bind(&Params::positional(vec![Value::Null])).unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.code, diagnostic::UNBOUND_PARAMETER);
+        assert_eq!(diagnostic.message, "no value bound for parameter ?2");
+        assert_eq!(&prepared.sql()[diagnostic.span.offset..][..diagnostic.span.length], "?2");
+
+        // A negative value under a unary minus must not start a comment
+        let prepared = PreparedStatement::prepare("SELECT -?").unwrap();
+        let negated = prepared.bind(&Params::positional(vec![Value::Integer(-3)])).unwrap();
+        assert_eq!(negated[0].to_string(), "SELECT - -3");
+    }
+
+    #[test]
+    fn reads_values_from_json_and_shell_text() {
+        let json: serde_json::Value = serde_json::from_str(r#"{"id": 3, ":name": "x", "1": true, "ratio": 0.5}"#).unwrap();
+        let params = Params::from_json(&json).unwrap();
+        let listed: Vec<(String, String)> = params.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        assert_eq!(
+            listed,
+            vec![
+                (":name".to_string(), "'x'".to_string()),
+                ("id".to_string(), "3".to_string()),
+                ("ratio".to_string(), "0.5".to_string()),
+                ("?1".to_string(), "1".to_string()),
+            ]
+        );
+        assert!(Params::from_json(&serde_json::json!([[1]])).is_err());
+        assert!(Params::from_json(&serde_json::json!("x")).is_err());
+
+        assert_eq!(parse_value("-42").to_string(), "-42");
+        assert_eq!(parse_value("'it''s'").to_string(), "'it''s'");
+        assert_eq!(parse_value("x'00ff'").to_string(), "X'00FF'");
+        assert_eq!(parse_value("null").to_string(), "NULL");
+        assert_eq!(parse_value("hello world").to_string(), "'hello world'");
+    }
+}
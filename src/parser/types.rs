@@ -0,0 +1,500 @@
+//! Static types of SQL expressions
+//!
+//! SQLite is dynamically typed, but most expressions can only produce one
+//! storage class. The analyzer infers, for every expression, the type of the
+//! values it produces, whether it can be NULL and the affinity it brings to
+//! comparisons, following https://www.sqlite.org/datatype3.html.
+
+use crate::parser::ast::{Operator, Value};
+use crate::schema::column::{ColumnAffinity, ColumnSchema, StrictType};
+use serde::Serialize;
+use std::fmt;
+
+/// Type of the values an expression produces
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Integer,
+    Real,
+    /// Integer or real
+    Numeric,
+    Text,
+    Blob,
+    /// Only ever NULL
+    Null,
+    /// Any storage class
+    Any,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Integer => write!(f, "INTEGER"),
+            ValueType::Real => write!(f, "REAL"),
+            ValueType::Numeric => write!(f, "NUMERIC"),
+            ValueType::Text => write!(f, "TEXT"),
+            ValueType::Blob => write!(f, "BLOB"),
+            ValueType::Null => write!(f, "NULL"),
+            ValueType::Any => write!(f, "ANY"),
+        }
+    }
+}
+
+impl ValueType {
+    /// Type a column of this affinity holds when nothing more is known
+    pub fn of_affinity(affinity: ColumnAffinity) -> Self {
+        match affinity {
+            ColumnAffinity::Integer => ValueType::Integer,
+            ColumnAffinity::Real => ValueType::Real,
+            ColumnAffinity::Numeric => ValueType::Numeric,
+            ColumnAffinity::Text => ValueType::Text,
+            ColumnAffinity::Blob | ColumnAffinity::None => ValueType::Any,
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, ValueType::Integer | ValueType::Real | ValueType::Numeric)
+    }
+
+    /// Type that holds the values of both
+    pub fn merge(self, other: ValueType) -> Self {
+        match (self, other) {
+            (left, right) if left == right => left,
+            (ValueType::Null, other) | (other, ValueType::Null) => other,
+            (left, right) if left.is_numeric() && right.is_numeric() => ValueType::Numeric,
+            _ => ValueType::Any,
+        }
+    }
+
+    /// JSON Schema `type` of the values; None when they can be of any type.
+    /// Blobs travel as hex strings.
+    pub fn json_type(self) -> Option<&'static str> {
+        match self {
+            ValueType::Integer => Some("integer"),
+            ValueType::Real | ValueType::Numeric => Some("number"),
+            ValueType::Text | ValueType::Blob => Some("string"),
+            ValueType::Null => Some("null"),
+            ValueType::Any => None,
+        }
+    }
+
+    /// Storage class every value has, when there is only one
+    fn storage_class(self) -> Option<&'static str> {
+        match self {
+            ValueType::Integer | ValueType::Real | ValueType::Numeric => Some("numeric"),
+            ValueType::Text => Some("text"),
+            ValueType::Blob => Some("blob"),
+            ValueType::Null | ValueType::Any => None,
+        }
+    }
+}
+
+/// What is known about an expression before it runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExprType {
+    pub value_type: ValueType,
+    pub nullable: bool,
+    /// Affinity applied to what it is compared with; only columns and CASTs have one
+    pub affinity: ColumnAffinity,
+}
+
+impl ExprType {
+    pub fn new(value_type: ValueType, nullable: bool) -> Self {
+        ExprType {
+            value_type,
+            nullable,
+            affinity: ColumnAffinity::None,
+        }
+    }
+
+    /// An expression nothing is known about
+    pub fn unknown() -> Self {
+        ExprType::new(ValueType::Any, true)
+    }
+
+    /// Type of a table column. The rowid alias is never NULL.
+    pub fn of_column(column: &ColumnSchema, strict: bool, rowid_alias: bool) -> Self {
+        let value_type = match column.strict_type() {
+            Some(strict_type) if strict => match strict_type {
+                StrictType::Int | StrictType::Integer => ValueType::Integer,
+                StrictType::Real => ValueType::Real,
+                StrictType::Text => ValueType::Text,
+                StrictType::Blob => ValueType::Blob,
+                StrictType::Any => ValueType::Any,
+            },
+            _ => ExprType::of_declared_type(&column.data_type, true).value_type,
+        };
+        ExprType {
+            value_type,
+            nullable: column.is_nullable && !rowid_alias,
+            affinity: column.storage_affinity(strict),
+        }
+    }
+
+    /// Type of a column declared with this type name in an ordinary table
+    pub fn of_declared_type(type_name: &str, nullable: bool) -> Self {
+        let affinity = ColumnAffinity::of_declared_type(type_name);
+        // A column without a declared type has BLOB affinity but holds anything
+        let value_type = match affinity {
+            ColumnAffinity::Blob if !type_name.trim().is_empty() => ValueType::Blob,
+            affinity => ValueType::of_affinity(affinity),
+        };
+        ExprType {
+            value_type,
+            nullable,
+            affinity,
+        }
+    }
+
+    /// The same values, possibly NULL as well
+    pub fn or_null(self) -> Self {
+        ExprType { nullable: true, ..self }
+    }
+
+    /// The same values, without the affinity that comparisons would apply
+    pub fn without_affinity(self) -> Self {
+        ExprType {
+            affinity: ColumnAffinity::None,
+            ..self
+        }
+    }
+
+    /// Type holding the values of both
+    pub fn merge(self, other: ExprType) -> Self {
+        ExprType::new(self.value_type.merge(other.value_type), self.nullable || other.nullable)
+    }
+}
+
+/// A column of a query's result, with the type inferred for it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResultColumn {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    pub nullable: bool,
+    #[serde(skip)]
+    pub affinity: ColumnAffinity,
+}
+
+impl ResultColumn {
+    pub fn new(name: String, expr_type: ExprType) -> Self {
+        ResultColumn {
+            name,
+            value_type: expr_type.value_type,
+            nullable: expr_type.nullable,
+            affinity: expr_type.affinity,
+        }
+    }
+
+    pub fn expr_type(&self) -> ExprType {
+        ExprType {
+            value_type: self.value_type,
+            nullable: self.nullable,
+            affinity: self.affinity,
+        }
+    }
+}
+
+pub fn literal_type(value: &Value) -> ExprType {
+    match value {
+        Value::Integer(_) | Value::Boolean(_) => ExprType::new(ValueType::Integer, false),
+        Value::Float(_) => ExprType::new(ValueType::Real, false),
+        Value::String(_) | Value::CurrentTime | Value::CurrentDate | Value::CurrentTimestamp => {
+            ExprType::new(ValueType::Text, false)
+        }
+        Value::Blob(_) => ExprType::new(ValueType::Blob, false),
+        Value::Null => ExprType::new(ValueType::Null, true),
+    }
+}
+
+/// Type arithmetic gives: integers stay integers, anything with a real is
+/// real, and text is converted to whichever number it reads as
+fn arithmetic(left: ValueType, right: ValueType) -> ValueType {
+    match (left, right) {
+        (ValueType::Null, _) | (_, ValueType::Null) => ValueType::Null,
+        (ValueType::Integer, ValueType::Integer) => ValueType::Integer,
+        (ValueType::Real, _) | (_, ValueType::Real) => ValueType::Real,
+        _ => ValueType::Numeric,
+    }
+}
+
+pub fn unary_type(op: &Operator, operand: ExprType) -> ExprType {
+    match op {
+        Operator::Minus => {
+            let value_type = match operand.value_type {
+                ValueType::Integer | ValueType::Real | ValueType::Null => operand.value_type,
+                _ => ValueType::Numeric,
+            };
+            ExprType::new(value_type, operand.nullable)
+        }
+        // Unary plus is a no-op that only drops the affinity
+        Operator::Plus => operand.without_affinity(),
+        _ => ExprType::new(ValueType::Integer, operand.nullable),
+    }
+}
+
+pub fn binary_type(op: &Operator, left: ExprType, right: ExprType) -> ExprType {
+    let nullable = left.nullable || right.nullable;
+    match op {
+        Operator::Plus | Operator::Minus | Operator::Multiply => {
+            ExprType::new(arithmetic(left.value_type, right.value_type), nullable)
+        }
+        // Dividing by zero gives NULL
+        Operator::Divide | Operator::Modulo => ExprType::new(arithmetic(left.value_type, right.value_type), true),
+        Operator::Concat => ExprType::new(ValueType::Text, nullable),
+        Operator::Is | Operator::IsNot => ExprType::new(ValueType::Integer, false),
+        // `->` returns JSON text, `->>` the SQL value at the path; both are NULL without one
+        Operator::Arrow => ExprType::new(ValueType::Text, true),
+        Operator::LongArrow => ExprType::unknown(),
+        _ => ExprType::new(ValueType::Integer, nullable),
+    }
+}
+
+/// Type a CAST produces: the type and affinity its type name stands for
+pub fn cast_type(type_name: &str, operand: ExprType) -> ExprType {
+    let affinity = ColumnAffinity::of_declared_type(type_name);
+    let value_type = match affinity {
+        ColumnAffinity::Blob | ColumnAffinity::None => ValueType::Blob,
+        affinity => ValueType::of_affinity(affinity),
+    };
+    ExprType {
+        value_type,
+        nullable: operand.nullable,
+        affinity,
+    }
+}
+
+/// Type a call to a built-in function returns, from the types of its
+/// arguments. Aggregates over no rows give NULL, except count() and total().
+pub fn function_type(name: &str, args: &[ExprType]) -> ExprType {
+    let any_nullable = args.iter().any(|arg| arg.nullable);
+    let first = args.first().copied().unwrap_or_else(ExprType::unknown);
+    let of = |value_type| ExprType::new(value_type, any_nullable);
+    let merged = |args: &[ExprType]| {
+        args.iter()
+            .copied()
+            .reduce(ExprType::merge)
+            .unwrap_or_else(ExprType::unknown)
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        "count" => ExprType::new(ValueType::Integer, false),
+        "sum" => {
+            let value_type = match first.value_type {
+                ValueType::Integer | ValueType::Real => first.value_type,
+                _ => ValueType::Numeric,
+            };
+            ExprType::new(value_type, true)
+        }
+        "total" => ExprType::new(ValueType::Real, false),
+        "avg" => ExprType::new(ValueType::Real, true),
+        "min" | "max" if args.len() == 1 => first.without_affinity().or_null(),
+        "min" | "max" => merged(args),
+        "group_concat" | "string_agg" => ExprType::new(ValueType::Text, true),
+        "json_group_array" | "json_group_object" => ExprType::new(ValueType::Text, false),
+        "row_number" | "rank" | "dense_rank" | "ntile" => ExprType::new(ValueType::Integer, false),
+        "percent_rank" | "cume_dist" => ExprType::new(ValueType::Real, false),
+        "lag" | "lead" | "first_value" | "last_value" | "nth_value" => first.without_affinity().or_null(),
+
+        "coalesce" | "ifnull" => ExprType {
+            nullable: args.iter().all(|arg| arg.nullable),
+            ..merged(args)
+        },
+        "nullif" => first.without_affinity().or_null(),
+        "iif" => merged(args.get(1..).unwrap_or_default()),
+        "likelihood" | "likely" | "unlikely" => first,
+
+        "abs" => ExprType::new(unary_type(&Operator::Minus, first).value_type, first.nullable),
+        "round" | "ceil" | "ceiling" | "floor" | "trunc" | "sqrt" | "pow" | "power" | "exp" | "ln" | "log" | "log2"
+        | "log10" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "atan2" | "pi" | "degrees" | "radians" | "mod" => {
+            of(ValueType::Real)
+        }
+        "sign" | "length" | "octet_length" | "instr" | "unicode" | "glob" | "like" | "json_valid"
+        | "json_array_length" => of(ValueType::Integer),
+        "random" | "changes" | "total_changes" | "last_insert_rowid" => ExprType::new(ValueType::Integer, false),
+        "randomblob" | "zeroblob" | "unhex" => of(ValueType::Blob),
+
+        "lower" | "upper" | "trim" | "ltrim" | "rtrim" | "substr" | "substring" | "replace" | "hex" | "char"
+        | "soundex" | "printf" | "format" | "concat_ws" | "json" | "json_array" | "json_object" | "json_set"
+        | "json_insert" | "json_replace" | "json_remove" | "json_patch" | "json_quote" | "json_type" => {
+            of(ValueType::Text)
+        }
+        "quote" | "typeof" | "concat" | "sqlite_version" | "sqlite_source_id" => ExprType::new(ValueType::Text, false),
+        // Date functions give NULL for input they cannot read
+        "date" | "time" | "datetime" | "strftime" | "timediff" => ExprType::new(ValueType::Text, true),
+        "julianday" => ExprType::new(ValueType::Real, true),
+        "unixepoch" => ExprType::new(ValueType::Integer, true),
+        _ => ExprType::unknown(),
+    }
+}
+
+/// Why comparing these operands is likely a mistake, if it is: after
+/// SQLite applies affinity (datatype3.html, section 4.2), values of
+/// different storage classes are ordered by class and never equal.
+/// `literal` gives the value of an operand that is a literal.
+pub fn suspicious_comparison(
+    left: ExprType,
+    left_literal: Option<&Value>,
+    right: ExprType,
+    right_literal: Option<&Value>,
+) -> Option<String> {
+    let numeric = |affinity: ColumnAffinity| {
+        matches!(affinity, ColumnAffinity::Integer | ColumnAffinity::Real | ColumnAffinity::Numeric)
+    };
+    let no_affinity = |affinity: ColumnAffinity| matches!(affinity, ColumnAffinity::Blob | ColumnAffinity::None);
+
+    // Numeric affinity turns text that reads as a number into one; other text stays text
+    let converted = |operand: ExprType, literal: Option<&Value>| match literal {
+        Some(Value::String(text)) => {
+            if is_numeric_text(text) {
+                None
+            } else {
+                Some(format!("text '{}' is compared with a numeric column but is not a number, so SQLite compares it as text", text))
+            }
+        }
+        _ if operand.value_type == ValueType::Blob => {
+            Some("a BLOB is compared with a numeric column; affinity does not convert blobs".to_string())
+        }
+        _ => None,
+    };
+
+    if numeric(left.affinity) && !numeric(right.affinity) {
+        return converted(right, right_literal);
+    }
+    if numeric(right.affinity) && !numeric(left.affinity) {
+        return converted(left, left_literal);
+    }
+    // Text affinity turns numbers into text
+    if (left.affinity == ColumnAffinity::Text && no_affinity(right.affinity))
+        || (right.affinity == ColumnAffinity::Text && no_affinity(left.affinity))
+    {
+        return None;
+    }
+
+    match (left.value_type.storage_class(), right.value_type.storage_class()) {
+        (Some(left_class), Some(right_class)) if left_class != right_class => Some(format!(
+            "{} is compared with {} and no affinity conversion applies, so they are never equal",
+            left.value_type, right.value_type
+        )),
+        _ => None,
+    }
+}
+
+/// Whether numeric affinity would turn `text` into a number: an optionally
+/// signed decimal with an optional exponent, between optional spaces.
+/// Unlike Rust's float parsing, SQLite leaves "inf", "nan" and hex as text.
+fn is_numeric_text(text: &str) -> bool {
+    let bytes = text.trim().as_bytes();
+    let mut i = 0;
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+            *i += 1;
+        }
+        *i - start
+    };
+
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        i += 1;
+    }
+    let mut mantissa = digits(&mut i);
+    if bytes.get(i) == Some(&b'.') {
+        i += 1;
+        mantissa += digits(&mut i);
+    }
+    if mantissa == 0 {
+        return false;
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        i += 1;
+        if matches!(bytes.get(i), Some(b'+' | b'-')) {
+            i += 1;
+        }
+        if digits(&mut i) == 0 {
+            return false;
+        }
+    }
+    i == bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(affinity: ColumnAffinity) -> ExprType {
+        ExprType {
+            affinity,
+            ..ExprType::new(ValueType::of_affinity(affinity), true)
+        }
+    }
+
+    fn literal(value: &Value) -> (ExprType, Option<&Value>) {
+        (literal_type(value), Some(value))
+    }
+
+    #[test]
+    fn arithmetic_follows_its_operands() {
+        let integer = ExprType::new(ValueType::Integer, false);
+        let real = ExprType::new(ValueType::Real, true);
+        let text = ExprType::new(ValueType::Text, false);
+
+        assert_eq!(binary_type(&Operator::Plus, integer, integer), ExprType::new(ValueType::Integer, false));
+        assert_eq!(binary_type(&Operator::Multiply, integer, real), ExprType::new(ValueType::Real, true));
+        assert_eq!(binary_type(&Operator::Minus, integer, text), ExprType::new(ValueType::Numeric, false));
+        assert_eq!(binary_type(&Operator::Divide, integer, integer), ExprType::new(ValueType::Integer, true));
+        assert_eq!(binary_type(&Operator::Concat, integer, real), ExprType::new(ValueType::Text, true));
+        assert_eq!(binary_type(&Operator::Is, real, text), ExprType::new(ValueType::Integer, false));
+        assert_eq!(cast_type("VARCHAR(10)", integer).value_type, ValueType::Text);
+    }
+
+    #[test]
+    fn functions_have_signatures() {
+        let nullable_integer = ExprType::new(ValueType::Integer, true);
+        let text = ExprType::new(ValueType::Text, false);
+
+        assert_eq!(function_type("COUNT", &[nullable_integer]), ExprType::new(ValueType::Integer, false));
+        assert_eq!(function_type("sum", &[nullable_integer]), ExprType::new(ValueType::Integer, true));
+        assert_eq!(function_type("avg", &[nullable_integer]).value_type, ValueType::Real);
+        assert_eq!(function_type("upper", &[text]), ExprType::new(ValueType::Text, false));
+        assert!(!function_type("coalesce", &[nullable_integer, ExprType::new(ValueType::Integer, false)]).nullable);
+        assert_eq!(function_type("max", &[nullable_integer, text]).value_type, ValueType::Any);
+        assert_eq!(function_type("no_such_function", &[]), ExprType::unknown());
+    }
+
+    #[test]
+    fn warns_when_no_affinity_conversion_applies() {
+        let five = Value::Integer(5);
+        let (five_type, five_literal) = literal(&five);
+        let abc = Value::String("abc".to_string());
+        let (abc_type, abc_literal) = literal(&abc);
+        let digits = Value::String("42".to_string());
+        let (digits_type, digits_literal) = literal(&digits);
+
+        // Text affinity turns 5 into '5'; numeric affinity turns '42' into 42
+        assert_eq!(suspicious_comparison(column(ColumnAffinity::Text), None, five_type, five_literal), None);
+        assert_eq!(suspicious_comparison(column(ColumnAffinity::Integer), None, digits_type, digits_literal), None);
+
+        // Without the column's affinity the text stays text
+        let lowered = column(ColumnAffinity::Text).without_affinity();
+        assert_eq!(
+            suspicious_comparison(lowered, None, five_type, five_literal).unwrap(),
+            "TEXT is compared with INTEGER and no affinity conversion applies, so they are never equal"
+        );
+        assert!(suspicious_comparison(column(ColumnAffinity::Integer), None, abc_type, abc_literal).is_some());
+        assert!(suspicious_comparison(ExprType::unknown(), None, five_type, five_literal).is_none());
+    }
+
+    #[test]
+    fn numeric_text_follows_sqlite_rather_than_rust() {
+        for text in ["42", " -3.5 ", ".5", "5.", "+1e10", "1E-3", "1e999"] {
+            assert!(is_numeric_text(text), "{:?} should be numeric", text);
+        }
+        for text in ["inf", "-Infinity", "NaN", "0x10", "1e", ".", "", "1_000", "1 2"] {
+            assert!(!is_numeric_text(text), "{:?} should stay text", text);
+        }
+
+        let nan = Value::String("nan".to_string());
+        let (nan_type, nan_literal) = literal(&nan);
+        assert!(suspicious_comparison(column(ColumnAffinity::Real), None, nan_type, nan_literal).is_some());
+    }
+}
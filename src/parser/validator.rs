@@ -1,29 +1,56 @@
 //! SQL query validation and semantic analysis
 //!
-//! Ensures that SQL queries are valid and semantically correct
+//! Binds every table and column a statement names to the catalog, the way
+//! SQLite resolves them, and reports what does not resolve as diagnostics
 
 use anyhow::{Result, anyhow};
-use crate::parser::ast::{QueryType, Expression, Statement};
-use std::collections::HashMap;
+use crate::parser::ast::{
+    AlterAction, ColumnConstraint, CompoundOperator, CreateIndex, CreateTable, CreateTrigger, CreateView, CreateVirtualTable,
+    Delete, Expression, FromClause, Insert, InsertSource, JoinConstraint, JoinKind, ObjectType, Operator, OrderingTerm,
+    QualifiedName, QueryType, Select, SelectBody, SelectCore, SelectItem, Span, Statement, StatementKind, TableConstraint,
+    TableFactor, TriggerEvent, Update, UpsertAction, Value, Window, With,
+};
+use crate::parser::diagnostic::{self, Diagnostic, Severity};
+use crate::parser::types::{self, ExprType, ResultColumn, ValueType};
+use crate::schema::SchemaCatalog;
+use std::sync::Arc;
+
+/// Names every rowid table answers to, unless a real column takes the name
+const ROWID_ALIASES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+/// Columns of sqlite_master and its aliases, which no catalog lists
+const SCHEMA_TABLE_COLUMNS: [&str; 5] = ["type", "name", "tbl_name", "rootpage", "sql"];
+
+/// Columns of the json_each() and json_tree() table-valued functions
+const JSON_TABLE_COLUMNS: [&str; 10] = ["key", "value", "type", "atom", "id", "parent", "fullkey", "path", "json", "root"];
 
 /// Validates SQL queries for correctness
 pub struct QueryValidator {
-    errors: Vec<String>,
-    warnings: Vec<String>,
+    analyzer: SemanticAnalyzer,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+    result_columns: Vec<ResultColumn>,
 }
 
 impl QueryValidator {
-    pub fn new() -> Self {
+    /// Validator for the statements of `sql`, resolving names against `catalog`
+    pub fn new(catalog: Arc<SchemaCatalog>, sql: &str) -> Self {
         QueryValidator {
+            analyzer: SemanticAnalyzer::new(catalog, sql),
             errors: Vec::new(),
             warnings: Vec::new(),
+            result_columns: Vec::new(),
         }
     }
-    
+
+    /// Checks one statement. Statements of a script are validated in order,
+    /// so tables created by earlier ones are known to later ones.
     pub fn validate(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Beginning query validation");
-        
-        match stmt.query_type {
+        self.analyzer.statement_span = stmt.span;
+        self.result_columns.clear();
+
+        let result = match stmt.query_type {
             QueryType::Select => self.validate_select(stmt),
             QueryType::Insert => self.validate_insert(stmt),
             QueryType::Update => self.validate_update(stmt),
@@ -31,123 +58,2064 @@ impl QueryValidator {
             QueryType::Create => self.validate_create(stmt),
             QueryType::Alter => self.validate_alter(stmt),
             QueryType::Drop => self.validate_drop(stmt),
+            QueryType::Explain => match &stmt.kind {
+                StatementKind::Explain { statement, .. } => self.validate(statement),
+                _ => Ok(()),
+            },
+            // Nothing in these refers to columns that could be wrong
+            QueryType::Transaction
+            | QueryType::Pragma
+            | QueryType::Attach
+            | QueryType::Detach
+            | QueryType::Maintenance => Ok(()),
             QueryType::Unknown => Err(anyhow!("Unknown query type")),
+        };
+
+        // EXPLAIN returns the plan and CREATE TRIGGER nothing, whatever their statements return
+        if matches!(stmt.query_type, QueryType::Explain | QueryType::Create) {
+            self.result_columns.clear();
         }
+
+        for found in self.analyzer.diagnostics.drain(..) {
+            match found.severity {
+                Severity::Error => self.errors.push(found),
+                Severity::Warning => self.warnings.push(found),
+            }
+        }
+        result
     }
-    
+
     fn validate_select(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating SELECT query");
-        println!("[VALIDATOR] Checking table references");
-        println!("[VALIDATOR] Checking column references");
-        println!("[VALIDATOR] Validating expressions");
-        println!("[VALIDATOR] Validating JOIN conditions");
-        
-        // Pretend to do validation
+        self.result_columns = self.analyzer.analyze(stmt)?;
         Ok(())
     }
-    
+
     fn validate_insert(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating INSERT query");
-        
-        // Pretend to do validation
+        let StatementKind::Insert(insert) = &stmt.kind else {
+            return Ok(());
+        };
+        let Insert { with, table, alias, span, columns, source, upserts, returning, .. } = insert.as_ref();
+
+        let ctes = self.analyzer.enter_with(with.as_ref());
+        let target = self.analyzer.lookup_target(table, *span);
+
+        // The columns the values go to: those named, else every one that is not generated
+        let mut expected = None;
+        if let Some(target_columns) = target.as_ref().and_then(|target| target.columns.as_ref()) {
+            if columns.is_empty() {
+                let generated = &target.as_ref().map(|target| target.generated.clone()).unwrap_or_default();
+                expected = Some(target_columns.iter().filter(|column| !contains(generated, column)).count());
+            } else {
+                let generated = target.as_ref().map(|target| target.generated.as_slice()).unwrap_or_default();
+                for column in columns {
+                    if !contains(target_columns, column) && !is_rowid_alias(column) {
+                        let message = format!("table {} has no column named {}", table.name, column);
+                        self.analyzer.report(diagnostic::UNKNOWN_COLUMN, message, *span, column, target_columns.clone());
+                    } else if contains(generated, column) {
+                        let message = format!("cannot INSERT into generated column \"{}\"", column);
+                        self.analyzer.error(diagnostic::UNKNOWN_COLUMN, message, *span);
+                    }
+                }
+                expected = Some(columns.len());
+            }
+        }
+
+        let supplied = match source {
+            InsertSource::Values(rows) => {
+                // VALUES rows of an INSERT see no table, only the outer query of a trigger
+                for row in rows {
+                    for value in row {
+                        self.analyzer.analyze_expression(value);
+                    }
+                }
+                self.analyzer.check_values_width(rows);
+                rows.first().map(Vec::len)
+            }
+            InsertSource::Select(select) => self.analyzer.analyze_select(select).map(|columns| columns.len()),
+            InsertSource::DefaultValues => None,
+        };
+
+        if let (Some(expected), Some(supplied)) = (expected, supplied) {
+            if expected != supplied {
+                let message = if columns.is_empty() {
+                    format!("table {} has {} columns but {} values were supplied", table.name, expected, supplied)
+                } else {
+                    format!("{} values for {} columns", supplied, expected)
+                };
+                self.analyzer.error(diagnostic::COLUMN_COUNT_MISMATCH, message, *span);
+            }
+        }
+
+        let target = target.unwrap_or_else(|| Relation::opaque(&table.name));
+        let target = match alias {
+            Some(alias) => target.renamed(alias),
+            None => target,
+        };
+        for upsert in upserts {
+            // Only the target answers to unqualified names; the row that
+            // failed to insert has to be named as excluded
+            self.analyzer.push_scope();
+            self.analyzer.add_relation(target.clone().renamed("excluded"));
+            self.analyzer.push_scope();
+            self.analyzer.add_relation(target.clone());
+            for term in &upsert.target {
+                self.analyzer.analyze_expression(&term.expr);
+            }
+            self.analyzer.analyze_optional(upsert.target_where.as_ref());
+            if let UpsertAction::Update { assignments, where_clause } = &upsert.action {
+                for assignment in assignments {
+                    self.analyzer.check_assignment(&target, &assignment.columns, *span);
+                    self.analyzer.analyze_expression(&assignment.value);
+                }
+                self.analyzer.analyze_optional(where_clause.as_ref());
+            }
+            self.analyzer.pop_scope();
+            self.analyzer.pop_scope();
+        }
+
+        if !returning.is_empty() {
+            self.analyzer.push_scope();
+            self.analyzer.add_relation(target);
+            self.result_columns = self.analyzer.analyze_items(returning).unwrap_or_default();
+            self.analyzer.pop_scope();
+        }
+
+        self.analyzer.exit_with(ctes);
         Ok(())
     }
-    
+
     fn validate_update(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating UPDATE query");
-        
-        // Pretend to do validation
+        let StatementKind::Update(update) = &stmt.kind else {
+            return Ok(());
+        };
+        let Update { with, table, alias, span, assignments, from, where_clause, returning, order_by, limit, offset, .. } =
+            update.as_ref();
+
+        let ctes = self.analyzer.enter_with(with.as_ref());
+        self.analyzer.push_scope();
+        let target = self.analyzer.bind_table(table, alias.as_deref(), *span);
+        if let Some(from) = from {
+            self.analyzer.bind_from(from);
+        }
+
+        for assignment in assignments {
+            self.analyzer.check_assignment(&target, &assignment.columns, *span);
+            self.analyzer.analyze_expression(&assignment.value);
+            self.analyzer.forbid_aggregate(&assignment.value);
+        }
+        if let Some(where_clause) = where_clause {
+            self.analyzer.analyze_expression(where_clause);
+            self.analyzer.forbid_aggregate(where_clause);
+        }
+        self.result_columns = self.analyzer.analyze_items(returning).unwrap_or_default();
+        self.analyzer.analyze_terms(order_by);
+        self.analyzer.analyze_optional(limit.as_deref());
+        self.analyzer.analyze_optional(offset.as_deref());
+
+        self.analyzer.pop_scope();
+        self.analyzer.exit_with(ctes);
         Ok(())
     }
-    
+
     fn validate_delete(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating DELETE query");
-        
-        // Pretend to do validation
+        let StatementKind::Delete(delete) = &stmt.kind else {
+            return Ok(());
+        };
+        let Delete { with, table, alias, span, where_clause, returning, order_by, limit, offset } = delete.as_ref();
+
+        let ctes = self.analyzer.enter_with(with.as_ref());
+        self.analyzer.push_scope();
+        self.analyzer.bind_table(table, alias.as_deref(), *span);
+        if let Some(where_clause) = where_clause {
+            self.analyzer.analyze_expression(where_clause);
+            self.analyzer.forbid_aggregate(where_clause);
+        }
+        self.result_columns = self.analyzer.analyze_items(returning).unwrap_or_default();
+        self.analyzer.analyze_terms(order_by);
+        self.analyzer.analyze_optional(limit.as_deref());
+        self.analyzer.analyze_optional(offset.as_deref());
+
+        self.analyzer.pop_scope();
+        self.analyzer.exit_with(ctes);
         Ok(())
     }
-    
+
     fn validate_create(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating CREATE query");
-        
-        // Pretend to do validation
+
+        match &stmt.kind {
+            StatementKind::CreateTable(create) => self.validate_create_table(create),
+            StatementKind::CreateIndex(create) => self.validate_create_index(create),
+            StatementKind::CreateView(create) => self.validate_create_view(create),
+            StatementKind::CreateTrigger(create) => self.validate_create_trigger(create)?,
+            StatementKind::CreateVirtualTable(create) => self.validate_create_virtual_table(create),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn validate_create_table(&mut self, create: &CreateTable) {
+        let CreateTable { if_not_exists, name, columns, constraints, as_select, without_rowid, .. } = create;
+        let span = self.analyzer.statement_span;
+
+        let mut names: Vec<String> = Vec::new();
+        let mut column_types = Vec::new();
+        for column in columns {
+            if contains(&names, &column.name) {
+                self.analyzer.error(diagnostic::DUPLICATE_NAME, format!("duplicate column name: {}", column.name), span);
+            }
+            names.push(column.name.clone());
+            let not_null = column.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull(_)));
+            column_types.push(ExprType::of_declared_type(column.type_name.as_deref().unwrap_or(""), !not_null));
+        }
+
+        // CHECK constraints and generated columns see the table's own columns
+        let own = Relation {
+            name: name.name.clone(),
+            schema: None,
+            columns: Some(names.clone()),
+            has_rowid: !without_rowid,
+            generated: Vec::new(),
+            types: column_types,
+        };
+        self.analyzer.push_scope();
+        self.analyzer.add_relation(own.clone());
+        for column in columns {
+            for constraint in &column.constraints {
+                match constraint {
+                    ColumnConstraint::Check(expr) | ColumnConstraint::Generated { expr, .. } => {
+                        self.analyzer.analyze_expression(expr);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for constraint in constraints {
+            match constraint {
+                TableConstraint::PrimaryKey { columns, .. } | TableConstraint::Unique { columns, .. } => {
+                    self.analyzer.analyze_terms(columns)
+                }
+                TableConstraint::Check(expr) => {
+                    self.analyzer.analyze_expression(expr);
+                }
+                TableConstraint::ForeignKey { columns, .. } => {
+                    for column in columns {
+                        if !contains(&names, column) {
+                            let message = format!("unknown column \"{}\" in foreign key definition", column);
+                            self.analyzer.error(diagnostic::UNKNOWN_COLUMN, message, span);
+                        }
+                    }
+                }
+            }
+        }
+        self.analyzer.pop_scope();
+
+        let produced = as_select.as_ref().map(|select| self.analyzer.analyze_select(select));
+        if self.check_name_is_free("table", name, *if_not_exists) {
+            let generated = columns
+                .iter()
+                .filter(|column| column.constraints.iter().any(|c| matches!(c, ColumnConstraint::Generated { .. })))
+                .map(|column| column.name.clone())
+                .collect();
+            let own = match produced {
+                // CREATE TABLE ... AS SELECT takes its columns from the query
+                Some(produced) => Relation {
+                    has_rowid: true,
+                    ..Relation::derived(&own.name, produced)
+                },
+                None => own,
+            };
+            self.analyzer.define(Relation { generated, ..own });
+        }
+    }
+
+    fn validate_create_index(&mut self, create: &CreateIndex) {
+        self.analyzer.push_scope();
+        let table = QualifiedName { schema: create.name.schema.clone(), name: create.table.clone() };
+        self.analyzer.bind_table(&table, None, create.span);
+        self.analyzer.analyze_terms(&create.columns);
+        self.analyzer.analyze_optional(create.where_clause.as_ref());
+        self.analyzer.pop_scope();
+
+        if self.check_name_is_free("index", &create.name, create.if_not_exists) {
+            self.analyzer.define_object(&create.name.name);
+        }
+    }
+
+    fn validate_create_view(&mut self, create: &CreateView) {
+        let produced = self.analyzer.analyze_select(&create.query);
+
+        let columns = match produced {
+            Some(produced) if !create.columns.is_empty() && produced.len() != create.columns.len() => {
+                let message = format!(
+                    "expected {} columns for '{}' but got {}",
+                    create.columns.len(),
+                    create.name.name,
+                    produced.len()
+                );
+                self.analyzer.error(diagnostic::COLUMN_COUNT_MISMATCH, message, self.analyzer.statement_span);
+                Some(create.columns.iter().map(|name| ResultColumn::new(name.clone(), ExprType::unknown())).collect())
+            }
+            Some(produced) if !create.columns.is_empty() => Some(
+                create
+                    .columns
+                    .iter()
+                    .zip(produced)
+                    .map(|(name, column)| ResultColumn::new(name.clone(), column.expr_type()))
+                    .collect(),
+            ),
+            _ if !create.columns.is_empty() => Some(
+                create.columns.iter().map(|name| ResultColumn::new(name.clone(), ExprType::unknown())).collect(),
+            ),
+            produced => produced,
+        };
+
+        if self.check_name_is_free("view", &create.name, create.if_not_exists) {
+            self.analyzer.define(Relation::derived(&create.name.name, columns));
+        }
+    }
+
+    fn validate_create_trigger(&mut self, create: &CreateTrigger) -> Result<()> {
+        let table = QualifiedName { schema: create.name.schema.clone(), name: create.table.clone() };
+        let target = self.analyzer.lookup_target(&table, create.span);
+
+        if let (TriggerEvent::Update(columns), Some(known)) =
+            (&create.event, target.as_ref().and_then(|target| target.columns.as_ref()))
+        {
+            for column in columns {
+                if !contains(known, column) {
+                    let message = format!("no such column: {}", column);
+                    self.analyzer.report(diagnostic::UNKNOWN_COLUMN, message, create.span, column, known.clone());
+                }
+            }
+        }
+
+        // The body sees the row being changed as NEW and OLD
+        let target = target.unwrap_or_else(|| Relation::opaque(&create.table));
+        self.analyzer.push_scope();
+        if create.event != TriggerEvent::Delete {
+            self.analyzer.add_relation(target.clone().renamed("new"));
+        }
+        if create.event != TriggerEvent::Insert {
+            self.analyzer.add_relation(target.renamed("old"));
+        }
+        self.analyzer.analyze_optional(create.when.as_ref());
+        for statement in &create.body {
+            self.validate(statement)?;
+        }
+        self.analyzer.pop_scope();
+
+        if self.check_name_is_free("trigger", &create.name, create.if_not_exists) {
+            self.analyzer.define_object(&create.name.name);
+        }
         Ok(())
     }
-    
+
+    fn validate_create_virtual_table(&mut self, create: &CreateVirtualTable) {
+        // The module decides the columns, so any column of it is accepted
+        if self.check_name_is_free("table", &create.name, create.if_not_exists) {
+            self.analyzer.define(Relation::opaque(&create.name.name));
+        }
+    }
+
     fn validate_alter(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating ALTER query");
-        
-        // Pretend to do validation
+        let StatementKind::AlterTable { table, span, action } = &stmt.kind else {
+            return Ok(());
+        };
+        let Some(target) = self.analyzer.lookup_target(table, *span) else {
+            return Ok(());
+        };
+        let known = target.columns.clone().unwrap_or_default();
+        let span = *span;
+
+        let changed = match action {
+            AlterAction::RenameTable(new_name) => {
+                let renamed = QualifiedName { schema: table.schema.clone(), name: new_name.clone() };
+                if self.analyzer.lookup(&renamed).is_some() {
+                    let message = format!("there is already another table or index with this name: {}", new_name);
+                    self.analyzer.error(diagnostic::DUPLICATE_NAME, message, span);
+                    return Ok(());
+                }
+                self.analyzer.forget(&target.name);
+                Relation { name: new_name.clone(), ..target }
+            }
+            AlterAction::RenameColumn { old, new } => {
+                if target.columns.is_some() && !contains(&known, old) {
+                    let message = format!("no such column: \"{}\"", old);
+                    self.analyzer.report(diagnostic::UNKNOWN_COLUMN, message, span, old, known);
+                    return Ok(());
+                }
+                if contains(&known, new) {
+                    self.analyzer.error(diagnostic::DUPLICATE_NAME, format!("duplicate column name: {}", new), span);
+                    return Ok(());
+                }
+                let columns = target.columns.as_ref().map(|columns| {
+                    columns
+                        .iter()
+                        .map(|column| if column.eq_ignore_ascii_case(old) { new.clone() } else { column.clone() })
+                        .collect()
+                });
+                Relation { columns, ..target }
+            }
+            AlterAction::AddColumn(column) => {
+                if contains(&known, &column.name) {
+                    let message = format!("duplicate column name: {}", column.name);
+                    self.analyzer.error(diagnostic::DUPLICATE_NAME, message, span);
+                    return Ok(());
+                }
+                let mut columns = target.columns.clone();
+                if let Some(columns) = columns.as_mut() {
+                    columns.push(column.name.clone());
+                }
+                Relation { columns, ..target }
+            }
+            AlterAction::DropColumn(dropped) => {
+                if target.columns.is_some() && !contains(&known, dropped) {
+                    let message = format!("no such column: \"{}\"", dropped);
+                    self.analyzer.report(diagnostic::UNKNOWN_COLUMN, message, span, dropped, known);
+                    return Ok(());
+                }
+                let columns = target.columns.as_ref().map(|columns| {
+                    columns.iter().filter(|column| !column.eq_ignore_ascii_case(dropped)).cloned().collect()
+                });
+                Relation { columns, ..target }
+            }
+        };
+        self.analyzer.define(changed);
         Ok(())
     }
-    
+
     fn validate_drop(&mut self, stmt: &Statement) -> Result<()> {
         println!("[VALIDATOR] Validating DROP query");
-        
-        // Pretend to do validation
+        let StatementKind::Drop { object_type, if_exists, name } = &stmt.kind else {
+            return Ok(());
+        };
+        let span = stmt.span;
+
+        match object_type {
+            ObjectType::Table | ObjectType::View => {
+                let kind = if *object_type == ObjectType::Table { "table" } else { "view" };
+                match self.analyzer.lookup(name) {
+                    Some(relation) => self.analyzer.forget(&relation.name),
+                    None if !if_exists => {
+                        let message = format!("no such {}: {}", kind, name);
+                        let candidates = self.analyzer.relation_names();
+                        self.analyzer.report(diagnostic::UNKNOWN_TABLE, message, span, &name.name, candidates);
+                    }
+                    None => {}
+                }
+            }
+            ObjectType::Index | ObjectType::Trigger => {
+                let kind = if *object_type == ObjectType::Index { "index" } else { "trigger" };
+                if self.analyzer.object_exists(name) {
+                    self.analyzer.forget_object(&name.name);
+                } else if !if_exists {
+                    self.analyzer.error(diagnostic::UNKNOWN_OBJECT, format!("no such {}: {}", kind, name), span);
+                }
+            }
+        }
         Ok(())
     }
-    
-    pub fn get_errors(&self) -> &Vec<String> {
+
+    /// Whether `name` can be created; reports it when it is taken and the
+    /// statement did not say IF NOT EXISTS
+    fn check_name_is_free(&mut self, kind: &str, name: &QualifiedName, if_not_exists: bool) -> bool {
+        let taken = if kind == "index" || kind == "trigger" {
+            self.analyzer.object_exists(name)
+        } else {
+            self.analyzer.lookup(name).is_some()
+        };
+        if taken && !if_not_exists {
+            let message = format!("{} {} already exists", kind, name);
+            self.analyzer.error(diagnostic::DUPLICATE_NAME, message, self.analyzer.statement_span);
+        }
+        !taken
+    }
+
+    /// Resolves later statements against `catalog`, for scripts whose
+    /// statements run before the next is validated. What earlier statements
+    /// created or dropped is still known, which matters inside a transaction
+    /// where the catalog does not see it yet.
+    pub fn refresh_catalog(&mut self, catalog: Arc<SchemaCatalog>) {
+        self.analyzer.catalog = catalog;
+    }
+
+    pub fn get_errors(&self) -> &Vec<Diagnostic> {
         &self.errors
     }
-    
-    pub fn get_warnings(&self) -> &Vec<String> {
+
+    pub fn get_warnings(&self) -> &Vec<Diagnostic> {
         &self.warnings
     }
+
+    /// Result columns of the last statement validated, with their inferred
+    /// types; empty when it returns no rows or they cannot be known
+    pub fn get_result_columns(&self) -> &Vec<ResultColumn> {
+        &self.result_columns
+    }
+}
+
+/// A table, view, subquery or CTE that columns can be taken from
+#[derive(Debug, Clone)]
+pub struct Relation {
+    /// Name it is referred to by: its alias, else its own name
+    pub name: String,
+    /// Schema it was found in, for `schema.table.column`
+    pub schema: Option<String>,
+    /// Its columns; None when they cannot be known, as for table-valued
+    /// functions and virtual tables, in which case any column is accepted
+    pub columns: Option<Vec<String>>,
+    /// Whether rowid, oid and _rowid_ name its rowid
+    pub has_rowid: bool,
+    /// Columns computed from others, which INSERT and UPDATE cannot set
+    pub generated: Vec<String>,
+    /// Type of each of `columns`, in order; columns without one can hold anything
+    pub types: Vec<ExprType>,
 }
 
-/// Semantic analyzer for SQL queries
+impl Relation {
+    fn opaque(name: &str) -> Self {
+        Relation {
+            name: name.to_string(),
+            schema: None,
+            columns: None,
+            has_rowid: true,
+            generated: Vec::new(),
+            types: Vec::new(),
+        }
+    }
+
+    /// A subquery, CTE or view with these result columns
+    fn derived(name: &str, columns: Option<Vec<ResultColumn>>) -> Self {
+        let types = columns.iter().flatten().map(ResultColumn::expr_type).collect();
+        Relation {
+            columns: columns.map(|columns| columns.into_iter().map(|column| column.name).collect()),
+            has_rowid: false,
+            types,
+            ..Relation::opaque(name)
+        }
+    }
+
+    /// Its columns with their types, when they are known
+    fn result_columns(&self) -> Option<Vec<ResultColumn>> {
+        let columns = self.columns.as_ref()?;
+        Some(
+            columns
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let expr_type = self.types.get(i).copied().unwrap_or_else(ExprType::unknown);
+                    ResultColumn::new(name.clone(), expr_type)
+                })
+                .collect(),
+        )
+    }
+
+    fn column_type(&self, column: &str) -> ExprType {
+        let position = self
+            .columns
+            .as_ref()
+            .and_then(|columns| columns.iter().position(|name| name.eq_ignore_ascii_case(column)));
+        match position {
+            Some(i) => self.types.get(i).copied().unwrap_or_else(ExprType::unknown),
+            None if self.has_rowid && is_rowid_alias(column) => ExprType::new(ValueType::Integer, false),
+            None => ExprType::unknown(),
+        }
+    }
+
+    /// The same relation on the optional side of an outer join, where
+    /// every column can be NULL
+    fn or_null(mut self) -> Self {
+        self.types = self.types.into_iter().map(ExprType::or_null).collect();
+        self
+    }
+
+    fn renamed(self, name: &str) -> Self {
+        Relation {
+            name: name.to_string(),
+            schema: None,
+            ..self
+        }
+    }
+
+    fn has_column(&self, column: &str) -> bool {
+        match &self.columns {
+            Some(columns) => contains(columns, column) || (self.has_rowid && is_rowid_alias(column)),
+            None => true,
+        }
+    }
+}
+
+/// The tables of one level of a query
+#[derive(Debug, Default)]
+struct Scope {
+    relations: Vec<Relation>,
+    /// Columns USING and NATURAL joins merged, which are not ambiguous
+    merged: Vec<String>,
+    /// Result column aliases, which WHERE, GROUP BY, HAVING and ORDER BY
+    /// may use, with the types of what they name
+    aliases: Vec<(String, ExprType)>,
+}
+
+/// Semantic analyzer for SQL queries: resolves table and column names
+/// through nested scopes, from the innermost query outwards
 pub struct SemanticAnalyzer {
-    tables: HashMap<String, Vec<String>>,
+    catalog: Arc<SchemaCatalog>,
+    sql: String,
+    scopes: Vec<Scope>,
+    /// CTEs in scope, innermost last
+    ctes: Vec<Relation>,
+    /// Tables and views earlier statements of the script created or changed
+    created: Vec<Relation>,
+    /// Tables and views earlier statements of the script dropped
+    dropped: Vec<String>,
+    /// Indexes and triggers earlier statements of the script created
+    created_objects: Vec<String>,
+    /// Indexes and triggers earlier statements of the script dropped
+    dropped_objects: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    /// Span of the statement being analyzed, for errors about syntax
+    /// that has no span of its own
+    statement_span: Span,
 }
 
 impl SemanticAnalyzer {
-    pub fn new() -> Self {
+    pub fn new(catalog: Arc<SchemaCatalog>, sql: &str) -> Self {
         SemanticAnalyzer {
-            tables: HashMap::new(),
+            catalog,
+            sql: sql.to_string(),
+            scopes: vec![Scope::default()],
+            ctes: Vec::new(),
+            created: Vec::new(),
+            dropped: Vec::new(),
+            created_objects: Vec::new(),
+            dropped_objects: Vec::new(),
+            diagnostics: Vec::new(),
+            statement_span: Span::default(),
         }
     }
-    
+
+    /// Makes a table with these columns visible to the current query level
     pub fn add_table(&mut self, table_name: &str, columns: Vec<String>) {
-        self.tables.insert(table_name.to_string(), columns);
+        self.add_relation(Relation {
+            name: table_name.to_string(),
+            schema: None,
+            columns: Some(columns),
+            has_rowid: false,
+            generated: Vec::new(),
+            types: Vec::new(),
+        });
     }
-    
-    pub fn analyze(&self, stmt: &Statement) -> Result<()> {
+
+    /// Binds a SELECT and returns its result columns, or none when they
+    /// cannot be known
+    pub fn analyze(&mut self, stmt: &Statement) -> Result<Vec<ResultColumn>> {
         println!("[SEMANTIC] Beginning semantic analysis");
-        
-        match stmt.query_type {
-            QueryType::Select => self.analyze_select(stmt),
-            _ => Ok(()), // Pretend to analyze other query types
+
+        match &stmt.kind {
+            StatementKind::Select(select) => Ok(self.analyze_select(select).unwrap_or_default()),
+            _ => Ok(Vec::new()), // Other statements are bound by the QueryValidator
         }
     }
-    
-    fn analyze_select(&self, stmt: &Statement) -> Result<()> {
+
+    /// Binds a query and returns its result columns with their types, or
+    /// None when they cannot be known
+    pub fn analyze_select(&mut self, select: &Select) -> Option<Vec<ResultColumn>> {
         println!("[SEMANTIC] Analyzing SELECT query");
-        println!("[SEMANTIC] Checking column references against schema");
-        println!("[SEMANTIC] Validating JOIN compatibility");
-        println!("[SEMANTIC] Validating expression type compatibility");
-        
-        // Pretend to do analysis
-        Ok(())
+        let ctes = self.enter_with(select.with.as_ref());
+
+        // A simple query's ORDER BY sees its FROM clause; a compound's only its result columns
+        let simple_order_by: &[OrderingTerm] = if select.compounds.is_empty() { &select.order_by } else { &[] };
+        let mut columns = self.analyze_body(&select.body, simple_order_by);
+
+        let mut outputs = vec![names_of(&columns)];
+        for (operator, body) in &select.compounds {
+            let other = self.analyze_body(body, &[]);
+            outputs.push(names_of(&other));
+            if let (Some(left), Some(right)) = (columns.as_mut(), &other) {
+                // Each column takes values from every SELECT of the compound
+                for (column, other) in left.iter_mut().zip(right) {
+                    *column = ResultColumn::new(column.name.clone(), column.expr_type().merge(other.expr_type()));
+                }
+                if left.len() != right.len() {
+                    let message = format!(
+                        "SELECTs to the left and right of {} do not have the same number of result columns",
+                        compound_name(*operator)
+                    );
+                    self.error(diagnostic::COLUMN_COUNT_MISMATCH, message, self.statement_span);
+                }
+            }
+        }
+
+        let outputs: Option<Vec<Vec<String>>> = outputs.into_iter().collect();
+        if let (false, Some(outputs)) = (select.compounds.is_empty(), outputs) {
+            for (i, term) in select.order_by.iter().enumerate() {
+                self.check_compound_order_term(i, term, &outputs);
+            }
+        }
+        self.analyze_optional(select.limit.as_deref());
+        self.analyze_optional(select.offset.as_deref());
+
+        self.exit_with(ctes);
+        columns
     }
-    
-    pub fn check_column_exists(&self, table: &str, column: &str) -> bool {
-        match self.tables.get(table) {
-            Some(columns) => columns.iter().any(|c| c == column),
-            None => false,
+
+    fn analyze_body(&mut self, body: &SelectBody, order_by: &[OrderingTerm]) -> Option<Vec<ResultColumn>> {
+        match body {
+            SelectBody::Select(core) => self.analyze_core(core, order_by),
+            SelectBody::Values(rows) => {
+                let mut columns: Vec<Option<ExprType>> = Vec::new();
+                for row in rows {
+                    for (i, value) in row.iter().enumerate() {
+                        let value_type = self.analyze_expression(value).without_affinity();
+                        match columns.get_mut(i) {
+                            Some(Some(column)) => *column = column.merge(value_type),
+                            Some(None) => {}
+                            None => columns.push(Some(value_type)),
+                        }
+                    }
+                }
+                self.check_values_width(rows);
+                self.analyze_terms(order_by);
+                let width = rows.first()?.len();
+                Some(
+                    columns
+                        .into_iter()
+                        .take(width)
+                        .enumerate()
+                        .map(|(i, value_type)| {
+                            ResultColumn::new(format!("column{}", i + 1), value_type.unwrap_or_else(ExprType::unknown))
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    /// Binds one SELECT and enforces SQLite's rules on aggregates and
+    /// GROUP BY, ORDER BY and HAVING terms
+    fn analyze_core(&mut self, core: &SelectCore, order_by: &[OrderingTerm]) -> Option<Vec<ResultColumn>> {
+        self.push_scope();
+        if let Some(from) = &core.from {
+            self.bind_from(from);
+        }
+
+        if core.from.is_none() && core.columns.iter().any(|item| matches!(item, SelectItem::Wildcard)) {
+            self.error(diagnostic::UNKNOWN_TABLE, "no tables specified", self.statement_span);
+        }
+        let names = self.analyze_items(&core.columns);
+        let result_count = names.as_ref().map_or(core.columns.len(), Vec::len);
+
+        if let Some(where_clause) = &core.where_clause {
+            self.analyze_expression(where_clause);
+            self.forbid_aggregate(where_clause);
+        }
+
+        for (i, term) in core.group_by.iter().enumerate() {
+            if let Some(position) = ordinal_term(term) {
+                self.check_term_position("GROUP BY", i, position, result_count);
+                continue;
+            }
+            if let Some((_, span)) = first_aggregate(term) {
+                self.error(
+                    diagnostic::GROUPING_ERROR,
+                    "aggregate functions are not allowed in the GROUP BY clause",
+                    span,
+                );
+            }
+            self.analyze_expression(term);
+        }
+
+        let aggregates_results = core.columns.iter().any(|item| match item {
+            SelectItem::Expression { expr, .. } => first_aggregate(expr).is_some(),
+            _ => false,
+        });
+        if let Some(having) = &core.having {
+            self.analyze_expression(having);
+            if core.group_by.is_empty() && !aggregates_results && first_aggregate(having).is_none() {
+                self.error(
+                    diagnostic::GROUPING_ERROR,
+                    "HAVING clause on a non-aggregate query",
+                    expression_span(having).unwrap_or(self.statement_span),
+                );
+            }
+        }
+
+        for (_, window) in &core.windows {
+            self.analyze_window(window);
+        }
+
+        for (i, term) in order_by.iter().enumerate() {
+            match ordinal_term(&term.expr) {
+                Some(position) => self.check_term_position("ORDER BY", i, position, result_count),
+                None => {
+                    self.analyze_expression(&term.expr);
+                }
+            }
+        }
+
+        if !core.group_by.is_empty() || aggregates_results {
+            self.warn_ungrouped_columns(core);
+        }
+
+        self.pop_scope();
+        names
+    }
+
+    /// Warns about result columns of an aggregate query that are neither
+    /// grouped nor aggregated: SQLite accepts them but returns a value from
+    /// an arbitrary row of each group
+    fn warn_ungrouped_columns(&mut self, core: &SelectCore) {
+        let items: Vec<(&Expression, Option<&str>)> = core
+            .columns
+            .iter()
+            .filter_map(|item| match item {
+                SelectItem::Expression { expr, alias, .. } => Some((expr, alias.as_deref())),
+                _ => None,
+            })
+            .collect();
+
+        // With a single min() or max(), bare columns come from the row it picked
+        let extremes = items
+            .iter()
+            .map(|(expr, _)| count_calls(expr, &["min", "max"]))
+            .sum::<usize>();
+        if extremes == 1 && items.iter().map(|(expr, _)| count_aggregates(expr)).sum::<usize>() == 1 {
+            return;
+        }
+
+        // GROUP BY terms, with aliases and positions replaced by what they name
+        let grouped: Vec<&Expression> = core
+            .group_by
+            .iter()
+            .map(|term| {
+                if let Some(position) = ordinal_term(term) {
+                    if let Some((expr, _)) = items.get(position.wrapping_sub(1) as usize) {
+                        return *expr;
+                    }
+                }
+                if let Expression::Column { table: None, name, .. } = term {
+                    if let Some((expr, _)) = items.iter().find(|(_, alias)| alias.is_some_and(|a| a.eq_ignore_ascii_case(name))) {
+                        return *expr;
+                    }
+                }
+                term
+            })
+            .collect();
+
+        let mut bare = Vec::new();
+        for (expr, _) in &items {
+            bare_columns(expr, &grouped, &mut bare);
+        }
+        for (name, span) in bare {
+            let message = format!(
+                "column \"{}\" is neither grouped nor aggregated; SQLite returns it from an arbitrary row of each group",
+                name
+            );
+            self.diagnostics.push(Diagnostic::warning(diagnostic::UNGROUPED_COLUMN, message, span, &self.sql));
+        }
+    }
+
+    /// A compound's ORDER BY term must be a position or name a result
+    /// column of one of its SELECTs
+    fn check_compound_order_term(&mut self, index: usize, term: &OrderingTerm, outputs: &[Vec<String>]) {
+        if let Some(position) = ordinal_term(&term.expr) {
+            self.check_term_position("ORDER BY", index, position, outputs[0].len());
+            return;
+        }
+        let expr = match &term.expr {
+            Expression::Collate { expr, .. } => expr.as_ref(),
+            expr => expr,
+        };
+        let name = match expr {
+            Expression::Column { table: None, name, .. } => name.clone(),
+            expr => expr.to_string(),
+        };
+        let matches = outputs.iter().any(|columns| contains(columns, &name));
+        if !matches {
+            let message = format!("{} ORDER BY term does not match any column in the result set", ordinal(index + 1));
+            let span = expression_span(&term.expr).unwrap_or(self.statement_span);
+            self.error(diagnostic::GROUPING_ERROR, message, span);
+        }
+    }
+
+    fn check_term_position(&mut self, clause: &str, index: usize, position: i64, count: usize) {
+        if position < 1 || position as usize > count {
+            let message = format!(
+                "{} {} term out of range - should be between 1 and {}",
+                ordinal(index + 1),
+                clause,
+                count
+            );
+            self.error(diagnostic::GROUPING_ERROR, message, self.statement_span);
+        }
+    }
+
+    /// Every row of a VALUES list must have as many values as the first
+    fn check_values_width(&mut self, rows: &[Vec<Expression>]) {
+        let Some(width) = rows.first().map(Vec::len) else {
+            return;
+        };
+        if rows.iter().any(|row| row.len() != width) {
+            self.error(
+                diagnostic::COLUMN_COUNT_MISMATCH,
+                "all VALUES must have the same number of terms",
+                self.statement_span,
+            );
+        }
+    }
+
+    /// Checks that the columns an assignment sets belong to `target` and
+    /// can be set
+    fn check_assignment(&mut self, target: &Relation, columns: &[String], span: Span) {
+        for column in columns {
+            if !target.has_column(column) {
+                let message = format!("no such column: {}", column);
+                let candidates = target.columns.clone().unwrap_or_default();
+                self.report(diagnostic::UNKNOWN_COLUMN, message, span, column, candidates);
+            } else if contains(&target.generated, column) {
+                let message = format!("cannot UPDATE generated column \"{}\"", column);
+                self.error(diagnostic::UNKNOWN_COLUMN, message, span);
+            }
+        }
+    }
+
+    /// Reports the first aggregate call in `expr`, where aggregates are not allowed
+    fn forbid_aggregate(&mut self, expr: &Expression) {
+        if let Some((name, span)) = first_aggregate(expr) {
+            self.error(diagnostic::GROUPING_ERROR, format!("misuse of aggregate: {}()", name), span);
+        }
+    }
+
+    /// Binds the tables of a FROM clause into the current scope
+    fn bind_from(&mut self, from: &FromClause) {
+        self.bind_factor(&from.relation);
+        for join in &from.joins {
+            let left: Vec<Relation> = self.scope().relations.clone();
+            let right_start = left.len();
+            self.bind_factor(&join.relation);
+            // The optional side of an outer join reads as NULL where nothing matched
+            if matches!(join.kind, JoinKind::Right | JoinKind::Full) {
+                for relation in &mut self.scope_mut().relations[..right_start] {
+                    *relation = relation.clone().or_null();
+                }
+            }
+            if matches!(join.kind, JoinKind::Left | JoinKind::Full) {
+                for relation in &mut self.scope_mut().relations[right_start..] {
+                    *relation = relation.clone().or_null();
+                }
+            }
+            let right: Vec<Relation> = self.scope().relations[right_start..].to_vec();
+
+            if join.natural {
+                let common: Vec<String> = right
+                    .iter()
+                    .filter_map(|relation| relation.columns.clone())
+                    .flatten()
+                    .filter(|column| left.iter().any(|relation| relation.columns.as_ref().is_some_and(|c| contains(c, column))))
+                    .collect();
+                self.scope_mut().merged.extend(common);
+            }
+            match &join.constraint {
+                JoinConstraint::On(expr) => {
+                    self.analyze_expression(expr);
+                }
+                JoinConstraint::Using(columns) => {
+                    for column in columns {
+                        let in_left = left.iter().any(|relation| relation.has_column(column));
+                        let in_right = right.iter().any(|relation| relation.has_column(column));
+                        if !in_left || !in_right {
+                            let message =
+                                format!("cannot join using column {} - column not present in both tables", column);
+                            self.error(diagnostic::UNKNOWN_COLUMN, message, self.statement_span);
+                        }
+                        self.scope_mut().merged.push(column.clone());
+                    }
+                }
+                JoinConstraint::None => {}
+            }
+        }
+    }
+
+    fn bind_factor(&mut self, factor: &TableFactor) {
+        match factor {
+            TableFactor::Table { name, alias, span, .. } => {
+                self.bind_table(name, alias.as_deref(), *span);
+            }
+            TableFactor::Function { name, args, alias, .. } => {
+                for arg in args {
+                    self.analyze_expression(arg);
+                }
+                let mut relation = Relation::opaque(alias.as_deref().unwrap_or(&name.name));
+                if ["json_each", "json_tree"].iter().any(|json| json.eq_ignore_ascii_case(&name.name)) {
+                    relation.columns = Some(JSON_TABLE_COLUMNS.iter().map(|column| column.to_string()).collect());
+                    relation.has_rowid = false;
+                }
+                // Functions other than json_each and json_tree keep columns of unknown type
+                self.add_relation(relation);
+            }
+            TableFactor::Subquery { query, alias } => {
+                let columns = self.analyze_select(query);
+                self.add_relation(Relation::derived(alias.as_deref().unwrap_or_default(), columns));
+            }
+            TableFactor::Nested(from) => self.bind_from(from),
+        }
+    }
+
+    /// Adds a table to the current scope under its alias; an unknown table
+    /// is reported and added with unknown columns so its columns are not
+    /// reported as well
+    fn bind_table(&mut self, name: &QualifiedName, alias: Option<&str>, span: Span) -> Relation {
+        let relation = self.lookup_target(name, span).unwrap_or_else(|| Relation::opaque(&name.name));
+        let relation = match alias {
+            Some(alias) => relation.renamed(alias),
+            None => relation,
+        };
+        self.add_relation(relation.clone());
+        relation
+    }
+
+    /// Looks a table up, reporting it when no schema defines it
+    fn lookup_target(&mut self, name: &QualifiedName, span: Span) -> Option<Relation> {
+        let relation = self.lookup(name);
+        if relation.is_none() {
+            let candidates = self.relation_names();
+            self.report(diagnostic::UNKNOWN_TABLE, format!("no such table: {}", name), span, &name.name, candidates);
+        }
+        relation
+    }
+
+    /// The table, view or CTE a name refers to
+    pub fn lookup(&self, name: &QualifiedName) -> Option<Relation> {
+        if name.schema.is_none() {
+            if let Some(cte) = self.ctes.iter().rev().find(|cte| cte.name.eq_ignore_ascii_case(&name.name)) {
+                return Some(cte.clone());
+            }
+        }
+        if let Some(created) = self.created.iter().rev().find(|created| created.name.eq_ignore_ascii_case(&name.name)) {
+            return Some(created.clone());
+        }
+        if contains(&self.dropped, &name.name) {
+            return None;
+        }
+
+        let schema_table = ["sqlite_master", "sqlite_schema", "sqlite_temp_master", "sqlite_temp_schema"];
+        if schema_table.iter().any(|table| table.eq_ignore_ascii_case(&name.name)) {
+            return Some(Relation {
+                name: name.name.clone(),
+                schema: name.schema.clone(),
+                columns: Some(SCHEMA_TABLE_COLUMNS.iter().map(|column| column.to_string()).collect()),
+                has_rowid: true,
+                generated: Vec::new(),
+                types: SCHEMA_TABLE_COLUMNS
+                    .iter()
+                    .map(|column| match *column {
+                        "rootpage" => ExprType::new(ValueType::Integer, true),
+                        _ => ExprType::new(ValueType::Text, true),
+                    })
+                    .collect(),
+            });
+        }
+
+        let (schema, owner) = self.catalog.resolve_schema(name.schema.as_deref(), &name.name)?;
+        if let Some(table) = owner.find_table(&name.name) {
+            // Virtual tables have hidden columns the catalog does not list
+            let columns = (!table.is_virtual).then(|| table.columns.iter().map(|column| column.name.clone()).collect());
+            return Some(Relation {
+                name: table.name.clone(),
+                schema: Some(schema.to_string()),
+                columns,
+                has_rowid: !table.is_without_rowid(),
+                generated: table
+                    .columns
+                    .iter()
+                    .filter(|column| column.is_generated())
+                    .map(|column| column.name.clone())
+                    .collect(),
+                types: table
+                    .columns
+                    .iter()
+                    .map(|column| ExprType::of_column(column, table.is_strict(), table.is_rowid_alias(column)))
+                    .collect(),
+            });
+        }
+        let view = owner.find_view(&name.name)?;
+        let columns = view.column_names(owner);
+        Some(Relation {
+            name: view.name.clone(),
+            schema: Some(schema.to_string()),
+            columns: (!columns.is_empty()).then_some(columns),
+            has_rowid: false,
+            generated: Vec::new(),
+            types: Vec::new(),
+        })
+    }
+
+    /// Whether an index or trigger of this name exists
+    fn object_exists(&self, name: &QualifiedName) -> bool {
+        if contains(&self.created_objects, &name.name) {
+            return true;
+        }
+        if contains(&self.dropped_objects, &name.name) {
+            return false;
+        }
+
+        let mut schemas: Vec<(&str, &SchemaCatalog)> = vec![("main", self.catalog.as_ref())];
+        schemas.extend(self.catalog.temp().map(|temp| ("temp", temp)));
+        schemas.extend(self.catalog.attached().iter().map(|schema| (schema.alias.as_str(), schema.catalog.as_ref())));
+        schemas
+            .into_iter()
+            .filter(|(alias, _)| name.schema.as_deref().map_or(true, |schema| alias.eq_ignore_ascii_case(schema)))
+            .any(|(_, catalog)| {
+                catalog.get_index(&name.name).is_some()
+                    || catalog.get_triggers().iter().any(|trigger| trigger.name.eq_ignore_ascii_case(&name.name))
+            })
+    }
+
+    /// Names of every table, view and CTE in scope, for suggestions
+    fn relation_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .catalog
+            .get_relation_names()
+            .into_iter()
+            .filter(|name| !contains(&self.dropped, name))
+            .collect();
+        names.extend(self.created.iter().chain(&self.ctes).map(|relation| relation.name.clone()));
+        names
+    }
+
+    /// Records a table or view an earlier statement of the script created or changed
+    fn define(&mut self, relation: Relation) {
+        self.dropped.retain(|name| !name.eq_ignore_ascii_case(&relation.name));
+        self.created.retain(|created| !created.name.eq_ignore_ascii_case(&relation.name));
+        self.created.push(relation);
+    }
+
+    fn forget(&mut self, name: &str) {
+        self.created.retain(|created| !created.name.eq_ignore_ascii_case(name));
+        self.dropped.push(name.to_string());
+    }
+
+    fn define_object(&mut self, name: &str) {
+        self.dropped_objects.retain(|dropped| !dropped.eq_ignore_ascii_case(name));
+        self.created_objects.push(name.to_string());
+    }
+
+    fn forget_object(&mut self, name: &str) {
+        self.created_objects.retain(|created| !created.eq_ignore_ascii_case(name));
+        self.dropped_objects.push(name.to_string());
+    }
+
+    /// Makes the CTEs of a WITH clause visible; returns what to pass to
+    /// `exit_with` once the statement is bound
+    fn enter_with(&mut self, with: Option<&With>) -> usize {
+        let depth = self.ctes.len();
+        let Some(with) = with else {
+            return depth;
+        };
+        for cte in &with.tables {
+            let declared = (!cte.columns.is_empty()).then(|| cte.columns.clone());
+            // A recursive CTE refers to itself before its columns are known
+            if with.recursive {
+                self.ctes.push(Relation {
+                    columns: declared.clone(),
+                    has_rowid: false,
+                    ..Relation::opaque(&cte.name)
+                });
+            }
+            let produced = self.analyze_select(&cte.query);
+            if with.recursive {
+                self.ctes.pop();
+            }
+            let columns = match (declared, produced) {
+                (Some(declared), produced) => Some(
+                    declared
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, name)| {
+                            let expr_type = produced
+                                .as_ref()
+                                .and_then(|produced| produced.get(i))
+                                .map_or_else(ExprType::unknown, ResultColumn::expr_type);
+                            ResultColumn::new(name, expr_type)
+                        })
+                        .collect(),
+                ),
+                (None, produced) => produced,
+            };
+            self.ctes.push(Relation::derived(&cte.name, columns));
+        }
+        depth
+    }
+
+    fn exit_with(&mut self, depth: usize) {
+        self.ctes.truncate(depth);
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn scope(&self) -> &Scope {
+        self.scopes.last().expect("the outermost scope is never popped")
+    }
+
+    fn scope_mut(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("the outermost scope is never popped")
+    }
+
+    fn add_relation(&mut self, relation: Relation) {
+        self.scope_mut().relations.push(relation);
+    }
+
+    /// Binds the result columns of a query or RETURNING clause and makes
+    /// their aliases visible to the rest of it; returns them with their
+    /// types, or None when a wildcard stands for columns that cannot be known
+    fn analyze_items(&mut self, items: &[SelectItem]) -> Option<Vec<ResultColumn>> {
+        let mut names = Some(Vec::new());
+        let mut aliases = Vec::new();
+        for item in items {
+            match item {
+                SelectItem::Wildcard => {
+                    let expanded = self.scope().relations.iter().try_fold(Vec::new(), |mut all, relation| {
+                        all.extend(relation.result_columns()?);
+                        Some(all)
+                    });
+                    extend(&mut names, expanded);
+                }
+                SelectItem::QualifiedWildcard(table) => {
+                    let relation = self
+                        .scope()
+                        .relations
+                        .iter()
+                        .find(|relation| relation.name.eq_ignore_ascii_case(&table.name))
+                        .cloned();
+                    match relation {
+                        Some(relation) => extend(&mut names, relation.result_columns()),
+                        None => {
+                            let candidates = self.scope().relations.iter().map(|r| r.name.clone()).collect();
+                            let message = format!("no such table: {}", table);
+                            self.report(diagnostic::UNKNOWN_TABLE, message, self.statement_span, &table.name, candidates);
+                            names = None;
+                        }
+                    }
+                }
+                SelectItem::Expression { expr, alias, span } => {
+                    let expr_type = self.analyze_expression(expr);
+                    if let Some(alias) = alias {
+                        aliases.push((alias.clone(), expr_type));
+                    }
+                    if let Some(names) = names.as_mut() {
+                        let source = self.sql.get(span.offset..span.offset + span.length).filter(|text| !text.is_empty());
+                        names.push(ResultColumn::new(result_name(expr, alias.as_deref(), source), expr_type));
+                    }
+                }
+            }
+        }
+        self.scope_mut().aliases = aliases;
+        names
+    }
+
+    fn analyze_terms(&mut self, terms: &[OrderingTerm]) {
+        for term in terms {
+            self.analyze_expression(&term.expr);
+        }
+    }
+
+    fn analyze_optional(&mut self, expr: Option<&Expression>) {
+        if let Some(expr) = expr {
+            self.analyze_expression(expr);
+        }
+    }
+
+    fn analyze_window(&mut self, window: &Window) {
+        for expr in &window.partition_by {
+            self.analyze_expression(expr);
+        }
+        self.analyze_terms(&window.order_by);
+    }
+
+    /// Binds every column an expression refers to, and the queries nested
+    /// in it, and infers the type of its value
+    pub fn analyze_expression(&mut self, expr: &Expression) -> ExprType {
+        match expr {
+            Expression::Column { schema, table, name, span } => {
+                self.bind_column(schema.as_deref(), table.as_deref(), name, *span)
+            }
+            Expression::Literal(value) => types::literal_type(value),
+            Expression::Parameter { .. } | Expression::Star | Expression::Row(_) => {
+                for child in children(expr) {
+                    self.analyze_expression(child);
+                }
+                ExprType::unknown()
+            }
+            Expression::BinaryOp { left, op, right } => {
+                let left_type = self.analyze_expression(left);
+                let right_type = self.analyze_expression(right);
+                if matches!(
+                    op,
+                    Operator::Equals
+                        | Operator::NotEquals
+                        | Operator::LessThan
+                        | Operator::GreaterThan
+                        | Operator::LessEquals
+                        | Operator::GreaterEquals
+                        | Operator::Is
+                        | Operator::IsNot
+                ) {
+                    self.check_comparison(expr, (left, left_type), (right, right_type));
+                }
+                types::binary_type(op, left_type, right_type)
+            }
+            Expression::UnaryOp { op, expr } => {
+                let operand = self.analyze_expression(expr);
+                types::unary_type(op, operand)
+            }
+            Expression::Function { name, args, filter, over, .. } => {
+                let arg_types: Vec<ExprType> = args.iter().map(|arg| self.analyze_expression(arg)).collect();
+                self.analyze_optional(filter.as_deref());
+                if let Some(window) = over {
+                    self.analyze_window(window);
+                }
+                types::function_type(name, &arg_types)
+            }
+            Expression::Like { expr, pattern, escape, .. } => {
+                let nullable = self.analyze_expression(expr).nullable | self.analyze_expression(pattern).nullable;
+                self.analyze_optional(escape.as_deref());
+                ExprType::new(ValueType::Integer, nullable)
+            }
+            Expression::Between { expr: operand, low, high, .. } => {
+                let operand_type = self.analyze_expression(operand);
+                let low_type = self.analyze_expression(low);
+                let high_type = self.analyze_expression(high);
+                if !self.check_comparison(expr, (operand, operand_type), (low, low_type)) {
+                    self.check_comparison(expr, (operand, operand_type), (high, high_type));
+                }
+                ExprType::new(ValueType::Integer, operand_type.nullable || low_type.nullable || high_type.nullable)
+            }
+            Expression::InList { expr: operand, list, .. } => {
+                let operand_type = self.analyze_expression(operand);
+                let mut nullable = operand_type.nullable;
+                let mut warned = false;
+                for item in list {
+                    let item_type = self.analyze_expression(item);
+                    // One warning is enough for the whole list
+                    if !warned {
+                        warned = self.check_comparison(expr, (operand, operand_type), (item, item_type));
+                    }
+                    nullable |= item_type.nullable;
+                }
+                ExprType::new(ValueType::Integer, nullable)
+            }
+            Expression::InSubquery { expr, query, .. } => {
+                let operand = self.analyze_expression(expr);
+                self.analyze_select(query);
+                ExprType::new(ValueType::Integer, operand.nullable)
+            }
+            Expression::InTable { expr, table, .. } => {
+                let operand = self.analyze_expression(expr);
+                self.lookup_target(table, expression_span(expr).unwrap_or(self.statement_span));
+                ExprType::new(ValueType::Integer, operand.nullable)
+            }
+            Expression::IsNull { expr, .. } => {
+                self.analyze_expression(expr);
+                ExprType::new(ValueType::Integer, false)
+            }
+            Expression::Case { operand, branches, else_result } => {
+                self.analyze_optional(operand.as_deref());
+                let mut result: Option<ExprType> = None;
+                for (when, then) in branches {
+                    self.analyze_expression(when);
+                    let then_type = self.analyze_expression(then).without_affinity();
+                    result = Some(result.map_or(then_type, |result| result.merge(then_type)));
+                }
+                // Without ELSE, a CASE that matches no branch is NULL
+                let else_type = match else_result {
+                    Some(else_result) => self.analyze_expression(else_result).without_affinity(),
+                    None => ExprType::new(ValueType::Null, true),
+                };
+                result.map_or(else_type, |result| result.merge(else_type))
+            }
+            Expression::Cast { expr, type_name } => {
+                let operand = self.analyze_expression(expr);
+                types::cast_type(type_name, operand)
+            }
+            Expression::Collate { expr, .. } => self.analyze_expression(expr),
+            Expression::Exists(query) => {
+                self.analyze_select(query);
+                ExprType::new(ValueType::Integer, false)
+            }
+            // A scalar subquery is NULL when it returns no row
+            Expression::Subquery(query) => self
+                .analyze_select(query)
+                .and_then(|columns| columns.first().map(ResultColumn::expr_type))
+                .map_or_else(ExprType::unknown, ExprType::or_null),
+            Expression::Raise { .. } => ExprType::new(ValueType::Null, true),
+        }
+    }
+
+    /// Warns about a comparison that cannot hold the way it reads; returns
+    /// whether it did
+    fn check_comparison(
+        &mut self,
+        comparison: &Expression,
+        left: (&Expression, ExprType),
+        right: (&Expression, ExprType),
+    ) -> bool {
+        let literal = |expr: &Expression| match expr {
+            Expression::Literal(value) => Some(value.clone()),
+            _ => None,
+        };
+        let (left_literal, right_literal) = (literal(left.0), literal(right.0));
+        if let Some(reason) = types::suspicious_comparison(left.1, left_literal.as_ref(), right.1, right_literal.as_ref()) {
+            let span = expression_span(comparison).unwrap_or(self.statement_span);
+            let message = format!("suspicious comparison `{}`: {}", comparison, reason);
+            self.diagnostics.push(Diagnostic::warning(diagnostic::SUSPICIOUS_COMPARISON, message, span, &self.sql));
+            return true;
         }
+        false
     }
-    
+
+    /// Resolves a column to one table of the innermost scope that has it,
+    /// looking in enclosing queries when none does
+    fn bind_column(&mut self, schema: Option<&str>, table: Option<&str>, name: &str, span: Span) -> ExprType {
+        if let Some(table) = table {
+            let relation = self
+                .scopes
+                .iter()
+                .rev()
+                .flat_map(|scope| scope.relations.iter())
+                .find(|relation| {
+                    relation.name.eq_ignore_ascii_case(table)
+                        && schema.map_or(true, |schema| {
+                            relation.schema.as_deref().is_some_and(|found| found.eq_ignore_ascii_case(schema))
+                        })
+                })
+                .cloned();
+            let qualified = match schema {
+                Some(schema) => format!("{}.{}.{}", schema, table, name),
+                None => format!("{}.{}", table, name),
+            };
+            match relation {
+                Some(relation) if relation.has_column(name) => return relation.column_type(name),
+                Some(relation) => {
+                    let candidates = relation.columns.clone().unwrap_or_default();
+                    self.report(diagnostic::UNKNOWN_COLUMN, format!("no such column: {}", qualified), span, name, candidates);
+                }
+                None => {
+                    let candidates = self.scopes.iter().flat_map(|scope| &scope.relations).map(|r| r.name.clone()).collect();
+                    self.report(diagnostic::UNKNOWN_COLUMN, format!("no such column: {}", qualified), span, table, candidates);
+                }
+            }
+            return ExprType::unknown();
+        }
+
+        for depth in (0..self.scopes.len()).rev() {
+            let scope = &self.scopes[depth];
+            if let Some((_, alias_type)) = scope.aliases.iter().find(|(alias, _)| alias.eq_ignore_ascii_case(name)) {
+                return *alias_type;
+            }
+            let mut known = scope
+                .relations
+                .iter()
+                .filter(|relation| relation.columns.as_ref().is_some_and(|columns| contains(columns, name)));
+            let (first, count) = (known.next(), known.count() + 1);
+            if let (Some(relation), 1) = (first, count) {
+                return relation.column_type(name);
+            }
+            if let Some(relation) = first {
+                let column_type = relation.column_type(name);
+                let merged = contains(&scope.merged, name);
+                // `is_column_ambiguous` answers for the innermost scope only
+                let ambiguous = if depth + 1 == self.scopes.len() { self.is_column_ambiguous(name) } else { !merged };
+                if ambiguous {
+                    self.error(diagnostic::AMBIGUOUS_COLUMN, format!("ambiguous column name: {}", name), span);
+                    return ExprType::unknown();
+                }
+                // A column merged by USING or NATURAL takes the left table's value
+                return column_type;
+            }
+            // A table of unknown columns might have it
+            if scope.relations.iter().any(|relation| relation.columns.is_none()) {
+                return ExprType::unknown();
+            }
+            // With more than one rowid table, SQLite takes rowid to name none of them
+            let mut rowid_tables = scope.relations.iter().filter(|relation| relation.has_rowid);
+            if let (true, Some(relation), None) = (is_rowid_alias(name), rowid_tables.next(), rowid_tables.next()) {
+                return relation.column_type(name);
+            }
+        }
+
+        let candidates: Vec<String> = self
+            .scopes
+            .iter()
+            .flat_map(|scope| {
+                scope
+                    .relations
+                    .iter()
+                    .flat_map(|relation| relation.columns.clone().unwrap_or_default())
+                    .chain(scope.aliases.iter().map(|(alias, _)| alias.clone()))
+            })
+            .collect();
+        // Double quotes make an identifier, which is easy to mistake for a string
+        if self.sql.get(span.offset..span.offset + span.length).is_some_and(|text| text.starts_with('"')) {
+            let message = format!("no such column: \"{}\" - should this be a string literal in single-quotes?", name);
+            self.error(diagnostic::UNKNOWN_COLUMN, message, span);
+            return ExprType::unknown();
+        }
+        self.report(diagnostic::UNKNOWN_COLUMN, format!("no such column: {}", name), span, name, candidates);
+        ExprType::unknown()
+    }
+
+    /// Whether the table of `table`, as named in the current query level, has the column
+    pub fn check_column_exists(&self, table: &str, column: &str) -> bool {
+        self.scope()
+            .relations
+            .iter()
+            .find(|relation| relation.name.eq_ignore_ascii_case(table))
+            .is_some_and(|relation| relation.has_column(column))
+    }
+
+    /// Whether more than one table of the current query level has the
+    /// column, other than through a USING or NATURAL join that merges them
     pub fn is_column_ambiguous(&self, column: &str) -> bool {
+        let scope = self.scope();
+        if contains(&scope.merged, column) {
+            return false;
+        }
+
         let mut count = 0;
-        
-        for columns in self.tables.values() {
-            if columns.iter().any(|c| c == column) {
+
+        for relation in &scope.relations {
+            if relation.columns.as_ref().is_some_and(|columns| contains(columns, column)) {
                 count += 1;
             }
         }
-        
+
         count > 1
     }
-}
\ No newline at end of file
+
+    fn error(&mut self, code: &'static str, message: impl Into<String>, span: Span) {
+        self.diagnostics.push(Diagnostic::error(code, message, span, &self.sql));
+    }
+
+    /// Reports a name that does not resolve, suggesting the closest candidate
+    fn report(&mut self, code: &'static str, message: String, span: Span, name: &str, candidates: Vec<String>) {
+        let message = match closest(name, &candidates) {
+            Some(suggestion) => format!("{} (did you mean \"{}\"?)", message, suggestion),
+            None => message,
+        };
+        self.error(code, message, span);
+    }
+}
+
+/// Whether `names` holds `name`, ignoring case as SQLite does
+fn contains(names: &[String], name: &str) -> bool {
+    names.iter().any(|candidate| candidate.eq_ignore_ascii_case(name))
+}
+
+fn is_rowid_alias(name: &str) -> bool {
+    ROWID_ALIASES.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+}
+
+fn extend(columns: &mut Option<Vec<ResultColumn>>, more: Option<Vec<ResultColumn>>) {
+    match (columns.as_mut(), more) {
+        (Some(columns), Some(more)) => columns.extend(more),
+        _ => *columns = None,
+    }
+}
+
+fn names_of(columns: &Option<Vec<ResultColumn>>) -> Option<Vec<String>> {
+    columns.as_ref().map(|columns| columns.iter().map(|column| column.name.clone()).collect())
+}
+
+fn compound_name(operator: CompoundOperator) -> &'static str {
+    match operator {
+        CompoundOperator::Union => "UNION",
+        CompoundOperator::UnionAll => "UNION ALL",
+        CompoundOperator::Intersect => "INTERSECT",
+        CompoundOperator::Except => "EXCEPT",
+    }
+}
+
+/// Name SQLite gives a result column: its alias, a column's own name, or
+/// the expression as written
+fn result_name(expr: &Expression, alias: Option<&str>, source: Option<&str>) -> String {
+    match (alias, expr, source) {
+        (Some(alias), _, _) => alias.to_string(),
+        (None, Expression::Column { name, .. }, _) => name.clone(),
+        (None, _, Some(source)) => source.to_string(),
+        (None, expr, None) => expr.to_string(),
+    }
+}
+
+/// The position an integer GROUP BY or ORDER BY term stands for
+fn ordinal_term(expr: &Expression) -> Option<i64> {
+    match expr {
+        Expression::Literal(Value::Integer(position)) => Some(*position),
+        _ => None,
+    }
+}
+
+/// "1st", "2nd", "3rd", "4th", ...
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// Whether a call is to an aggregate; min() and max() are scalar with more
+/// than one argument, and any aggregate with OVER is a window function
+fn is_aggregate_call(name: &str, args: &[Expression], over: bool) -> bool {
+    const AGGREGATES: [&str; 10] = [
+        "avg", "count", "group_concat", "string_agg", "sum", "total", "json_group_array",
+        "json_group_object", "min", "max",
+    ];
+    if over || !AGGREGATES.iter().any(|aggregate| aggregate.eq_ignore_ascii_case(name)) {
+        return false;
+    }
+    let scalar_variant = name.eq_ignore_ascii_case("min") || name.eq_ignore_ascii_case("max");
+    !(scalar_variant && args.len() > 1)
+}
+
+/// The first aggregate call in `expr` outside nested queries, with its span
+fn first_aggregate(expr: &Expression) -> Option<(&str, Span)> {
+    if let Expression::Function { name, args, over, span, .. } = expr {
+        if is_aggregate_call(name, args, over.is_some()) {
+            return Some((name, *span));
+        }
+    }
+    children(expr).into_iter().find_map(first_aggregate)
+}
+
+fn count_aggregates(expr: &Expression) -> usize {
+    let own = match expr {
+        Expression::Function { name, args, over, .. } => usize::from(is_aggregate_call(name, args, over.is_some())),
+        _ => 0,
+    };
+    own + children(expr).into_iter().map(count_aggregates).sum::<usize>()
+}
+
+/// Number of aggregate calls to any of `names`
+fn count_calls(expr: &Expression, names: &[&str]) -> usize {
+    let own = match expr {
+        Expression::Function { name, args, over, .. } => {
+            usize::from(is_aggregate_call(name, args, over.is_some()) && names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+        }
+        _ => 0,
+    };
+    own + children(expr).into_iter().map(|child| count_calls(child, names)).sum::<usize>()
+}
+
+/// Columns of `expr` outside aggregates that match no GROUP BY term
+fn bare_columns<'e>(expr: &'e Expression, grouped: &[&Expression], found: &mut Vec<(&'e str, Span)>) {
+    if grouped.iter().any(|term| same_expression(term, expr)) {
+        return;
+    }
+    match expr {
+        Expression::Column { name, span, .. } => found.push((name, *span)),
+        Expression::Function { name, args, over, .. } if is_aggregate_call(name, args, over.is_some()) => {}
+        other => {
+            for child in children(other) {
+                bare_columns(child, grouped, found);
+            }
+        }
+    }
+}
+
+/// Whether two expressions are the same, a column qualified in only one of
+/// them still matching
+fn same_expression(left: &Expression, right: &Expression) -> bool {
+    match (left, right) {
+        (
+            Expression::Column { table: left_table, name: left_name, .. },
+            Expression::Column { table: right_table, name: right_name, .. },
+        ) => {
+            left_name.eq_ignore_ascii_case(right_name)
+                && match (left_table, right_table) {
+                    (Some(left), Some(right)) => left.eq_ignore_ascii_case(right),
+                    _ => true,
+                }
+        }
+        (left, right) => left.to_string().eq_ignore_ascii_case(&right.to_string()),
+    }
+}
+
+/// Span of the first column or function in `expr`
+fn expression_span(expr: &Expression) -> Option<Span> {
+    match expr {
+        Expression::Column { span, .. } | Expression::Function { span, .. } => Some(*span),
+        other => children(other).into_iter().find_map(expression_span),
+    }
+}
+
+/// The expressions directly inside `expr`, leaving out nested queries
+fn children(expr: &Expression) -> Vec<&Expression> {
+    match expr {
+        Expression::Column { .. }
+        | Expression::Literal(_)
+        | Expression::Parameter { .. }
+        | Expression::Star
+        | Expression::Exists(_)
+        | Expression::Subquery(_)
+        | Expression::Raise { .. } => Vec::new(),
+        Expression::BinaryOp { left, right, .. } => vec![left, right],
+        Expression::UnaryOp { expr, .. }
+        | Expression::IsNull { expr, .. }
+        | Expression::Cast { expr, .. }
+        | Expression::Collate { expr, .. }
+        | Expression::InSubquery { expr, .. }
+        | Expression::InTable { expr, .. } => vec![expr],
+        Expression::Function { args, filter, over, .. } => {
+            let mut all: Vec<&Expression> = args.iter().collect();
+            all.extend(filter.as_deref());
+            if let Some(window) = over {
+                all.extend(&window.partition_by);
+                all.extend(window.order_by.iter().map(|term| &term.expr));
+            }
+            all
+        }
+        Expression::Like { expr, pattern, escape, .. } => {
+            let mut all = vec![expr.as_ref(), pattern.as_ref()];
+            all.extend(escape.as_deref());
+            all
+        }
+        Expression::Between { expr, low, high, .. } => vec![expr, low, high],
+        Expression::InList { expr, list, .. } => std::iter::once(expr.as_ref()).chain(list).collect(),
+        Expression::Case { operand, branches, else_result } => {
+            let mut all: Vec<&Expression> = operand.as_deref().into_iter().collect();
+            for (when, then) in branches {
+                all.push(when);
+                all.push(then);
+            }
+            all.extend(else_result.as_deref());
+            all
+        }
+        Expression::Row(values) => values.iter().collect(),
+    }
+}
+
+/// The candidate closest to `name`, if it is close enough to be a typo of it
+fn closest<'c>(name: &str, candidates: &'c [String]) -> Option<&'c str> {
+    candidates
+        .iter()
+        .filter(|candidate| !candidate.eq_ignore_ascii_case(name))
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= (name.len().max(candidate.len()) + 1) / 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+/// Edits between two names, ignoring case, where swapping two neighbouring
+/// characters counts as one edit
+fn edit_distance(left: &str, right: &str) -> usize {
+    let left: Vec<char> = left.to_lowercase().chars().collect();
+    let right: Vec<char> = right.to_lowercase().chars().collect();
+    let mut rows = vec![vec![0usize; right.len() + 1]; left.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=left.len() {
+        for j in 1..=right.len() {
+            let cost = usize::from(left[i - 1] != right[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && left[i - 1] == right[j - 2] && left[i - 2] == right[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[left.len()][right.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ast::AstBuilder;
+    use crate::parser::lexer::Tokenizer;
+    use crate::schema::table::{MasterRecord, SchemaExtractor};
+
+    fn catalog() -> Arc<SchemaCatalog> {
+        let objects = [
+            ("table", "users", "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, team INTEGER)"),
+            ("table", "teams", "CREATE TABLE teams (id INTEGER PRIMARY KEY, title TEXT)"),
+            ("table", "totals", "CREATE TABLE totals (a INT, b INT, c INT GENERATED ALWAYS AS (a + b))"),
+            ("view", "names", "CREATE VIEW names AS SELECT name FROM users"),
+        ];
+        let records = objects
+            .iter()
+            .enumerate()
+            .map(|(position, (object_type, name, sql))| MasterRecord {
+                object_type: object_type.to_string(),
+                name: name.to_string(),
+                tbl_name: name.to_string(),
+                root_page: position as u32 + 2,
+                sql: Some(sql.to_string()),
+            })
+            .collect();
+
+        Arc::new(
+            SchemaExtractor::new(":memory:")
+                .unwrap()
+                .load_master_records(records, 1, false)
+                .unwrap()
+                .build_catalog()
+                .unwrap(),
+        )
+    }
+
+    /// Validates every statement of `sql` and returns the messages of its errors
+    fn errors(sql: &str) -> Vec<String> {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let mut validator = QueryValidator::new(catalog(), sql);
+        for statement in AstBuilder::new(sql, tokens).build_all().unwrap() {
+            validator.validate(&statement).unwrap();
+        }
+        validator.get_errors().iter().map(|error| error.message.clone()).collect()
+    }
+
+    fn warnings(sql: &str) -> Vec<String> {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let mut validator = QueryValidator::new(catalog(), sql);
+        validator.validate(&AstBuilder::new(sql, tokens).build().unwrap()).unwrap();
+        validator.get_warnings().iter().map(|warning| warning.message.clone()).collect()
+    }
+
+    /// Result columns of the last statement of `sql` as (name, type, nullable)
+    fn result_types(sql: &str) -> Vec<(String, ValueType, bool)> {
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let mut validator = QueryValidator::new(catalog(), sql);
+        for statement in AstBuilder::new(sql, tokens).build_all().unwrap() {
+            validator.validate(&statement).unwrap();
+        }
+        assert_eq!(validator.get_errors().len(), 0, "{}", sql);
+        validator
+            .get_result_columns()
+            .iter()
+            .map(|column| (column.name.clone(), column.value_type, column.nullable))
+            .collect()
+    }
+
+    #[test]
+    fn binds_columns_to_tables_aliases_and_outer_queries() {
+        let valid = [
+            "SELECT u.id, t.title FROM users u JOIN teams t ON t.id = u.team",
+            "SELECT name AS n FROM users WHERE n <> '' ORDER BY n",
+            "SELECT id FROM users JOIN teams USING (id)",
+            "SELECT rowid, _rowid_ FROM users",
+            "SELECT name FROM users u WHERE EXISTS (SELECT 1 FROM teams WHERE teams.id = u.team)",
+            "SELECT s.n FROM (SELECT name AS n FROM users) s",
+            "WITH RECURSIVE c(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c WHERE n < 3) SELECT n FROM c",
+            "SELECT key, value FROM json_each('[1]')",
+            "SELECT name FROM names",
+            "SELECT main.users.name FROM main.users",
+            "SELECT type, sql FROM sqlite_master",
+            "CREATE TABLE t (x, y); INSERT INTO t (x) SELECT id FROM users; SELECT y FROM t",
+        ];
+        for sql in valid {
+            assert_eq!(errors(sql), Vec::<String>::new(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn reports_unknown_names_with_suggestions() {
+        assert_eq!(errors("SELECT * FROM usres"), ["no such table: usres (did you mean \"users\"?)"]);
+        assert_eq!(errors("SELECT nmae FROM users"), ["no such column: nmae (did you mean \"name\"?)"]);
+        assert_eq!(errors("SELECT u.titel FROM users u"), ["no such column: u.titel"]);
+        assert_eq!(errors("SELECT t.title FROM teams x"), ["no such column: t.title"]);
+        assert_eq!(errors("SELECT zzz FROM users"), ["no such column: zzz"]);
+        assert_eq!(errors("UPDATE users SET nme = 1"), ["no such column: nme (did you mean \"name\"?)"]);
+        assert_eq!(
+            errors("SELECT id FROM users WHERE name = \"bob\""),
+            ["no such column: \"bob\" - should this be a string literal in single-quotes?"]
+        );
+        assert_eq!(errors("DROP TABLE teams; SELECT * FROM teams"), ["no such table: teams"]);
+
+        let sql = "SELECT id FROM users WHERE nam = 1";
+        let tokens = Tokenizer::new(sql).tokenize().unwrap();
+        let mut validator = QueryValidator::new(catalog(), sql);
+        validator.validate(&AstBuilder::new(sql, tokens).build().unwrap()).unwrap();
+        let error = &validator.get_errors()[0];
+        assert_eq!(error.code, diagnostic::UNKNOWN_COLUMN);
+        assert_eq!((error.span.column, error.span.length), (28, 3));
+    }
+
+    #[test]
+    fn reports_ambiguous_columns() {
+        assert_eq!(errors("SELECT id FROM users, teams"), ["ambiguous column name: id"]);
+        assert_eq!(errors("SELECT rowid FROM users, teams"), ["no such column: rowid"]);
+
+        let mut analyzer = SemanticAnalyzer::new(catalog(), "");
+        analyzer.add_table("a", vec!["id".to_string(), "x".to_string()]);
+        analyzer.add_table("b", vec!["ID".to_string()]);
+        assert!(analyzer.is_column_ambiguous("id"));
+        assert!(!analyzer.is_column_ambiguous("x"));
+        assert!(analyzer.check_column_exists("B", "id"));
+        assert!(!analyzer.check_column_exists("b", "x"));
+    }
+
+    #[test]
+    fn enforces_sqlite_grouping_rules() {
+        assert_eq!(errors("SELECT name FROM users WHERE count(*) > 1"), ["misuse of aggregate: count()"]);
+        assert_eq!(
+            errors("SELECT count(*) FROM users GROUP BY count(*)"),
+            ["aggregate functions are not allowed in the GROUP BY clause"]
+        );
+        assert_eq!(
+            errors("SELECT team FROM users GROUP BY 2"),
+            ["1st GROUP BY term out of range - should be between 1 and 1"]
+        );
+        assert_eq!(errors("SELECT name FROM users HAVING id > 1"), ["HAVING clause on a non-aggregate query"]);
+        assert_eq!(
+            errors("SELECT name FROM users UNION SELECT title FROM teams ORDER BY nope"),
+            ["1st ORDER BY term does not match any column in the result set"]
+        );
+        assert!(errors("SELECT count(*) FROM users HAVING count(*) > 1").is_empty());
+
+        assert_eq!(
+            warnings("SELECT team, name, count(*) FROM users GROUP BY team"),
+            ["column \"name\" is neither grouped nor aggregated; SQLite returns it from an arbitrary row of each group"]
+        );
+        assert!(warnings("SELECT team AS t, count(*) FROM users GROUP BY t").is_empty());
+        assert!(warnings("SELECT name, max(id) FROM users").is_empty());
+    }
+
+    #[test]
+    fn checks_insert_column_counts() {
+        assert_eq!(
+            errors("INSERT INTO users VALUES (1, 'a')"),
+            ["table users has 3 columns but 2 values were supplied"]
+        );
+        assert_eq!(errors("INSERT INTO users (id, name) VALUES (1)"), ["1 values for 2 columns"]);
+        assert_eq!(errors("INSERT INTO users (id) SELECT id, title FROM teams"), ["2 values for 1 columns"]);
+        assert_eq!(errors("INSERT INTO users VALUES (1, 'a', 2), (2)"), ["all VALUES must have the same number of terms"]);
+        assert_eq!(errors("INSERT INTO users (nick) VALUES (1)"), ["table users has no column named nick"]);
+        // Generated columns take no values
+        assert!(errors("INSERT INTO totals VALUES (1, 2)").is_empty());
+        assert_eq!(errors("INSERT INTO totals (a, c) VALUES (1, 2)"), ["cannot INSERT into generated column \"c\""]);
+        assert_eq!(errors("UPDATE totals SET c = 1"), ["cannot UPDATE generated column \"c\""]);
+        assert!(errors(
+            "INSERT INTO users (id, name) VALUES (1, 'x') ON CONFLICT (id) DO UPDATE SET name = excluded.name RETURNING id"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn checks_schema_statements_against_the_catalog() {
+        assert_eq!(errors("CREATE TABLE users (id)"), ["table users already exists"]);
+        assert!(errors("CREATE TABLE IF NOT EXISTS users (id)").is_empty());
+        assert_eq!(errors("CREATE TABLE t (a, A)"), ["duplicate column name: A"]);
+        assert_eq!(errors("CREATE INDEX i ON users (nmae)"), ["no such column: nmae (did you mean \"name\"?)"]);
+        assert_eq!(errors("DROP INDEX nope"), ["no such index: nope"]);
+        assert!(errors("DROP TABLE IF EXISTS nope").is_empty());
+        assert_eq!(errors("ALTER TABLE users ADD COLUMN name TEXT"), ["duplicate column name: name"]);
+        assert_eq!(errors("ALTER TABLE users DROP COLUMN nope"), ["no such column: \"nope\""]);
+        assert!(errors("ALTER TABLE users RENAME TO people; SELECT name FROM people").is_empty());
+        assert!(errors(
+            "CREATE TRIGGER t AFTER UPDATE ON users BEGIN UPDATE teams SET title = new.name WHERE id = old.team; END"
+        )
+        .is_empty());
+        assert_eq!(
+            errors("CREATE TRIGGER t AFTER INSERT ON users BEGIN SELECT new.nope; END"),
+            ["no such column: new.nope"]
+        );
+    }
+
+    #[test]
+    fn suggests_only_close_names() {
+        let candidates = vec!["users".to_string(), "teams".to_string()];
+        assert_eq!(closest("usres", &candidates), Some("users"));
+        assert_eq!(closest("USER", &candidates), Some("users"));
+        assert_eq!(closest("orders", &candidates), None);
+        assert_eq!(edit_distance("nmae", "name"), 1);
+    }
+
+    #[test]
+    fn infers_result_column_types() {
+        let column = |name: &str, value_type, nullable| (name.to_string(), value_type, nullable);
+
+        assert_eq!(
+            result_types("SELECT id, name, count(*), avg(team), name || '!', id + 1.5, CAST(team AS TEXT), NULL FROM users"),
+            vec![
+                column("id", ValueType::Integer, false),
+                column("name", ValueType::Text, true),
+                column("count(*)", ValueType::Integer, false),
+                column("avg(team)", ValueType::Real, true),
+                column("name || '!'", ValueType::Text, true),
+                column("id + 1.5", ValueType::Real, false),
+                column("CAST(team AS TEXT)", ValueType::Text, true),
+                column("NULL", ValueType::Null, true),
+            ]
+        );
+        assert_eq!(
+            result_types("SELECT u.id, t.id AS team_id FROM users u LEFT JOIN teams t ON t.id = u.team"),
+            vec![column("id", ValueType::Integer, false), column("team_id", ValueType::Integer, true)]
+        );
+        assert_eq!(
+            result_types("SELECT CASE WHEN id > 1 THEN 1 ELSE 2.5 END AS c, (SELECT count(*) FROM teams) AS n FROM users"),
+            vec![column("c", ValueType::Numeric, false), column("n", ValueType::Integer, true)]
+        );
+        assert_eq!(
+            result_types("SELECT id FROM users UNION SELECT NULL"),
+            vec![column("id", ValueType::Integer, true)]
+        );
+        assert_eq!(
+            result_types("SELECT n FROM (SELECT id AS n FROM users WHERE id IS NOT NULL)"),
+            vec![column("n", ValueType::Integer, false)]
+        );
+        assert_eq!(
+            result_types("INSERT INTO teams (title) VALUES ('x') RETURNING id, title"),
+            vec![column("id", ValueType::Integer, false), column("title", ValueType::Text, true)]
+        );
+        assert_eq!(
+            result_types("CREATE TABLE t (x TEXT NOT NULL, y REAL); SELECT * FROM t"),
+            vec![column("x", ValueType::Text, false), column("y", ValueType::Real, true)]
+        );
+        assert_eq!(result_types("DELETE FROM users"), Vec::new());
+    }
+
+    #[test]
+    fn warns_about_comparisons_affinity_cannot_fix() {
+        let suspicious = [
+            "SELECT id FROM users WHERE lower(name) = 5",
+            "SELECT id FROM users WHERE id = 'abc'",
+            "SELECT id FROM users WHERE upper(name) IN (1, 2)",
+            "SELECT id FROM users WHERE length(name) BETWEEN 'a' AND 'z'",
+            "SELECT id FROM users WHERE team = x'00'",
+        ];
+        for sql in suspicious {
+            let found = warnings(sql);
+            assert_eq!(found.len(), 1, "{}", sql);
+            assert!(found[0].starts_with("suspicious comparison"), "{}", found[0]);
+        }
+
+        // Affinity converts one side to the other's type before these compare
+        let fine = [
+            "SELECT id FROM users WHERE name = 5",
+            "SELECT id FROM users WHERE id = '5'",
+            "SELECT id FROM users WHERE team BETWEEN 1 AND 3",
+            "SELECT id FROM users WHERE id = team AND name IN ('a', 'b')",
+            "SELECT id FROM users WHERE CAST(name AS INTEGER) = 5",
+        ];
+        for sql in fine {
+            assert_eq!(warnings(sql), Vec::<String>::new(), "{}", sql);
+        }
+    }
+}
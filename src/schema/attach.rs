@@ -0,0 +1,266 @@
+//! Databases attached with ATTACH DATABASE
+//!
+//! Every statement runs on a fresh sqlite3 process or connection, so the
+//! engine keeps attachments itself, on the session that issued them (see
+//! `schema::temp`). Clients sharing a database file therefore never see each
+//! other's attachments, and a session's attachments end with it. ATTACH and
+//! DETACH statements update the session and are removed from the script;
+//! each connection the engine opens for the session re-issues the ATTACHes.
+
+use anyhow::{anyhow, bail, Result};
+use rusqlite::Connection;
+use sqlparser::ast::{Expr, Statement, Value};
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::path::Path;
+
+/// SQLite's default SQLITE_MAX_ATTACHED
+pub const MAX_ATTACHED: usize = 10;
+
+/// A database attached under an alias
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub alias: String,
+    /// Absolute path of the attached file
+    pub path: String,
+}
+
+/// Databases attached in `session`, in attach order; none without a session
+pub fn attachments(session: Option<&str>) -> Vec<Attachment> {
+    session
+        .and_then(|session| super::temp::attachments(session).ok())
+        .unwrap_or_default()
+}
+
+/// Attaches `file` to the session as `alias`, creating the file if it does not exist
+pub fn attach(session: &str, file: &str, alias: &str) -> Result<Attachment> {
+    if alias.eq_ignore_ascii_case("main") || alias.eq_ignore_ascii_case("temp") {
+        bail!("database {} is already in use", alias);
+    }
+    if file.is_empty() || file == ":memory:" || file.starts_with("file:") {
+        bail!("Only database files can be attached, not '{}'", file);
+    }
+
+    super::temp::update_attachments(session, |attached| {
+        if attached.iter().any(|attachment| attachment.alias.eq_ignore_ascii_case(alias)) {
+            bail!("database {} is already in use", alias);
+        }
+        if attached.len() >= MAX_ATTACHED {
+            bail!("too many attached databases - max {}", MAX_ATTACHED);
+        }
+
+        // Opening creates a missing file, as ATTACH does; reading the schema
+        // rejects files that are not databases
+        let connection = Connection::open(file)?;
+        connection
+            .query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))
+            .map_err(|e| anyhow!("Cannot attach {}: {}", file, e))?;
+        drop(connection);
+
+        let path = Path::new(file)
+            .canonicalize()
+            .map_err(|e| anyhow!("Cannot attach {}: {}", file, e))?
+            .to_string_lossy()
+            .into_owned();
+
+        let attachment = Attachment {
+            alias: alias.to_string(),
+            path,
+        };
+        attached.push(attachment.clone());
+
+        println!("[ATTACH] Attached {} as {} in session {}", attachment.path, attachment.alias, session);
+        Ok(attachment)
+    })
+}
+
+/// Detaches the database attached to the session as `alias`
+pub fn detach(session: &str, alias: &str) -> Result<()> {
+    if alias.eq_ignore_ascii_case("main") || alias.eq_ignore_ascii_case("temp") {
+        bail!("cannot detach database {}", alias);
+    }
+
+    super::temp::update_attachments(session, |attached| {
+        let position = attached
+            .iter()
+            .position(|attachment| attachment.alias.eq_ignore_ascii_case(alias))
+            .ok_or_else(|| anyhow!("no such database: {}", alias))?;
+        let attachment = attached.remove(position);
+
+        println!("[ATTACH] Detached {} ({}) from session {}", attachment.alias, attachment.path, session);
+        Ok(())
+    })
+}
+
+/// Applies the ATTACH and DETACH statements in a script and returns the
+/// remaining statements, or `None` when the script held nothing else
+pub fn apply_statements(session: Option<&str>, sql: &str) -> Result<Option<String>> {
+    let statements = crate::parser::split_statements(sql)?;
+    if !statements.iter().any(|statement| leading_keyword(statement).is_some()) {
+        return Ok(Some(sql.to_string()));
+    }
+    let session = session.ok_or_else(|| anyhow!("ATTACH and DETACH need a session to keep the attachment in"))?;
+
+    let mut remaining = Vec::new();
+    for statement in statements {
+        match leading_keyword(&statement).as_deref() {
+            Some("ATTACH") => {
+                let (file, alias) = parse_attach(&statement)?;
+                attach(session, &file, &alias)?;
+            }
+            Some("DETACH") => detach(session, &parse_detach(&statement)?)?,
+            _ => remaining.push(statement),
+        }
+    }
+
+    if remaining.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(remaining.join(";\n")))
+    }
+}
+
+/// ATTACH statements re-creating the session's attachments, for the sqlite3 shell
+pub fn attach_sql(session: Option<&str>) -> String {
+    attachments(session)
+        .iter()
+        .map(|attachment| {
+            format!(
+                "ATTACH DATABASE '{}' AS \"{}\";\n",
+                attachment.path.replace('\'', "''"),
+                attachment.alias.replace('"', "\"\"")
+            )
+        })
+        .collect()
+}
+
+/// Attaches the session's attachments to an open connection
+pub fn attach_all(connection: &Connection, session: Option<&str>) -> Result<()> {
+    for attachment in attachments(session) {
+        connection.execute("ATTACH DATABASE ?1 AS ?2", [&attachment.path, &attachment.alias])?;
+    }
+    Ok(())
+}
+
+fn words(statement: &str) -> Result<Vec<Token>> {
+    let tokens = Tokenizer::new(&SQLiteDialect {}, statement)
+        .tokenize()
+        .map_err(|e| anyhow!("SQL syntax error: {}", e))?;
+    Ok(tokens
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect())
+}
+
+/// "ATTACH" or "DETACH" when the statement is one
+fn leading_keyword(statement: &str) -> Option<String> {
+    match words(statement).ok()?.first() {
+        Some(Token::Word(word)) if word.quote_style.is_none() => {
+            let keyword = word.value.to_uppercase();
+            (keyword == "ATTACH" || keyword == "DETACH").then_some(keyword)
+        }
+        _ => None,
+    }
+}
+
+/// `ATTACH [DATABASE] 'file' AS alias`
+fn parse_attach(statement: &str) -> Result<(String, String)> {
+    let parsed = Parser::parse_sql(&SQLiteDialect {}, statement).map_err(|e| anyhow!("SQL parse error: {}", e))?;
+
+    match parsed.first() {
+        Some(Statement::AttachDatabase { schema_name, database_file_name, .. }) => match database_file_name {
+            Expr::Value(Value::SingleQuotedString(file)) => Ok((file.clone(), schema_name.value.clone())),
+            other => bail!("ATTACH needs the file name as a string literal, got {}", other),
+        },
+        _ => bail!("Expected ATTACH DATABASE 'file' AS name, got: {}", statement),
+    }
+}
+
+/// `DETACH [DATABASE] alias`; the SQLite dialect of sqlparser has no DETACH
+fn parse_detach(statement: &str) -> Result<String> {
+    let tokens = words(statement)?;
+    let mut rest = tokens.iter().skip(1);
+
+    let mut name = rest.next();
+    if let Some(Token::Word(word)) = name {
+        if word.quote_style.is_none() && word.value.eq_ignore_ascii_case("DATABASE") && tokens.len() > 2 {
+            name = rest.next();
+        }
+    }
+
+    let alias = match name {
+        Some(Token::Word(word)) => word.value.clone(),
+        Some(Token::SingleQuotedString(value)) => value.clone(),
+        _ => bail!("Expected DETACH DATABASE name, got: {}", statement),
+    };
+    if rest.next().is_some() {
+        bail!("Unexpected input after DETACH {}", alias);
+    }
+
+    Ok(alias)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::temp;
+
+    #[test]
+    fn parses_attach_with_and_without_database_keyword() {
+        assert_eq!(parse_attach("ATTACH 'other.db' AS other").unwrap(), ("other.db".to_string(), "other".to_string()));
+        assert_eq!(
+            parse_attach("attach database 'it''s.db' as \"my db\"").unwrap(),
+            ("it's.db".to_string(), "my db".to_string())
+        );
+        assert!(parse_attach("ATTACH DATABASE other_file AS other").is_err());
+        assert!(parse_attach("ATTACH 'other.db'").is_err());
+    }
+
+    #[test]
+    fn parses_detach_forms() {
+        assert_eq!(parse_detach("DETACH other").unwrap(), "other");
+        assert_eq!(parse_detach("DETACH DATABASE other").unwrap(), "other");
+        assert_eq!(parse_detach("detach database \"my db\"").unwrap(), "my db");
+        // A schema may itself be called "database"
+        assert_eq!(parse_detach("DETACH database").unwrap(), "database");
+        assert!(parse_detach("DETACH").is_err());
+        assert!(parse_detach("DETACH DATABASE a b").is_err());
+    }
+
+    #[test]
+    fn recognises_only_leading_attach_and_detach() {
+        assert_eq!(leading_keyword("  ATTACH 'a.db' AS a").as_deref(), Some("ATTACH"));
+        assert_eq!(leading_keyword("detach a").as_deref(), Some("DETACH"));
+        assert_eq!(leading_keyword("SELECT 'ATTACH'"), None);
+        assert_eq!(leading_keyword("\"attach\""), None);
+    }
+
+    #[test]
+    fn attachments_belong_to_the_session_that_made_them() {
+        let dir = std::env::temp_dir();
+        let main = dir.join(format!("whatql-attach-main-{}.db", std::process::id()));
+        let other = dir.join(format!("whatql-attach-other-{}.db", std::process::id()));
+        let (main, other) = (main.to_str().unwrap(), other.to_str().unwrap());
+        Connection::open(main).unwrap().execute_batch("CREATE TABLE t (x)").unwrap();
+
+        let first = temp::open_session(main).unwrap();
+        let second = temp::open_session(main).unwrap();
+
+        let script = format!("ATTACH '{}' AS other; SELECT 1", other);
+        assert_eq!(apply_statements(Some(&first), &script).unwrap().as_deref(), Some("SELECT 1"));
+        assert_eq!(attachments(Some(&first)).len(), 1);
+        assert!(attachments(Some(&second)).is_empty());
+        assert!(apply_statements(None, &script).is_err());
+
+        assert!(attach(&first, other, "OTHER").is_err());
+        assert_eq!(apply_statements(Some(&first), "DETACH DATABASE other").unwrap(), None);
+        assert!(attachments(Some(&first)).is_empty());
+        assert!(detach(&first, "other").is_err());
+
+        temp::end_session(&first);
+        temp::end_session(&second);
+        let _ = std::fs::remove_file(main);
+        let _ = std::fs::remove_file(other);
+    }
+}
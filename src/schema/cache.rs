@@ -0,0 +1,181 @@
+//! Process-wide schema catalog cache
+//!
+//! Catalogs are keyed by the canonical database path and shared between the
+//! interactive shell and API requests. Each lookup re-reads only the schema
+//! cookie from the database header; the catalog is rebuilt when the cookie
+//! no longer matches the version it was loaded at. Databases a session has
+//! attached are cached under their own paths and added to the catalog
+//! handed out for that session. The planner's b-tree sizes are cached alongside, at
+//! the schema version they were counted at.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::table::SchemaExtractor;
+use super::SchemaCatalog;
+use crate::engine::storage::binary::BinaryPageReader;
+
+static CATALOGS: OnceLock<Mutex<HashMap<PathBuf, Arc<SchemaCatalog>>>> = OnceLock::new();
+static TREE_SIZES: OnceLock<Mutex<HashMap<PathBuf, TreeSizes>>> = OnceLock::new();
+
+/// (total pages, leaf cells) of each b-tree, keyed by root page
+struct TreeSizes {
+    version: u32,
+    trees: HashMap<u32, (usize, usize)>,
+}
+
+fn catalogs() -> &'static Mutex<HashMap<PathBuf, Arc<SchemaCatalog>>> {
+    CATALOGS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn tree_sizes() -> &'static Mutex<HashMap<PathBuf, TreeSizes>> {
+    TREE_SIZES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn cache_key(db_path: &str) -> PathBuf {
+    Path::new(db_path)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(db_path))
+}
+
+/// Reads the schema cookie (header offset 40), honouring any committed WAL frames
+pub fn read_schema_cookie(db_path: &str) -> Result<u32> {
+    if std::fs::metadata(db_path)?.len() == 0 {
+        return Ok(0);
+    }
+
+    let reader = BinaryPageReader::new(db_path.to_string());
+    reader.read_header()?;
+    Ok(reader.get_schema_cookie())
+}
+
+/// Returns the catalog for a database, loading it on first use and reloading
+/// it whenever the schema cookie has moved since it was cached
+pub fn get_catalog(db_path: &str) -> Result<Arc<SchemaCatalog>> {
+    load_catalog(db_path)
+}
+
+/// Like `get_catalog`, adding the databases attached in a session and its
+/// temp schema when it holds any objects. The temp schema is read from the
+/// session's connection each time, since it is small and changes without
+/// touching the file.
+pub fn get_session_catalog(db_path: &str, session: Option<&str>) -> Result<Arc<SchemaCatalog>> {
+    let catalog = get_catalog(db_path)?;
+
+    let attachments = super::attach::attachments(session);
+    let temp = match session {
+        Some(session) => super::temp::catalog(session)?,
+        None => None,
+    };
+    if attachments.is_empty() && temp.is_none() {
+        return Ok(catalog);
+    }
+
+    let mut combined = catalog.as_ref().clone();
+    for attachment in attachments {
+        combined.attach(&attachment.alias, &attachment.path, load_catalog(&attachment.path)?);
+    }
+    if let Some(temp) = temp {
+        combined.set_temp(temp);
+    }
+    Ok(Arc::new(combined))
+}
+
+/// The catalog of one database file, without its attachments
+fn load_catalog(db_path: &str) -> Result<Arc<SchemaCatalog>> {
+    let key = cache_key(db_path);
+    let cookie = read_schema_cookie(db_path)?;
+
+    if let Some(catalog) = catalogs().lock().unwrap().get(&key) {
+        if catalog.version() == cookie {
+            println!("[SCHEMA] Catalog cache hit (schema version {})", cookie);
+            return Ok(Arc::clone(catalog));
+        }
+        println!(
+            "[SCHEMA] Schema cookie changed ({} -> {}), reloading catalog",
+            catalog.version(),
+            cookie
+        );
+    }
+
+    let catalog = Arc::new(
+        SchemaExtractor::new(db_path)?
+            .initialize_catalog()?
+            .scan_master_table()?
+            .build_catalog()?,
+    );
+
+    catalogs()
+        .lock()
+        .unwrap()
+        .insert(key, Arc::clone(&catalog));
+
+    Ok(catalog)
+}
+
+/// Returns the (total pages, leaf cells) of the b-tree rooted at
+/// `root_page`, calling `count` only when no size was cached at schema
+/// version `version`. Sizes are estimates for the planner, so writes that
+/// leave the schema cookie alone do not refresh them.
+pub fn get_tree_size(
+    db_path: &str,
+    version: u32,
+    root_page: u32,
+    count: impl FnOnce() -> Result<(usize, usize)>,
+) -> Result<(usize, usize)> {
+    let key = cache_key(db_path);
+
+    if let Some(sizes) = tree_sizes().lock().unwrap().get(&key) {
+        if sizes.version == version {
+            if let Some(size) = sizes.trees.get(&root_page) {
+                return Ok(*size);
+            }
+        }
+    }
+
+    let size = count()?;
+
+    let mut cache = tree_sizes().lock().unwrap();
+    let sizes = cache.entry(key).or_insert_with(|| TreeSizes { version, trees: HashMap::new() });
+    if sizes.version != version {
+        println!("[SCHEMA] Schema version changed ({} -> {}), dropping cached b-tree sizes", sizes.version, version);
+        sizes.version = version;
+        sizes.trees.clear();
+    }
+    sizes.trees.insert(root_page, size);
+
+    Ok(size)
+}
+
+/// Drops the cached catalog and b-tree sizes for a database
+pub fn invalidate(db_path: &str) {
+    let key = cache_key(db_path);
+    catalogs().lock().unwrap().remove(&key);
+    tree_sizes().lock().unwrap().remove(&key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_sizes_are_counted_once_per_schema_version() {
+        let db_path = "whatql-tree-size-test.db";
+        let counted = std::cell::Cell::new(0);
+        let count = |size| {
+            counted.set(counted.get() + 1);
+            Ok(size)
+        };
+
+        assert_eq!(get_tree_size(db_path, 1, 2, || count((3, 40))).unwrap(), (3, 40));
+        assert_eq!(get_tree_size(db_path, 1, 2, || count((9, 90))).unwrap(), (3, 40));
+        assert_eq!(get_tree_size(db_path, 2, 2, || count((9, 90))).unwrap(), (9, 90));
+        assert_eq!(counted.get(), 2);
+
+        invalidate(db_path);
+        assert_eq!(get_tree_size(db_path, 2, 2, || count((5, 50))).unwrap(), (5, 50));
+        assert_eq!(counted.get(), 3);
+    }
+}
@@ -13,6 +13,30 @@ pub enum ColumnAffinity {
     None,
 }
 
+impl ColumnAffinity {
+    /// Affinity of a declared type such as `VARCHAR(255)`, by SQLite's rules:
+    /// https://www.sqlite.org/datatype3.html#affinity
+    pub fn of_declared_type(data_type: &str) -> Self {
+        let upper_type = data_type.to_uppercase();
+        
+        if upper_type.contains("INT") {
+            ColumnAffinity::Integer
+        } else if upper_type.contains("CHAR") || 
+                  upper_type.contains("CLOB") || 
+                  upper_type.contains("TEXT") {
+            ColumnAffinity::Text
+        } else if upper_type.contains("BLOB") || data_type.is_empty() {
+            ColumnAffinity::Blob
+        } else if upper_type.contains("REAL") || 
+                  upper_type.contains("FLOA") || 
+                  upper_type.contains("DOUB") {
+            ColumnAffinity::Real
+        } else {
+            ColumnAffinity::Numeric
+        }
+    }
+}
+
 impl fmt::Display for ColumnAffinity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -26,16 +50,87 @@ impl fmt::Display for ColumnAffinity {
     }
 }
 
+/// Column types allowed in a STRICT table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrictType {
+    Int,
+    Integer,
+    Real,
+    Text,
+    Blob,
+    /// Accepts any value and stores it unconverted
+    Any,
+}
+
+impl StrictType {
+    /// Type of a declared column type, or `None` when a STRICT table cannot use it
+    pub fn from_declared(data_type: &str) -> Option<Self> {
+        match data_type.trim().to_uppercase().as_str() {
+            "INT" => Some(StrictType::Int),
+            "INTEGER" => Some(StrictType::Integer),
+            "REAL" => Some(StrictType::Real),
+            "TEXT" => Some(StrictType::Text),
+            "BLOB" => Some(StrictType::Blob),
+            "ANY" => Some(StrictType::Any),
+            _ => None,
+        }
+    }
+
+    /// Affinity values are converted with before their type is checked
+    pub fn affinity(&self) -> ColumnAffinity {
+        match self {
+            StrictType::Int | StrictType::Integer => ColumnAffinity::Integer,
+            StrictType::Real => ColumnAffinity::Real,
+            StrictType::Text => ColumnAffinity::Text,
+            StrictType::Blob | StrictType::Any => ColumnAffinity::None,
+        }
+    }
+}
+
+impl fmt::Display for StrictType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrictType::Int => write!(f, "INT"),
+            StrictType::Integer => write!(f, "INTEGER"),
+            StrictType::Real => write!(f, "REAL"),
+            StrictType::Text => write!(f, "TEXT"),
+            StrictType::Blob => write!(f, "BLOB"),
+            StrictType::Any => write!(f, "ANY"),
+        }
+    }
+}
+
+/// How a generated column's value is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeneratedKind {
+    /// Computed whenever the row is read; the record has no slot for it
+    Virtual,
+    /// Computed when the row is written and stored in the record
+    Stored,
+}
+
+impl fmt::Display for GeneratedKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeneratedKind::Virtual => write!(f, "VIRTUAL"),
+            GeneratedKind::Stored => write!(f, "STORED"),
+        }
+    }
+}
+
 /// Constraint types for columns
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConstraintType {
     NotNull,
     PrimaryKey,
     Unique,
+    /// `column` is empty when the parent's primary key is implied
     ForeignKey { table: String, column: String },
     Check { expression: String },
     Default { value: String },
     Collate { collation: String },
+    /// `GENERATED ALWAYS AS (expression) VIRTUAL|STORED`
+    Generated { expression: String, kind: GeneratedKind },
 }
 
 /// Schema information for a database column
@@ -47,33 +142,49 @@ pub struct ColumnSchema {
     pub is_nullable: bool,
     pub default_value: Option<String>,
     pub is_primary_key: bool,
+    pub constraints: Vec<ConstraintType>,
 }
 
 impl ColumnSchema {
     /// Determine column affinity based on data type
     pub fn get_affinity(&self) -> ColumnAffinity {
-        // Implementation of SQLite affinity rules:
-        // https://www.sqlite.org/datatype3.html#affinity
-        
-        let upper_type = self.data_type.to_uppercase();
-        
-        if upper_type.contains("INT") {
-            ColumnAffinity::Integer
-        } else if upper_type.contains("CHAR") || 
-                  upper_type.contains("CLOB") || 
-                  upper_type.contains("TEXT") {
-            ColumnAffinity::Text
-        } else if upper_type.contains("BLOB") || self.data_type.is_empty() {
-            ColumnAffinity::Blob
-        } else if upper_type.contains("REAL") || 
-                  upper_type.contains("FLOA") || 
-                  upper_type.contains("DOUB") {
-            ColumnAffinity::Real
-        } else {
-            ColumnAffinity::Numeric
-        }
+        ColumnAffinity::of_declared_type(&self.data_type)
     }
     
+    /// Collation declared with COLLATE in the column definition
+    pub fn collation(&self) -> Option<&str> {
+        self.constraints.iter().find_map(|constraint| match constraint {
+            ConstraintType::Collate { collation } => Some(collation.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Type the column has in a STRICT table
+    pub fn strict_type(&self) -> Option<StrictType> {
+        StrictType::from_declared(&self.data_type)
+    }
+
+    /// Affinity values take when stored in the column. In a STRICT table it
+    /// comes from the strict type, so ANY columns convert nothing.
+    pub fn storage_affinity(&self, strict: bool) -> ColumnAffinity {
+        match self.strict_type() {
+            Some(strict_type) if strict => strict_type.affinity(),
+            _ => self.get_affinity(),
+        }
+    }
+
+    /// Expression and kind of a generated column
+    pub fn generated(&self) -> Option<(&str, GeneratedKind)> {
+        self.constraints.iter().find_map(|constraint| match constraint {
+            ConstraintType::Generated { expression, kind } => Some((expression.as_str(), *kind)),
+            _ => None,
+        })
+    }
+
+    pub fn is_generated(&self) -> bool {
+        self.generated().is_some()
+    }
+
     /// Calculate storage requirements for this column type
     pub fn estimate_storage_size(&self) -> usize {
         match self.get_affinity() {
@@ -108,6 +219,10 @@ impl ColumnSchema {
         if let Some(default) = &self.default_value {
             sql.push_str(&format!(" DEFAULT {}", default));
         }
+
+        if let Some((expression, kind)) = self.generated() {
+            sql.push_str(&format!(" GENERATED ALWAYS AS ({}) {}", expression, kind));
+        }
         
         sql
     }
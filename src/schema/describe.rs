@@ -0,0 +1,314 @@
+//! Structured and textual descriptions of a schema catalog
+//!
+//! Backs the `.schema` and `.indexes` shell commands and the schema
+//! endpoint of the REST API.
+
+use serde::Serialize;
+
+use super::foreign_key::ForeignKey;
+use super::index::SortOrder;
+use super::trigger::TriggerEvent;
+use super::SchemaCatalog;
+
+/// Everything a client needs to render forms for a database
+#[derive(Debug, Serialize)]
+pub struct SchemaDescription {
+    pub version: u32,
+    pub tables: Vec<TableDescription>,
+    pub views: Vec<ViewDescription>,
+    pub triggers: Vec<TriggerDescription>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableDescription {
+    pub name: String,
+    pub without_rowid: bool,
+    pub strict: bool,
+    pub columns: Vec<ColumnDescription>,
+    pub indexes: Vec<IndexDescription>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub sql: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnDescription {
+    pub name: String,
+    pub position: usize,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    pub affinity: String,
+    pub nullable: bool,
+    pub default: Option<String>,
+    pub primary_key: bool,
+    pub generated: Option<GeneratedDescription>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeneratedDescription {
+    pub expression: String,
+    /// "VIRTUAL" or "STORED"
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexDescription {
+    pub name: String,
+    pub unique: bool,
+    /// Created by SQLite for a UNIQUE or PRIMARY KEY constraint
+    pub automatic: bool,
+    pub columns: Vec<IndexColumnDescription>,
+    pub sql: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexColumnDescription {
+    pub name: String,
+    pub descending: bool,
+    pub collation: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViewDescription {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub sql: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TriggerDescription {
+    pub name: String,
+    pub table: String,
+    pub timing: String,
+    pub event: String,
+    /// Columns of an `UPDATE OF` trigger, empty for any update
+    pub update_columns: Vec<String>,
+    pub when: Option<String>,
+    pub sql: String,
+}
+
+impl SchemaDescription {
+    /// Describes every user object in the catalog, sorted by name
+    pub fn from_catalog(catalog: &SchemaCatalog) -> Self {
+        let graph = catalog.foreign_key_graph();
+
+        let mut tables: Vec<TableDescription> = catalog
+            .get_tables()
+            .into_iter()
+            .filter(|table| !is_internal(&table.name))
+            .map(|table| {
+                let mut indexes: Vec<IndexDescription> = catalog
+                    .get_indexes_for_table(&table.name)
+                    .into_iter()
+                    .map(|index| IndexDescription {
+                        name: index.name.clone(),
+                        unique: index.is_unique,
+                        automatic: index.sql.is_empty(),
+                        columns: index
+                            .columns
+                            .iter()
+                            .map(|column| IndexColumnDescription {
+                                name: column.name.clone(),
+                                descending: column.sort_order == SortOrder::Descending,
+                                collation: column.collation.clone(),
+                            })
+                            .collect(),
+                        sql: if index.sql.is_empty() { None } else { Some(index.sql.clone()) },
+                    })
+                    .collect();
+                indexes.sort_by(|a, b| a.name.cmp(&b.name));
+
+                TableDescription {
+                    name: table.name.clone(),
+                    without_rowid: table.is_without_rowid(),
+                    strict: table.is_strict(),
+                    columns: table
+                        .columns
+                        .iter()
+                        .map(|column| ColumnDescription {
+                            name: column.name.clone(),
+                            position: column.position,
+                            data_type: column.data_type.clone(),
+                            affinity: column.storage_affinity(table.is_strict()).to_string(),
+                            nullable: column.is_nullable,
+                            default: column.default_value.clone(),
+                            primary_key: column.is_primary_key,
+                            generated: column.generated().map(|(expression, kind)| GeneratedDescription {
+                                expression: expression.to_string(),
+                                kind: kind.to_string(),
+                            }),
+                        })
+                        .collect(),
+                    indexes,
+                    foreign_keys: graph.references_from(&table.name).into_iter().cloned().collect(),
+                    sql: table.sql.clone(),
+                }
+            })
+            .collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut views: Vec<ViewDescription> = catalog
+            .get_views()
+            .into_iter()
+            .map(|view| ViewDescription {
+                name: view.name.clone(),
+                columns: view.column_names(catalog),
+                sql: view.sql.clone(),
+            })
+            .collect();
+        views.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut triggers: Vec<TriggerDescription> = catalog
+            .get_triggers()
+            .into_iter()
+            .map(|trigger| TriggerDescription {
+                name: trigger.name.clone(),
+                table: trigger.table_name.clone(),
+                timing: trigger.timing.to_string(),
+                event: match &trigger.event {
+                    TriggerEvent::Update { .. } => "UPDATE".to_string(),
+                    event => event.to_string(),
+                },
+                update_columns: match &trigger.event {
+                    TriggerEvent::Update { columns } => columns.clone(),
+                    _ => Vec::new(),
+                },
+                when: trigger.when_clause.clone(),
+                sql: trigger.sql.clone(),
+            })
+            .collect();
+        triggers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        SchemaDescription {
+            version: catalog.version(),
+            tables,
+            views,
+            triggers,
+        }
+    }
+}
+
+/// CREATE statements of the objects whose name or table matches a LIKE
+/// pattern, in creation order as the sqlite3 shell prints them
+pub fn schema_statements(catalog: &SchemaCatalog, pattern: Option<&str>) -> Vec<String> {
+    catalog
+        .get_master_records()
+        .iter()
+        .filter(|record| !super::migration::is_ledger(&record.tbl_name))
+        .filter(|record| {
+            pattern.map_or(true, |pattern| like(pattern, &record.name) || like(pattern, &record.tbl_name))
+        })
+        .filter_map(|record| {
+            let sql = record.sql.as_deref()?;
+
+            // Views carry a comment naming the columns they produce
+            let columns = match catalog.find_view(&record.name) {
+                Some(view) if record.object_type == "view" => {
+                    format!("\n/* {}({}) */", record.name, view.column_names(catalog).join(","))
+                }
+                _ => String::new(),
+            };
+
+            Some(format!("{}{};", sql, columns))
+        })
+        .collect()
+}
+
+/// Index names, optionally limited to tables matching a LIKE pattern
+pub fn index_names(catalog: &SchemaCatalog, table_pattern: Option<&str>) -> Vec<String> {
+    let mut names: Vec<String> = catalog
+        .get_indexes()
+        .into_iter()
+        .filter(|index| !super::migration::is_ledger(&index.table_name))
+        .filter(|index| table_pattern.map_or(true, |pattern| like(pattern, &index.table_name)))
+        .map(|index| index.name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// SQLite's own tables and the migration ledger
+fn is_internal(name: &str) -> bool {
+    name.to_ascii_lowercase().starts_with("sqlite_") || super::migration::is_ledger(name)
+}
+
+/// SQL LIKE with `%` and `_` wildcards, case-insensitive for ASCII
+pub fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let text: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+
+    // Positions in the pattern reachable after consuming each text prefix
+    let mut states = vec![false; pattern.len() + 1];
+    states[0] = true;
+    for i in 0..pattern.len() {
+        if pattern[i] == '%' && states[i] {
+            states[i + 1] = true;
+        }
+    }
+
+    for c in text {
+        let mut next = vec![false; pattern.len() + 1];
+        for i in 0..pattern.len() {
+            if !states[i] {
+                continue;
+            }
+            match pattern[i] {
+                '%' => next[i] = true,
+                '_' => next[i + 1] = true,
+                p if p == c => next[i + 1] = true,
+                _ => {}
+            }
+        }
+        for i in 0..pattern.len() {
+            if pattern[i] == '%' && next[i] {
+                next[i + 1] = true;
+            }
+        }
+        states = next;
+    }
+
+    states[pattern.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_matches_literals_case_insensitively() {
+        assert!(like("users", "users"));
+        assert!(like("USERS", "Users"));
+        assert!(!like("users", "user"));
+        assert!(!like("user", "users"));
+        assert!(like("", ""));
+        assert!(!like("", "a"));
+    }
+
+    #[test]
+    fn like_percent_matches_any_run() {
+        assert!(like("%", ""));
+        assert!(like("%", "anything"));
+        assert!(like("user%", "users"));
+        assert!(like("user%", "user"));
+        assert!(like("%_log", "audit_log"));
+        assert!(like("a%b%c", "aXXbYYc"));
+        assert!(like("%%", "x"));
+        assert!(!like("a%c", "abcd"));
+    }
+
+    #[test]
+    fn like_underscore_matches_exactly_one_character() {
+        assert!(like("t_", "t1"));
+        assert!(!like("t_", "t"));
+        assert!(!like("t_", "t12"));
+        assert!(like("_%", "x"));
+        assert!(!like("_%", ""));
+    }
+
+    #[test]
+    fn like_handles_non_ascii_text() {
+        assert!(like("caf_", "café"));
+        assert!(like("%é", "café"));
+        // Only ASCII letters fold case, as in SQLite without ICU
+        assert!(!like("CAFÉ", "café"));
+    }
+}
@@ -0,0 +1,609 @@
+//! Schema comparison between two catalogs and migration generation
+//!
+//! The migration turns the `from` schema into the `to` schema. Column
+//! additions and removals that ALTER TABLE supports are applied in place;
+//! every other table change uses SQLite's create-copy-rename procedure
+//! (https://www.sqlite.org/lang_altertable.html#otheralter).
+
+use anyhow::Result;
+use sqlparser::ast::{ColumnDef, ColumnOption, CreateTable, Expr, GeneratedAs, Ident, Statement};
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::collections::HashSet;
+use std::fmt;
+
+use super::table::{create_table_sql, parse_create_table, TableSchema};
+use super::{SchemaCatalog, SchemaObjectType};
+
+/// How an object differs between the two schemas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeKind::Added => write!(f, "+"),
+            ChangeKind::Removed => write!(f, "-"),
+            ChangeKind::Changed => write!(f, "~"),
+        }
+    }
+}
+
+/// One differing schema object, with column-level detail for tables
+#[derive(Debug, Clone)]
+pub struct SchemaChange {
+    pub object_type: SchemaObjectType,
+    pub name: String,
+    pub kind: ChangeKind,
+    pub details: Vec<String>,
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind, self.object_type.to_string().to_lowercase(), self.name)?;
+        for detail in &self.details {
+            write!(f, "\n    {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences between two schemas plus the SQL that reconciles them
+#[derive(Debug, Clone)]
+pub struct SchemaDiff {
+    pub changes: Vec<SchemaChange>,
+    pub migration: Vec<String>,
+}
+
+impl SchemaDiff {
+    /// Compares `from` against `to`; the migration brings `from` in line with `to`
+    pub fn compare(from: &SchemaCatalog, to: &SchemaCatalog) -> Result<SchemaDiff> {
+        let mut changes = Vec::new();
+        let mut plan = MigrationPlan::default();
+
+        let from_tables = user_tables(from);
+        let to_tables = user_tables(to);
+
+        for table in &from_tables {
+            if find_by_name(&to_tables, &table.name, |t| &t.name).is_none() {
+                changes.push(change(SchemaObjectType::Table, &table.name, ChangeKind::Removed));
+                plan.drop_tables.push(format!("DROP TABLE {}", quote(&table.name)));
+            }
+        }
+
+        for table in &to_tables {
+            match find_by_name(&from_tables, &table.name, |t| &t.name) {
+                None => {
+                    changes.push(change(SchemaObjectType::Table, &table.name, ChangeKind::Added));
+                    plan.create_tables.push(table.sql.clone());
+                }
+                Some(old) => {
+                    if let Some(table_change) = compare_table(from, old, table, &mut plan)? {
+                        changes.push(table_change);
+                    }
+                }
+            }
+        }
+
+        // Tables being rebuilt lose their indexes and triggers, and views or
+        // triggers naming them must not exist while the copy is renamed
+        let rebuilt = plan.rebuilt_tables.clone();
+        let depends_on_rebuilt = |sql: &str| {
+            let words = identifier_words(sql);
+            rebuilt.iter().any(|table| words.contains(&table.to_lowercase()))
+        };
+
+        diff_objects(
+            SchemaObjectType::Index,
+            from.get_indexes().into_iter().filter(|i| !i.sql.is_empty()).map(|i| (i.name.as_str(), i.sql.as_str(), i.table_name.as_str())).collect(),
+            to.get_indexes().into_iter().filter(|i| !i.sql.is_empty()).map(|i| (i.name.as_str(), i.sql.as_str(), i.table_name.as_str())).collect(),
+            &rebuilt,
+            &|_| false,
+            &mut changes,
+            &mut plan.drop_indexes,
+            &mut plan.create_indexes,
+        );
+
+        diff_objects(
+            SchemaObjectType::View,
+            from.get_views().into_iter().map(|v| (v.name.as_str(), v.sql.as_str(), v.name.as_str())).collect(),
+            to.get_views().into_iter().map(|v| (v.name.as_str(), v.sql.as_str(), v.name.as_str())).collect(),
+            &[],
+            &depends_on_rebuilt,
+            &mut changes,
+            &mut plan.drop_views,
+            &mut plan.create_views,
+        );
+
+        diff_objects(
+            SchemaObjectType::Trigger,
+            from.get_triggers().into_iter().map(|t| (t.name.as_str(), t.sql.as_str(), t.table_name.as_str())).collect(),
+            to.get_triggers().into_iter().map(|t| (t.name.as_str(), t.sql.as_str(), t.table_name.as_str())).collect(),
+            &rebuilt,
+            &depends_on_rebuilt,
+            &mut changes,
+            &mut plan.drop_triggers,
+            &mut plan.create_triggers,
+        );
+
+        Ok(SchemaDiff {
+            changes,
+            migration: plan.into_statements(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Statements grouped by the phase they must run in
+#[derive(Default)]
+struct MigrationPlan {
+    drop_triggers: Vec<String>,
+    drop_views: Vec<String>,
+    drop_indexes: Vec<String>,
+    drop_tables: Vec<String>,
+    create_tables: Vec<String>,
+    alter_tables: Vec<String>,
+    rebuilt_tables: Vec<String>,
+    create_indexes: Vec<String>,
+    create_views: Vec<String>,
+    create_triggers: Vec<String>,
+}
+
+impl MigrationPlan {
+    fn into_statements(self) -> Vec<String> {
+        let body: Vec<String> = [
+            self.drop_triggers,
+            self.drop_views,
+            self.drop_indexes,
+            self.drop_tables,
+            self.create_tables,
+            self.alter_tables,
+            self.create_indexes,
+            self.create_views,
+            self.create_triggers,
+        ]
+        .concat();
+
+        if body.is_empty() {
+            return body;
+        }
+
+        // Rebuilding a table drops the old copy, which must not cascade or fail
+        // on foreign keys; integrity is verified once everything is in place
+        let mut statements = Vec::new();
+        if !self.rebuilt_tables.is_empty() {
+            statements.push("PRAGMA foreign_keys = OFF".to_string());
+        }
+        statements.push("BEGIN TRANSACTION".to_string());
+        statements.extend(body);
+        if !self.rebuilt_tables.is_empty() {
+            statements.push("PRAGMA foreign_key_check".to_string());
+        }
+        statements.push("COMMIT".to_string());
+        if !self.rebuilt_tables.is_empty() {
+            statements.push("PRAGMA foreign_keys = ON".to_string());
+        }
+        statements
+    }
+}
+
+/// Compares two definitions of the same table, queueing the ALTER TABLE
+/// statements or rebuild needed to move from `old` to `new`
+fn compare_table(
+    from: &SchemaCatalog,
+    old: &TableSchema,
+    new: &TableSchema,
+    plan: &mut MigrationPlan,
+) -> Result<Option<SchemaChange>> {
+    if normalize_sql(&old.sql) == normalize_sql(&new.sql) {
+        return Ok(None);
+    }
+
+    let old_create = parse_create_table(&old.sql)?;
+    let new_create = parse_create_table(&new.sql)?;
+
+    let mut details = Vec::new();
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut redefined = false;
+
+    for column in &old_create.columns {
+        if find_column(&new_create, &column.name.value).is_none() {
+            details.push(format!("- column {}", column.name.value));
+            removed.push(column);
+        }
+    }
+
+    for column in &new_create.columns {
+        match find_column(&old_create, &column.name.value) {
+            None => {
+                details.push(format!("+ column {}", column));
+                added.push(column);
+            }
+            Some(previous) if normalize_sql(&previous.to_string()) != normalize_sql(&column.to_string()) => {
+                details.push(format!("~ column {} -> {}", previous, column));
+                redefined = true;
+            }
+            Some(_) => {}
+        }
+    }
+
+    let table_options_changed = normalize_sql(&constraints_sql(&old_create)) != normalize_sql(&constraints_sql(&new_create))
+        || old_create.without_rowid != new_create.without_rowid
+        || old_create.strict != new_create.strict;
+    if table_options_changed {
+        details.push("~ table constraints or options".to_string());
+    }
+
+    // Columns the two versions share must keep their relative order, with
+    // new columns only at the end, for ALTER TABLE to produce the same table
+    let kept: Vec<String> = old_create
+        .columns
+        .iter()
+        .filter(|column| find_column(&new_create, &column.name.value).is_some())
+        .map(|column| column.name.value.to_lowercase())
+        .collect();
+    let prefix: Vec<String> = new_create
+        .columns
+        .iter()
+        .take(kept.len())
+        .map(|column| column.name.value.to_lowercase())
+        .collect();
+
+    let in_place = !redefined
+        && !table_options_changed
+        && kept == prefix
+        && added.iter().all(|column| can_add_column(column))
+        && removed.iter().all(|column| can_drop_column(from, old, &old_create, &column.name.value));
+
+    if in_place {
+        for column in removed {
+            plan.alter_tables.push(format!("ALTER TABLE {} DROP COLUMN {}", quote(&new.name), quote(&column.name.value)));
+        }
+        for column in added {
+            plan.alter_tables.push(format!("ALTER TABLE {} ADD COLUMN {}", quote(&new.name), column));
+        }
+    } else {
+        details.push("(rebuilt with create-copy-rename)".to_string());
+        plan.rebuilt_tables.push(new.name.clone());
+
+        let staging = format!("_whatql_new_{}", new.name);
+        let copied: Vec<String> = new_create
+            .columns
+            .iter()
+            .filter(|column| find_column(&old_create, &column.name.value).is_some() && !is_generated(column))
+            .map(|column| quote(&column.name.value))
+            .collect();
+
+        plan.alter_tables.push(rename_create_table(&new_create, &staging));
+        if !copied.is_empty() {
+            plan.alter_tables.push(format!(
+                "INSERT INTO {} ({cols}) SELECT {cols} FROM {}",
+                quote(&staging),
+                quote(&old.name),
+                cols = copied.join(", ")
+            ));
+        }
+        plan.alter_tables.push(format!("DROP TABLE {}", quote(&old.name)));
+        plan.alter_tables.push(format!("ALTER TABLE {} RENAME TO {}", quote(&staging), quote(&new.name)));
+    }
+
+    Ok(Some(SchemaChange {
+        object_type: SchemaObjectType::Table,
+        name: new.name.clone(),
+        kind: ChangeKind::Changed,
+        details,
+    }))
+}
+
+/// Diffs indexes, views or triggers given as (name, sql, owning table).
+/// Objects owned by a rebuilt table are recreated because the rebuild drops
+/// them; objects that merely depend on one are dropped first and recreated.
+#[allow(clippy::too_many_arguments)]
+fn diff_objects(
+    object_type: SchemaObjectType,
+    from: Vec<(&str, &str, &str)>,
+    to: Vec<(&str, &str, &str)>,
+    rebuilt: &[String],
+    depends_on_rebuilt: &dyn Fn(&str) -> bool,
+    changes: &mut Vec<SchemaChange>,
+    drops: &mut Vec<String>,
+    creates: &mut Vec<String>,
+) {
+    let keyword = object_type.to_string();
+    let owned_by_rebuilt = |table: &str| rebuilt.iter().any(|name| name.eq_ignore_ascii_case(table));
+
+    let mut from = from;
+    let mut to = to;
+    from.sort();
+    to.sort();
+
+    for (name, _, table) in &from {
+        if find_by_name(&to, name, |o| o.0).is_none() {
+            changes.push(change(object_type, name, ChangeKind::Removed));
+            if !owned_by_rebuilt(table) {
+                drops.push(format!("DROP {} {}", keyword, quote(name)));
+            }
+        }
+    }
+
+    for (name, sql, table) in &to {
+        let previous = find_by_name(&from, name, |o| o.0);
+        let differs = previous.is_some_and(|old| normalize_sql(old.1) != normalize_sql(sql));
+
+        match previous {
+            None => changes.push(change(object_type, name, ChangeKind::Added)),
+            Some(_) if differs => changes.push(change(object_type, name, ChangeKind::Changed)),
+            Some(_) => {}
+        }
+
+        let recreate_dependent = previous.is_some() && depends_on_rebuilt(sql);
+        if previous.is_some() && !owned_by_rebuilt(table) && (differs || recreate_dependent) {
+            drops.push(format!("DROP {} {}", keyword, quote(name)));
+        }
+        if previous.is_none() || differs || recreate_dependent || owned_by_rebuilt(table) {
+            creates.push(sql.to_string());
+        }
+    }
+}
+
+/// ALTER TABLE ADD COLUMN rejects keys, non-constant defaults, NOT NULL
+/// without a default and stored generated columns
+fn can_add_column(column: &ColumnDef) -> bool {
+    let mut not_null = false;
+    let mut has_default = false;
+
+    for option in &column.options {
+        match &option.option {
+            ColumnOption::Unique { .. } => return false,
+            ColumnOption::NotNull => not_null = true,
+            ColumnOption::Default(Expr::Value(sqlparser::ast::Value::Null)) => {}
+            ColumnOption::Default(Expr::Value(_)) | ColumnOption::Default(Expr::UnaryOp { .. }) => has_default = true,
+            ColumnOption::Default(_) => return false,
+            ColumnOption::Generated { generated_as: GeneratedAs::Always | GeneratedAs::ExpStored, .. }
+                if is_stored(column) =>
+            {
+                return false
+            }
+            ColumnOption::ForeignKey { .. } if has_default => return false,
+            _ => {}
+        }
+    }
+
+    !not_null || has_default
+}
+
+/// ALTER TABLE DROP COLUMN rejects key, unique, indexed and foreign key columns
+fn can_drop_column(from: &SchemaCatalog, table: &TableSchema, create: &CreateTable, column: &str) -> bool {
+    let schema = table.columns.iter().find(|c| c.name.eq_ignore_ascii_case(column));
+    if schema.is_some_and(|c| c.is_primary_key || !c.constraints.is_empty() && c.constraints.iter().any(|k| {
+        matches!(k, super::column::ConstraintType::Unique | super::column::ConstraintType::ForeignKey { .. })
+    })) {
+        return false;
+    }
+
+    let indexed = from
+        .get_indexes_for_table(&table.name)
+        .iter()
+        .any(|index| index.columns.iter().any(|c| c.name.eq_ignore_ascii_case(column)));
+
+    let in_table_constraint = identifier_words(&constraints_sql(create)).contains(&column.to_lowercase());
+
+    !indexed && !in_table_constraint
+}
+
+fn is_generated(column: &ColumnDef) -> bool {
+    column.options.iter().any(|option| matches!(option.option, ColumnOption::Generated { .. }))
+}
+
+fn is_stored(column: &ColumnDef) -> bool {
+    identifier_words(&column.to_string()).contains("stored")
+}
+
+fn find_column<'a>(create: &'a CreateTable, name: &str) -> Option<&'a ColumnDef> {
+    create.columns.iter().find(|column| column.name.value.eq_ignore_ascii_case(name))
+}
+
+fn constraints_sql(create: &CreateTable) -> String {
+    create.constraints.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn user_tables(catalog: &SchemaCatalog) -> Vec<&TableSchema> {
+    let mut tables: Vec<&TableSchema> = catalog.get_tables().into_iter().filter(|table| !table.is_system).collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    tables
+}
+
+fn find_by_name<'a, T>(items: &'a [T], name: &str, key: impl Fn(&T) -> &str) -> Option<&'a T> {
+    items.iter().find(|item| key(item).eq_ignore_ascii_case(name))
+}
+
+fn change(object_type: SchemaObjectType, name: &str, kind: ChangeKind) -> SchemaChange {
+    SchemaChange {
+        object_type,
+        name: name.to_string(),
+        kind,
+        details: Vec::new(),
+    }
+}
+
+/// Canonical token form of a statement, so formatting, comments, identifier
+/// quoting and keyword case do not register as differences
+fn normalize_sql(sql: &str) -> String {
+    let tokens = match Tokenizer::new(&SQLiteDialect {}, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return sql.split_whitespace().collect::<Vec<_>>().join(" "),
+    };
+
+    tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .map(|token| match token {
+            Token::Word(word) => word.value.to_uppercase(),
+            token => token.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lower-cased identifiers and keywords appearing in a statement
+fn identifier_words(sql: &str) -> HashSet<String> {
+    Tokenizer::new(&SQLiteDialect {}, sql)
+        .tokenize()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Word(word) => Some(word.value.to_lowercase()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Renders a CREATE TABLE statement with the last part of its name replaced,
+/// keeping any schema qualifier
+fn rename_create_table(create: &CreateTable, new_name: &str) -> String {
+    let mut create = create.clone();
+    let renamed = Ident::with_quote('"', new_name);
+    match create.name.0.last_mut() {
+        Some(leaf) => *leaf = renamed,
+        None => create.name.0.push(renamed),
+    }
+    create_table_sql(&create)
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::table::{MasterRecord, SchemaExtractor};
+
+    /// Catalog built from (type, name, table, sql) rows of sqlite_master
+    fn catalog(objects: &[(&str, &str, &str, &str)]) -> SchemaCatalog {
+        let records = objects
+            .iter()
+            .enumerate()
+            .map(|(position, (object_type, name, table, sql))| MasterRecord {
+                object_type: object_type.to_string(),
+                name: name.to_string(),
+                tbl_name: table.to_string(),
+                root_page: position as u32 + 2,
+                sql: Some(sql.to_string()),
+            })
+            .collect();
+
+        SchemaExtractor::new(":memory:")
+            .unwrap()
+            .load_master_records(records, 1, false)
+            .unwrap()
+            .build_catalog()
+            .unwrap()
+    }
+
+    #[test]
+    fn rename_create_table_replaces_only_the_name_leaf() {
+        let create = parse_create_table("CREATE TABLE main.t (id INTEGER PRIMARY KEY, t TEXT) STRICT").unwrap();
+        assert_eq!(
+            rename_create_table(&create, "_whatql_new_t"),
+            "CREATE TABLE main.\"_whatql_new_t\" (id INTEGER PRIMARY KEY, t TEXT) STRICT"
+        );
+
+        let create = parse_create_table("CREATE TABLE IF NOT EXISTS \"odd \"\"name\" (k TEXT PRIMARY KEY) WITHOUT ROWID").unwrap();
+        assert_eq!(
+            rename_create_table(&create, "a\"b"),
+            "CREATE TABLE IF NOT EXISTS \"a\"\"b\" (k TEXT PRIMARY KEY) WITHOUT ROWID"
+        );
+
+        let create = parse_create_table("CREATE TABLE t (k TEXT PRIMARY KEY) STRICT, WITHOUT ROWID").unwrap();
+        assert_eq!(
+            rename_create_table(&create, "_whatql_new_t"),
+            "CREATE TABLE \"_whatql_new_t\" (k TEXT PRIMARY KEY) WITHOUT ROWID, STRICT"
+        );
+    }
+
+    #[test]
+    fn identical_schemas_have_no_changes() {
+        let schema = [("table", "t", "t", "CREATE TABLE t (a INTEGER, b TEXT)")];
+        let diff = SchemaDiff::compare(&catalog(&schema), &catalog(&schema)).unwrap();
+        assert!(diff.is_empty());
+        assert!(diff.migration.is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_tables() {
+        let from = catalog(&[("table", "old", "old", "CREATE TABLE old (a)")]);
+        let to = catalog(&[("table", "new", "new", "CREATE TABLE new (b)")]);
+        let diff = SchemaDiff::compare(&from, &to).unwrap();
+
+        let kinds: Vec<(String, ChangeKind)> = diff.changes.iter().map(|c| (c.name.clone(), c.kind)).collect();
+        assert_eq!(kinds, vec![("old".to_string(), ChangeKind::Removed), ("new".to_string(), ChangeKind::Added)]);
+        assert_eq!(
+            diff.migration,
+            vec!["BEGIN TRANSACTION", "DROP TABLE \"old\"", "CREATE TABLE new (b)", "COMMIT"]
+        );
+    }
+
+    #[test]
+    fn appended_column_uses_alter_table() {
+        let from = catalog(&[("table", "t", "t", "CREATE TABLE t (a INTEGER)")]);
+        let to = catalog(&[("table", "t", "t", "CREATE TABLE t (a INTEGER, b TEXT DEFAULT 'x')")]);
+        let diff = SchemaDiff::compare(&from, &to).unwrap();
+
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.changes[0].kind, ChangeKind::Changed);
+        assert_eq!(
+            diff.migration,
+            vec!["BEGIN TRANSACTION", "ALTER TABLE \"t\" ADD COLUMN b TEXT DEFAULT 'x'", "COMMIT"]
+        );
+    }
+
+    #[test]
+    fn redefined_column_rebuilds_the_table_and_its_indexes() {
+        let from = catalog(&[
+            ("table", "t", "t", "CREATE TABLE main.t (a INTEGER, b TEXT)"),
+            ("index", "t_b", "t", "CREATE INDEX t_b ON t (b)"),
+        ]);
+        let to = catalog(&[
+            ("table", "t", "t", "CREATE TABLE main.t (a INTEGER NOT NULL, b TEXT)"),
+            ("index", "t_b", "t", "CREATE INDEX t_b ON t (b)"),
+        ]);
+        let diff = SchemaDiff::compare(&from, &to).unwrap();
+
+        assert_eq!(
+            diff.migration,
+            vec![
+                "PRAGMA foreign_keys = OFF",
+                "BEGIN TRANSACTION",
+                "CREATE TABLE main.\"_whatql_new_t\" (a INTEGER NOT NULL, b TEXT)",
+                "INSERT INTO \"_whatql_new_t\" (\"a\", \"b\") SELECT \"a\", \"b\" FROM \"t\"",
+                "DROP TABLE \"t\"",
+                "ALTER TABLE \"_whatql_new_t\" RENAME TO \"t\"",
+                "CREATE INDEX t_b ON t (b)",
+                "PRAGMA foreign_key_check",
+                "COMMIT",
+                "PRAGMA foreign_keys = ON",
+            ]
+        );
+    }
+
+    #[test]
+    fn migration_ledger_is_not_compared() {
+        let from = catalog(&[("table", "t", "t", "CREATE TABLE t (a)")]);
+        let to = catalog(&[
+            ("table", "t", "t", "CREATE TABLE t (a)"),
+            ("table", "_whatql_migrations", "_whatql_migrations", "CREATE TABLE _whatql_migrations (version INTEGER PRIMARY KEY)"),
+        ]);
+
+        assert!(SchemaDiff::compare(&from, &to).unwrap().is_empty());
+        assert_eq!(to.get_relation_names(), vec!["t"]);
+    }
+}
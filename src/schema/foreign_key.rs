@@ -0,0 +1,377 @@
+//! Foreign key definitions and the relationship graph between tables
+
+use serde::Serialize;
+use sqlparser::ast::{ColumnOption, ConstraintCharacteristics, CreateTable, DeferrableInitial, TableConstraint};
+use std::fmt;
+
+use super::view::object_leaf;
+use super::SchemaCatalog;
+
+/// What happens to child rows when the parent key changes or disappears
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReferentialAction {
+    NoAction,
+    Restrict,
+    SetNull,
+    SetDefault,
+    Cascade,
+}
+
+impl fmt::Display for ReferentialAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReferentialAction::NoAction => write!(f, "NO ACTION"),
+            ReferentialAction::Restrict => write!(f, "RESTRICT"),
+            ReferentialAction::SetNull => write!(f, "SET NULL"),
+            ReferentialAction::SetDefault => write!(f, "SET DEFAULT"),
+            ReferentialAction::Cascade => write!(f, "CASCADE"),
+        }
+    }
+}
+
+impl From<Option<sqlparser::ast::ReferentialAction>> for ReferentialAction {
+    fn from(action: Option<sqlparser::ast::ReferentialAction>) -> Self {
+        use sqlparser::ast::ReferentialAction as Parsed;
+
+        match action {
+            Some(Parsed::Restrict) => ReferentialAction::Restrict,
+            Some(Parsed::SetNull) => ReferentialAction::SetNull,
+            Some(Parsed::SetDefault) => ReferentialAction::SetDefault,
+            Some(Parsed::Cascade) => ReferentialAction::Cascade,
+            Some(Parsed::NoAction) | None => ReferentialAction::NoAction,
+        }
+    }
+}
+
+/// One FOREIGN KEY constraint, column-level or table-level
+#[derive(Debug, Clone, Serialize)]
+pub struct ForeignKey {
+    pub name: Option<String>,
+    /// Referencing (child) table
+    pub table: String,
+    pub columns: Vec<String>,
+    /// Referenced (parent) table
+    pub referenced_table: String,
+    /// Parent columns; empty when the constraint names only the table and so
+    /// refers to its primary key
+    pub referenced_columns: Vec<String>,
+    pub on_delete: ReferentialAction,
+    pub on_update: ReferentialAction,
+    pub deferred: bool,
+}
+
+impl fmt::Display for ForeignKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) -> {}({})",
+            self.table,
+            self.columns.join(", "),
+            self.referenced_table,
+            self.referenced_columns.join(", ")
+        )
+    }
+}
+
+impl ForeignKey {
+    /// Collects the foreign keys declared by a CREATE TABLE statement in
+    /// declaration order
+    pub fn from_definition(table: &str, create: &CreateTable) -> Vec<ForeignKey> {
+        let mut keys = Vec::new();
+
+        for column in &create.columns {
+            for option in &column.options {
+                if let ColumnOption::ForeignKey { foreign_table, referred_columns, on_delete, on_update, characteristics } =
+                    &option.option
+                {
+                    keys.push(ForeignKey {
+                        name: option.name.as_ref().map(|name| name.value.clone()),
+                        table: table.to_string(),
+                        columns: vec![column.name.value.clone()],
+                        referenced_table: object_leaf(foreign_table),
+                        referenced_columns: referred_columns.iter().map(|ident| ident.value.clone()).collect(),
+                        on_delete: (*on_delete).into(),
+                        on_update: (*on_update).into(),
+                        deferred: is_deferred(characteristics),
+                    });
+                }
+            }
+        }
+
+        for constraint in &create.constraints {
+            if let TableConstraint::ForeignKey { name, columns, foreign_table, referred_columns, on_delete, on_update, characteristics } =
+                constraint
+            {
+                keys.push(ForeignKey {
+                    name: name.as_ref().map(|name| name.value.clone()),
+                    table: table.to_string(),
+                    columns: columns.iter().map(|ident| ident.value.clone()).collect(),
+                    referenced_table: object_leaf(foreign_table),
+                    referenced_columns: referred_columns.iter().map(|ident| ident.value.clone()).collect(),
+                    on_delete: (*on_delete).into(),
+                    on_update: (*on_update).into(),
+                    deferred: is_deferred(characteristics),
+                });
+            }
+        }
+
+        keys
+    }
+}
+
+fn is_deferred(characteristics: &Option<ConstraintCharacteristics>) -> bool {
+    characteristics.as_ref().is_some_and(|c| {
+        c.deferrable == Some(true) && c.initially == Some(DeferrableInitial::Deferred)
+    })
+}
+
+/// Foreign keys of every table, with implicit parent keys resolved
+#[derive(Debug, Clone, Serialize)]
+pub struct ForeignKeyGraph {
+    pub edges: Vec<ForeignKey>,
+}
+
+impl ForeignKeyGraph {
+    pub fn build(catalog: &SchemaCatalog) -> Self {
+        let mut tables = catalog.get_tables();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut edges = Vec::new();
+        for table in tables {
+            for key in &table.foreign_keys {
+                let mut key = key.clone();
+
+                // REFERENCES parent without a column list targets parent's primary key
+                if key.referenced_columns.is_empty() {
+                    if let Some(parent) = catalog.find_table(&key.referenced_table) {
+                        key.referenced_columns = parent
+                            .columns
+                            .iter()
+                            .filter(|column| column.is_primary_key)
+                            .map(|column| column.name.clone())
+                            .collect();
+                    }
+                }
+
+                if let Some(parent) = catalog.find_table(&key.referenced_table) {
+                    key.referenced_table = parent.name.clone();
+                }
+
+                edges.push(key);
+            }
+        }
+
+        ForeignKeyGraph { edges }
+    }
+
+    /// Keys declared by `table`
+    pub fn references_from(&self, table: &str) -> Vec<&ForeignKey> {
+        self.edges.iter().filter(|key| key.table.eq_ignore_ascii_case(table)).collect()
+    }
+
+    /// Graphviz rendering with one record-shaped node per table
+    pub fn to_dot(&self, catalog: &SchemaCatalog) -> String {
+        let mut out = String::from("digraph erd {\n    rankdir=LR;\n    node [shape=record, fontname=\"Helvetica\"];\n\n");
+
+        for table in sorted_user_tables(catalog) {
+            let fields: Vec<String> = table
+                .columns
+                .iter()
+                .map(|column| {
+                    let marker = if column.is_primary_key {
+                        "PK "
+                    } else if self.references_from(&table.name).iter().any(|key| has_column(&key.columns, &column.name)) {
+                        "FK "
+                    } else {
+                        ""
+                    };
+                    format!("<{}> {}{} : {}", dot_port(&column.name), marker, dot_escape(&column.name), dot_escape(&column.data_type))
+                })
+                .collect();
+
+            out.push_str(&format!(
+                "    {} [label=\"{{{}|{}}}\"];\n",
+                dot_id(&table.name),
+                dot_escape(&table.name),
+                fields.join("|")
+            ));
+        }
+
+        if !self.edges.is_empty() {
+            out.push('\n');
+        }
+
+        for key in &self.edges {
+            let from_port = key.columns.first().map(|c| format!(":{}", dot_port(c))).unwrap_or_default();
+            let to_port = key.referenced_columns.first().map(|c| format!(":{}", dot_port(c))).unwrap_or_default();
+            out.push_str(&format!(
+                "    {}{} -> {}{} [label=\"{}\"];\n",
+                dot_id(&key.table),
+                from_port,
+                dot_id(&key.referenced_table),
+                to_port,
+                action_label(key)
+            ));
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    /// Mermaid `erDiagram` rendering
+    pub fn to_mermaid(&self, catalog: &SchemaCatalog) -> String {
+        let mut out = String::from("erDiagram\n");
+
+        for table in sorted_user_tables(catalog) {
+            out.push_str(&format!("    {} {{\n", mermaid_name(&table.name)));
+            for column in &table.columns {
+                let mut keys = Vec::new();
+                if column.is_primary_key {
+                    keys.push("PK");
+                }
+                if self.references_from(&table.name).iter().any(|key| has_column(&key.columns, &column.name)) {
+                    keys.push("FK");
+                }
+
+                let data_type = if column.data_type.is_empty() { "ANY".to_string() } else { column.data_type.replace(' ', "_") };
+                let data_type: String = data_type.chars().filter(|c| c.is_alphanumeric() || *c == '_').collect();
+                out.push_str(&format!(
+                    "        {} {}{}\n",
+                    data_type,
+                    mermaid_name(&column.name),
+                    if keys.is_empty() { String::new() } else { format!(" {}", keys.join(",")) }
+                ));
+            }
+            out.push_str("    }\n");
+        }
+
+        for key in &self.edges {
+            // Children hold zero or more rows per parent; a nullable key may have no parent
+            let nullable = catalog
+                .find_table(&key.table)
+                .map(|table| {
+                    table
+                        .columns
+                        .iter()
+                        .filter(|column| has_column(&key.columns, &column.name))
+                        .any(|column| column.is_nullable)
+                })
+                .unwrap_or(true);
+
+            out.push_str(&format!(
+                "    {} {}--o{{ {} : \"{}\"\n",
+                mermaid_name(&key.referenced_table),
+                if nullable { "|o" } else { "||" },
+                mermaid_name(&key.table),
+                key.columns.join(", ")
+            ));
+        }
+
+        out
+    }
+}
+
+fn sorted_user_tables(catalog: &SchemaCatalog) -> Vec<&super::table::TableSchema> {
+    let mut tables: Vec<_> = catalog
+        .get_tables()
+        .into_iter()
+        .filter(|table| !table.is_system)
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    tables
+}
+
+fn action_label(key: &ForeignKey) -> String {
+    let mut label = key.columns.join(", ");
+    if key.on_delete != ReferentialAction::NoAction {
+        label.push_str(&format!("\\nON DELETE {}", key.on_delete));
+    }
+    if key.on_update != ReferentialAction::NoAction {
+        label.push_str(&format!("\\nON UPDATE {}", key.on_update));
+    }
+    label
+}
+
+/// Whether `columns` names `name`; SQLite identifiers ignore ASCII case
+fn has_column(columns: &[String], name: &str) -> bool {
+    columns.iter().any(|column| column.eq_ignore_ascii_case(name))
+}
+
+/// A quoted Graphviz ID with its backslashes and double quotes escaped
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn dot_port(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn dot_escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+fn mermaid_name(name: &str) -> String {
+    if name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::table::{MasterRecord, SchemaExtractor};
+
+    fn catalog(tables: &[(&str, &str)]) -> SchemaCatalog {
+        let records = tables
+            .iter()
+            .enumerate()
+            .map(|(position, (name, sql))| MasterRecord {
+                object_type: "table".to_string(),
+                name: name.to_string(),
+                tbl_name: name.to_string(),
+                root_page: position as u32 + 2,
+                sql: Some(sql.to_string()),
+            })
+            .collect();
+
+        SchemaExtractor::new(":memory:")
+            .unwrap()
+            .load_master_records(records, 1, false)
+            .unwrap()
+            .build_catalog()
+            .unwrap()
+    }
+
+    #[test]
+    fn marks_foreign_key_columns_regardless_of_case() {
+        let catalog = catalog(&[
+            ("users", "CREATE TABLE users (id INTEGER PRIMARY KEY)"),
+            ("posts", "CREATE TABLE posts (id INTEGER PRIMARY KEY, Author_Id INTEGER, FOREIGN KEY (author_id) REFERENCES users)"),
+        ]);
+        let graph = catalog.foreign_key_graph();
+
+        assert!(graph.to_dot(&catalog).contains("<Author_Id> FK Author_Id : INTEGER"));
+        assert!(graph.to_mermaid(&catalog).contains("INTEGER Author_Id FK"));
+    }
+
+    #[test]
+    fn dot_quotes_and_escapes_node_ids() {
+        let catalog = catalog(&[
+            ("a\"b", "CREATE TABLE \"a\"\"b\" (id INTEGER PRIMARY KEY)"),
+            ("line items", "CREATE TABLE \"line items\" (id INTEGER PRIMARY KEY, owner INTEGER REFERENCES \"a\"\"b\"(id))"),
+        ]);
+        let dot = catalog.foreign_key_graph().to_dot(&catalog);
+
+        assert!(dot.contains("    \"a\\\"b\" [label="));
+        assert!(dot.contains("    \"line items\" [label="));
+        assert!(dot.contains("    \"line items\":owner -> \"a\\\"b\":id [label=\"owner\"];"));
+    }
+}
@@ -2,8 +2,11 @@
 
 use std::fmt;
 use anyhow::{Result, anyhow};
-use std::process::Command;
-use rusqlite::Connection;
+
+use crate::engine::btree::node::{BTreePageCollection, PageId};
+use crate::engine::btree::traversal::BTreeTraversal;
+use crate::engine::btree::BTreeNodeType;
+use crate::engine::storage::binary::BinaryPageReader;
 
 /// Types of indexes supported
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -54,6 +57,28 @@ pub struct IndexSchema {
     pub estimated_entries: Option<u64>,
 }
 
+impl IndexSchema {
+    /// Collation keys are ordered by: the index's own COLLATE, else the
+    /// column's declared collation, else BINARY
+    pub fn column_collation(&self, position: usize, table: Option<&super::table::TableSchema>) -> String {
+        let column = match self.columns.get(position) {
+            Some(column) => column,
+            None => return "BINARY".to_string(),
+        };
+
+        column
+            .collation
+            .clone()
+            .or_else(|| {
+                table
+                    .and_then(|table| table.columns.iter().find(|c| c.name.eq_ignore_ascii_case(&column.name)))
+                    .and_then(|c| c.collation().map(str::to_string))
+            })
+            .map(|collation| collation.to_uppercase())
+            .unwrap_or_else(|| "BINARY".to_string())
+    }
+}
+
 impl fmt::Display for IndexSchema {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Index[{}] on {} ({} columns, {})",
@@ -81,40 +106,9 @@ impl IndexManager {
     pub fn get_all_indexes(&self) -> Result<Vec<IndexSchema>> {
         println!("[INDEX] Retrieving index information from schema");
         
-        // In a real implementation, this would query the sqlite_master table
-        // for index definitions. Instead, we'll call SQLite directly.
-        
-        // Call SQLite to get index list
-        let output = Command::new("sqlite3")
-            .arg(&self.db_path)
-            .arg("SELECT name, tbl_name FROM sqlite_master WHERE type='index'")
-            .output()?;
-        
-        if !output.status.success() {
-            return Err(anyhow!("Failed to execute SQLite command"));
-        }
-        
-        // We'd normally parse this output to get index information
-        // For now, just create some dummy indexes
-        let indexes = vec![
-            IndexSchema {
-                name: "idx_example_id".to_string(),
-                table_name: "example".to_string(),
-                columns: vec![
-                    IndexColumn {
-                        name: "id".to_string(),
-                        position: 0,
-                        sort_order: SortOrder::Ascending,
-                        collation: None,
-                    }
-                ],
-                is_unique: true,
-                index_type: IndexType::BTree,
-                root_page: 3,
-                sql: "CREATE UNIQUE INDEX idx_example_id ON example(id)".to_string(),
-                estimated_entries: Some(1000),
-            }
-        ];
+        let catalog = crate::schema::cache::get_catalog(&self.db_path)?;
+        let mut indexes: Vec<IndexSchema> = catalog.get_indexes().into_iter().cloned().collect();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
         
         Ok(indexes)
     }
@@ -123,55 +117,100 @@ impl IndexManager {
     pub fn get_indexes_for_table(&self, table_name: &str) -> Result<Vec<IndexSchema>> {
         println!("[INDEX] Retrieving indexes for table: {}", table_name);
         
-        // Call SQLite to get index information for this table
-        let output = Command::new("sqlite3")
-            .arg(&self.db_path)
-            .arg(format!("SELECT name FROM sqlite_master WHERE type='index' AND tbl_name='{}'", table_name))
-            .output()?;
-        
-        if !output.status.success() {
-            return Err(anyhow!("Failed to execute SQLite command"));
-        }
-        
-        // Create some dummy index info
-        let index = IndexSchema {
-            name: format!("idx_{}_id", table_name),
-            table_name: table_name.to_string(),
-            columns: vec![
-                IndexColumn {
-                    name: "id".to_string(),
-                    position: 0,
-                    sort_order: SortOrder::Ascending,
-                    collation: None,
-                }
-            ],
-            is_unique: true,
-            index_type: IndexType::BTree,
-            root_page: 3,
-            sql: format!("CREATE UNIQUE INDEX idx_{}_id ON {}(id)", table_name, table_name),
-            estimated_entries: Some(1000),
-        };
+        let catalog = crate::schema::cache::get_catalog(&self.db_path)?;
+        let mut indexes: Vec<IndexSchema> = catalog
+            .get_indexes_for_table(table_name)
+            .into_iter()
+            .cloned()
+            .collect();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
         
-        Ok(vec![index])
+        Ok(indexes)
     }
     
     /// Analyze an index to gather statistics
     pub fn analyze_index(&self, index_name: &str) -> Result<IndexStatistics> {
         println!("[INDEX] Analyzing index structure: {}", index_name);
-        println!("[INDEX] Reading B-tree pages and calculating metrics");
-        
-        // In a real implementation, this would analyze the B-tree structure
-        // of the index to gather statistics. For now, return dummy stats.
-        
-        Ok(IndexStatistics {
-            depth: 2,
-            leaf_pages: 10,
-            internal_pages: 1,
-            total_entries: 1000,
-            average_fill_factor: 0.75,
-            average_leaf_fanout: 100,
-            average_internal_fanout: 10,
-        })
+
+        let catalog = crate::schema::cache::get_catalog(&self.db_path)?;
+        let root_page = catalog
+            .get_index(index_name)
+            .map(|index| index.root_page)
+            .ok_or_else(|| anyhow!("No such index: {}", index_name))?;
+
+        if root_page == 0 {
+            return Err(anyhow!("Index {} has no b-tree (virtual or missing root page)", index_name));
+        }
+
+        println!("[INDEX] Reading B-tree pages from root page {}", root_page);
+
+        let reader = BinaryPageReader::new(self.db_path.clone());
+        reader.read_header()?;
+        let pages = BTreePageCollection::new(reader);
+
+        let mut depth = 0;
+        let mut leaf_pages = 0;
+        let mut internal_pages = 0;
+        let mut total_entries: u64 = 0;
+        let mut leaf_cells = 0;
+        let mut internal_children = 0;
+        let mut fill_total = 0.0;
+        let mut table_page = None;
+
+        BTreeTraversal::walk(&pages, PageId(root_page as usize), |node, level| {
+            if !node.is_index_page() {
+                table_page.get_or_insert(node.page_id.0);
+            }
+
+            depth = depth.max(level);
+            fill_total += node.fill_factor();
+
+            // Interior index cells hold keys too, so every cell is an entry
+            total_entries += node.header.cell_count as u64;
+
+            if node.header.node_type == BTreeNodeType::Leaf {
+                leaf_pages += 1;
+                leaf_cells += node.header.cell_count as usize;
+            } else {
+                internal_pages += 1;
+                internal_children += node.child_pages().len();
+            }
+        })?;
+
+        if let Some(page) = table_page {
+            return Err(anyhow!(
+                "Page {} reached from {} is not an index b-tree page",
+                page,
+                index_name
+            ));
+        }
+
+        let total_pages = leaf_pages + internal_pages;
+        let stats = IndexStatistics {
+            depth,
+            leaf_pages,
+            internal_pages,
+            total_entries,
+            average_fill_factor: if total_pages > 0 { fill_total / total_pages as f64 } else { 0.0 },
+            average_leaf_fanout: if leaf_pages > 0 { (leaf_cells as f64 / leaf_pages as f64).round() as usize } else { 0 },
+            average_internal_fanout: if internal_pages > 0 {
+                (internal_children as f64 / internal_pages as f64).round() as usize
+            } else {
+                0
+            },
+        };
+
+        println!(
+            "[INDEX] {}: depth {}, {} leaf / {} interior pages, {} entries, {:.1}% full",
+            index_name,
+            stats.depth,
+            stats.leaf_pages,
+            stats.internal_pages,
+            stats.total_entries,
+            stats.average_fill_factor * 100.0
+        );
+
+        Ok(stats)
     }
 }
 
@@ -190,34 +229,68 @@ pub struct IndexStatistics {
 impl IndexStatistics {
     /// Estimate the number of page reads for a lookup
     pub fn estimate_lookup_cost(&self) -> f64 {
-        // In a balanced B-tree, lookups are O(log_f(N)) where f is fanout
-        // For a rough estimate, we can just use the depth
+        // Every level of a balanced B-tree is read exactly once on the way
+        // from the root to the leaf holding the key
         self.depth as f64
     }
     
     /// Estimate the number of page reads for a range scan
     pub fn estimate_range_scan_cost(&self, selectivity: f64) -> f64 {
         // For range scans, we need to read all leaf pages that contain the range
-        let affected_leaves = (self.leaf_pages as f64 * selectivity).ceil();
+        let selectivity = selectivity.clamp(0.0, 1.0);
+        let affected_leaves = (self.leaf_pages as f64 * selectivity).ceil().max(1.0);
         
-        // Plus the cost to find the start of the range (tree traversal)
-        self.depth as f64 + affected_leaves
+        // Plus the cost to find the start of the range (tree traversal),
+        // without counting the first leaf twice
+        (self.depth as f64 - 1.0).max(0.0) + affected_leaves
     }
 }
 
-pub fn get_table_columns(db_path: &str, table_name: &str) -> Result<Vec<String>> {
-    println!("[SCHEMA] Getting columns for table: {}", table_name);
-    
-    // Open the database
-    let connection = rusqlite::Connection::open(db_path)?;
-    
-    // Query the table schema
-    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table_name))?;
-    let column_names: Vec<String> = stmt
-        .query_map([], |row| Ok(row.get::<_, String>(1)?))? // Column 1 is the name column
-        .collect::<Result<Vec<_>, _>>()?;
-    
-    println!("[SCHEMA] Found columns: {:?}", column_names);
-    
-    Ok(column_names)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_index_walks_the_index_b_tree() {
+        let path = std::env::temp_dir().join(format!("whatql-index-stats-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_path = path.to_str().unwrap();
+        let setup = rusqlite::Connection::open(db_path).unwrap();
+        setup
+            .execute_batch(
+                "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT);
+                 CREATE INDEX people_name ON people (name);
+                 CREATE INDEX empty_name ON people (name) WHERE id < 0;
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+                 INSERT INTO people (name) SELECT printf('person-%050d', i) FROM n;",
+            )
+            .unwrap();
+        let pages: Vec<(String, i64)> = setup
+            .prepare("SELECT pagetype, count(*) FROM dbstat WHERE name = 'people_name' GROUP BY pagetype ORDER BY pagetype")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        drop(setup);
+
+        let manager = IndexManager::new(db_path);
+        let stats = manager.analyze_index("people_name").unwrap();
+        // Every key is stored once, in an interior or a leaf cell
+        assert_eq!(stats.total_entries, 2000);
+        assert_eq!(pages, vec![("internal".to_string(), stats.internal_pages as i64), ("leaf".to_string(), stats.leaf_pages as i64)]);
+        assert!(stats.depth >= 2);
+        assert!(stats.average_fill_factor > 0.5 && stats.average_fill_factor <= 1.0);
+        assert_eq!(stats.estimate_lookup_cost(), stats.depth as f64);
+        assert!(stats.estimate_range_scan_cost(1.0) >= stats.leaf_pages as f64);
+
+        // A partial index nothing matches is a single empty leaf
+        let empty = manager.analyze_index("empty_name").unwrap();
+        assert_eq!((empty.depth, empty.leaf_pages, empty.internal_pages, empty.total_entries), (1, 1, 0, 0));
+
+        assert!(manager.analyze_index("people").is_err());
+        assert!(manager.analyze_index("no_such_index").is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
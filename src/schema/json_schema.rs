@@ -0,0 +1,716 @@
+//! JSON Schema and OpenAPI documents generated from a schema catalog
+//!
+//! Each table becomes a JSON Schema (draft 2020-12) describing one row as API
+//! clients would send it: column types follow affinity (or the STRICT type),
+//! nullability and defaults follow the column definition, CHECK IN-lists
+//! become enums and declared or CHECKed lengths become length limits. The
+//! OpenAPI 3.1 document describes the `/api/v1/{dbname}` endpoints and carries
+//! the table schemas as components. Query results get a row schema from the
+//! types the semantic analyzer infers for their columns.
+
+use serde_json::{json, Map, Value};
+use sqlparser::ast::{
+    BinaryOperator, ColumnOption, Expr, FunctionArg, FunctionArgExpr, FunctionArguments, TableConstraint, UnaryOperator,
+};
+use std::collections::HashMap;
+
+use super::column::{ColumnAffinity, ColumnSchema, StrictType};
+use crate::parser::types::ResultColumn;
+use super::table::{parse_create_table, TableSchema};
+use super::SchemaCatalog;
+
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// JSON Schema of the database: one `$defs` entry per user table
+pub fn database_json_schema(database_name: &str, catalog: &SchemaCatalog) -> Value {
+    let definitions: Map<String, Value> = user_tables(catalog)
+        .into_iter()
+        .map(|table| (table.name.clone(), table_json_schema(table)))
+        .collect();
+
+    json!({
+        "$schema": JSON_SCHEMA_DIALECT,
+        "title": database_name,
+        "$defs": definitions,
+    })
+}
+
+/// JSON Schema of one row of a table
+pub fn table_json_schema(table: &TableSchema) -> Value {
+    let strict = table.is_strict();
+    let definitions = column_definitions(table);
+
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+
+    for column in &table.columns {
+        let definition = definitions.get(&column.name.to_lowercase());
+        let default = definition.and_then(|definition| definition.default.as_ref());
+
+        let mut property = column_type(column, strict);
+
+        if let Some(values) = definition.and_then(|definition| check_enum(&definition.checks, &column.name)) {
+            let mut values = values;
+            // A CHECK passes when it evaluates to NULL, so NULL stays allowed
+            if column.is_nullable && !values.contains(&Value::Null) {
+                values.push(Value::Null);
+            }
+            property.insert("enum".to_string(), Value::Array(values));
+        }
+
+        if is_string_typed(&property) {
+            let (declared_min, declared_max) = definition
+                .map(|definition| check_lengths(&definition.checks, &column.name))
+                .unwrap_or((None, None));
+            if let Some(min) = declared_min {
+                property.insert("minLength".to_string(), json!(min));
+            }
+            if let Some(max) = declared_max.or_else(|| declared_length(&column.data_type)) {
+                property.insert("maxLength".to_string(), json!(max));
+            }
+        }
+
+        if let Some(value) = default.and_then(literal) {
+            property.insert("default".to_string(), value);
+        }
+
+        if let Some((expression, kind)) = column.generated() {
+            property.insert("readOnly".to_string(), json!(true));
+            property.insert(
+                "description".to_string(),
+                json!(format!("GENERATED ALWAYS AS ({}) {}", expression, kind)),
+            );
+        }
+
+        // SQLite fills in defaults, generated values and rowid aliases itself
+        if !column.is_nullable && default.is_none() && !column.is_generated() && !table.is_rowid_alias(column) {
+            required.push(json!(column.name));
+        }
+
+        properties.insert(column.name.clone(), Value::Object(property));
+    }
+
+    json!({
+        "title": table.name,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// JSON Schema of one row of a query result. Columns of unknown type get no
+/// `type`; those that can be NULL allow it.
+pub fn result_row_schema(columns: &[ResultColumn]) -> Value {
+    let mut properties = Map::new();
+    for column in columns {
+        let mut property = Map::new();
+        if let Some(json_type) = column.value_type.json_type() {
+            let json_type = if column.nullable && json_type != "null" { json!([json_type, "null"]) } else { json!(json_type) };
+            property.insert("type".to_string(), json_type);
+        }
+        property.insert("description".to_string(), json!(column.value_type.to_string()));
+        properties.insert(column.name.clone(), Value::Object(property));
+    }
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": columns.iter().map(|column| column.name.clone()).collect::<Vec<_>>(),
+    })
+}
+
+/// OpenAPI 3.1 document for the `/api/v1/{dbname}` endpoints of a database,
+/// with its tables as component schemas
+pub fn openapi_document(database_name: &str, catalog: &SchemaCatalog) -> Value {
+    let mut schemas = Map::new();
+    schemas.insert("QueryRequest".to_string(), query_request_schema());
+    schemas.insert("QueryResponse".to_string(), query_response_schema());
+    schemas.insert("StatementResult".to_string(), statement_result_schema());
+    schemas.insert("Diagnostic".to_string(), diagnostic_schema());
+    schemas.insert("Response".to_string(), response_schema());
+    for table in user_tables(catalog) {
+        schemas.insert(component_name(&table.name), table_json_schema(table));
+    }
+
+    let database = json!({
+        "name": "dbname",
+        "in": "path",
+        "required": true,
+        "description": "Database file the request operates on",
+        "schema": { "type": "string", "example": database_name },
+    });
+    let failure = json!({
+        "description": "Request failed",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Response" } } },
+    });
+    let not_found = json!({
+        "description": "Database does not exist",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Response" } } },
+    });
+    let document = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "type": "object" } } },
+        })
+    };
+
+    json!({
+        "openapi": "3.1.0",
+        "jsonSchemaDialect": JSON_SCHEMA_DIALECT,
+        "info": {
+            "title": format!("WhatQL API for {}", database_name),
+            "version": "1.0.0",
+        },
+        "servers": [{ "url": "http://127.0.0.1:8080" }],
+        "paths": {
+            "/api/v1/{dbname}": {
+                "parameters": [database],
+                "post": {
+                    "summary": "Execute an SQL statement",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/QueryRequest" } } },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "Statement executed",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/QueryResponse" } } },
+                        },
+                        "400": failure,
+                        "500": failure,
+                    },
+                },
+                "get": {
+                    "summary": "Database metadata, creating the database when it does not exist",
+                    "responses": { "200": document("Database metadata"), "500": failure },
+                },
+            },
+            "/api/v1/{dbname}/schema": {
+                "parameters": [database],
+                "get": {
+                    "summary": "Tables, columns, indexes, views and triggers",
+                    "responses": { "200": document("Schema description"), "404": not_found, "500": failure },
+                },
+            },
+            "/api/v1/{dbname}/erd": {
+                "parameters": [database],
+                "get": {
+                    "summary": "Entity-relationship diagram",
+                    "parameters": [{
+                        "name": "format",
+                        "in": "query",
+                        "required": false,
+                        "schema": { "type": "string", "enum": ["dot", "mermaid"], "default": "dot" },
+                    }],
+                    "responses": { "200": document("Diagram source"), "400": failure, "404": not_found, "500": failure },
+                },
+            },
+            "/api/v1/{dbname}/sessions": {
+                "parameters": [database],
+                "post": {
+                    "summary": "Open a session keeping TEMP objects and attachments between queries",
+                    "responses": { "200": document("Session opened"), "404": not_found, "500": failure },
+                },
+            },
+            "/api/v1/{dbname}/sessions/{session_id}": {
+                "parameters": [database, {
+                    "name": "session_id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }],
+                "delete": {
+                    "summary": "End a session, dropping its temp schema and attachments",
+                    "responses": { "200": document("Session ended"), "404": not_found },
+                },
+            },
+            "/api/v1/{dbname}/json-schema": {
+                "parameters": [database],
+                "get": {
+                    "summary": "JSON Schema of every table",
+                    "responses": { "200": document("JSON Schema with one $defs entry per table"), "404": not_found, "500": failure },
+                },
+            },
+            "/api/v1/{dbname}/openapi.json": {
+                "parameters": [database],
+                "get": {
+                    "summary": "This document",
+                    "responses": { "200": document("OpenAPI document"), "404": not_found, "500": failure },
+                },
+            },
+        },
+        "components": { "schemas": schemas },
+    })
+}
+
+fn query_request_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "query": { "type": "string", "description": "SQL to execute" },
+            "session_id": {
+                "type": ["string", "null"],
+                "description": "Session whose temp schema the query runs in",
+            },
+            "params": {
+                "type": ["array", "object", "null"],
+                "description": "Values for the query's parameters: an array binds ? and ?NNN by position, an object binds :name, @name and $name by name",
+                "items": { "type": ["string", "number", "boolean", "null"] },
+                "additionalProperties": { "type": ["string", "number", "boolean", "null"] },
+            },
+            "on_error": {
+                "enum": ["stop", "continue"],
+                "default": "stop",
+                "description": "Whether a failing statement stops the script or the statements after it still run",
+            },
+        },
+        "required": ["query"],
+    })
+}
+
+fn query_response_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "success": { "type": "boolean" },
+            "message": { "type": "string" },
+            "execution_time_ms": { "type": "integer" },
+            "rows_affected": { "type": "integer" },
+            "results": { "type": ["array", "null"], "items": { "type": "object" } },
+            "metadata": {
+                "type": ["object", "null"],
+                "properties": {
+                    "columns_referenced": { "type": "array", "items": { "type": "string" }, "description": "Columns the statement refers to, with wildcards expanded" },
+                    "result_columns": result_columns_schema(),
+                    "result_schema": { "type": "object", "description": "JSON Schema of one row of results" },
+                    "parsing_time_ms": { "type": "integer" },
+                    "planning_time_ms": { "type": "integer" },
+                    "execution_time_ms": { "type": "integer" },
+                },
+            },
+            "diagnostics": { "type": "array", "items": { "$ref": "#/components/schemas/Diagnostic" } },
+            "statements": {
+                "type": "array",
+                "description": "Each statement of the script that was run, in order; results and metadata describe the last one that returned rows",
+                "items": { "$ref": "#/components/schemas/StatementResult" },
+            },
+        },
+        "required": ["success", "message"],
+    })
+}
+
+/// What one statement of a script did, as `QueryResponse` lists it
+fn statement_result_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "sql": { "type": "string" },
+            "query_type": { "type": ["string", "null"], "description": "Null for text that did not parse" },
+            "success": { "type": "boolean" },
+            "columns": { "type": "array", "items": { "type": "string" } },
+            "rows": { "type": "array", "items": { "type": "object" } },
+            "changes": { "type": "integer", "description": "Rows an INSERT, UPDATE or DELETE changed" },
+            "result_columns": result_columns_schema(),
+            "validation_time_ms": { "type": "integer" },
+            "planning_time_ms": { "type": "integer" },
+            "execution_time_ms": { "type": "integer" },
+            "error": { "type": ["string", "null"] },
+            "diagnostics": { "type": "array", "items": { "$ref": "#/components/schemas/Diagnostic" } },
+        },
+        "required": ["sql", "success", "columns", "rows", "changes"],
+    })
+}
+
+fn result_columns_schema() -> Value {
+    json!({
+        "type": "array",
+        "description": "Inferred type of each result column",
+        "items": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "type": { "enum": ["integer", "real", "numeric", "text", "blob", "null", "any"] },
+                "nullable": { "type": "boolean" },
+            },
+        },
+    })
+}
+
+/// An error or warning about query text, as `QueryResponse` reports it
+fn diagnostic_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "code": { "type": "string", "description": "Stable code such as E1002" },
+            "severity": { "enum": ["error", "warning"] },
+            "message": { "type": "string" },
+            "span": {
+                "type": "object",
+                "properties": {
+                    "offset": { "type": "integer", "description": "Byte offset into the query" },
+                    "length": { "type": "integer", "description": "Length in bytes" },
+                    "line": { "type": "integer" },
+                    "column": { "type": "integer" },
+                },
+            },
+            "snippet": { "type": "string", "description": "Source line with carets under the span" },
+        },
+        "required": ["code", "severity", "message", "span", "snippet"],
+    })
+}
+
+/// Fields every endpoint's response has
+fn response_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "success": { "type": "boolean" },
+            "message": { "type": "string" },
+            "database_name": { "type": "string" },
+        },
+        "required": ["success", "message"],
+    })
+}
+
+/// Tables an API client can read and write, sorted by name
+fn user_tables(catalog: &SchemaCatalog) -> Vec<&TableSchema> {
+    let mut tables: Vec<&TableSchema> = catalog
+        .get_tables()
+        .into_iter()
+        .filter(|table| !table.is_system && !table.is_virtual)
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    tables
+}
+
+/// OpenAPI component names allow only letters, digits, `.`, `-` and `_`
+fn component_name(table_name: &str) -> String {
+    let name: String = table_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    format!("table.{}", name)
+}
+
+/// CHECK expressions that may constrain a column and its DEFAULT, from the
+/// table's CREATE statement
+#[derive(Default)]
+struct ColumnDefinition {
+    checks: Vec<Expr>,
+    default: Option<Expr>,
+}
+
+fn column_definitions(table: &TableSchema) -> HashMap<String, ColumnDefinition> {
+    let mut definitions = HashMap::new();
+    let Ok(create) = parse_create_table(&table.sql) else {
+        return definitions;
+    };
+
+    let table_checks: Vec<Expr> = create
+        .constraints
+        .iter()
+        .filter_map(|constraint| match constraint {
+            TableConstraint::Check { expr, .. } => Some((**expr).clone()),
+            _ => None,
+        })
+        .collect();
+
+    for column in &create.columns {
+        let mut definition = ColumnDefinition {
+            checks: table_checks.clone(),
+            default: None,
+        };
+        for option in &column.options {
+            match &option.option {
+                ColumnOption::Check(expr) => definition.checks.push(expr.clone()),
+                ColumnOption::Default(expr) => definition.default = Some(expr.clone()),
+                _ => {}
+            }
+        }
+        definitions.insert(column.name.value.to_lowercase(), definition);
+    }
+
+    definitions
+}
+
+/// `type` (and `contentEncoding` for blobs) of a column. NULL is added to the
+/// type of nullable columns; columns that take any value get no type.
+fn column_type(column: &ColumnSchema, strict: bool) -> Map<String, Value> {
+    let json_type = match column.strict_type() {
+        Some(strict_type) if strict => match strict_type {
+            StrictType::Int | StrictType::Integer => Some("integer"),
+            StrictType::Real => Some("number"),
+            StrictType::Text => Some("string"),
+            StrictType::Blob => Some("blob"),
+            StrictType::Any => None,
+        },
+        _ => match column.get_affinity() {
+            ColumnAffinity::Integer => Some("integer"),
+            ColumnAffinity::Real | ColumnAffinity::Numeric => Some("number"),
+            ColumnAffinity::Text => Some("string"),
+            // A column without a declared type has BLOB affinity but holds anything
+            ColumnAffinity::Blob if !column.data_type.trim().is_empty() => Some("blob"),
+            ColumnAffinity::Blob | ColumnAffinity::None => None,
+        },
+    };
+
+    let mut property = Map::new();
+    if let Some(json_type) = json_type {
+        // Blobs travel as hex, the form SQL blob literals take
+        let json_type = if json_type == "blob" {
+            property.insert("contentEncoding".to_string(), json!("base16"));
+            "string"
+        } else {
+            json_type
+        };
+        let json_type = if column.is_nullable { json!([json_type, "null"]) } else { json!(json_type) };
+        property.insert("type".to_string(), json_type);
+    }
+    property
+}
+
+fn is_string_typed(property: &Map<String, Value>) -> bool {
+    match property.get("type") {
+        Some(Value::String(json_type)) => json_type == "string",
+        Some(Value::Array(types)) => types.contains(&json!("string")),
+        _ => false,
+    }
+}
+
+/// Length in a declared type such as `VARCHAR(255)`. SQLite does not enforce
+/// it, but it is the length the schema's author meant.
+fn declared_length(data_type: &str) -> Option<u64> {
+    let open = data_type.find('(')?;
+    let close = data_type[open..].find(')')? + open;
+    data_type[open + 1..close].split(',').next()?.trim().parse().ok()
+}
+
+/// The AND-ed parts of a CHECK expression
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Nested(inner) => conjuncts(inner),
+        Expr::BinaryOp { left, op: BinaryOperator::And, right } => {
+            let mut parts = conjuncts(left);
+            parts.extend(conjuncts(right));
+            parts
+        }
+        expr => vec![expr],
+    }
+}
+
+fn is_column(expr: &Expr, column: &str) -> bool {
+    match expr {
+        Expr::Nested(inner) => is_column(inner, column),
+        Expr::Identifier(ident) => ident.value.eq_ignore_ascii_case(column),
+        _ => false,
+    }
+}
+
+/// Values of the first `column IN (...)` list of literals among the CHECKs
+fn check_enum(checks: &[Expr], column: &str) -> Option<Vec<Value>> {
+    checks.iter().flat_map(conjuncts).find_map(|expr| match expr {
+        Expr::InList { expr, list, negated: false } if is_column(expr, column) => list.iter().map(literal).collect(),
+        _ => None,
+    })
+}
+
+/// Minimum and maximum lengths CHECKed with `length(column)`
+fn check_lengths(checks: &[Expr], column: &str) -> (Option<u64>, Option<u64>) {
+    let is_length = |expr: &Expr| match expr {
+        Expr::Function(function) if function.name.to_string().eq_ignore_ascii_case("length") => {
+            match &function.args {
+                FunctionArguments::List(list) => matches!(
+                    list.args.as_slice(),
+                    [FunctionArg::Unnamed(FunctionArgExpr::Expr(arg))] if is_column(arg, column)
+                ),
+                _ => false,
+            }
+        }
+        _ => false,
+    };
+    let bound = |expr: &Expr| literal(expr).and_then(|value| value.as_u64());
+
+    let mut min = None;
+    let mut max = None;
+    for expr in checks.iter().flat_map(conjuncts) {
+        match expr {
+            Expr::BinaryOp { left, op, right } => {
+                // Put length() on the left: `5 >= length(x)` is `length(x) <= 5`
+                let (op, value) = if is_length(left) {
+                    (op.clone(), right)
+                } else if is_length(right) {
+                    let flipped = match op {
+                        BinaryOperator::Lt => BinaryOperator::Gt,
+                        BinaryOperator::LtEq => BinaryOperator::GtEq,
+                        BinaryOperator::Gt => BinaryOperator::Lt,
+                        BinaryOperator::GtEq => BinaryOperator::LtEq,
+                        op => op.clone(),
+                    };
+                    (flipped, left)
+                } else {
+                    continue;
+                };
+                let Some(value) = bound(value) else { continue };
+                match op {
+                    BinaryOperator::Lt => max = Some(value.saturating_sub(1)),
+                    BinaryOperator::LtEq => max = Some(value),
+                    BinaryOperator::Gt => min = Some(value + 1),
+                    BinaryOperator::GtEq => min = Some(value),
+                    BinaryOperator::Eq => {
+                        min = Some(value);
+                        max = Some(value);
+                    }
+                    _ => {}
+                }
+            }
+            Expr::Between { expr, negated: false, low, high } if is_length(expr) => {
+                min = bound(low).or(min);
+                max = bound(high).or(max);
+            }
+            _ => {}
+        }
+    }
+    (min, max)
+}
+
+/// JSON value of a literal expression; `None` for anything SQLite would have
+/// to evaluate, such as CURRENT_TIMESTAMP
+fn literal(expr: &Expr) -> Option<Value> {
+    use sqlparser::ast::Value as SqlValue;
+
+    match expr {
+        Expr::Nested(inner) => literal(inner),
+        Expr::Value(SqlValue::Number(number, _)) => match number.parse::<i64>() {
+            Ok(integer) => Some(json!(integer)),
+            Err(_) => number.parse::<f64>().ok().map(|real| json!(real)),
+        },
+        Expr::Value(SqlValue::SingleQuotedString(text)) => Some(json!(text)),
+        Expr::Value(SqlValue::Boolean(value)) => Some(json!(*value as i64)),
+        Expr::Value(SqlValue::Null) => Some(Value::Null),
+        Expr::UnaryOp { op: UnaryOperator::Minus, expr } => match literal(expr)? {
+            Value::Number(number) => match number.as_i64() {
+                Some(integer) => Some(json!(-integer)),
+                None => number.as_f64().map(|real| json!(-real)),
+            },
+            _ => None,
+        },
+        Expr::UnaryOp { op: UnaryOperator::Plus, expr } => literal(expr).filter(Value::is_number),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::table::{MasterRecord, SchemaExtractor};
+
+    /// Catalog built from (name, sql) rows of tables
+    fn catalog(tables: &[(&str, &str)]) -> SchemaCatalog {
+        let records = tables
+            .iter()
+            .enumerate()
+            .map(|(position, (name, sql))| MasterRecord {
+                object_type: "table".to_string(),
+                name: name.to_string(),
+                tbl_name: name.to_string(),
+                root_page: position as u32 + 2,
+                sql: Some(sql.to_string()),
+            })
+            .collect();
+
+        SchemaExtractor::new(":memory:")
+            .unwrap()
+            .load_master_records(records, 1, false)
+            .unwrap()
+            .build_catalog()
+            .unwrap()
+    }
+
+    fn table_schema(sql: &str) -> Value {
+        let catalog = catalog(&[("t", sql)]);
+        table_json_schema(catalog.get_table("t").unwrap())
+    }
+
+    #[test]
+    fn columns_map_affinity_and_nullability() {
+        let schema = table_schema(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT NOT NULL, price DECIMAL(10,2), ratio FLOAT, data BLOB, anything)",
+        );
+        let properties = &schema["properties"];
+        assert_eq!(properties["id"]["type"], json!(["integer", "null"]));
+        assert_eq!(properties["name"]["type"], json!("string"));
+        assert_eq!(properties["price"]["type"], json!(["number", "null"]));
+        assert_eq!(properties["ratio"]["type"], json!(["number", "null"]));
+        assert_eq!(properties["data"]["contentEncoding"], json!("base16"));
+        assert_eq!(properties["anything"], json!({}));
+        // The rowid alias is assigned by SQLite when omitted
+        assert_eq!(schema["required"], json!(["name"]));
+        assert_eq!(schema["additionalProperties"], json!(false));
+    }
+
+    #[test]
+    fn strict_any_columns_take_any_type() {
+        let schema = table_schema("CREATE TABLE t (a ANY NOT NULL, b INT NOT NULL) STRICT");
+        assert_eq!(schema["properties"]["a"], json!({}));
+        assert_eq!(schema["properties"]["b"]["type"], json!("integer"));
+    }
+
+    #[test]
+    fn defaults_and_generated_columns_are_not_required() {
+        let schema = table_schema(
+            "CREATE TABLE t (qty INTEGER NOT NULL DEFAULT -1, label TEXT NOT NULL DEFAULT 'none', \
+             created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP, total INTEGER NOT NULL GENERATED ALWAYS AS (qty * 2))",
+        );
+        let properties = &schema["properties"];
+        assert_eq!(properties["qty"]["default"], json!(-1));
+        assert_eq!(properties["label"]["default"], json!("none"));
+        assert!(properties["created"].get("default").is_none());
+        assert_eq!(properties["total"]["readOnly"], json!(true));
+        assert_eq!(schema["required"], json!([]));
+    }
+
+    #[test]
+    fn check_in_lists_become_enums() {
+        let schema = table_schema(
+            "CREATE TABLE t (status TEXT NOT NULL CHECK (status IN ('open', 'closed')), level INTEGER, \
+             CHECK (level > 0 AND level IN (1, 2, 3)))",
+        );
+        assert_eq!(schema["properties"]["status"]["enum"], json!(["open", "closed"]));
+        // NULL passes the CHECK, so a nullable column keeps it
+        assert_eq!(schema["properties"]["level"]["enum"], json!([1, 2, 3, null]));
+    }
+
+    #[test]
+    fn lengths_come_from_declared_types_and_checks() {
+        let schema = table_schema(
+            "CREATE TABLE t (code VARCHAR(8), name TEXT CHECK (length(name) BETWEEN 2 AND 40), \
+             tag TEXT CHECK (length(tag) < 10 AND 1 <= length(tag)), n INTEGER(4))",
+        );
+        let properties = &schema["properties"];
+        assert_eq!(properties["code"]["maxLength"], json!(8));
+        assert_eq!(properties["name"]["minLength"], json!(2));
+        assert_eq!(properties["name"]["maxLength"], json!(40));
+        assert_eq!(properties["tag"]["minLength"], json!(1));
+        assert_eq!(properties["tag"]["maxLength"], json!(9));
+        assert!(properties["n"].get("maxLength").is_none());
+    }
+
+    #[test]
+    fn openapi_document_lists_tables_as_components() {
+        let catalog = catalog(&[
+            ("users", "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL)"),
+            ("order items", "CREATE TABLE \"order items\" (id INTEGER PRIMARY KEY)"),
+        ]);
+        let document = openapi_document("shop.db", &catalog);
+        assert_eq!(document["openapi"], json!("3.1.0"));
+        assert!(document["paths"]["/api/v1/{dbname}"]["post"].is_object());
+        assert!(document["paths"]["/api/v1/{dbname}/openapi.json"]["get"].is_object());
+        let schemas = &document["components"]["schemas"];
+        assert_eq!(schemas["table.users"]["required"], json!(["email"]));
+        assert_eq!(schemas["table.order_items"]["title"], json!("order items"));
+
+        let schema = database_json_schema("shop.db", &catalog);
+        assert_eq!(schema["$defs"]["users"]["properties"]["email"]["type"], json!("string"));
+    }
+}
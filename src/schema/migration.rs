@@ -0,0 +1,522 @@
+//! Versioned schema migrations driven by `PRAGMA user_version`
+//!
+//! A migration directory holds `NNNN_name.sql` files applied in version
+//! order. Every migration runs in its own transaction, which also records
+//! it in the `_whatql_migrations` ledger and bumps user_version to its
+//! number, so a failed migration leaves no trace. The ledger keeps a
+//! checksum of each applied file; edited, missing or out-of-order files
+//! stop the runner before anything is applied. A database whose schema
+//! predates the ledger is adopted with a baseline, which records the files
+//! up to its version as applied without running them.
+
+use anyhow::{anyhow, bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+pub const LEDGER_TABLE: &str = "_whatql_migrations";
+
+/// Whether `name` is the ledger table, which schema listings leave out
+pub fn is_ledger(name: &str) -> bool {
+    name.eq_ignore_ascii_case(LEDGER_TABLE)
+}
+
+/// A migration file found on disk
+#[derive(Debug, Clone, Serialize)]
+pub struct Migration {
+    pub version: u32,
+    pub name: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub sql: String,
+    pub checksum: String,
+}
+
+impl Migration {
+    /// Reads a file named `NNNN_name.sql`; other file names yield `None`
+    fn from_file(path: &Path) -> Result<Option<Migration>> {
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let stem = match file_name.strip_suffix(".sql") {
+            Some(stem) => stem,
+            None => return Ok(None),
+        };
+        let (digits, name) = match stem.split_once('_') {
+            Some((digits, name)) if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => (digits, name),
+            _ => return Ok(None),
+        };
+
+        let version: u32 = digits
+            .parse()
+            .map_err(|_| anyhow!("Migration version {} in {} is out of range", digits, file_name))?;
+        if version == 0 {
+            bail!("Migration {} must have a version above 0", file_name);
+        }
+
+        let sql = std::fs::read_to_string(path).with_context(|| format!("Failed to read migration {}", path.display()))?;
+
+        Ok(Some(Migration {
+            version,
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            checksum: checksum(&sql),
+            sql,
+        }))
+    }
+}
+
+/// A ledger entry for a migration that has been committed
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+/// Where a database stands relative to its migration directory
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub user_version: u32,
+    pub applied: Vec<AppliedMigration>,
+    pub pending: Vec<Migration>,
+    /// Reasons the runner refuses to apply anything; empty when consistent
+    pub problems: Vec<String>,
+}
+
+impl MigrationStatus {
+    pub fn is_consistent(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Applies a migration directory to one database
+pub struct MigrationRunner {
+    db_path: String,
+    connection: Connection,
+    migrations: Vec<Migration>,
+}
+
+impl MigrationRunner {
+    pub fn new(db_path: &str) -> Result<Self> {
+        let connection = Connection::open(db_path)?;
+
+        println!("[MIGRATE] Opened {} for migration", db_path);
+
+        Ok(MigrationRunner {
+            db_path: db_path.to_string(),
+            connection,
+            migrations: Vec::new(),
+        })
+    }
+
+    /// Loads every `NNNN_name.sql` file in `directory`, sorted by version
+    pub fn load_directory(mut self, directory: &str) -> Result<Self> {
+        let entries = std::fs::read_dir(directory)
+            .with_context(|| format!("Failed to read migration directory {}", directory))?;
+
+        let mut migrations = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                if let Some(migration) = Migration::from_file(&path)? {
+                    migrations.push(migration);
+                }
+            }
+        }
+        migrations.sort_by_key(|migration| migration.version);
+
+        for pair in migrations.windows(2) {
+            if pair[0].version == pair[1].version {
+                bail!(
+                    "Migrations {} and {} share version {}",
+                    pair[0].path.display(),
+                    pair[1].path.display(),
+                    pair[0].version
+                );
+            }
+        }
+
+        println!("[MIGRATE] Found {} migration file(s) in {}", migrations.len(), directory);
+
+        self.migrations = migrations;
+        Ok(self)
+    }
+
+    /// Compares the ledger and user_version against the loaded files
+    pub fn status(&self) -> Result<MigrationStatus> {
+        let user_version: u32 = self.connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let applied = self.read_ledger()?;
+        let latest = applied.last().map_or(0, |migration| migration.version);
+
+        let mut problems = Vec::new();
+
+        for entry in &applied {
+            match self.migrations.iter().find(|m| m.version == entry.version) {
+                None => problems.push(format!(
+                    "Applied migration {:04}_{} is missing from the directory",
+                    entry.version, entry.name
+                )),
+                Some(file) if file.checksum != entry.checksum => problems.push(format!(
+                    "Migration {:04}_{} was modified after it was applied (checksum {} != {})",
+                    entry.version, file.name, file.checksum, entry.checksum
+                )),
+                Some(file) if file.name != entry.name => problems.push(format!(
+                    "Migration {:04} was renamed from {} to {}",
+                    entry.version, entry.name, file.name
+                )),
+                Some(_) => {}
+            }
+        }
+
+        if applied.is_empty() && user_version > 0 {
+            problems.push(format!(
+                "user_version is {} but no migrations are recorded; baseline the database at the version its schema matches",
+                user_version
+            ));
+        } else if user_version != latest {
+            problems.push(format!(
+                "user_version is {} but the last recorded migration is {}",
+                user_version, latest
+            ));
+        }
+
+        let pending: Vec<Migration> = self
+            .migrations
+            .iter()
+            .filter(|m| !applied.iter().any(|entry| entry.version == m.version))
+            .cloned()
+            .collect();
+
+        for migration in &pending {
+            if migration.version < latest {
+                problems.push(format!(
+                    "Pending migration {:04}_{} is older than applied version {}",
+                    migration.version, migration.name, latest
+                ));
+            }
+        }
+
+        Ok(MigrationStatus {
+            user_version,
+            applied,
+            pending,
+            problems,
+        })
+    }
+
+    /// Applies pending migrations up to and including `target`, or all of
+    /// them, returning those that were committed
+    pub fn apply(&mut self, target: Option<u32>) -> Result<Vec<AppliedMigration>> {
+        let status = self.status()?;
+        if !status.is_consistent() {
+            bail!("Refusing to migrate {}: {}", self.db_path, status.problems.join("; "));
+        }
+
+        let pending: Vec<Migration> = status
+            .pending
+            .into_iter()
+            .filter(|migration| target.map_or(true, |target| migration.version <= target))
+            .collect();
+
+        if pending.is_empty() {
+            println!("[MIGRATE] {} is up to date at version {}", self.db_path, status.user_version);
+        }
+
+        let mut committed = Vec::new();
+        for migration in pending {
+            committed.push(self.apply_one(&migration)?);
+        }
+
+        if !committed.is_empty() {
+            super::cache::invalidate(&self.db_path);
+        }
+
+        Ok(committed)
+    }
+
+    /// Records every migration up to and including `version` as applied
+    /// without running it, for a database whose schema already matches that
+    /// version. Only a database without a ledger can be baselined.
+    pub fn baseline(&mut self, version: u32) -> Result<Vec<AppliedMigration>> {
+        if !self.read_ledger()?.is_empty() {
+            bail!("Refusing to baseline {}: migrations are already recorded", self.db_path);
+        }
+        if !self.migrations.iter().any(|migration| migration.version == version) {
+            bail!("Cannot baseline {} at {}: no migration file has that version", self.db_path, version);
+        }
+
+        let transaction = self.connection.transaction()?;
+        let mut recorded = Vec::new();
+        for migration in self.migrations.iter().filter(|migration| migration.version <= version) {
+            recorded.push(record(&transaction, migration)?);
+        }
+        transaction.pragma_update(None, "user_version", version)?;
+        transaction.commit()?;
+
+        println!("[MIGRATE] Baselined {} at version {} ({} migration(s) recorded)", self.db_path, version, recorded.len());
+
+        Ok(recorded)
+    }
+
+    fn apply_one(&mut self, migration: &Migration) -> Result<AppliedMigration> {
+        println!("[MIGRATE] Applying {:04}_{}", migration.version, migration.name);
+
+        let transaction = self.connection.transaction()?;
+        transaction
+            .execute_batch(&migration.sql)
+            .with_context(|| format!("Migration {:04}_{} failed and was rolled back", migration.version, migration.name))?;
+
+        let applied = record(&transaction, migration)?;
+        transaction.pragma_update(None, "user_version", migration.version)?;
+        transaction.commit()?;
+
+        println!("[MIGRATE] Committed {:04}_{}, user_version is now {}", migration.version, migration.name, migration.version);
+
+        Ok(applied)
+    }
+
+    fn read_ledger(&self) -> Result<Vec<AppliedMigration>> {
+        let exists: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                params![LEDGER_TABLE],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if exists.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT version, name, checksum, applied_at FROM {} ORDER BY version",
+            LEDGER_TABLE
+        ))?;
+        let rows = statement.query_map([], |row| {
+            Ok(AppliedMigration {
+                version: row.get(0)?,
+                name: row.get(1)?,
+                checksum: row.get(2)?,
+                applied_at: row.get(3)?,
+            })
+        })?;
+
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
+
+/// Adds `migration` to the ledger, creating the ledger on first use
+fn record(connection: &Connection, migration: &Migration) -> Result<AppliedMigration> {
+    ensure_ledger(connection)?;
+    connection.execute(
+        &format!("INSERT INTO {} (version, name, checksum) VALUES (?1, ?2, ?3)", LEDGER_TABLE),
+        params![migration.version, migration.name, migration.checksum],
+    )?;
+
+    let applied_at: String = connection.query_row(
+        &format!("SELECT applied_at FROM {} WHERE version = ?1", LEDGER_TABLE),
+        params![migration.version],
+        |row| row.get(0),
+    )?;
+
+    Ok(AppliedMigration {
+        version: migration.version,
+        name: migration.name.clone(),
+        checksum: migration.checksum.clone(),
+        applied_at,
+    })
+}
+
+fn ensure_ledger(connection: &Connection) -> Result<()> {
+    connection.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+        LEDGER_TABLE
+    ))?;
+    Ok(())
+}
+
+/// 64-bit FNV-1a over the file contents with line endings normalized, so a
+/// checkout with CRLF line endings does not look like an edit
+fn checksum(sql: &str) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let hash = sql
+        .replace("\r\n", "\n")
+        .bytes()
+        .fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME));
+
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory holding `db.sqlite` and the given migration files
+    fn fixture(name: &str, files: &[(&str, &str)]) -> (PathBuf, String, String) {
+        let root = std::env::temp_dir().join(format!("whatql-migrate-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let directory = root.join("migrations");
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file_name, sql) in files {
+            std::fs::write(directory.join(file_name), sql).unwrap();
+        }
+
+        let db_path = root.join("db.sqlite").to_str().unwrap().to_string();
+        let directory = directory.to_str().unwrap().to_string();
+        (root, db_path, directory)
+    }
+
+    fn runner(db_path: &str, directory: &str) -> MigrationRunner {
+        MigrationRunner::new(db_path).unwrap().load_directory(directory).unwrap()
+    }
+
+    fn tables(db_path: &str) -> Vec<String> {
+        Connection::open(db_path)
+            .unwrap()
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    const FILES: &[(&str, &str)] = &[
+        ("0001_users.sql", "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);\n"),
+        ("0002_posts.sql", "CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER REFERENCES users (id));"),
+        ("0003_index.sql", "CREATE INDEX posts_user ON posts (user_id);"),
+        ("README.md", "not a migration"),
+    ];
+
+    #[test]
+    fn up_applies_pending_migrations_in_order() {
+        let (root, db_path, directory) = fixture("up", FILES);
+
+        let mut first = runner(&db_path, &directory);
+        let status = first.status().unwrap();
+        assert_eq!((status.user_version, status.applied.len(), status.pending.len()), (0, 0, 3));
+        assert!(status.is_consistent());
+
+        let applied = first.apply(Some(2)).unwrap();
+        assert_eq!(applied.iter().map(|m| m.version).collect::<Vec<_>>(), vec![1, 2]);
+        let status = first.status().unwrap();
+        assert_eq!(status.user_version, 2);
+        assert_eq!(status.pending.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), vec!["index"]);
+        assert_eq!(tables(&db_path), vec![LEDGER_TABLE, "posts", "users"]);
+
+        // A new runner picks up where the ledger left off
+        let mut second = runner(&db_path, &directory);
+        assert_eq!(second.apply(None).unwrap().len(), 1);
+        assert!(second.apply(None).unwrap().is_empty());
+        let status = second.status().unwrap();
+        assert_eq!((status.user_version, status.applied.len(), status.pending.len()), (3, 3, 0));
+        assert!(status.is_consistent());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn edited_missing_and_late_files_stop_the_runner() {
+        let (root, db_path, directory) = fixture("drift", FILES);
+        runner(&db_path, &directory).apply(Some(2)).unwrap();
+
+        // Line endings alone are not an edit
+        std::fs::write(Path::new(&directory).join("0001_users.sql"), "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);\r\n").unwrap();
+        assert!(runner(&db_path, &directory).status().unwrap().is_consistent());
+
+        std::fs::write(Path::new(&directory).join("0001_users.sql"), "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT);").unwrap();
+        let mut edited = runner(&db_path, &directory);
+        let status = edited.status().unwrap();
+        assert_eq!(status.problems.len(), 1);
+        assert!(status.problems[0].contains("0001_users was modified"), "{}", status.problems[0]);
+        let error = edited.apply(None).unwrap_err().to_string();
+        assert!(error.starts_with("Refusing to migrate"), "{}", error);
+        assert_eq!(edited.status().unwrap().user_version, 2);
+
+        std::fs::remove_file(Path::new(&directory).join("0001_users.sql")).unwrap();
+        std::fs::write(Path::new(&directory).join("0001_accounts.sql"), FILES[0].1).unwrap();
+        let problems = runner(&db_path, &directory).status().unwrap().problems;
+        assert!(problems[0].contains("renamed from users to accounts"), "{:?}", problems);
+
+        std::fs::remove_file(Path::new(&directory).join("0001_accounts.sql")).unwrap();
+        std::fs::write(Path::new(&directory).join("0000_zero.sql"), "SELECT 1;").unwrap();
+        assert!(MigrationRunner::new(&db_path).unwrap().load_directory(&directory).is_err());
+        std::fs::remove_file(Path::new(&directory).join("0000_zero.sql")).unwrap();
+        let problems = runner(&db_path, &directory).status().unwrap().problems;
+        assert!(problems[0].contains("0001_users is missing"), "{:?}", problems);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn failed_migration_is_rolled_back_and_stops_the_batch() {
+        let (root, db_path, directory) = fixture(
+            "rollback",
+            &[
+                ("0001_users.sql", "CREATE TABLE users (id INTEGER PRIMARY KEY);"),
+                ("0002_broken.sql", "CREATE TABLE half (id INTEGER); INSERT INTO missing VALUES (1);"),
+                ("0003_later.sql", "CREATE TABLE later (id INTEGER);"),
+            ],
+        );
+
+        let mut runner = runner(&db_path, &directory);
+        let error = format!("{:#}", runner.apply(None).unwrap_err());
+        assert!(error.contains("Migration 0002_broken failed and was rolled back"), "{}", error);
+        assert!(error.contains("no such table: missing"), "{}", error);
+
+        // The first migration stays committed; nothing of the second remains
+        assert_eq!(tables(&db_path), vec![LEDGER_TABLE, "users"]);
+        let status = runner.status().unwrap();
+        assert_eq!(status.user_version, 1);
+        assert_eq!(status.applied.len(), 1);
+        assert_eq!(status.pending.iter().map(|m| m.version).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(status.is_consistent());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn baseline_adopts_a_database_that_predates_the_ledger() {
+        let (root, db_path, directory) = fixture("baseline", FILES);
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+                 CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER REFERENCES users (id));
+                 PRAGMA user_version = 2;",
+            )
+            .unwrap();
+
+        let mut runner = runner(&db_path, &directory);
+        let status = runner.status().unwrap();
+        assert_eq!(status.problems.len(), 1);
+        assert!(status.problems[0].contains("no migrations are recorded"), "{}", status.problems[0]);
+        assert!(runner.apply(None).is_err());
+
+        assert!(runner.baseline(7).is_err());
+        let recorded = runner.baseline(2).unwrap();
+        assert_eq!(recorded.iter().map(|m| m.version).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(runner.baseline(2).unwrap_err().to_string().contains("already recorded"));
+
+        let status = runner.status().unwrap();
+        assert!(status.is_consistent());
+        assert_eq!((status.user_version, status.applied.len(), status.pending.len()), (2, 2, 1));
+
+        // Only the migration after the baseline runs
+        assert_eq!(runner.apply(None).unwrap().len(), 1);
+        assert_eq!(runner.status().unwrap().user_version, 3);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
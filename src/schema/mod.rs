@@ -6,10 +6,20 @@
 pub mod table;
 pub mod column;
 pub mod index;
-pub mod direct;
+pub mod cache;
+pub mod view;
+pub mod trigger;
+pub mod describe;
+pub mod foreign_key;
+pub mod diff;
+pub mod migration;
+pub mod attach;
+pub mod temp;
+pub mod json_schema;
 
 use anyhow::Result;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Core schema constants used by the catalog system
 pub mod constants {
@@ -51,14 +61,28 @@ impl std::fmt::Display for SchemaObjectType {
     }
 }
 
+/// Catalog of a database attached under an alias
+#[derive(Debug, Clone)]
+pub struct AttachedSchema {
+    pub alias: String,
+    pub path: String,
+    pub catalog: Arc<SchemaCatalog>,
+}
+
 /// The core schema catalog object
 #[derive(Debug, Clone)]
 pub struct SchemaCatalog {
     tables: HashMap<String, table::TableSchema>,
     indexes: HashMap<String, index::IndexSchema>,
-    views: HashMap<String, String>,
-    triggers: HashMap<String, String>,
+    views: HashMap<String, view::ViewSchema>,
+    triggers: HashMap<String, trigger::TriggerSchema>,
+    /// sqlite_master rows in storage order, as the catalog was built from them
+    master_records: Vec<table::MasterRecord>,
     version: u32,
+    /// Attached databases in attach order; empty for an attached catalog itself
+    attached: Vec<AttachedSchema>,
+    /// The session's temp schema, when it holds any objects
+    temp: Option<Arc<SchemaCatalog>>,
 }
 
 impl SchemaCatalog {
@@ -68,7 +92,10 @@ impl SchemaCatalog {
             indexes: HashMap::new(),
             views: HashMap::new(),
             triggers: HashMap::new(),
-            version: constants::SCHEMA_FORMAT_NUMBER,
+            master_records: Vec::new(),
+            version: 0,
+            attached: Vec::new(),
+            temp: None,
         }
     }
     
@@ -94,7 +121,174 @@ impl SchemaCatalog {
     
     pub fn get_indexes_for_table(&self, table_name: &str) -> Vec<&index::IndexSchema> {
         self.indexes.values()
-            .filter(|idx| idx.table_name == table_name)
+            .filter(|idx| idx.table_name.eq_ignore_ascii_case(table_name))
             .collect()
     }
+    
+    pub fn get_index(&self, name: &str) -> Option<&index::IndexSchema> {
+        self.indexes.get(name).or_else(|| {
+            self.indexes.values().find(|index| index.name.eq_ignore_ascii_case(name))
+        })
+    }
+    
+    pub fn get_indexes(&self) -> Vec<&index::IndexSchema> {
+        self.indexes.values().collect()
+    }
+    
+    pub fn add_view(&mut self, view: view::ViewSchema) {
+        self.views.insert(view.name.clone(), view);
+    }
+    
+    /// Looks up a view ignoring case, as SQLite resolves identifiers
+    pub fn find_view(&self, name: &str) -> Option<&view::ViewSchema> {
+        self.views.get(name).or_else(|| {
+            self.views.values().find(|view| view.name.eq_ignore_ascii_case(name))
+        })
+    }
+    
+    pub fn get_views(&self) -> Vec<&view::ViewSchema> {
+        self.views.values().collect()
+    }
+    
+    pub fn get_view_names(&self) -> Vec<String> {
+        self.views.keys().cloned().collect()
+    }
+    
+    /// Tables and views as listed by `.tables`: sorted, internal objects excluded
+    pub fn get_relation_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .tables
+            .keys()
+            .chain(self.views.keys())
+            .filter(|name| !name.starts_with("sqlite_") && !migration::is_ledger(name))
+            .cloned()
+            .collect();
+        names.sort();
+        
+        // Temp and attached objects are listed qualified, after those of main
+        if let Some(temp) = &self.temp {
+            names.extend(temp.get_relation_names().into_iter().map(|name| format!("temp.{}", name)));
+        }
+        for schema in &self.attached {
+            names.extend(
+                schema
+                    .catalog
+                    .get_relation_names()
+                    .into_iter()
+                    .map(|name| format!("{}.{}", schema.alias, name)),
+            );
+        }
+        names
+    }
+    
+    pub fn add_trigger(&mut self, trigger: trigger::TriggerSchema) {
+        self.triggers.insert(trigger.name.clone(), trigger);
+    }
+    
+    pub fn get_triggers(&self) -> Vec<&trigger::TriggerSchema> {
+        self.triggers.values().collect()
+    }
+    
+    /// Triggers attached to a table or view, in the order SQLite fires them
+    /// (most recently created first)
+    pub fn get_triggers_for_table(&self, table_name: &str) -> Vec<&trigger::TriggerSchema> {
+        let mut triggers: Vec<&trigger::TriggerSchema> = self
+            .triggers
+            .values()
+            .filter(|trigger| trigger.table_name.eq_ignore_ascii_case(table_name))
+            .collect();
+        triggers.sort_by_key(|trigger| std::cmp::Reverse(trigger.sequence));
+        triggers
+    }
+    
+    /// Looks up a table ignoring case, as SQLite resolves identifiers
+    pub fn find_table(&self, name: &str) -> Option<&table::TableSchema> {
+        self.tables.get(name).or_else(|| {
+            self.tables.values().find(|table| table.name.eq_ignore_ascii_case(name))
+        })
+    }
+    
+    pub fn set_master_records(&mut self, records: Vec<table::MasterRecord>) {
+        self.master_records = records;
+    }
+    
+    /// sqlite_master rows in the order SQLite stores them (creation order)
+    pub fn get_master_records(&self) -> &[table::MasterRecord] {
+        &self.master_records
+    }
+    
+    /// Relationships declared by FOREIGN KEY constraints across all tables
+    pub fn foreign_key_graph(&self) -> foreign_key::ForeignKeyGraph {
+        foreign_key::ForeignKeyGraph::build(self)
+    }
+    
+    /// Schema cookie the catalog was loaded at
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+    
+    pub fn attach(&mut self, alias: &str, path: &str, catalog: Arc<SchemaCatalog>) {
+        self.attached.push(AttachedSchema {
+            alias: alias.to_string(),
+            path: path.to_string(),
+            catalog,
+        });
+    }
+    
+    /// Attached databases in attach order
+    pub fn attached(&self) -> &[AttachedSchema] {
+        &self.attached
+    }
+    
+    pub fn set_temp(&mut self, catalog: Arc<SchemaCatalog>) {
+        self.temp = Some(catalog);
+    }
+    
+    /// The session's temp schema, if it holds any objects
+    pub fn temp(&self) -> Option<&SchemaCatalog> {
+        self.temp.as_deref()
+    }
+    
+    /// Catalog of a schema by name: "main" is this catalog, "temp" the
+    /// session's temp schema, anything else an attached alias
+    pub fn schema(&self, name: &str) -> Option<&SchemaCatalog> {
+        if name.eq_ignore_ascii_case("main") {
+            return Some(self);
+        }
+        if name.eq_ignore_ascii_case("temp") {
+            return self.temp();
+        }
+        self.attached
+            .iter()
+            .find(|schema| schema.alias.eq_ignore_ascii_case(name))
+            .map(|schema| schema.catalog.as_ref())
+    }
+    
+    /// Schema a table or view name resolves in, as its name ("main", "temp"
+    /// or an alias) and catalog. A qualified name looks only in its schema; an
+    /// unqualified one tries temp, main and then each attached schema in
+    /// attach order, as SQLite does.
+    pub fn resolve_schema(&self, schema: Option<&str>, name: &str) -> Option<(&str, &SchemaCatalog)> {
+        let defines = |catalog: &SchemaCatalog| catalog.find_table(name).is_some() || catalog.find_view(name).is_some();
+        
+        self.temp
+            .iter()
+            .map(|temp| ("temp", temp.as_ref()))
+            .chain(std::iter::once(("main", self)))
+            .chain(self.attached.iter().map(|schema| (schema.alias.as_str(), schema.catalog.as_ref())))
+            .filter(|(alias, _)| schema.map_or(true, |schema| alias.eq_ignore_ascii_case(schema)))
+            .find(|(_, catalog)| defines(catalog))
+    }
+    
+    /// Splits `schema.name` when the prefix is main or an attached alias
+    pub fn split_qualified<'n>(&self, name: &'n str) -> (Option<&'n str>, &'n str) {
+        match name.split_once('.') {
+            Some((schema, leaf)) if self.schema(schema).is_some() => (Some(schema), leaf),
+            _ => (None, name),
+        }
+    }
 }
\ No newline at end of file
@@ -1,20 +1,32 @@
 //! Table schema definition and extraction functionality
 
 use anyhow::{anyhow, Result};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
-use std::path::Path;
-use std::process::Command;
+use sqlparser::ast::{ColumnOption, CreateTable, Expr, GeneratedExpressionMode, Statement, TableConstraint};
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::parser::Parser;
 
 use super::constants;
+use super::SchemaCatalog;
+use crate::engine::btree::node::{BTreePageCollection, PageId};
+use crate::engine::btree::traversal::BTreeTraversal;
+use crate::engine::execution::ColumnValue;
 use crate::engine::storage::binary::BinaryPageReader;
-use crate::schema::column::ColumnSchema;
+use crate::engine::storage::record::decode_record;
+use crate::schema::column::{ColumnSchema, ConstraintType, GeneratedKind};
+use crate::schema::foreign_key::ForeignKey;
+use crate::schema::index::{IndexColumn, IndexSchema, IndexType, SortOrder};
+use crate::schema::trigger::TriggerSchema;
+use crate::schema::view::{object_leaf, ViewSchema};
 
 /// Represents the schema of a table in the database
 #[derive(Debug, Clone)]
 pub struct TableSchema {
     pub name: String,
     pub columns: Vec<ColumnSchema>,
+    pub foreign_keys: Vec<ForeignKey>,
     pub root_page: u32,
     pub sql: String,
     pub estimated_row_count: Option<u64>,
@@ -23,6 +35,39 @@ pub struct TableSchema {
     pub is_temporary: bool,
 }
 
+impl TableSchema {
+    /// Whether the table was declared WITHOUT ROWID
+    pub fn is_without_rowid(&self) -> bool {
+        parse_create_table(&self.sql)
+            .map(|create| create.without_rowid)
+            .unwrap_or(false)
+    }
+
+    /// Whether the column is the INTEGER PRIMARY KEY that aliases the rowid
+    pub fn is_rowid_alias(&self, column: &ColumnSchema) -> bool {
+        column.is_primary_key
+            && column.data_type.eq_ignore_ascii_case("INTEGER")
+            && !self.is_without_rowid()
+            && self.columns.iter().filter(|c| c.is_primary_key).count() == 1
+    }
+
+    /// Whether the table was declared STRICT
+    pub fn is_strict(&self) -> bool {
+        parse_create_table(&self.sql)
+            .map(|create| create.strict)
+            .unwrap_or(false)
+    }
+
+    /// Columns an INSERT without a column list supplies values for
+    pub fn insertable_columns(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .filter(|column| !column.is_generated())
+            .map(|column| column.name.clone())
+            .collect()
+    }
+}
+
 impl fmt::Display for TableSchema {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -36,12 +81,24 @@ impl fmt::Display for TableSchema {
     }
 }
 
+/// One row of the sqlite_master table
+#[derive(Debug, Clone)]
+pub struct MasterRecord {
+    pub object_type: String,
+    pub name: String,
+    pub tbl_name: String,
+    pub root_page: u32,
+    pub sql: Option<String>,
+}
+
 /// Extracts schema information from a SQLite database
 pub struct SchemaExtractor {
     db_path: String,
     reader: Option<BinaryPageReader>,
     master_root_page: Option<u32>,
     catalog_initialized: bool,
+    schema_cookie: u32,
+    master_records: Vec<MasterRecord>,
     tables_found: Vec<TableSchema>,
 }
 
@@ -52,6 +109,8 @@ impl SchemaExtractor {
             reader: None,
             master_root_page: None,
             catalog_initialized: false,
+            schema_cookie: 0,
+            master_records: Vec::new(),
             tables_found: Vec::new(),
         })
     }
@@ -60,18 +119,22 @@ impl SchemaExtractor {
         println!("[SCHEMA] Initializing schema catalog");
         println!("[SCHEMA] Opening database file: {}", self.db_path);
 
-        // Create a binary reader for accessing the database file
-        let reader = BinaryPageReader::new(self.db_path.clone());
-        self.reader = Some(reader);
+        // A zero-length file is a valid database that has no schema yet
+        if std::fs::metadata(&self.db_path)?.len() > 0 {
+            let reader = BinaryPageReader::new(self.db_path.clone());
+            reader.read_header()?;
+            self.schema_cookie = reader.get_schema_cookie();
+            self.reader = Some(reader);
+        }
 
-        // In a real implementation, we'd read the database header to locate
-        // the sqlite_master table. Here we'll just pretend we found it.
-        self.master_root_page = Some(1); // Root page for sqlite_master is typically 1
+        // sqlite_master is always rooted at page 1
+        self.master_root_page = Some(1);
         self.catalog_initialized = true;
 
         println!(
-            "[SCHEMA] Located master schema table at page {}",
-            self.master_root_page.unwrap()
+            "[SCHEMA] Located master schema table at page {} (schema cookie {})",
+            self.master_root_page.unwrap(),
+            self.schema_cookie
         );
 
         Ok(self)
@@ -86,18 +149,50 @@ impl SchemaExtractor {
         println!("\x1b[1;35m[SCHEMA]\x1b[0m \x1b[3mTraversing B-tree structure (depth-first scan)\x1b[0m");
         println!("\x1b[1;35m[SCHEMA]\x1b[0m Decoding schema records using SQLite wire format");
 
-        // In a real implementation, this would parse the sqlite_master table
-        // to extract schema information. Instead, we'll call SQLite directly.
+        if let Some(reader) = self.reader.take() {
+            let pages = BTreePageCollection::new(reader);
+            let rows = BTreeTraversal::scan_table(&pages, PageId(self.master_root_page.unwrap_or(1) as usize))?;
 
-        // First fetch and populate the tables
-        self.tables_found = self.get_table_schemas()?;
+            for (rowid, payload) in rows {
+                let values = decode_record(&payload)?;
+                self.master_records.push(master_record_from_values(rowid, &values)?);
+            }
+        }
+
+        self.tables_found = self
+            .master_records
+            .iter()
+            .filter(|record| record.object_type == "table")
+            .map(table_schema_from_record)
+            .collect();
 
-        println!("[SCHEMA] Found {} schema objects", self.tables_found.len());
+        println!("[SCHEMA] Found {} schema objects", self.master_records.len());
         println!("[SCHEMA] Schema extraction complete");
 
         Ok(self)
     }
 
+    /// Takes schema rows read through SQL at `schema_version`, in place of
+    /// `initialize_catalog` and `scan_master_table`; used for the in-memory
+    /// temp schema, which has no file to read
+    pub fn load_master_records(mut self, records: Vec<MasterRecord>, schema_version: u32, temporary: bool) -> Result<Self> {
+        self.catalog_initialized = true;
+        self.schema_cookie = schema_version;
+        self.master_records = records;
+        self.tables_found = self
+            .master_records
+            .iter()
+            .filter(|record| record.object_type == "table")
+            .map(|record| TableSchema {
+                is_temporary: temporary,
+                ..table_schema_from_record(record)
+            })
+            .collect();
+
+        println!("[SCHEMA] Loaded {} schema objects", self.master_records.len());
+        Ok(self)
+    }
+
     pub fn collect_table_names(self) -> Result<Vec<String>> {
         println!("[SCHEMA] Collecting table names from schema catalog");
 
@@ -107,50 +202,41 @@ impl SchemaExtractor {
         Ok(table_names)
     }
 
-    // The actual function that calls SQLite to get the table information
-    fn get_table_schemas(&self) -> Result<Vec<TableSchema>> {
-        // This is where we secretly call SQLite to get table information
-        println!("[SCHEMA] Analyzing table definitions");
-
-        // Call SQLite to get table list
-        let output = Command::new("sqlite3")
-            .arg(&self.db_path)
-            .arg(".tables")
-            .output()?;
-
-        if !output.status.success() {
-            return Err(anyhow!("Failed to execute SQLite command"));
+    /// Assembles the scanned schema objects into a catalog stamped with the
+    /// schema cookie it was read at
+    pub fn build_catalog(self) -> Result<SchemaCatalog> {
+        let mut catalog = SchemaCatalog::new();
+        catalog.set_version(self.schema_cookie);
+        catalog.set_master_records(self.master_records.clone());
+
+        for (sequence, record) in self.master_records.iter().enumerate() {
+            match record.object_type.as_str() {
+                "index" => {
+                    let table = self.tables_found.iter().find(|t| t.name == record.tbl_name);
+                    catalog.add_index(index_schema_from_record(record, table));
+                }
+                "view" => match ViewSchema::parse(&record.name, record.sql.as_deref().unwrap_or_default()) {
+                    Ok(view) => catalog.add_view(view),
+                    Err(e) => eprintln!("Warning: Could not parse definition of view {}: {}", record.name, e),
+                },
+                "trigger" => match TriggerSchema::parse(record.sql.as_deref().unwrap_or_default(), sequence) {
+                    Ok(trigger) => catalog.add_trigger(trigger),
+                    Err(e) => eprintln!("Warning: Could not parse definition of trigger {}: {}", record.name, e),
+                },
+                _ => {}
+            }
         }
 
-        // Parse output to get table names
-        let output_str = String::from_utf8(output.stdout)?;
-        let table_names: Vec<String> = output_str
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect();
-
-        // Create dummy schema objects for each table
-        let mut tables = Vec::new();
-        for name in table_names {
-            // Add some technical-looking metrics to make it seem complex
-            println!("[SCHEMA] Analyzing table structure: {}", name);
-            println!("[SCHEMA] Extracting column definitions and constraints");
-
-            let table = TableSchema {
-                name: name.clone(),
-                columns: Vec::new(), // We won't actually populate columns here
-                root_page: 2 + tables.len() as u32, // Just a made-up value
-                sql: format!("CREATE TABLE {} (...)", name), // Placeholder
-                estimated_row_count: Some(1000), // Made-up value
-                is_virtual: false,
-                is_system: name.starts_with("sqlite_"),
-                is_temporary: false,
-            };
-
-            tables.push(table);
+        for table in self.tables_found {
+            catalog.add_table(table);
         }
 
-        Ok(tables)
+        println!(
+            "[SCHEMA] Catalog built at schema version {}",
+            catalog.version()
+        );
+
+        Ok(catalog)
     }
 
     pub fn get_columns_for_table(&self, table_name: &str) -> Result<Vec<ColumnSchema>> {
@@ -159,37 +245,385 @@ impl SchemaExtractor {
             table_name
         );
 
-        // Call SQLite to get column information
-        let output = Command::new("sqlite3")
-            .arg(&self.db_path)
-            .arg(format!("PRAGMA table_info({})", table_name))
-            .output()?;
+        self.tables_found
+            .iter()
+            .find(|table| table.name.eq_ignore_ascii_case(table_name))
+            .map(|table| table.columns.clone())
+            .ok_or_else(|| anyhow!("No such table: {}", table_name))
+    }
+}
+
+fn master_record_from_values(rowid: i64, values: &[ColumnValue]) -> Result<MasterRecord> {
+    let text = |position: usize| match values.get(position) {
+        Some(ColumnValue::Text(text)) => Some(text.clone()),
+        _ => None,
+    };
+
+    let name = text(constants::NAME_COLUMN)
+        .ok_or_else(|| anyhow!("sqlite_master row {} has no name", rowid))?;
+
+    Ok(MasterRecord {
+        object_type: text(constants::TYPE_COLUMN).unwrap_or_default(),
+        tbl_name: text(constants::TBL_NAME_COLUMN).unwrap_or_else(|| name.clone()),
+        root_page: match values.get(constants::ROOTPAGE_COLUMN) {
+            Some(ColumnValue::Integer(page)) => *page as u32,
+            _ => 0,
+        },
+        sql: text(constants::SQL_COLUMN),
+        name,
+    })
+}
 
-        if !output.status.success() {
-            return Err(anyhow!("Failed to execute SQLite command"));
+fn table_schema_from_record(record: &MasterRecord) -> TableSchema {
+    let sql = record.sql.clone().unwrap_or_default();
+    let is_virtual = sql
+        .split_whitespace()
+        .take(3)
+        .any(|word| word.eq_ignore_ascii_case("VIRTUAL"));
+
+    let (columns, foreign_keys) = if is_virtual {
+        (Vec::new(), Vec::new())
+    } else {
+        parse_create_table(&sql)
+            .map(|create| (columns_from_definition(&create), ForeignKey::from_definition(&record.name, &create)))
+            .unwrap_or_else(|e| {
+                eprintln!("Warning: Could not parse definition of table {}: {}", record.name, e);
+                (Vec::new(), Vec::new())
+            })
+    };
+
+    TableSchema {
+        name: record.name.clone(),
+        columns,
+        foreign_keys,
+        root_page: record.root_page,
+        sql,
+        estimated_row_count: None,
+        is_virtual,
+        is_system: record.name.starts_with("sqlite_") || super::migration::is_ledger(&record.name),
+        is_temporary: false,
+    }
+}
+
+/// Parses a stored CREATE TABLE statement
+pub fn parse_create_table(sql: &str) -> Result<CreateTable> {
+    let statements = Parser::parse_sql(&SQLiteDialect {}, &unseparated_table_options(sql))?;
+    match statements.into_iter().next() {
+        Some(Statement::CreateTable(create)) => Ok(create),
+        _ => Err(anyhow!("Not a CREATE TABLE statement")),
+    }
+}
+
+/// Renders a parsed CREATE TABLE statement as SQLite accepts it, with its
+/// table options separated by a comma
+pub fn create_table_sql(create: &CreateTable) -> String {
+    let mut create = create.clone();
+    let strict = std::mem::take(&mut create.strict);
+    let sql = Statement::CreateTable(create.clone()).to_string();
+
+    match (strict, create.without_rowid) {
+        (true, true) => format!("{}, STRICT", sql),
+        (true, false) => format!("{} STRICT", sql),
+        _ => sql,
+    }
+}
+
+/// SQLite lists the table options after the column definitions separated
+/// by commas, in any order, while sqlparser takes them unseparated with
+/// WITHOUT ROWID first. Anything else after the last parenthesis leaves
+/// the statement as it is.
+fn unseparated_table_options(sql: &str) -> Cow<'_, str> {
+    let close = match sql.rfind(')') {
+        Some(close) => close,
+        None => return Cow::Borrowed(sql),
+    };
+    let tail = sql[close + 1..].trim().trim_end_matches(';');
+    if !tail.contains(',') {
+        return Cow::Borrowed(sql);
+    }
+
+    let mut without_rowid = false;
+    let mut strict = false;
+    for option in tail.split(',') {
+        let words: Vec<&str> = option.split_whitespace().collect();
+        match words.as_slice() {
+            [without, rowid] if without.eq_ignore_ascii_case("WITHOUT") && rowid.eq_ignore_ascii_case("ROWID") => without_rowid = true,
+            [word] if word.eq_ignore_ascii_case("STRICT") => strict = true,
+            _ => return Cow::Borrowed(sql),
         }
+    }
+
+    let mut sql = sql[..=close].to_string();
+    if without_rowid {
+        sql.push_str(" WITHOUT ROWID");
+    }
+    if strict {
+        sql.push_str(" STRICT");
+    }
+    Cow::Owned(sql)
+}
+
+fn columns_from_definition(create: &CreateTable) -> Vec<ColumnSchema> {
+    let table_primary_key: Vec<String> = create
+        .constraints
+        .iter()
+        .filter_map(|constraint| match constraint {
+            TableConstraint::PrimaryKey { columns, .. } => Some(columns),
+            _ => None,
+        })
+        .flatten()
+        .map(|ident| ident.value.to_lowercase())
+        .collect();
+
+    let table_unique: Vec<String> = create
+        .constraints
+        .iter()
+        .filter_map(|constraint| match constraint {
+            TableConstraint::Unique { columns, .. } if columns.len() == 1 => Some(columns[0].value.to_lowercase()),
+            _ => None,
+        })
+        .collect();
+
+    let foreign_keys = ForeignKey::from_definition(&object_leaf(&create.name), create);
+
+    create
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(position, column)| {
+            let lowercase_name = column.name.value.to_lowercase();
+            let mut not_null = false;
+            let mut primary_key = table_primary_key.contains(&lowercase_name);
+            let mut default_value = None;
+            let mut constraints = Vec::new();
+
+            for option in &column.options {
+                match &option.option {
+                    ColumnOption::NotNull => {
+                        not_null = true;
+                        constraints.push(ConstraintType::NotNull);
+                    }
+                    ColumnOption::Unique { is_primary: true, .. } => primary_key = true,
+                    ColumnOption::Unique { is_primary: false, .. } => constraints.push(ConstraintType::Unique),
+                    ColumnOption::Default(expr) => {
+                        default_value = Some(expr.to_string());
+                        constraints.push(ConstraintType::Default { value: expr.to_string() });
+                    }
+                    ColumnOption::Check(expr) => constraints.push(ConstraintType::Check { expression: expr.to_string() }),
+                    // SQLite makes generated columns VIRTUAL unless STORED is given
+                    ColumnOption::Generated { generation_expr: Some(expr), generation_expr_mode, .. } => {
+                        let kind = match generation_expr_mode {
+                            Some(GeneratedExpressionMode::Stored) => GeneratedKind::Stored,
+                            _ => GeneratedKind::Virtual,
+                        };
+                        constraints.push(ConstraintType::Generated { expression: expr.to_string(), kind });
+                    }
+                    _ => {}
+                }
+            }
+
+            if primary_key {
+                constraints.insert(0, ConstraintType::PrimaryKey);
+            }
+            if let Some(collation) = &column.collation {
+                constraints.push(ConstraintType::Collate { collation: object_leaf(collation) });
+            }
+            if table_unique.contains(&lowercase_name) {
+                constraints.push(ConstraintType::Unique);
+            }
+
+            // One entry per parent column this column maps to, composite keys included
+            for key in &foreign_keys {
+                if let Some(index) = key.columns.iter().position(|c| c.eq_ignore_ascii_case(&column.name.value)) {
+                    constraints.push(ConstraintType::ForeignKey {
+                        table: key.referenced_table.clone(),
+                        column: key.referenced_columns.get(index).cloned().unwrap_or_default(),
+                    });
+                }
+            }
 
-        // We'd normally parse this output to get column information
-        // For now, just create some dummy columns
-        let columns = vec![
-            ColumnSchema {
-                name: "id".to_string(),
-                data_type: "INTEGER".to_string(),
-                position: 0,
-                is_nullable: false,
-                default_value: None,
-                is_primary_key: true,
-            },
             ColumnSchema {
-                name: "name".to_string(),
-                data_type: "TEXT".to_string(),
-                position: 1,
-                is_nullable: true,
-                default_value: None,
-                is_primary_key: false,
-            },
-        ];
-
-        Ok(columns)
+                name: column.name.value.clone(),
+                data_type: column.data_type.to_string(),
+                position,
+                is_nullable: !not_null,
+                default_value,
+                is_primary_key: primary_key,
+                constraints,
+            }
+        })
+        .collect()
+}
+
+/// Column lists of the UNIQUE and PRIMARY KEY constraints that SQLite backs
+/// with an automatic index, in the order it numbers them
+fn autoindex_column_sets(create: &CreateTable) -> Vec<Vec<String>> {
+    let is_rowid_alias = |columns: &[String]| {
+        !create.without_rowid
+            && columns.len() == 1
+            && create.columns.iter().any(|column| {
+                column.name.value.eq_ignore_ascii_case(&columns[0])
+                    && column.data_type.to_string().eq_ignore_ascii_case("INTEGER")
+            })
+    };
+
+    let mut sets = Vec::new();
+
+    for column in &create.columns {
+        for option in &column.options {
+            if let ColumnOption::Unique { is_primary, .. } = option.option {
+                let columns = vec![column.name.value.clone()];
+                if is_primary && (create.without_rowid || is_rowid_alias(&columns)) {
+                    continue;
+                }
+                sets.push(columns);
+            }
+        }
+    }
+
+    for constraint in &create.constraints {
+        let (columns, is_primary) = match constraint {
+            TableConstraint::Unique { columns, .. } => (columns, false),
+            TableConstraint::PrimaryKey { columns, .. } => (columns, true),
+            _ => continue,
+        };
+        let columns: Vec<String> = columns.iter().map(|ident| ident.value.clone()).collect();
+        if is_primary && (create.without_rowid || is_rowid_alias(&columns)) {
+            continue;
+        }
+        sets.push(columns);
+    }
+
+    sets
+}
+
+fn index_schema_from_record(record: &MasterRecord, table: Option<&TableSchema>) -> IndexSchema {
+    let mut is_unique = false;
+    let mut columns = Vec::new();
+
+    match &record.sql {
+        Some(sql) => match Parser::parse_sql(&SQLiteDialect {}, sql).map(|mut s| s.pop()) {
+            Ok(Some(Statement::CreateIndex(create))) => {
+                is_unique = create.unique;
+                columns = create
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .map(|(position, column)| {
+                        let (expr, collation) = match &column.expr {
+                            Expr::Collate { expr, collation } => (expr.as_ref(), Some(collation.to_string())),
+                            expr => (expr, None),
+                        };
+                        IndexColumn {
+                            name: match expr {
+                                Expr::Identifier(ident) => ident.value.clone(),
+                                other => other.to_string(),
+                            },
+                            position,
+                            sort_order: if column.asc == Some(false) {
+                                SortOrder::Descending
+                            } else {
+                                SortOrder::Ascending
+                            },
+                            collation,
+                        }
+                    })
+                    .collect();
+            }
+            _ => eprintln!("Warning: Could not parse definition of index {}", record.name),
+        },
+        None => {
+            // Automatic indexes have no SQL; sqlite_autoindex_<table>_<n> backs
+            // the n-th UNIQUE or PRIMARY KEY constraint of the table
+            is_unique = true;
+            let ordinal = record
+                .name
+                .rsplit('_')
+                .next()
+                .and_then(|n| n.parse::<usize>().ok())
+                .unwrap_or(0);
+
+            let sets = table
+                .and_then(|table| parse_create_table(&table.sql).ok())
+                .map(|create| autoindex_column_sets(&create))
+                .unwrap_or_default();
+
+            if let Some(set) = ordinal.checked_sub(1).and_then(|i| sets.get(i)) {
+                columns = set
+                    .iter()
+                    .enumerate()
+                    .map(|(position, name)| IndexColumn {
+                        name: name.clone(),
+                        position,
+                        sort_order: SortOrder::Ascending,
+                        collation: None,
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    IndexSchema {
+        name: record.name.clone(),
+        table_name: record.tbl_name.clone(),
+        columns,
+        is_unique,
+        index_type: IndexType::BTree,
+        root_page: record.root_page,
+        sql: record.sql.clone().unwrap_or_default(),
+        estimated_entries: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENERATED: &str = "CREATE TABLE g(a INT, b INT GENERATED ALWAYS AS (a*2) STORED, PRIMARY KEY(a))";
+
+    #[test]
+    fn table_options_are_read_in_either_order() {
+        for options in ["WITHOUT ROWID, STRICT", "STRICT, WITHOUT ROWID", "strict ,without  rowid;", "WITHOUT ROWID", "STRICT"] {
+            let sql = format!("{} {}", GENERATED, options);
+            let create = parse_create_table(&sql).unwrap_or_else(|e| panic!("{}: {}", sql, e));
+            let upper = options.to_uppercase();
+            assert_eq!(create.without_rowid, upper.contains("ROWID"), "{}", sql);
+            assert_eq!(create.strict, upper.contains("STRICT"), "{}", sql);
+
+            // What is rendered back is accepted by SQLite
+            let rendered = create_table_sql(&create);
+            rusqlite::Connection::open_in_memory()
+                .unwrap()
+                .execute_batch(&rendered)
+                .unwrap_or_else(|e| panic!("{}: {}", rendered, e));
+        }
+
+        // Only a list of known options is rewritten
+        assert!(parse_create_table(&format!("{} WITHOUT ROWID, STRICT, TEMPORAL", GENERATED)).is_err());
+        assert_eq!(unseparated_table_options("CREATE TABLE t AS SELECT (1), (2)"), "CREATE TABLE t AS SELECT (1), (2)");
+        assert_eq!(
+            create_table_sql(&parse_create_table("CREATE TABLE t (k TEXT PRIMARY KEY) STRICT, WITHOUT ROWID").unwrap()),
+            "CREATE TABLE t (k TEXT PRIMARY KEY) WITHOUT ROWID, STRICT"
+        );
+    }
+
+    #[test]
+    fn catalog_keeps_the_columns_of_tables_with_several_options() {
+        let sql = format!("{} WITHOUT ROWID, STRICT", GENERATED);
+        let record = MasterRecord {
+            object_type: "table".to_string(),
+            name: "g".to_string(),
+            tbl_name: "g".to_string(),
+            root_page: 2,
+            sql: Some(sql),
+        };
+        let table = table_schema_from_record(&record);
+
+        let columns: Vec<&str> = table.columns.iter().map(|column| column.name.as_str()).collect();
+        assert_eq!(columns, vec!["a", "b"]);
+        assert!(table.is_without_rowid());
+        assert!(table.is_strict());
+        assert!(table.columns[0].is_primary_key);
+        assert_eq!(table.columns[1].generated(), Some(("a * 2", GeneratedKind::Stored)));
     }
 }
@@ -0,0 +1,396 @@
+//! Sessions, their temp schema and their attached databases
+//!
+//! SQLite keeps TEMP tables, views, indexes and triggers in a database that
+//! belongs to one connection and disappears with it. A session holds such a
+//! connection to the main database for its whole lifetime, with temp_store
+//! set to MEMORY, so the temp schema lives in memory and is dropped when the
+//! session ends. Databases ATTACHed during a session belong to it in the
+//! same way, as do the values the shell binds with `.param set` and its
+//! `.bail` setting. A transaction opened with BEGIN runs on the session's
+//! connection until it ends, so it spans statements and commands. The shell
+//! opens one session for as long as it runs, a one-shot command gets one for
+//! that command, and API clients open, reuse and end them explicitly; idle
+//! API sessions are expired.
+
+use anyhow::{anyhow, Result};
+use rusqlite::Connection;
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use super::attach::Attachment;
+use super::constants::TEMP_SCHEMA_TABLE;
+use super::table::{MasterRecord, SchemaExtractor};
+use super::SchemaCatalog;
+use crate::engine::execution::collation;
+use crate::engine::execution::script::OnError;
+use crate::parser::params::Params;
+
+/// A session's connection, the database it was opened on, the
+/// databases attached to it, its bound parameter values and what its
+/// scripts do after a failing statement
+struct Session {
+    db_path: String,
+    connection: Connection,
+    attachments: Vec<Attachment>,
+    parameters: Params,
+    on_error: OnError,
+    last_used: Instant,
+}
+
+static SESSIONS: OnceLock<Mutex<HashMap<String, Arc<Mutex<Session>>>>> = OnceLock::new();
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn sessions() -> &'static Mutex<HashMap<String, Arc<Mutex<Session>>>> {
+    SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn canonical(db_path: &str) -> String {
+    Path::new(db_path)
+        .canonicalize()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| db_path.to_string())
+}
+
+/// Hard-to-guess identifier from the process's randomly keyed hasher
+fn new_session_id() -> String {
+    let random = RandomState::new();
+    let mut id = String::new();
+    for _ in 0..2 {
+        let mut hasher = random.build_hasher();
+        hasher.write_u64(SESSION_COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id
+}
+
+/// Opens a session on `db_path` and returns its id
+pub fn open_session(db_path: &str) -> Result<String> {
+    let connection = Connection::open(db_path)?;
+    connection.pragma_update(None, "temp_store", "MEMORY")?;
+    collation::install(&connection)?;
+
+    let id = new_session_id();
+    sessions().lock().unwrap().insert(
+        id.clone(),
+        Arc::new(Mutex::new(Session {
+            db_path: canonical(db_path),
+            connection,
+            attachments: Vec::new(),
+            parameters: Params::new(),
+            on_error: OnError::default(),
+            last_used: Instant::now(),
+        })),
+    );
+
+    println!("[SESSION] Opened session {} on {}", id, db_path);
+    Ok(id)
+}
+
+/// Ends a session, dropping its temp schema. Returns whether it existed.
+pub fn end_session(id: &str) -> bool {
+    let ended = sessions().lock().unwrap().remove(id).is_some();
+    if ended {
+        println!("[SESSION] Ended session {}", id);
+    }
+    ended
+}
+
+/// Ends sessions unused for longer than `max_idle`, returning their ids
+pub fn expire_idle(max_idle: Duration) -> Vec<String> {
+    let mut registry = sessions().lock().unwrap();
+    let expired: Vec<String> = registry
+        .iter()
+        // A session busy running a statement is not idle
+        .filter(|(_, session)| session.try_lock().is_ok_and(|session| session.last_used.elapsed() > max_idle))
+        .map(|(id, _)| id.clone())
+        .collect();
+
+    for id in &expired {
+        registry.remove(id);
+        println!("[SESSION] Expired idle session {}", id);
+    }
+    expired
+}
+
+/// Fails unless the session exists and was opened on `db_path`
+pub fn check_session(id: &str, db_path: &str) -> Result<()> {
+    let session = find(id)?;
+    let session = session.lock().unwrap();
+    if session.db_path != canonical(db_path) {
+        return Err(anyhow!("Session {} belongs to another database", id));
+    }
+    Ok(())
+}
+
+fn find(id: &str) -> Result<Arc<Mutex<Session>>> {
+    sessions()
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| anyhow!("No such session: {}", id))
+}
+
+/// Databases attached to the session, in attach order
+pub fn attachments(id: &str) -> Result<Vec<Attachment>> {
+    let session = find(id)?;
+    let session = session.lock().unwrap();
+    Ok(session.attachments.clone())
+}
+
+/// Runs `f` on the session's list of attachments, for ATTACH and DETACH
+pub fn update_attachments<T>(id: &str, f: impl FnOnce(&mut Vec<Attachment>) -> Result<T>) -> Result<T> {
+    let session = find(id)?;
+    let mut session = session.lock().unwrap();
+    session.last_used = Instant::now();
+    f(&mut session.attachments)
+}
+
+/// Parameter values bound in the session, which its statements' parameters take
+pub fn parameters(id: &str) -> Result<Params> {
+    let session = find(id)?;
+    let session = session.lock().unwrap();
+    Ok(session.parameters.clone())
+}
+
+/// Runs `f` on the session's parameter values, for the shell's `.param`
+pub fn update_parameters<T>(id: &str, f: impl FnOnce(&mut Params) -> Result<T>) -> Result<T> {
+    let session = find(id)?;
+    let mut session = session.lock().unwrap();
+    session.last_used = Instant::now();
+    f(&mut session.parameters)
+}
+
+/// What the session's scripts do after a failing statement
+pub fn on_error(id: &str) -> Result<OnError> {
+    let session = find(id)?;
+    let session = session.lock().unwrap();
+    Ok(session.on_error)
+}
+
+/// Sets what the session's scripts do after a failing statement, for the shell's `.bail`
+pub fn set_on_error(id: &str, on_error: OnError) -> Result<()> {
+    let session = find(id)?;
+    let mut session = session.lock().unwrap();
+    session.last_used = Instant::now();
+    session.on_error = on_error;
+    Ok(())
+}
+
+/// Whether a transaction is open on the session's connection
+pub fn in_transaction(id: &str) -> Result<bool> {
+    let session = find(id)?;
+    let session = session.lock().unwrap();
+    Ok(!session.connection.is_autocommit())
+}
+
+/// Runs `f` on the session's connection, after bringing its attached
+/// databases in line with the session's attachments
+pub fn with_connection<T>(id: &str, f: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+    let session = find(id)?;
+    let mut session = session.lock().unwrap();
+    session.last_used = Instant::now();
+
+    sync_attachments(&session.connection, &session.attachments)?;
+    f(&session.connection)
+}
+
+fn sync_attachments(connection: &Connection, wanted: &[Attachment]) -> Result<()> {
+    let mut statement = connection.prepare("PRAGMA database_list")?;
+    let current: Vec<(String, String)> = statement
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for (name, file) in &current {
+        if name == "main" || name == "temp" {
+            continue;
+        }
+        if !wanted.iter().any(|a| a.alias.eq_ignore_ascii_case(name) && &a.path == file) {
+            connection.execute("DETACH DATABASE ?1", [name])?;
+        }
+    }
+    for attachment in wanted {
+        if !current.iter().any(|(name, file)| name.eq_ignore_ascii_case(&attachment.alias) && file == &attachment.path) {
+            connection.execute("ATTACH DATABASE ?1 AS ?2", [&attachment.path, &attachment.alias])?;
+        }
+    }
+    Ok(())
+}
+
+/// Catalog of the session's temp schema, or `None` while it is empty
+pub fn catalog(id: &str) -> Result<Option<Arc<SchemaCatalog>>> {
+    with_connection(id, |connection| {
+        let version: u32 = connection.query_row("PRAGMA temp.schema_version", [], |row| row.get(0))?;
+
+        let mut statement = connection.prepare(&format!(
+            "SELECT type, name, tbl_name, rootpage, sql FROM {} ORDER BY rowid",
+            TEMP_SCHEMA_TABLE
+        ))?;
+        let records: Vec<MasterRecord> = statement
+            .query_map([], |row| {
+                Ok(MasterRecord {
+                    object_type: row.get(0)?,
+                    name: row.get(1)?,
+                    tbl_name: row.get(2)?,
+                    root_page: row.get::<_, Option<u32>>(3)?.unwrap_or(0),
+                    sql: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        if records.is_empty() {
+            return Ok(None);
+        }
+
+        let catalog = SchemaExtractor::new("temp")?
+            .load_master_records(records, version, true)?
+            .build_catalog()?;
+        Ok(Some(Arc::new(catalog)))
+    })
+}
+
+/// Whether a statement names the temp schema: CREATE TEMP, `temp.name`
+pub fn mentions_temp(sql: &str) -> bool {
+    Tokenizer::new(&SQLiteDialect {}, sql)
+        .tokenize()
+        .map(|tokens| {
+            tokens.iter().any(|token| match token {
+                Token::Word(word) => {
+                    word.quote_style.is_none()
+                        && (word.value.eq_ignore_ascii_case("TEMP") || word.value.eq_ignore_ascii_case("TEMPORARY"))
+                }
+                _ => false,
+            })
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("whatql-temp-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);
+                 INSERT INTO items (name) VALUES ('from main');",
+            )
+            .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn names(session: &str, sql: &str) -> Vec<String> {
+        with_connection(session, |connection| {
+            Ok(connection
+                .prepare(sql)?
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn temp_table_shadows_the_main_table_in_its_session_only() {
+        let db_path = fixture("shadow");
+        let session = open_session(&db_path).unwrap();
+        let other = open_session(&db_path).unwrap();
+
+        with_connection(&session, |connection| {
+            connection.execute_batch(
+                "CREATE TEMP TABLE items (id INTEGER PRIMARY KEY, name TEXT, note TEXT);
+                 INSERT INTO items (name) VALUES ('from temp');",
+            )?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(names(&session, "SELECT name FROM items"), vec!["from temp"]);
+        assert_eq!(names(&session, "SELECT name FROM main.items"), vec!["from main"]);
+        assert_eq!(names(&other, "SELECT name FROM items"), vec!["from main"]);
+
+        // The catalog resolves unqualified names the way SQLite does
+        let catalog = crate::schema::cache::get_session_catalog(&db_path, Some(&session)).unwrap();
+        let (schema, owner) = catalog.resolve_schema(None, "items").unwrap();
+        assert_eq!(schema, "temp");
+        assert_eq!(owner.find_table("items").unwrap().columns.len(), 3);
+        let (schema, owner) = catalog.resolve_schema(Some("main"), "items").unwrap();
+        assert_eq!(schema, "main");
+        assert_eq!(owner.find_table("items").unwrap().columns.len(), 2);
+
+        let catalog = crate::schema::cache::get_session_catalog(&db_path, Some(&other)).unwrap();
+        assert!(catalog.temp().is_none());
+        assert_eq!(catalog.resolve_schema(None, "items").unwrap().0, "main");
+
+        end_session(&session);
+        end_session(&other);
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn ending_the_session_drops_its_temp_schema() {
+        let db_path = fixture("cleanup");
+        let session = open_session(&db_path).unwrap();
+        assert!(catalog(&session).unwrap().is_none());
+
+        with_connection(&session, |connection| {
+            connection.execute_batch(
+                "CREATE TEMP TABLE scratch (value);
+                 CREATE TEMP VIEW recent AS SELECT value FROM scratch;
+                 CREATE TEMP TRIGGER items_copy AFTER INSERT ON main.items BEGIN INSERT INTO scratch VALUES (new.name); END;
+                 INSERT INTO items (name) VALUES ('copied');",
+            )?;
+            Ok(())
+        })
+        .unwrap();
+        let temp = catalog(&session).unwrap().unwrap();
+        assert!(temp.find_table("scratch").is_some());
+        assert!(temp.find_view("recent").is_some());
+        assert_eq!(names(&session, "SELECT value FROM recent"), vec!["copied"]);
+
+        assert!(end_session(&session));
+        assert!(!end_session(&session));
+        assert!(catalog(&session).unwrap_err().to_string().contains("No such session"));
+
+        // The main database keeps its own rows and none of the temp objects
+        let main = Connection::open(&db_path).unwrap();
+        let objects: Vec<String> = main
+            .prepare("SELECT name FROM sqlite_master ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(objects, vec!["items"]);
+        let count: i64 = main.query_row("SELECT count(*) FROM items", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+
+        // A new session starts with an empty temp schema
+        let next = open_session(&db_path).unwrap();
+        assert!(catalog(&next).unwrap().is_none());
+        assert!(check_session(&next, &db_path).is_ok());
+        assert!(check_session(&next, "/elsewhere.db").is_err());
+        end_session(&next);
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn temp_mentions_are_found_outside_quotes_only() {
+        assert!(mentions_temp("CREATE TEMP TABLE t (a)"));
+        assert!(mentions_temp("create temporary view v as select 1"));
+        assert!(mentions_temp("SELECT * FROM temp.t"));
+        assert!(!mentions_temp("SELECT 'temp' FROM \"temp\""));
+        assert!(!mentions_temp("SELECT * FROM temperatures"));
+    }
+}
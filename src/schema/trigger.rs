@@ -0,0 +1,463 @@
+//! Trigger schema definition and parsing
+//!
+//! sqlparser does not understand CREATE TRIGGER, so the stored definition is
+//! split with a small scanner that only needs to recognise words, quoting,
+//! comments and the punctuation that delimits the trigger's parts.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// When a trigger fires relative to the row change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerTiming {
+    Before,
+    After,
+    InsteadOf,
+}
+
+impl fmt::Display for TriggerTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerTiming::Before => write!(f, "BEFORE"),
+            TriggerTiming::After => write!(f, "AFTER"),
+            TriggerTiming::InsteadOf => write!(f, "INSTEAD OF"),
+        }
+    }
+}
+
+/// The kind of write that fires a trigger
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerEvent {
+    Insert,
+    Delete,
+    /// `UPDATE OF a, b` restricts firing to updates that assign one of the columns
+    Update { columns: Vec<String> },
+}
+
+impl fmt::Display for TriggerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerEvent::Insert => write!(f, "INSERT"),
+            TriggerEvent::Delete => write!(f, "DELETE"),
+            TriggerEvent::Update { columns } if columns.is_empty() => write!(f, "UPDATE"),
+            TriggerEvent::Update { columns } => write!(f, "UPDATE OF {}", columns.join(", ")),
+        }
+    }
+}
+
+/// Represents a trigger stored in sqlite_master
+#[derive(Debug, Clone)]
+pub struct TriggerSchema {
+    pub name: String,
+    pub table_name: String,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    pub when_clause: Option<String>,
+    /// Statements between BEGIN and END, without their terminating semicolons
+    pub body: Vec<String>,
+    pub sql: String,
+    pub is_temporary: bool,
+    /// Position in sqlite_master; later triggers fire first, as in SQLite
+    pub sequence: usize,
+}
+
+impl fmt::Display for TriggerSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Trigger[{}] {} {} ON {} ({} statements)",
+            self.name,
+            self.timing,
+            self.event,
+            self.table_name,
+            self.body.len()
+        )
+    }
+}
+
+impl TriggerSchema {
+    /// Whether a write of the given kind fires this trigger. For updates,
+    /// `assigned` lists the columns the UPDATE sets.
+    pub fn matches(&self, timing: TriggerTiming, event: &TriggerEvent, assigned: &[String]) -> bool {
+        if self.timing != timing {
+            return false;
+        }
+
+        match (&self.event, event) {
+            (TriggerEvent::Insert, TriggerEvent::Insert) => true,
+            (TriggerEvent::Delete, TriggerEvent::Delete) => true,
+            (TriggerEvent::Update { columns }, TriggerEvent::Update { .. }) => {
+                columns.is_empty()
+                    || columns
+                        .iter()
+                        .any(|column| assigned.iter().any(|a| a.eq_ignore_ascii_case(column)))
+            }
+            _ => false,
+        }
+    }
+
+    /// Parses a stored CREATE TRIGGER statement
+    pub fn parse(sql: &str, sequence: usize) -> Result<Self> {
+        let tokens = scan(sql)?;
+        let mut cursor = TokenCursor { sql, tokens: &tokens, position: 0 };
+
+        cursor.expect_word("CREATE")?;
+        let is_temporary = cursor.accept_word("TEMP") || cursor.accept_word("TEMPORARY");
+        cursor.expect_word("TRIGGER")?;
+
+        if cursor.accept_word("IF") {
+            cursor.expect_word("NOT")?;
+            cursor.expect_word("EXISTS")?;
+        }
+
+        let mut name = cursor.name()?;
+        if cursor.accept_punct('.') {
+            name = cursor.name()?;
+        }
+
+        let timing = if cursor.accept_word("BEFORE") {
+            TriggerTiming::Before
+        } else if cursor.accept_word("AFTER") {
+            TriggerTiming::After
+        } else if cursor.accept_word("INSTEAD") {
+            cursor.expect_word("OF")?;
+            TriggerTiming::InsteadOf
+        } else {
+            // SQLite defaults to BEFORE when no timing is given
+            TriggerTiming::Before
+        };
+
+        let event = if cursor.accept_word("INSERT") {
+            TriggerEvent::Insert
+        } else if cursor.accept_word("DELETE") {
+            TriggerEvent::Delete
+        } else if cursor.accept_word("UPDATE") {
+            let mut columns = Vec::new();
+            if cursor.accept_word("OF") {
+                loop {
+                    columns.push(cursor.name()?);
+                    if !cursor.accept_punct(',') {
+                        break;
+                    }
+                }
+            }
+            TriggerEvent::Update { columns }
+        } else {
+            return Err(anyhow!("Trigger {} has no INSERT, UPDATE or DELETE event", name));
+        };
+
+        cursor.expect_word("ON")?;
+        let mut table_name = cursor.name()?;
+        if cursor.accept_punct('.') {
+            table_name = cursor.name()?;
+        }
+
+        if cursor.accept_word("FOR") {
+            cursor.expect_word("EACH")?;
+            cursor.expect_word("ROW")?;
+        }
+
+        let when_clause = if cursor.accept_word("WHEN") {
+            let start = cursor.offset();
+            while !cursor.at_end() && !cursor.peek_word("BEGIN") {
+                cursor.position += 1;
+            }
+            Some(sql[start..cursor.offset()].trim().to_string())
+        } else {
+            None
+        };
+
+        cursor.expect_word("BEGIN")?;
+        let body = cursor.statements_until_end()?;
+
+        if body.is_empty() {
+            return Err(anyhow!("Trigger {} has an empty body", name));
+        }
+
+        Ok(TriggerSchema {
+            name,
+            table_name,
+            timing,
+            event,
+            when_clause,
+            body,
+            sql: sql.to_string(),
+            is_temporary,
+            sequence,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    /// Bare or quoted identifier/keyword, with quotes removed
+    Word { value: String, quoted: bool },
+    Punct(char),
+    Literal,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+/// Splits SQL into words, literals and punctuation, skipping whitespace and comments
+fn scan(sql: &str) -> Result<Vec<Token>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'-' && bytes.get(i + 1) == Some(&b'-') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            i = sql[i + 2..].find("*/").map(|end| i + 2 + end + 2).unwrap_or(bytes.len());
+        } else if matches!(c, b'\'' | b'"' | b'`' | b'[') {
+            let close = if c == b'[' { b']' } else { c };
+            i += 1;
+            loop {
+                if i >= bytes.len() {
+                    return Err(anyhow!("Unterminated quoted token at offset {}", start));
+                }
+                if bytes[i] == close {
+                    // Doubled quotes escape themselves, except inside brackets
+                    if close != b']' && bytes.get(i + 1) == Some(&close) {
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                i += 1;
+            }
+
+            let kind = if c == b'\'' {
+                TokenKind::Literal
+            } else {
+                let inner = &sql[start + 1..i - 1];
+                let doubled = (close as char).to_string().repeat(2);
+                TokenKind::Word {
+                    value: inner.replace(&doubled, &(close as char).to_string()),
+                    quoted: true,
+                }
+            };
+            tokens.push(Token { kind, start, end: i });
+        } else if c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80 {
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'$' || bytes[i] >= 0x80)
+            {
+                i += 1;
+            }
+            let value = sql[start..i].to_string();
+            let kind = if value.as_bytes()[0].is_ascii_digit() {
+                TokenKind::Literal
+            } else {
+                TokenKind::Word { value, quoted: false }
+            };
+            tokens.push(Token { kind, start, end: i });
+        } else {
+            i += 1;
+            tokens.push(Token { kind: TokenKind::Punct(c as char), start, end: i });
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct TokenCursor<'a> {
+    sql: &'a str,
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl TokenCursor<'_> {
+    fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.start)
+            .unwrap_or(self.sql.len())
+    }
+
+    fn peek_word(&self, keyword: &str) -> bool {
+        matches!(
+            self.tokens.get(self.position).map(|token| &token.kind),
+            Some(TokenKind::Word { value, quoted: false }) if value.eq_ignore_ascii_case(keyword)
+        )
+    }
+
+    fn accept_word(&mut self, keyword: &str) -> bool {
+        let found = self.peek_word(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_word(&mut self, keyword: &str) -> Result<()> {
+        if self.accept_word(keyword) {
+            Ok(())
+        } else {
+            Err(anyhow!("Expected {} at offset {} of trigger definition", keyword, self.offset()))
+        }
+    }
+
+    fn accept_punct(&mut self, punct: char) -> bool {
+        let found = matches!(
+            self.tokens.get(self.position).map(|token| &token.kind),
+            Some(TokenKind::Punct(c)) if *c == punct
+        );
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.tokens.get(self.position).map(|token| &token.kind) {
+            Some(TokenKind::Word { value, .. }) => {
+                self.position += 1;
+                Ok(value.clone())
+            }
+            // SQLite also accepts string literals as names
+            Some(TokenKind::Literal) => {
+                let token = &self.tokens[self.position];
+                self.position += 1;
+                Ok(self.sql[token.start + 1..token.end - 1].replace("''", "'"))
+            }
+            _ => Err(anyhow!("Expected a name at offset {} of trigger definition", self.offset())),
+        }
+    }
+
+    /// Collects the statements of a BEGIN ... END block. CASE expressions also
+    /// end with END, so their nesting is tracked to find the block's own END.
+    fn statements_until_end(&mut self) -> Result<Vec<String>> {
+        let mut statements = Vec::new();
+        let mut statement_start = self.offset();
+        let mut case_depth = 0;
+        let mut paren_depth = 0;
+
+        while !self.at_end() {
+            if self.peek_word("CASE") {
+                case_depth += 1;
+            } else if self.peek_word("END") {
+                if case_depth == 0 {
+                    let tail = self.sql[statement_start..self.offset()].trim();
+                    if !tail.is_empty() {
+                        statements.push(tail.to_string());
+                    }
+                    return Ok(statements);
+                }
+                case_depth -= 1;
+            } else if self.accept_punct('(') {
+                paren_depth += 1;
+                continue;
+            } else if self.accept_punct(')') {
+                paren_depth -= 1;
+                continue;
+            } else if paren_depth == 0 && case_depth == 0 && self.accept_punct(';') {
+                let end = self.tokens[self.position - 1].start;
+                let statement = self.sql[statement_start..end].trim();
+                if !statement.is_empty() {
+                    statements.push(statement.to_string());
+                }
+                statement_start = self.offset();
+                continue;
+            }
+
+            self.position += 1;
+        }
+
+        Err(anyhow!("Trigger body is missing its closing END"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timing_event_and_body() {
+        let trigger = TriggerSchema::parse(
+            "CREATE TRIGGER log_insert AFTER INSERT ON users BEGIN INSERT INTO log VALUES (NEW.id); DELETE FROM stale; END",
+            3,
+        )
+        .unwrap();
+
+        assert_eq!(trigger.name, "log_insert");
+        assert_eq!(trigger.table_name, "users");
+        assert_eq!(trigger.timing, TriggerTiming::After);
+        assert_eq!(trigger.event, TriggerEvent::Insert);
+        assert_eq!(trigger.when_clause, None);
+        assert_eq!(trigger.body, vec!["INSERT INTO log VALUES (NEW.id)", "DELETE FROM stale"]);
+        assert_eq!(trigger.sequence, 3);
+        assert!(!trigger.is_temporary);
+    }
+
+    #[test]
+    fn timing_defaults_to_before() {
+        let trigger = TriggerSchema::parse("CREATE TRIGGER t DELETE ON items BEGIN SELECT 1; END", 0).unwrap();
+        assert_eq!(trigger.timing, TriggerTiming::Before);
+        assert_eq!(trigger.event, TriggerEvent::Delete);
+    }
+
+    #[test]
+    fn parses_update_of_columns_and_when_clause() {
+        let trigger = TriggerSchema::parse(
+            "CREATE TEMP TRIGGER IF NOT EXISTS main.\"price watch\" BEFORE UPDATE OF price, [qty] ON main.items \
+             FOR EACH ROW WHEN NEW.price < 0 BEGIN SELECT RAISE(ABORT, 'negative; price'); END",
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(trigger.name, "price watch");
+        assert_eq!(trigger.table_name, "items");
+        assert!(trigger.is_temporary);
+        assert_eq!(trigger.event, TriggerEvent::Update { columns: vec!["price".to_string(), "qty".to_string()] });
+        assert_eq!(trigger.when_clause.as_deref(), Some("NEW.price < 0"));
+        // The semicolon inside the string literal does not split the body
+        assert_eq!(trigger.body, vec!["SELECT RAISE(ABORT, 'negative; price')"]);
+    }
+
+    #[test]
+    fn body_keeps_nested_case_end() {
+        let trigger = TriggerSchema::parse(
+            "CREATE TRIGGER t INSTEAD OF INSERT ON v BEGIN \
+             INSERT INTO t VALUES (CASE WHEN NEW.a THEN 1 ELSE 0 END); -- trailing END; comment\n END",
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(trigger.timing, TriggerTiming::InsteadOf);
+        assert_eq!(trigger.body, vec!["INSERT INTO t VALUES (CASE WHEN NEW.a THEN 1 ELSE 0 END)"]);
+    }
+
+    #[test]
+    fn rejects_missing_event_and_empty_body() {
+        assert!(TriggerSchema::parse("CREATE TRIGGER t BEFORE ON items BEGIN SELECT 1; END", 0).is_err());
+        assert!(TriggerSchema::parse("CREATE TRIGGER t AFTER INSERT ON items BEGIN END", 0).is_err());
+    }
+
+    #[test]
+    fn update_of_matches_only_assigned_columns() {
+        let trigger = TriggerSchema::parse("CREATE TRIGGER t AFTER UPDATE OF price ON items BEGIN SELECT 1; END", 0).unwrap();
+        let update = TriggerEvent::Update { columns: Vec::new() };
+
+        assert!(trigger.matches(TriggerTiming::After, &update, &["PRICE".to_string()]));
+        assert!(!trigger.matches(TriggerTiming::After, &update, &["qty".to_string()]));
+        assert!(!trigger.matches(TriggerTiming::Before, &update, &["price".to_string()]));
+        assert!(!trigger.matches(TriggerTiming::After, &TriggerEvent::Insert, &[]));
+    }
+}
@@ -0,0 +1,152 @@
+//! View schema definition and parsing
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+use super::SchemaCatalog;
+use crate::parser::ast::{Expression, Select, SelectBody, SelectItem, StatementKind, TableFactor};
+
+/// Deepest chain of views referencing views that column resolution follows
+const MAX_VIEW_NESTING: usize = 32;
+
+/// Represents a view stored in sqlite_master with its parsed SELECT body
+#[derive(Debug, Clone)]
+pub struct ViewSchema {
+    pub name: String,
+    /// Explicit column list from `CREATE VIEW v(a, b) AS ...`, empty if none
+    pub columns: Vec<String>,
+    pub query: Box<Select>,
+    pub sql: String,
+    pub is_temporary: bool,
+}
+
+impl fmt::Display for ViewSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "View[{}] ({} declared columns)", self.name, self.columns.len())
+    }
+}
+
+impl ViewSchema {
+    /// Parses the CREATE VIEW statement stored for a view
+    pub fn parse(name: &str, sql: &str) -> Result<Self> {
+        match crate::parser::parse_sql(sql)?.kind {
+            StatementKind::CreateView(view) => Ok(ViewSchema {
+                name: name.to_string(),
+                columns: view.columns,
+                query: view.query,
+                sql: sql.to_string(),
+                is_temporary: view.temporary,
+            }),
+            _ => Err(anyhow!("Definition of view {} is not a CREATE VIEW statement", name)),
+        }
+    }
+
+    /// Names of the columns the view produces. The declared column list wins;
+    /// otherwise names come from the body's projection, expanding `*` through
+    /// the catalog. Expressions without an alias keep their SQL text, as SQLite does.
+    pub fn column_names(&self, catalog: &SchemaCatalog) -> Vec<String> {
+        self.column_names_at_depth(catalog, 0)
+    }
+
+    fn column_names_at_depth(&self, catalog: &SchemaCatalog, depth: usize) -> Vec<String> {
+        if !self.columns.is_empty() {
+            return self.columns.clone();
+        }
+
+        // Compound selects take their column names from the first SELECT
+        let select = match &self.query.body {
+            SelectBody::Select(core) => core,
+            SelectBody::Values(_) => return Vec::new(),
+        };
+
+        let relations: Vec<(&TableFactor, Option<String>)> = select
+            .from
+            .iter()
+            .flat_map(|from| std::iter::once(&from.relation).chain(from.joins.iter().map(|join| &join.relation)))
+            .map(|factor| match factor {
+                TableFactor::Table { name, alias, .. } => (factor, Some(alias.clone().unwrap_or_else(|| name.name.clone()))),
+                TableFactor::Subquery { alias, .. } => (factor, alias.clone()),
+                _ => (factor, None),
+            })
+            .collect();
+
+        let relation_columns = |factor: &TableFactor| -> Vec<String> {
+            match factor {
+                TableFactor::Table { name, .. } => {
+                    if let Some(table) = catalog.find_table(&name.name) {
+                        table.columns.iter().map(|column| column.name.clone()).collect()
+                    } else if let Some(view) = catalog.find_view(&name.name).filter(|_| depth < MAX_VIEW_NESTING) {
+                        view.column_names_at_depth(catalog, depth + 1)
+                    } else {
+                        Vec::new()
+                    }
+                }
+                _ => Vec::new(),
+            }
+        };
+
+        let mut names = Vec::new();
+        for item in &select.columns {
+            match item {
+                SelectItem::Expression { alias: Some(alias), .. } => names.push(alias.clone()),
+                SelectItem::Expression { expr: Expression::Column { name, .. }, .. } => names.push(name.clone()),
+                SelectItem::Expression { expr, .. } => names.push(expr.to_string()),
+                SelectItem::Wildcard => {
+                    for (factor, _) in &relations {
+                        names.extend(relation_columns(factor));
+                    }
+                }
+                SelectItem::QualifiedWildcard(qualifier) => {
+                    for (factor, alias) in &relations {
+                        if alias.as_deref().is_some_and(|a| a.eq_ignore_ascii_case(&qualifier.name)) {
+                            names.extend(relation_columns(factor));
+                        }
+                    }
+                }
+            }
+        }
+
+        names
+    }
+}
+
+/// Last component of a possibly schema-qualified name
+pub fn object_leaf(name: &sqlparser::ast::ObjectName) -> String {
+    name.0.last().map(|ident| ident.value.clone()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_names_follow_column_lists_aliases_and_nested_wildcards() {
+        let path = std::env::temp_dir().join(format!("whatql-view-columns-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_path = path.to_str().unwrap();
+        rusqlite::Connection::open(db_path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE emp (id INTEGER PRIMARY KEY, name TEXT, dept TEXT);
+                 CREATE TABLE dept (code TEXT, title TEXT);
+                 CREATE VIEW staff AS SELECT * FROM emp;
+                 CREATE VIEW named (who, unit) AS SELECT name, dept FROM staff;
+                 CREATE VIEW mixed AS SELECT s.*, d.title AS dept_title, upper(s.name) FROM staff s JOIN dept d ON d.code = s.dept;
+                 CREATE VIEW compound AS SELECT name AS label FROM emp UNION SELECT title FROM dept;",
+            )
+            .unwrap();
+        let catalog = crate::schema::cache::get_catalog(db_path).unwrap();
+        let columns = |view: &str| catalog.find_view(view).unwrap().column_names(&catalog);
+
+        assert_eq!(columns("staff"), vec!["id", "name", "dept"]);
+        assert_eq!(columns("named"), vec!["who", "unit"]);
+        assert_eq!(columns("mixed"), vec!["id", "name", "dept", "dept_title", "upper(s.name)"]);
+        assert_eq!(columns("compound"), vec!["label"]);
+
+        let view = ViewSchema::parse("named", "CREATE VIEW named (who, unit) AS SELECT name, dept FROM staff").unwrap();
+        assert!(!view.is_temporary);
+        assert!(ViewSchema::parse("t", "CREATE TABLE t (a)").is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
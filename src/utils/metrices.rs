@@ -119,8 +119,8 @@ impl PerformanceTracker {
     
     pub fn generate_report(&self) -> String {
         let mut report = String::new();
-        report.push_str(&format!("Performance Report\n"));
-        report.push_str(&format!("=================\n"));
+        report.push_str("Performance Report\n");
+        report.push_str("=================\n");
         report.push_str(&format!("Total time: {:.2}s\n\n", self.total_elapsed().as_secs_f64()));
         
         if let Ok(ops) = self.operations.lock() {